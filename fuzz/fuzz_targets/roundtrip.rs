@@ -0,0 +1,19 @@
+#![no_main]
+
+use jsonp::parser::Parser;
+use jsonp::serializer::Serializer;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let json = match std::str::from_utf8(data) {
+        Ok(json) => json,
+        Err(_) => return,
+    };
+    if let Ok(element) = Parser::parse(json) {
+        let serialized = Serializer::serialize(&element);
+        match Parser::parse(&serialized) {
+            Ok(reparsed) => assert_eq!(element, reparsed),
+            Err(e) => panic!("serialized output {serialized:?} does not parse: {}", e.details),
+        }
+    }
+});
@@ -0,0 +1,330 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Reading and writing json on tokio's async readers and writers.
+//!
+//! Input is handed to a `PushParser` one read at a time, so a task waiting for
+//! the rest of a body leaves its executor thread free and only the unread part
+//! of the input is buffered.
+
+use crate::json_element::JsonElement;
+use crate::parser::{ParseError, ParseErrorKind, ParserOptions};
+use crate::push::{PushParser, Status};
+use crate::serializer::{Serializer, SerializerOptions};
+
+use futures_core::Stream;
+use std::io;
+use std::pin::{pin, Pin};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// How many bytes are read at a time.
+const CHUNK_LEN: usize = 8 * 1024;
+
+fn io_error(error: io::Error) -> ParseError {
+    return ParseError::with_kind(ParseErrorKind::Io, &error.to_string());
+}
+
+/// Reads the next chunk into `parser`, finishing it at the end of the input.
+async fn read_chunk<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    parser: &mut PushParser,
+    buffer: &mut [u8],
+) -> Result<(), ParseError> {
+    let read = reader.read(buffer).await.map_err(io_error)?;
+    if read == 0 {
+        parser.finish();
+    } else {
+        parser.feed(&buffer[..read]);
+    }
+    return Ok(());
+}
+
+/// Reads one json document from `reader`. It fails when `Parser::parse` would
+/// on the whole input, or when reading fails.
+pub async fn parse_async(reader: impl AsyncRead) -> Result<JsonElement, ParseError> {
+    return parse_async_with_options(reader, &ParserOptions::default()).await;
+}
+
+pub async fn parse_async_with_options(
+    reader: impl AsyncRead,
+    options: &ParserOptions,
+) -> Result<JsonElement, ParseError> {
+    let mut reader = pin!(reader);
+    let mut parser = PushParser::with_options(*options);
+    let mut buffer = vec![0; CHUNK_LEN];
+    let value = loop {
+        match parser.next_value()? {
+            Status::Ready(value) => break value,
+            Status::NeedMoreData => read_chunk(&mut reader, &mut parser, &mut buffer).await?,
+            Status::Done => return Err(ParseError::new("Invalid json value")),
+        }
+    };
+    loop {
+        match parser.next_event()? {
+            Status::Ready(_) => {
+                return Err(ParseError::new(
+                    "Unexpected characters after the json value",
+                ))
+            }
+            Status::NeedMoreData => read_chunk(&mut reader, &mut parser, &mut buffer).await?,
+            Status::Done => return Ok(value),
+        }
+    }
+}
+
+/// The values of newline delimited json (any whitespace separates them), read
+/// as they arrive. The stream ends after the first error.
+pub struct NdjsonStream<R> {
+    reader: R,
+    parser: PushParser,
+    buffer: Box<[u8]>,
+    ended: bool,
+}
+
+impl<R: AsyncRead + Unpin> NdjsonStream<R> {
+    /// A stream over `reader`. A reader that is not `Unpin` can be passed
+    /// boxed, as `Box::pin(reader)`.
+    pub fn new(reader: R) -> NdjsonStream<R> {
+        return NdjsonStream::with_options(reader, &ParserOptions::default());
+    }
+
+    /// Like `new`, with the limits applying to each value.
+    pub fn with_options(reader: R, options: &ParserOptions) -> NdjsonStream<R> {
+        return NdjsonStream {
+            reader,
+            parser: PushParser::with_options(*options),
+            buffer: vec![0; CHUNK_LEN].into_boxed_slice(),
+            ended: false,
+        };
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for NdjsonStream<R> {
+    type Item = Result<JsonElement, ParseError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let stream = self.get_mut();
+        loop {
+            if stream.ended {
+                return Poll::Ready(None);
+            }
+            match stream.parser.next_value() {
+                Ok(Status::Ready(value)) => return Poll::Ready(Some(Ok(value))),
+                Ok(Status::NeedMoreData) => {}
+                Ok(Status::Done) => {
+                    stream.ended = true;
+                    return Poll::Ready(None);
+                }
+                Err(error) => {
+                    stream.ended = true;
+                    return Poll::Ready(Some(Err(error)));
+                }
+            }
+            let mut buffer = ReadBuf::new(&mut stream.buffer);
+            match Pin::new(&mut stream.reader).poll_read(cx, &mut buffer) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(error)) => {
+                    stream.ended = true;
+                    return Poll::Ready(Some(Err(io_error(error))));
+                }
+                Poll::Ready(Ok(())) if buffer.filled().is_empty() => stream.parser.finish(),
+                Poll::Ready(Ok(())) => stream.parser.feed(buffer.filled()),
+            }
+        }
+    }
+}
+
+/// Writes serialized values to an async writer.
+pub struct JsonWriter<W> {
+    writer: W,
+    options: SerializerOptions,
+}
+
+impl<W: AsyncWrite + Unpin> JsonWriter<W> {
+    /// A writer of compact values.
+    pub fn new(writer: W) -> JsonWriter<W> {
+        return JsonWriter::with_options(writer, SerializerOptions::default());
+    }
+
+    pub fn with_options(writer: W, options: SerializerOptions) -> JsonWriter<W> {
+        return JsonWriter { writer, options };
+    }
+
+    pub async fn write(&mut self, element: &JsonElement) -> io::Result<()> {
+        let out = Serializer::serialize_with_options(element, &self.options);
+        return self.writer.write_all(out.as_bytes()).await;
+    }
+
+    /// Writes `element` and a newline, one record of newline delimited json
+    /// when the options are compact.
+    pub async fn write_line(&mut self, element: &JsonElement) -> io::Result<()> {
+        let mut out = Serializer::serialize_with_options(element, &self.options);
+        out.push('\n');
+        return self.writer.write_all(out.as_bytes()).await;
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        return self.writer.flush().await;
+    }
+
+    pub fn into_inner(self) -> W {
+        return self.writer;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_async, parse_async_with_options, JsonWriter, NdjsonStream};
+    use crate::json_element::JsonElement;
+    use crate::parser::{ParseError, ParseErrorKind, Parser, ParserOptions};
+    use crate::test_support::json;
+
+    use futures_core::Stream;
+    use std::future::poll_fn;
+    use std::io;
+    use std::pin::Pin;
+    use tokio::io::{duplex, AsyncRead, AsyncWriteExt, ReadBuf};
+
+    async fn collect<R: AsyncRead + Unpin>(
+        mut stream: NdjsonStream<R>,
+    ) -> Vec<Result<JsonElement, ParseError>> {
+        let mut items = Vec::new();
+        while let Some(item) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            items.push(item);
+        }
+        return items;
+    }
+
+    #[tokio::test]
+    async fn should_parse_from_a_duplex_stream() {
+        let text = "{\"name\": \"caf\u{e9}\", \"list\": [1, 2.5, true, null]}\n";
+        let (mut client, server) = duplex(4);
+        let writer = tokio::spawn(async move {
+            for byte in text.as_bytes() {
+                client.write_all(&[*byte]).await.unwrap();
+            }
+        });
+        assert_eq!(Ok(json(text)), parse_async(server).await);
+        writer.await.unwrap();
+
+        assert_eq!(
+            "Unexpected characters after the json value",
+            parse_async(&b"[1] 2"[..]).await.err().unwrap().details
+        );
+        assert_eq!(
+            Parser::parse("").err().map(|error| error.details),
+            parse_async(&b"  "[..])
+                .await
+                .err()
+                .map(|error| error.details)
+        );
+        let options = ParserOptions {
+            max_depth: 1,
+            ..ParserOptions::default()
+        };
+        assert_eq!(
+            ParseErrorKind::DepthLimitExceeded,
+            parse_async_with_options(&b"[[1]]"[..], &options)
+                .await
+                .err()
+                .unwrap()
+                .kind
+        );
+    }
+
+    #[tokio::test]
+    async fn should_stream_ndjson() {
+        let (mut client, server) = duplex(16);
+        let writer = tokio::spawn(async move {
+            client
+                .write_all(b"{\"id\": 1}\n{\"id\": 2, \"tags\": [\"a\",")
+                .await
+                .unwrap();
+            client.write_all(b" \"b\"]}\n\n3\n").await.unwrap();
+        });
+        let items = collect(NdjsonStream::new(server)).await;
+        writer.await.unwrap();
+        assert_eq!(
+            vec![
+                Ok(json(r#"{"id": 1}"#)),
+                Ok(json(r#"{"id": 2, "tags": ["a", "b"]}"#)),
+                Ok(json("3")),
+            ],
+            items
+        );
+
+        let items = collect(NdjsonStream::new(&b"1\n[2,\n{]\n4\n"[..])).await;
+        assert_eq!(2, items.len());
+        assert_eq!(Ok(json("1")), items[0]);
+        assert!(items[1].is_err());
+
+        let items = collect(NdjsonStream::new(&b"1\n2true\n"[..])).await;
+        assert_eq!(3, items.len());
+        assert_eq!(Ok(json("2")), items[1]);
+        assert_eq!(
+            "Expected whitespace between values",
+            items[2].as_ref().err().unwrap().details
+        );
+    }
+
+    /// A reader that fails after its first read.
+    struct Failing(bool);
+
+    impl AsyncRead for Failing {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            if self.0 {
+                return std::task::Poll::Ready(Err(io::Error::other("connection reset")));
+            }
+            self.0 = true;
+            buf.put_slice(b"[1, ");
+            return std::task::Poll::Ready(Ok(()));
+        }
+    }
+
+    #[tokio::test]
+    async fn should_report_read_errors() {
+        let error = parse_async(Failing(false)).await.err().unwrap();
+        assert_eq!(ParseErrorKind::Io, error.kind);
+        assert_eq!("connection reset", error.details);
+        let items = collect(NdjsonStream::new(Failing(false))).await;
+        assert_eq!(ParseErrorKind::Io, items[0].as_ref().err().unwrap().kind);
+    }
+
+    #[tokio::test]
+    async fn should_write_values() {
+        let (client, mut server) = duplex(8);
+        let reader = tokio::spawn(async move {
+            let mut out = String::new();
+            tokio::io::AsyncReadExt::read_to_string(&mut server, &mut out)
+                .await
+                .unwrap();
+            out
+        });
+        let mut writer = JsonWriter::new(client);
+        writer
+            .write_line(&json(r#"{"a": [1, "x"]}"#))
+            .await
+            .unwrap();
+        writer.write(&json("null")).await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer.into_inner());
+        assert_eq!("{\"a\":[1,\"x\"]}\nnull", reader.await.unwrap());
+    }
+}
@@ -0,0 +1,475 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Language server for json files, speaking the Language Server Protocol over
+//! stdio. JSON-RPC messages are read and written with the library itself.
+//!
+//! Diagnostics come from the recovering parser, so every problem in a file is
+//! reported at once. Formatting, symbols, hover and folding need the file to
+//! be valid json and answer with nothing until it is.
+
+use jsonp::json_element::JsonElement;
+use jsonp::jsonrpc::{
+    read_message, write_message, Framing, Id, Message, Notification, Response, RpcError,
+};
+use jsonp::parser::{Parser, Position, Span, SpanTable};
+use jsonp::pointer::JsonPointer;
+use jsonp::serializer::{Serializer, SerializerOptions};
+
+use std::collections::HashMap;
+use std::io;
+use std::process;
+
+/// LSP `SymbolKind` values.
+const SYMBOL_STRING: f64 = 15.0;
+const SYMBOL_NUMBER: f64 = 16.0;
+const SYMBOL_BOOLEAN: f64 = 17.0;
+const SYMBOL_ARRAY: f64 = 18.0;
+const SYMBOL_OBJECT: f64 = 19.0;
+const SYMBOL_NULL: f64 = 21.0;
+
+const SEVERITY_ERROR: f64 = 1.0;
+const SYNC_FULL: f64 = 1.0;
+
+type RequestResult = Result<JsonElement, RpcError>;
+
+fn object(members: Vec<(&str, JsonElement)>) -> JsonElement {
+    return JsonElement::Object(
+        members
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    );
+}
+
+fn string(s: &str) -> JsonElement {
+    return JsonElement::Str(s.to_string());
+}
+
+/// The value at `pointer` inside a message, e.g. `/params/textDocument/uri`.
+fn field<'a>(message: &'a JsonElement, pointer: &str) -> Option<&'a JsonElement> {
+    return JsonPointer::parse(pointer).ok()?.resolve(message);
+}
+
+fn str_field<'a>(message: &'a JsonElement, pointer: &str) -> Option<&'a str> {
+    return match field(message, pointer) {
+        Some(JsonElement::Str(s)) => Some(s),
+        _ => None,
+    };
+}
+
+fn number_field(message: &JsonElement, pointer: &str) -> Option<f64> {
+    return match field(message, pointer) {
+        Some(JsonElement::Number(n)) => Some(*n),
+        _ => None,
+    };
+}
+
+fn invalid_params(what: &str) -> RpcError {
+    return RpcError::invalid_params(&format!("Missing or invalid {what}"));
+}
+
+fn response(id: Id, result: RequestResult) -> JsonElement {
+    return Message::Response(Response { id, result }).to_element();
+}
+
+fn notification(method: &str, params: JsonElement) -> JsonElement {
+    let notification = Notification {
+        method: method.to_string(),
+        params: Some(params),
+    };
+    return Message::Notification(notification).to_element();
+}
+
+/// A position as LSP counts it: lines from 0 and characters in UTF-16 code
+/// units from the start of the line.
+fn lsp_position(text: &str, position: Position) -> JsonElement {
+    let line_start = text[..position.offset].rfind('\n').map_or(0, |i| i + 1);
+    let character = text[line_start..position.offset].encode_utf16().count();
+    return object(vec![
+        ("line", JsonElement::Number((position.line - 1) as f64)),
+        ("character", JsonElement::Number(character as f64)),
+    ]);
+}
+
+fn lsp_range(text: &str, span: Span) -> JsonElement {
+    return object(vec![
+        ("start", lsp_position(text, span.start)),
+        ("end", lsp_position(text, span.end)),
+    ]);
+}
+
+/// The byte offset of an LSP position. Characters past the end of the line
+/// stop at its end.
+fn offset_of(text: &str, line: usize, character: usize) -> Option<usize> {
+    let line_start = if line == 0 {
+        0
+    } else {
+        text.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    return Some(text.len());
+}
+
+fn document_end(text: &str) -> Position {
+    return Position {
+        offset: text.len(),
+        line: text.matches('\n').count() + 1,
+        column: 0,
+    };
+}
+
+fn symbol_kind(element: &JsonElement) -> f64 {
+    return match element {
+        JsonElement::Object(_) => SYMBOL_OBJECT,
+        JsonElement::Array(_) => SYMBOL_ARRAY,
+        JsonElement::Str(_) => SYMBOL_STRING,
+        JsonElement::Number(_) => SYMBOL_NUMBER,
+        JsonElement::Boolean(_) => SYMBOL_BOOLEAN,
+        JsonElement::Null => SYMBOL_NULL,
+    };
+}
+
+/// The outline of the members of `element`, in document order. The parser
+/// bounds the nesting, so recursing here is safe.
+fn symbols(
+    text: &str,
+    spans: &SpanTable,
+    element: &JsonElement,
+    pointer: &JsonPointer,
+) -> Vec<JsonElement> {
+    let mut children: Vec<(String, &JsonElement, JsonPointer)> = match element {
+        JsonElement::Object(members) => members
+            .iter()
+            .map(|(key, value)| (key.clone(), value, pointer.child(key)))
+            .collect(),
+        JsonElement::Array(elements) => elements
+            .iter()
+            .enumerate()
+            .map(|(i, value)| (i.to_string(), value, pointer.index(i)))
+            .collect(),
+        _ => return Vec::new(),
+    };
+    children.sort_by_key(|(_, _, child)| spans.value(child).map(|span| span.start.offset));
+    let mut outline = Vec::new();
+    for (name, value, child) in children {
+        let value_span = match spans.value(&child) {
+            Some(span) => span,
+            None => continue,
+        };
+        let selection = spans.key(&child).unwrap_or(value_span);
+        let range = Span {
+            start: selection.start,
+            end: value_span.end,
+        };
+        outline.push(object(vec![
+            ("name", JsonElement::Str(name)),
+            ("kind", JsonElement::Number(symbol_kind(value))),
+            ("range", lsp_range(text, range)),
+            ("selectionRange", lsp_range(text, selection)),
+            (
+                "children",
+                JsonElement::Array(symbols(text, spans, value, &child)),
+            ),
+        ]));
+    }
+    return outline;
+}
+
+#[derive(Default)]
+struct Server {
+    /// The text of every open document, by uri.
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    exit_code: Option<i32>,
+}
+
+impl Server {
+    /// Handles one incoming message and returns the messages to send back.
+    fn handle(&mut self, message: &JsonElement) -> Vec<JsonElement> {
+        let method = str_field(message, "/method").unwrap_or_default();
+        let id = field(message, "/id").map(|id| Id::from_element(id).unwrap_or(Id::Null));
+        if method == "exit" {
+            self.exit_code = Some(if self.shutdown { 0 } else { 1 });
+            return Vec::new();
+        }
+        return match id {
+            Some(id) => {
+                let result = if self.shutdown {
+                    Err(RpcError::invalid_request("The server is shutting down"))
+                } else {
+                    self.request(method, message)
+                };
+                vec![response(id, result)]
+            }
+            None => self.notify(method, message),
+        };
+    }
+
+    fn request(&mut self, method: &str, message: &JsonElement) -> RequestResult {
+        return match method {
+            "initialize" => Ok(object(vec![
+                (
+                    "capabilities",
+                    object(vec![
+                        ("textDocumentSync", JsonElement::Number(SYNC_FULL)),
+                        ("documentFormattingProvider", JsonElement::Boolean(true)),
+                        ("documentSymbolProvider", JsonElement::Boolean(true)),
+                        ("hoverProvider", JsonElement::Boolean(true)),
+                        ("foldingRangeProvider", JsonElement::Boolean(true)),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    object(vec![
+                        ("name", string("jsonp-lsp")),
+                        ("version", string(env!("CARGO_PKG_VERSION"))),
+                    ]),
+                ),
+            ])),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(JsonElement::Null)
+            }
+            "textDocument/formatting" => self.formatting(message),
+            "textDocument/documentSymbol" => self.document_symbols(message),
+            "textDocument/hover" => self.hover(message),
+            "textDocument/foldingRange" => self.folding_ranges(message),
+            _ => Err(RpcError::method_not_found(method)),
+        };
+    }
+
+    fn notify(&mut self, method: &str, message: &JsonElement) -> Vec<JsonElement> {
+        let uri = match str_field(message, "/params/textDocument/uri") {
+            Some(uri) => uri.to_string(),
+            None => return Vec::new(),
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = str_field(message, "/params/textDocument/text").unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didChange" => {
+                // With full sync every change carries the whole text.
+                let changes = match field(message, "/params/contentChanges") {
+                    Some(JsonElement::Array(changes)) => changes,
+                    _ => return Vec::new(),
+                };
+                match changes.last().and_then(|change| str_field(change, "/text")) {
+                    Some(text) => self.documents.insert(uri.clone(), text.to_string()),
+                    None => return Vec::new(),
+                };
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+        let text = &self.documents[&uri];
+        let diagnostics = Parser::parse_recovering(text)
+            .diagnostics
+            .iter()
+            .map(|diagnostic| {
+                object(vec![
+                    ("range", lsp_range(text, diagnostic.span)),
+                    ("severity", JsonElement::Number(SEVERITY_ERROR)),
+                    ("source", string("jsonp")),
+                    ("message", string(&diagnostic.message)),
+                ])
+            })
+            .collect();
+        return vec![publish_diagnostics(&uri, diagnostics)];
+    }
+
+    fn document(&self, message: &JsonElement) -> Result<&str, RpcError> {
+        let uri = str_field(message, "/params/textDocument/uri")
+            .ok_or_else(|| invalid_params("textDocument.uri"))?;
+        return match self.documents.get(uri) {
+            Some(text) => Ok(text),
+            None => Err(RpcError::invalid_params(&format!(
+                "Document {uri} is not open"
+            ))),
+        };
+    }
+
+    fn formatting(&self, message: &JsonElement) -> RequestResult {
+        let text = self.document(message)?;
+        let element = match Parser::parse(text) {
+            Ok(element) => element,
+            Err(_) => return Ok(JsonElement::Null),
+        };
+        let tab_size = number_field(message, "/params/options/tabSize").unwrap_or(2.0);
+        let indent = match field(message, "/params/options/insertSpaces") {
+            Some(JsonElement::Boolean(false)) => "\t".to_string(),
+            _ => " ".repeat(tab_size as usize),
+        };
+        let mut formatted =
+            Serializer::serialize_with_options(&element, &SerializerOptions::pretty(&indent));
+        formatted.push('\n');
+        if formatted == text {
+            return Ok(JsonElement::Array(Vec::new()));
+        }
+        let whole = Span {
+            start: Position {
+                offset: 0,
+                line: 1,
+                column: 1,
+            },
+            end: document_end(text),
+        };
+        return Ok(JsonElement::Array(vec![object(vec![
+            ("range", lsp_range(text, whole)),
+            ("newText", JsonElement::Str(formatted)),
+        ])]));
+    }
+
+    fn document_symbols(&self, message: &JsonElement) -> RequestResult {
+        let text = self.document(message)?;
+        return match Parser::parse_with_spans(text) {
+            Ok((element, spans)) => Ok(JsonElement::Array(symbols(
+                text,
+                &spans,
+                &element,
+                &JsonPointer::root(),
+            ))),
+            Err(_) => Ok(JsonElement::Null),
+        };
+    }
+
+    fn hover(&self, message: &JsonElement) -> RequestResult {
+        let text = self.document(message)?;
+        let line = number_field(message, "/params/position/line")
+            .ok_or_else(|| invalid_params("position.line"))?;
+        let character = number_field(message, "/params/position/character")
+            .ok_or_else(|| invalid_params("position.character"))?;
+        let offset = match offset_of(text, line as usize, character as usize) {
+            Some(offset) => offset,
+            None => return Ok(JsonElement::Null),
+        };
+        let spans = match Parser::parse_with_spans(text) {
+            Ok((_, spans)) => spans,
+            Err(_) => return Ok(JsonElement::Null),
+        };
+        // The innermost value or key under the cursor.
+        let found = spans
+            .values()
+            .chain(spans.keys())
+            .filter(|(_, span)| span.start.offset <= offset && offset < span.end.offset)
+            .min_by_key(|(_, span)| span.end.offset - span.start.offset);
+        let (pointer, span) = match found {
+            Some(found) => found,
+            None => return Ok(JsonElement::Null),
+        };
+        let path = if pointer.tokens().is_empty() {
+            "(root)".to_string()
+        } else {
+            pointer.to_string()
+        };
+        return Ok(object(vec![
+            (
+                "contents",
+                object(vec![
+                    ("kind", string("plaintext")),
+                    ("value", JsonElement::Str(path)),
+                ]),
+            ),
+            ("range", lsp_range(text, *span)),
+        ]));
+    }
+
+    /// One range per array or object spanning several lines, from its opening
+    /// line to the line before its closing bracket.
+    fn folding_ranges(&self, message: &JsonElement) -> RequestResult {
+        let text = self.document(message)?;
+        let spans = match Parser::parse_with_spans(text) {
+            Ok((_, spans)) => spans,
+            Err(_) => return Ok(JsonElement::Null),
+        };
+        let mut lines: Vec<(usize, usize)> = spans
+            .values()
+            .filter(|(_, span)| matches!(text.as_bytes()[span.start.offset], b'{' | b'['))
+            .filter(|(_, span)| span.end.line > span.start.line + 1)
+            .map(|(_, span)| (span.start.line - 1, span.end.line - 2))
+            .collect();
+        lines.sort_unstable();
+        return Ok(JsonElement::Array(
+            lines
+                .into_iter()
+                .map(|(start, end)| {
+                    object(vec![
+                        ("startLine", JsonElement::Number(start as f64)),
+                        ("endLine", JsonElement::Number(end as f64)),
+                    ])
+                })
+                .collect(),
+        ));
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<JsonElement>) -> JsonElement {
+    return notification(
+        "textDocument/publishDiagnostics",
+        object(vec![
+            ("uri", string(uri)),
+            ("diagnostics", JsonElement::Array(diagnostics)),
+        ]),
+    );
+}
+
+fn main() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut server = Server::default();
+    loop {
+        let body = match read_message(&mut input, Framing::ContentLength) {
+            Ok(Some(body)) => body,
+            // Input closing without an exit notification counts as a crash.
+            Ok(None) => process::exit(if server.shutdown { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("jsonp-lsp: {e}");
+                process::exit(1);
+            }
+        };
+        let parsed = match std::str::from_utf8(&body) {
+            Ok(body) => Parser::parse(body).map_err(RpcError::from),
+            Err(_) => Err(RpcError::new(
+                RpcError::PARSE_ERROR,
+                "Message is not valid UTF-8",
+            )),
+        };
+        let replies = match parsed {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![response(Id::Null, Err(e))],
+        };
+        for reply in &replies {
+            if let Err(e) = write_message(&mut output, Framing::ContentLength, reply) {
+                eprintln!("jsonp-lsp: {e}");
+                process::exit(1);
+            }
+        }
+        if let Some(code) = server.exit_code {
+            process::exit(code);
+        }
+    }
+}
@@ -0,0 +1,470 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Command line front end for the library. It only uses the public API, so it
+//! doubles as a check that the API is enough to build real tools on.
+
+use jsonp::diff;
+use jsonp::json_element::JsonElement;
+use jsonp::parser::{ParseError, Parser};
+use jsonp::query::Filter;
+use jsonp::serializer::{Serializer, SerializerOptions};
+
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "Usage: jsonp <command> [options] [FILE...]
+
+Commands:
+  validate [FILE...]            Exit with 1 and print line:column when a file is not valid json
+  fmt [options] [FILE...]       Pretty print, with object keys sorted
+      --indent <N>              Indent with N spaces (default 2)
+      --tab                     Indent with tabs
+      -i, --in-place            Rewrite the files instead of printing them
+  minify [-i] [FILE...]         Print without any whitespace
+  check [PATTERN...]            Validate every file matching the glob patterns and print a summary
+  query [options] FILTER [FILE...]
+                                Print every value the jq-like FILTER produces for each input
+      -r, --raw-output          Print strings without quotes
+      -c, --compact             Print one compact value per line (NDJSON output)
+      --ndjson                  Read one document per line (NDJSON input), implies --compact
+  diff [options] OLD NEW        Print the structural changes between two documents
+      --json                    Print the changes as a json array
+      --color <WHEN>            Color the output: auto (default), always or never
+
+Standard input is read when no file is given, or when the file is '-'.
+Exit status: 0 on success, 1 when some input is not valid json or the filter fails on it,
+2 on usage or I/O errors. diff exits with 1 when the documents differ and 2 when one
+of them is not valid json.";
+
+const EXIT_INVALID: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+struct Input {
+    name: String,
+    path: Option<PathBuf>,
+    contents: String,
+}
+
+fn read_input(file: Option<&str>) -> io::Result<Input> {
+    return match file {
+        None | Some("-") => {
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents)?;
+            Ok(Input {
+                name: "<stdin>".to_string(),
+                path: None,
+                contents,
+            })
+        }
+        Some(file) => Ok(Input {
+            name: file.to_string(),
+            path: Some(PathBuf::from(file)),
+            contents: fs::read_to_string(file)?,
+        }),
+    };
+}
+
+fn report(name: &str, error: &ParseError) {
+    match error.position {
+        Some(position) => eprintln!(
+            "{name}:{}:{}: {}",
+            position.line, position.column, error.details
+        ),
+        None => eprintln!("{name}: {}", error.details),
+    }
+}
+
+/// The files named on the command line, or standard input when there are none.
+fn files(args: &[String]) -> Vec<Option<&str>> {
+    if args.is_empty() {
+        return vec![None];
+    }
+    return args.iter().map(|arg| Some(arg.as_str())).collect();
+}
+
+/// The documents in an input: the whole contents, or every non blank line when
+/// `ndjson` is set. Errors on a line report the line's number in the input.
+fn documents(input: &Input, ndjson: bool) -> Vec<Result<JsonElement, ParseError>> {
+    if !ndjson {
+        return vec![Parser::parse(&input.contents)];
+    }
+    return input
+        .contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            Parser::parse(line).map_err(|mut e| {
+                if let Some(position) = &mut e.position {
+                    position.line = number + 1;
+                }
+                e
+            })
+        })
+        .collect();
+}
+
+/// Parses every input and hands the valid documents to `output`. Returns the exit status.
+fn for_each_document(
+    files: &[Option<&str>],
+    ndjson: bool,
+    mut output: impl FnMut(&Input, JsonElement) -> io::Result<()>,
+) -> i32 {
+    let mut status = 0;
+    for file in files {
+        let input = match read_input(*file) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("{}: {e}", file.unwrap_or("<stdin>"));
+                status = EXIT_ERROR;
+                continue;
+            }
+        };
+        for document in documents(&input, ndjson) {
+            match document {
+                Ok(element) => {
+                    if let Err(e) = output(&input, element) {
+                        eprintln!("{}: {e}", input.name);
+                        status = EXIT_ERROR;
+                    }
+                }
+                Err(e) => {
+                    report(&input.name, &e);
+                    status = status.max(EXIT_INVALID);
+                }
+            }
+        }
+    }
+    return status;
+}
+
+fn write_output(input: &Input, in_place: bool, mut text: String) -> io::Result<()> {
+    text.push('\n');
+    return match (&input.path, in_place) {
+        (Some(path), true) => fs::write(path, text),
+        _ => io::stdout().write_all(text.as_bytes()),
+    };
+}
+
+fn validate(args: &[String]) -> i32 {
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with('-') && *arg != "-") {
+        eprintln!("Unknown option {flag} for validate");
+        return EXIT_ERROR;
+    }
+    return for_each_document(&files(args), false, |_, _| Ok(()));
+}
+
+fn format(args: &[String], minify: bool) -> i32 {
+    let mut indent = "  ".to_string();
+    let mut in_place = false;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--in-place" => in_place = true,
+            "--tab" if !minify => indent = "\t".to_string(),
+            "--indent" if !minify => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(width) => indent = " ".repeat(width),
+                None => {
+                    eprintln!("--indent expects a number of spaces");
+                    return EXIT_ERROR;
+                }
+            },
+            "-" => paths.push(arg.clone()),
+            flag if flag.starts_with('-') => {
+                eprintln!("Unknown option {flag}");
+                return EXIT_ERROR;
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if in_place && paths.iter().all(|path| path == "-") {
+        eprintln!("--in-place needs at least one file");
+        return EXIT_ERROR;
+    }
+    let options = if minify {
+        SerializerOptions {
+            indent: None,
+            sort_keys: true,
+        }
+    } else {
+        SerializerOptions::pretty(&indent)
+    };
+    return for_each_document(&files(&paths), false, |input, element| {
+        let text = Serializer::serialize_with_options(&element, &options);
+        write_output(input, in_place, text)
+    });
+}
+
+/// Matches a file name against a pattern where `*` stands for any run of
+/// characters and `?` for exactly one.
+fn wildcard_matches(pattern: &[char], name: &[char]) -> bool {
+    return match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| wildcard_matches(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && wildcard_matches(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && wildcard_matches(rest, &name[1..]),
+    };
+}
+
+/// `dir` and every directory below it. Symbolic links to directories are not
+/// followed, so that a link to a parent cannot make the walk loop forever.
+fn subdirectories(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    found.push(dir.to_path_buf());
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            subdirectories(&entry.path(), found)?;
+        }
+    }
+    return Ok(());
+}
+
+/// Expands `*`, `?` and `**` (any number of directories) in a path pattern.
+/// Patterns without wildcards are returned unchanged, even if they do not exist.
+fn expand_glob(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    if !pattern.contains(['*', '?']) {
+        return Ok(vec![PathBuf::from(pattern)]);
+    }
+    let absolute = pattern.starts_with('/');
+    let mut candidates = vec![PathBuf::from(if absolute { "/" } else { "." })];
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    for (i, component) in components.iter().enumerate() {
+        let last = i + 1 == components.len();
+        let mut next = Vec::new();
+        for dir in &candidates {
+            if *component == "**" {
+                subdirectories(dir, &mut next)?;
+            } else if component.contains(['*', '?']) {
+                let pattern: Vec<char> = component.chars().collect();
+                let entries = match fs::read_dir(dir) {
+                    Ok(entries) => entries,
+                    Err(_) => continue,
+                };
+                for entry in entries {
+                    let path = entry?.path();
+                    let name: Vec<char> = match path.file_name() {
+                        Some(name) => name.to_string_lossy().chars().collect(),
+                        None => continue,
+                    };
+                    if wildcard_matches(&pattern, &name) && (last || path.is_dir()) {
+                        next.push(path);
+                    }
+                }
+            } else {
+                next.push(dir.join(component));
+            }
+        }
+        candidates = next;
+    }
+    candidates.retain(|path| path.is_file());
+    candidates.sort();
+    candidates.dedup();
+    return Ok(candidates
+        .into_iter()
+        .map(|path| match path.strip_prefix(".") {
+            Ok(relative) if !absolute => relative.to_path_buf(),
+            _ => path,
+        })
+        .collect());
+}
+
+fn check(args: &[String]) -> i32 {
+    let mut paths: Vec<String> = Vec::new();
+    for pattern in args {
+        match expand_glob(pattern) {
+            Ok(found) if found.is_empty() => eprintln!("{pattern}: no files match"),
+            Ok(found) => paths.extend(found.iter().map(|p| p.to_string_lossy().into_owned())),
+            Err(e) => {
+                eprintln!("{pattern}: {e}");
+                return EXIT_ERROR;
+            }
+        }
+    }
+    if !args.is_empty() && paths.is_empty() {
+        return EXIT_ERROR;
+    }
+    let files = files(&paths);
+    let mut valid = 0;
+    let status = for_each_document(&files, false, |_, _| {
+        valid += 1;
+        Ok(())
+    });
+    println!(
+        "{} files checked: {valid} valid, {} invalid",
+        files.len(),
+        files.len() - valid
+    );
+    return status;
+}
+
+fn query(args: &[String]) -> i32 {
+    let mut raw = false;
+    let mut compact = false;
+    let mut ndjson = false;
+    let mut source = None;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-r" | "--raw-output" => raw = true,
+            "-c" | "--compact" => compact = true,
+            "--ndjson" => ndjson = true,
+            "-" => paths.push(arg.clone()),
+            flag if flag.starts_with('-') => {
+                eprintln!("Unknown option {flag}");
+                return EXIT_ERROR;
+            }
+            _ if source.is_none() => source = Some(arg.as_str()),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    let filter = match source.map(Filter::parse) {
+        Some(Ok(filter)) => filter,
+        Some(Err(e)) => {
+            eprintln!("Invalid filter: {e}");
+            return EXIT_ERROR;
+        }
+        None => {
+            eprintln!("query expects a filter");
+            return EXIT_ERROR;
+        }
+    };
+    let options = if compact || ndjson {
+        SerializerOptions {
+            indent: None,
+            sort_keys: true,
+        }
+    } else {
+        SerializerOptions::pretty("  ")
+    };
+    let mut failed = false;
+    let status = for_each_document(&files(&paths), ndjson, |input, element| {
+        let results = match filter.apply(&element) {
+            Ok(results) => results,
+            Err(e) => {
+                eprintln!("{}: {e}", input.name);
+                failed = true;
+                return Ok(());
+            }
+        };
+        let mut out = io::stdout().lock();
+        for result in &results {
+            match result {
+                JsonElement::Str(string) if raw => writeln!(out, "{string}")?,
+                _ => writeln!(
+                    out,
+                    "{}",
+                    Serializer::serialize_with_options(result, &options)
+                )?,
+            }
+        }
+        Ok(())
+    });
+    return if failed {
+        status.max(EXIT_INVALID)
+    } else {
+        status
+    };
+}
+
+fn compare(args: &[String]) -> i32 {
+    let mut json = false;
+    let mut color = io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--color" => match args.next().map(String::as_str) {
+                Some("always") => color = true,
+                Some("never") => color = false,
+                Some("auto") => {}
+                _ => {
+                    eprintln!("--color expects auto, always or never");
+                    return EXIT_ERROR;
+                }
+            },
+            "-" => paths.push(arg.as_str()),
+            flag if flag.starts_with('-') => {
+                eprintln!("Unknown option {flag}");
+                return EXIT_ERROR;
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    if paths.len() != 2 {
+        eprintln!("diff expects two files");
+        return EXIT_ERROR;
+    }
+    let mut documents = Vec::new();
+    for path in paths {
+        let input = match read_input(Some(path)) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                return EXIT_ERROR;
+            }
+        };
+        match Parser::parse(&input.contents) {
+            Ok(element) => documents.push(element),
+            Err(e) => {
+                report(&input.name, &e);
+                return EXIT_ERROR;
+            }
+        }
+    }
+    let changes = diff::diff(&documents[0], &documents[1]);
+    if json {
+        let text = Serializer::serialize_pretty(&diff::to_json(&changes), "  ");
+        println!("{text}");
+    } else {
+        print!("{}", diff::render(&changes, color));
+    }
+    return if changes.is_empty() { 0 } else { EXIT_INVALID };
+}
+
+fn run(args: &[String]) -> i32 {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => {
+            eprintln!("{USAGE}");
+            return EXIT_ERROR;
+        }
+    };
+    return match command {
+        "validate" => validate(args),
+        "fmt" => format(args, false),
+        "minify" => format(args, true),
+        "check" => check(args),
+        "query" => query(args),
+        "diff" => compare(args),
+        "help" | "-h" | "--help" => {
+            println!("{USAGE}");
+            0
+        }
+        _ => {
+            eprintln!("Unknown command {command}\n\n{USAGE}");
+            EXIT_ERROR
+        }
+    };
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    process::exit(run(&args));
+}
@@ -0,0 +1,433 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! What the binary encodings (`cbor`, `msgpack`) share: decoding options, the
+//! error type, and walking values without recursion.
+//!
+//! Numbers that are integers between -2^53 and 2^53 are encoded as integers,
+//! and every other number as a 64-bit float. Decoded integers and floats of
+//! any size become `JsonElement::Number`, rounded to the nearest `f64` like
+//! long number literals are; NaN and infinities are errors. How byte strings
+//! and map keys other than strings are decoded is set in `DecodeOptions`.
+
+use crate::json_element::{map_with_capacity, JsonElement, Map};
+use crate::serializer::Serializer;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryError {
+    pub details: String,
+    /// Where decoding stopped, in bytes from the start of the input.
+    pub offset: usize,
+}
+
+impl BinaryError {
+    pub fn new(msg: &str, offset: usize) -> BinaryError {
+        return BinaryError {
+            details: msg.to_string(),
+            offset,
+        };
+    }
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.details, self.offset)
+    }
+}
+
+impl core::error::Error for BinaryError {}
+
+/// What a byte string decodes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Bytes {
+    /// A string of the bytes in unpadded base64url, as RFC 8949 suggests when
+    /// converting CBOR to json.
+    #[default]
+    Base64Url,
+    /// An array of numbers from 0 to 255.
+    Array,
+    /// Byte strings are an error.
+    Error,
+}
+
+/// What a map key other than a string decodes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Keys {
+    /// Such keys are an error.
+    #[default]
+    Error,
+    /// The compact json text of the key, so `1` becomes `"1"`.
+    Stringify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    pub bytes: Bytes,
+    pub keys: Keys,
+    /// Arrays and maps may be nested this deep, like `ParserOptions::max_depth`.
+    pub max_depth: usize,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> DecodeOptions {
+        return DecodeOptions {
+            bytes: Bytes::default(),
+            keys: Keys::default(),
+            max_depth: 128,
+        };
+    }
+}
+
+pub(crate) type BinaryResult<T> = Result<T, BinaryError>;
+
+/// The integer a number is encoded as, when it is one that an `f64` holds
+/// exactly. Negative zero stays a float.
+pub(crate) fn as_integer(number: f64) -> Option<i64> {
+    const LIMIT: f64 = 9007199254740992.0;
+    let integer = number as i64;
+    if !(-LIMIT..=LIMIT).contains(&number)
+        || integer as f64 != number
+        || number.to_bits() == (-0.0f64).to_bits()
+    {
+        return None;
+    }
+    return Some(integer);
+}
+
+/// Writes the parts of a value in one encoding.
+pub(crate) trait Encoder {
+    fn null(&mut self);
+    fn boolean(&mut self, value: bool);
+    fn number(&mut self, number: f64);
+    fn string(&mut self, value: &str);
+    fn array(&mut self, len: usize);
+    fn map(&mut self, len: usize);
+}
+
+enum Frame<'a> {
+    Array(core::slice::Iter<'a, JsonElement>),
+    Object(alloc::vec::IntoIter<(&'a String, &'a JsonElement)>),
+}
+
+/// Writes `element` with `encoder`, containers first and then what they hold.
+/// Object members are written sorted by key, so equal values encode the same.
+pub(crate) fn encode<E: Encoder>(element: &JsonElement, encoder: &mut E) {
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut next = Some(element);
+    loop {
+        if let Some(element) = next.take() {
+            match element {
+                JsonElement::Null => encoder.null(),
+                JsonElement::Boolean(value) => encoder.boolean(*value),
+                JsonElement::Number(number) => encoder.number(*number),
+                JsonElement::Str(value) => encoder.string(value),
+                JsonElement::Array(array) => {
+                    encoder.array(array.len());
+                    stack.push(Frame::Array(array.iter()));
+                }
+                JsonElement::Object(object) => {
+                    encoder.map(object.len());
+                    let mut members: Vec<(&String, &JsonElement)> = object.iter().collect();
+                    members.sort_by(|a, b| a.0.cmp(b.0));
+                    stack.push(Frame::Object(members.into_iter()));
+                }
+            }
+        }
+        let frame = match stack.last_mut() {
+            Some(frame) => frame,
+            None => return,
+        };
+        match frame {
+            Frame::Array(elements) => next = elements.next(),
+            Frame::Object(members) => {
+                if let Some((key, value)) = members.next() {
+                    encoder.string(key);
+                    next = Some(value);
+                }
+            }
+        }
+        if next.is_none() {
+            stack.pop();
+        }
+    }
+}
+
+/// One item read from the input: a scalar, the start of an array or map with
+/// its length (`None` until a `Break`), or the end of one.
+pub(crate) enum Item {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    Array(Option<usize>),
+    Map(Option<usize>),
+    #[cfg_attr(not(feature = "cbor"), allow(dead_code))]
+    Break,
+}
+
+/// The input of a decoder.
+pub(crate) struct Input<'a> {
+    bytes: &'a [u8],
+    pub(crate) offset: usize,
+}
+
+impl<'a> Input<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Input<'a> {
+        return Input { bytes, offset: 0 };
+    }
+
+    pub(crate) fn error(&self, msg: &str) -> BinaryError {
+        return BinaryError::new(msg, self.offset);
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        return self.bytes.len() - self.offset;
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> BinaryResult<&'a [u8]> {
+        if len > self.remaining() {
+            return Err(self.error("Unexpected end of input"));
+        }
+        let taken = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        return Ok(taken);
+    }
+
+    pub(crate) fn byte(&mut self) -> BinaryResult<u8> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    /// A big-endian unsigned integer of `len` bytes.
+    pub(crate) fn uint(&mut self, len: usize) -> BinaryResult<u64> {
+        let bytes = self.take(len)?;
+        return Ok(bytes.iter().fold(0, |n, byte| n << 8 | *byte as u64));
+    }
+
+    /// A length read from the input, checked against what is left of it so a
+    /// corrupt length cannot make the decoder reserve huge buffers.
+    pub(crate) fn length(&self, len: u64) -> BinaryResult<usize> {
+        return match usize::try_from(len) {
+            Ok(len) if len <= self.remaining() => Ok(len),
+            _ => Err(self.error("Length is larger than the input")),
+        };
+    }
+
+    pub(crate) fn string(&mut self, len: usize) -> BinaryResult<String> {
+        let start = self.offset;
+        return match core::str::from_utf8(self.take(len)?) {
+            Ok(value) => Ok(value.to_string()),
+            Err(_) => Err(BinaryError::new("Invalid UTF-8 in a string", start)),
+        };
+    }
+
+    /// A big-endian IEEE 754 float of `len` bytes: 2, 4 or 8.
+    pub(crate) fn float(&mut self, len: usize) -> BinaryResult<Item> {
+        let bits = self.uint(len)?;
+        let number = match len {
+            2 => half(bits as u16),
+            4 => f32::from_bits(bits as u32) as f64,
+            _ => f64::from_bits(bits),
+        };
+        if !number.is_finite() {
+            return Err(self.error("Numbers must be finite"));
+        }
+        return Ok(Item::Number(number));
+    }
+}
+
+fn half(bits: u16) -> f64 {
+    let exponent = (bits >> 10 & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    // 2^-24, the value of the lowest mantissa bit of subnormal halves.
+    const SUBNORMAL: f64 = 5.960464477539063e-8;
+    let magnitude = match exponent {
+        0 => mantissa * SUBNORMAL,
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => f64::from_bits(((exponent + 1008) as u64) << 52 | (bits as u64 & 0x3ff) << 42),
+    };
+    return if bits & 0x8000 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    };
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    return out;
+}
+
+/// An array or map being decoded. `remaining` counts items, two per map
+/// entry, and is `None` for containers that end with a `Break`.
+enum Building {
+    Array(Vec<JsonElement>, Option<usize>),
+    Map(Map<String, JsonElement>, Option<String>, Option<usize>),
+}
+
+impl Building {
+    fn remaining(&mut self) -> &mut Option<usize> {
+        return match self {
+            Building::Array(_, remaining) | Building::Map(_, _, remaining) => remaining,
+        };
+    }
+}
+
+/// Decodes one value from `input`, reading items with `read_item`, and
+/// checks that nothing follows it.
+pub(crate) fn decode(
+    input: &mut Input,
+    options: &DecodeOptions,
+    read_item: fn(&mut Input) -> BinaryResult<Item>,
+) -> BinaryResult<JsonElement> {
+    let mut stack: Vec<Building> = Vec::new();
+    loop {
+        let start = input.offset;
+        let item = read_item(input)?;
+        let expects_key = matches!(stack.last(), Some(Building::Map(_, None, _)));
+        if expects_key && options.keys == Keys::Error && !matches!(item, Item::Str(_) | Item::Break)
+        {
+            return Err(BinaryError::new("Map keys must be strings", start));
+        }
+        let mut value = match item {
+            Item::Null => Some(JsonElement::Null),
+            Item::Boolean(value) => Some(JsonElement::Boolean(value)),
+            Item::Number(number) => Some(JsonElement::Number(number)),
+            Item::Str(value) => Some(JsonElement::Str(value)),
+            Item::Bytes(bytes) => Some(match options.bytes {
+                Bytes::Base64Url => JsonElement::Str(base64url(&bytes)),
+                Bytes::Array => JsonElement::Array(
+                    bytes
+                        .iter()
+                        .map(|byte| JsonElement::Number(*byte as f64))
+                        .collect(),
+                ),
+                Bytes::Error => {
+                    return Err(BinaryError::new("Byte strings are not allowed", start))
+                }
+            }),
+            Item::Array(_) | Item::Map(_) if stack.len() >= options.max_depth => {
+                return Err(BinaryError::new(
+                    &format!("Nesting is deeper than {} levels", options.max_depth),
+                    start,
+                ));
+            }
+            Item::Array(len) => {
+                let capacity = len.unwrap_or(0).min(input.remaining());
+                stack.push(Building::Array(Vec::with_capacity(capacity), len));
+                complete(&mut stack)
+            }
+            Item::Map(len) => {
+                let capacity = len.unwrap_or(0).min(input.remaining());
+                let remaining = len.map(|len| len * 2);
+                stack.push(Building::Map(map_with_capacity(capacity), None, remaining));
+                complete(&mut stack)
+            }
+            Item::Break => match stack.last_mut() {
+                Some(Building::Array(_, remaining @ None)) => {
+                    *remaining = Some(0);
+                    complete(&mut stack)
+                }
+                Some(Building::Map(_, None, remaining @ None)) => {
+                    *remaining = Some(0);
+                    complete(&mut stack)
+                }
+                _ => return Err(BinaryError::new("Unexpected break", start)),
+            },
+        };
+        // Hands finished values to their parents, finishing those in turn.
+        while let Some(element) = value.take() {
+            let parent = match stack.last_mut() {
+                Some(parent) => parent,
+                None => {
+                    if input.remaining() > 0 {
+                        return Err(input.error("Unexpected bytes after the value"));
+                    }
+                    return Ok(element);
+                }
+            };
+            if let Some(remaining) = parent.remaining() {
+                *remaining -= 1;
+            }
+            match parent {
+                Building::Array(elements, _) => elements.push(element),
+                Building::Map(_, key @ None, _) => {
+                    // Other keys were rejected when read unless stringified.
+                    let mut element = element;
+                    *key = Some(match &mut element {
+                        JsonElement::Str(key) => core::mem::take(key),
+                        element => Serializer::serialize(element),
+                    });
+                }
+                Building::Map(members, key, _) => {
+                    members.insert(key.take().unwrap(), element);
+                }
+            }
+            value = complete(&mut stack);
+        }
+    }
+}
+
+/// Pops the innermost container when all its items have been read.
+fn complete(stack: &mut Vec<Building>) -> Option<JsonElement> {
+    if stack
+        .last_mut()
+        .is_some_and(|top| *top.remaining() != Some(0))
+    {
+        return None;
+    }
+    return match stack.pop() {
+        Some(Building::Array(elements, _)) => Some(JsonElement::Array(elements)),
+        // Maps end after values only: counts are even and breaks after keys
+        // are rejected.
+        Some(Building::Map(members, _, _)) => Some(JsonElement::Object(members)),
+        None => None,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::base64url;
+
+    #[test]
+    fn should_encode_base64url_without_padding() {
+        for (bytes, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg"),
+            (b"fo", "Zm8"),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg"),
+            (&[0xfb, 0xff, 0xbf], "-_-_"),
+        ] {
+            assert_eq!(encoded, base64url(bytes));
+        }
+    }
+}
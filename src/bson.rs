@@ -0,0 +1,1343 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Converts objects to and from BSON documents.
+//!
+//! Values that json has no type for are written as MongoDB Extended JSON v2
+//! objects such as `{"$oid": "..."}` or `{"$numberDecimal": "..."}`, so they
+//! keep their BSON type through `Parser::parse` and the serializer. `encode`
+//! reads these objects in their canonical and relaxed forms; `decode` writes
+//! the form chosen in `DecodeOptions`. Plain numbers are encoded as int32 or
+//! int64 when they are whole and fit, and as doubles otherwise.
+//!
+//! Code with scope and the deprecated `$regex` and `$binary`/`$type` legacy
+//! forms are not supported.
+
+use crate::json_element::{map_with_capacity, JsonElement, Map};
+use crate::pointer::JsonPointer;
+use crate::serializer::write_number;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BsonError {
+    pub details: String,
+}
+
+impl BsonError {
+    pub fn new(msg: &str) -> BsonError {
+        return BsonError {
+            details: msg.to_string(),
+        };
+    }
+}
+
+impl fmt::Display for BsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl core::error::Error for BsonError {}
+
+/// Which Extended JSON form `decode` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Every number keeps its BSON type (`{"$numberInt": "1"}`) and dates
+    /// are milliseconds (`{"$date": {"$numberLong": "0"}}`), so encoding
+    /// the result gives back the same bytes.
+    Canonical,
+    /// Numbers are plain json numbers where that loses nothing, and dates
+    /// from 1970 to 9999 are ISO 8601 strings. Encoding the result may
+    /// change the type of numbers, such as a double `1.0` to an int32.
+    #[default]
+    Relaxed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    pub mode: Mode,
+    /// Documents may be nested this deep, like `ParserOptions::max_depth`.
+    pub max_depth: usize,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> DecodeOptions {
+        return DecodeOptions {
+            mode: Mode::default(),
+            max_depth: 128,
+        };
+    }
+}
+
+const DOUBLE: u8 = 0x01;
+const STRING: u8 = 0x02;
+const DOCUMENT: u8 = 0x03;
+const ARRAY: u8 = 0x04;
+const BINARY: u8 = 0x05;
+const UNDEFINED: u8 = 0x06;
+const OBJECT_ID: u8 = 0x07;
+const BOOLEAN: u8 = 0x08;
+const DATE: u8 = 0x09;
+const NULL: u8 = 0x0a;
+const REGEX: u8 = 0x0b;
+const DB_POINTER: u8 = 0x0c;
+const CODE: u8 = 0x0d;
+const SYMBOL: u8 = 0x0e;
+const INT32: u8 = 0x10;
+const TIMESTAMP: u8 = 0x11;
+const INT64: u8 = 0x12;
+const DECIMAL128: u8 = 0x13;
+const MIN_KEY: u8 = 0xff;
+const MAX_KEY: u8 = 0x7f;
+
+/// Whole numbers an `f64` holds exactly, from -2^53 to 2^53.
+const EXACT_INTEGERS: f64 = 9007199254740992.0;
+
+/// Encodes an object as a BSON document. Object members are written sorted
+/// by key, so equal values encode the same.
+pub fn encode(element: &JsonElement) -> Result<Vec<u8>, BsonError> {
+    let not_a_document = || BsonError::new("Only objects can be encoded as BSON documents");
+    let object = match element {
+        JsonElement::Object(object) => object,
+        _ => return Err(not_a_document()),
+    };
+    match extended(object) {
+        Ok(None) => {}
+        Ok(Some(_)) => return Err(not_a_document()),
+        Err(msg) => return Err(BsonError::new(&msg)),
+    }
+    let mut out = Vec::new();
+    let mut stack = vec![Frame::new(&mut out, object_members(object))];
+    loop {
+        let frame = stack.last_mut().unwrap();
+        let (token, element) = match frame.members.next() {
+            Some(member) => member,
+            None => {
+                out.push(0);
+                let len = (out.len() - frame.start) as i32;
+                out[frame.start..frame.start + 4].copy_from_slice(&len.to_le_bytes());
+                stack.pop();
+                if stack.is_empty() {
+                    return Ok(out);
+                }
+                continue;
+            }
+        };
+        let error = |msg: &str| {
+            let mut path = JsonPointer::root();
+            for frame in &stack[..stack.len() - 1] {
+                path.push(&frame.token);
+            }
+            path.push(&token);
+            return BsonError::new(&format!("{} at {}", msg, path));
+        };
+        let kind_at = out.len();
+        out.push(0);
+        write_cstring(&mut out, &token).map_err(&error)?;
+        let members = match element {
+            JsonElement::Null => {
+                out[kind_at] = NULL;
+                None
+            }
+            JsonElement::Boolean(value) => {
+                out[kind_at] = BOOLEAN;
+                out.push(*value as u8);
+                None
+            }
+            JsonElement::Number(number) => {
+                out[kind_at] = write_number_value(&mut out, *number);
+                None
+            }
+            JsonElement::Str(value) => {
+                out[kind_at] = STRING;
+                write_string(&mut out, value);
+                None
+            }
+            JsonElement::Array(array) => {
+                out[kind_at] = ARRAY;
+                Some(Members::Array(array.iter().enumerate()))
+            }
+            JsonElement::Object(object) => match extended(object).map_err(|msg| error(&msg))? {
+                Some((kind, bytes)) => {
+                    out[kind_at] = kind;
+                    out.extend_from_slice(&bytes);
+                    None
+                }
+                None => {
+                    out[kind_at] = DOCUMENT;
+                    Some(object_members(object))
+                }
+            },
+        };
+        if let Some(members) = members {
+            let frame = stack.last_mut().unwrap();
+            frame.token = token;
+            stack.push(Frame::new(&mut out, members));
+        }
+    }
+}
+
+enum Members<'a> {
+    Array(core::iter::Enumerate<core::slice::Iter<'a, JsonElement>>),
+    Object(alloc::vec::IntoIter<(&'a String, &'a JsonElement)>),
+}
+
+impl<'a> Iterator for Members<'a> {
+    type Item = (String, &'a JsonElement);
+
+    fn next(&mut self) -> Option<(String, &'a JsonElement)> {
+        return match self {
+            Members::Array(elements) => elements.next().map(|(i, e)| (i.to_string(), e)),
+            Members::Object(members) => members.next().map(|(k, v)| (k.clone(), v)),
+        };
+    }
+}
+
+fn object_members(object: &Map<String, JsonElement>) -> Members<'_> {
+    let mut members: Vec<(&String, &JsonElement)> = object.iter().collect();
+    members.sort_by(|a, b| a.0.cmp(b.0));
+    return Members::Object(members.into_iter());
+}
+
+/// A document being written: where its length goes, what is left of it, and
+/// the key of the member being written, for error messages.
+struct Frame<'a> {
+    start: usize,
+    members: Members<'a>,
+    token: String,
+}
+
+impl<'a> Frame<'a> {
+    fn new(out: &mut Vec<u8>, members: Members<'a>) -> Frame<'a> {
+        let start = out.len();
+        out.extend_from_slice(&[0; 4]);
+        return Frame {
+            start,
+            members,
+            token: String::new(),
+        };
+    }
+}
+
+fn write_cstring(out: &mut Vec<u8>, value: &str) -> Result<(), &'static str> {
+    if value.contains('\0') {
+        return Err("Keys and regular expressions cannot contain NUL");
+    }
+    out.extend_from_slice(value.as_bytes());
+    out.push(0);
+    return Ok(());
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as i32 + 1).to_le_bytes());
+    out.extend_from_slice(value.as_bytes());
+    out.push(0);
+}
+
+/// Writes a plain json number and returns its BSON type.
+fn write_number_value(out: &mut Vec<u8>, number: f64) -> u8 {
+    let whole = number as i64;
+    let exact = (-EXACT_INTEGERS..=EXACT_INTEGERS).contains(&number)
+        && whole as f64 == number
+        && number.to_bits() != (-0.0f64).to_bits();
+    if exact && i32::try_from(whole).is_ok() {
+        out.extend_from_slice(&(whole as i32).to_le_bytes());
+        return INT32;
+    }
+    if exact {
+        out.extend_from_slice(&whole.to_le_bytes());
+        return INT64;
+    }
+    out.extend_from_slice(&number.to_le_bytes());
+    return DOUBLE;
+}
+
+/// The BSON type and bytes of an Extended JSON object, `None` for other
+/// objects, or a message when the object has the keys of one but the wrong
+/// values.
+fn extended(object: &Map<String, JsonElement>) -> Result<Option<(u8, Vec<u8>)>, String> {
+    let (key, value) = match object.iter().next() {
+        Some((key, value)) if object.len() == 1 && key.starts_with('$') => (key.as_str(), value),
+        _ => return Ok(None),
+    };
+    let invalid = || format!("Invalid {} value", key);
+    let mut out = Vec::new();
+    let kind = match (key, value) {
+        ("$oid", JsonElement::Str(hex)) => {
+            out = object_id(hex).ok_or_else(invalid)?.to_vec();
+            OBJECT_ID
+        }
+        ("$date", value) => {
+            let millis = match value {
+                JsonElement::Str(text) => parse_date(text),
+                JsonElement::Object(_) => long(value),
+                _ => None,
+            };
+            out.extend_from_slice(&millis.ok_or_else(invalid)?.to_le_bytes());
+            DATE
+        }
+        ("$numberInt", JsonElement::Str(text)) => {
+            out.extend_from_slice(&text.parse::<i32>().map_err(|_| invalid())?.to_le_bytes());
+            INT32
+        }
+        ("$numberLong", JsonElement::Str(text)) => {
+            out.extend_from_slice(&text.parse::<i64>().map_err(|_| invalid())?.to_le_bytes());
+            INT64
+        }
+        ("$numberDouble", JsonElement::Str(text)) => {
+            let number = match text.as_str() {
+                "Infinity" => f64::INFINITY,
+                "-Infinity" => f64::NEG_INFINITY,
+                "NaN" => f64::NAN,
+                text if text.starts_with(|c: char| c == '-' || c.is_ascii_digit()) => {
+                    text.parse::<f64>().map_err(|_| invalid())?
+                }
+                _ => return Err(invalid()),
+            };
+            out.extend_from_slice(&number.to_le_bytes());
+            DOUBLE
+        }
+        ("$numberDecimal", JsonElement::Str(text)) => {
+            out.extend_from_slice(&parse_decimal(text).ok_or_else(invalid)?.to_le_bytes());
+            DECIMAL128
+        }
+        ("$binary", JsonElement::Object(binary)) => {
+            let bytes = match (binary.get("base64"), binary.get("subType")) {
+                (Some(JsonElement::Str(base64)), Some(JsonElement::Str(subtype)))
+                    if binary.len() == 2 && (1..=2).contains(&subtype.len()) =>
+                {
+                    u8::from_str_radix(subtype, 16)
+                        .ok()
+                        .zip(from_base64(base64))
+                }
+                _ => None,
+            };
+            let (subtype, bytes) = bytes.ok_or_else(invalid)?;
+            // The old binary subtype repeats the length inside the value.
+            let inner = if subtype == 2 { 4 } else { 0 };
+            out.extend_from_slice(&((bytes.len() + inner) as i32).to_le_bytes());
+            out.push(subtype);
+            if subtype == 2 {
+                out.extend_from_slice(&(bytes.len() as i32).to_le_bytes());
+            }
+            out.extend_from_slice(&bytes);
+            BINARY
+        }
+        ("$timestamp", JsonElement::Object(timestamp)) => {
+            let part = |name: &str| match timestamp.get(name) {
+                Some(JsonElement::Number(n))
+                    if (0.0..=u32::MAX as f64).contains(n) && *n as u32 as f64 == *n =>
+                {
+                    Some(*n as u64)
+                }
+                _ => None,
+            };
+            match (part("t"), part("i")) {
+                (Some(t), Some(i)) if timestamp.len() == 2 => {
+                    out.extend_from_slice(&(t << 32 | i).to_le_bytes())
+                }
+                _ => return Err(invalid()),
+            }
+            TIMESTAMP
+        }
+        ("$regularExpression", JsonElement::Object(regex)) => {
+            match (regex.get("pattern"), regex.get("options")) {
+                (Some(JsonElement::Str(pattern)), Some(JsonElement::Str(options)))
+                    if regex.len() == 2 =>
+                {
+                    let mut options: Vec<char> = options.chars().collect();
+                    options.sort_unstable();
+                    let options: String = options.into_iter().collect();
+                    write_cstring(&mut out, pattern).map_err(|msg| msg.to_string())?;
+                    write_cstring(&mut out, &options).map_err(|msg| msg.to_string())?;
+                }
+                _ => return Err(invalid()),
+            }
+            REGEX
+        }
+        ("$dbPointer", JsonElement::Object(pointer)) => {
+            let id = match pointer.get("$id") {
+                Some(JsonElement::Object(id)) => match extended(id) {
+                    Ok(Some((OBJECT_ID, bytes))) => Some(bytes),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match (pointer.get("$ref"), id) {
+                (Some(JsonElement::Str(namespace)), Some(id)) if pointer.len() == 2 => {
+                    write_string(&mut out, namespace);
+                    out.extend_from_slice(&id);
+                }
+                _ => return Err(invalid()),
+            }
+            DB_POINTER
+        }
+        ("$code", JsonElement::Str(code)) => {
+            write_string(&mut out, code);
+            CODE
+        }
+        ("$symbol", JsonElement::Str(symbol)) => {
+            write_string(&mut out, symbol);
+            SYMBOL
+        }
+        ("$undefined", JsonElement::Boolean(true)) => UNDEFINED,
+        ("$minKey", JsonElement::Number(n)) if *n == 1.0 => MIN_KEY,
+        ("$maxKey", JsonElement::Number(n)) if *n == 1.0 => MAX_KEY,
+        (
+            "$oid" | "$numberInt" | "$numberLong" | "$numberDouble" | "$numberDecimal" | "$binary"
+            | "$timestamp" | "$regularExpression" | "$dbPointer" | "$code" | "$symbol"
+            | "$undefined" | "$minKey" | "$maxKey",
+            _,
+        ) => return Err(invalid()),
+        _ => return Ok(None),
+    };
+    return Ok(Some((kind, out)));
+}
+
+/// The value of a `{"$numberLong": "..."}` object.
+fn long(element: &JsonElement) -> Option<i64> {
+    return match element {
+        JsonElement::Object(object) if object.len() == 1 => match object.get("$numberLong") {
+            Some(JsonElement::Str(text)) => text.parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    };
+}
+
+fn object_id(hex: &str) -> Option<[u8; 12]> {
+    if hex.len() != 24 || !hex.is_ascii() {
+        return None;
+    }
+    let mut id = [0; 12];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    return Some(id);
+}
+
+/// Decodes a BSON document with the default `DecodeOptions`: relaxed
+/// Extended JSON.
+pub fn decode(bytes: &[u8]) -> Result<JsonElement, BsonError> {
+    return decode_with_options(bytes, &DecodeOptions::default());
+}
+
+pub fn decode_with_options(
+    bytes: &[u8],
+    options: &DecodeOptions,
+) -> Result<JsonElement, BsonError> {
+    let mut input = Input { bytes, offset: 0 };
+    let mut stack = vec![Document::open(&mut input, String::new(), false)?];
+    loop {
+        let start = input.offset;
+        let end = stack.last().unwrap().end;
+        let kind = input.byte()?;
+        if kind == 0 {
+            if input.offset != end {
+                return Err(input.error_at("Document ended before its length", start));
+            }
+            let (key, value) = stack.pop().unwrap().finish();
+            match stack.last_mut() {
+                Some(parent) => parent.insert(key, value),
+                None if input.offset < bytes.len() => {
+                    return Err(input.error("Unexpected bytes after the document"));
+                }
+                None => return Ok(value),
+            }
+            continue;
+        }
+        let key = input.cstring()?;
+        if kind == DOCUMENT || kind == ARRAY {
+            if stack.len() >= options.max_depth {
+                let msg = format!("Nesting is deeper than {} levels", options.max_depth);
+                return Err(input.error_at(&msg, start));
+            }
+            let document = Document::open(&mut input, key, kind == ARRAY)?;
+            if document.end > end {
+                return Err(input.error_at("Element runs past the end of its document", start));
+            }
+            stack.push(document);
+            continue;
+        }
+        let value = read_value(&mut input, kind, options.mode, start)?;
+        if input.offset > end {
+            return Err(input.error_at("Element runs past the end of its document", start));
+        }
+        stack.last_mut().unwrap().insert(key, value);
+    }
+}
+
+/// A document or array being decoded. Array keys are ignored, as the
+/// elements are in order anyway.
+struct Document {
+    key: String,
+    end: usize,
+    value: JsonElement,
+}
+
+impl Document {
+    fn open(input: &mut Input, key: String, array: bool) -> Result<Document, BsonError> {
+        let start = input.offset;
+        let len = input.i32()?;
+        if len < 5 || len as usize - 4 > input.remaining() {
+            return Err(input.error_at("Invalid document length", start));
+        }
+        let value = match array {
+            true => JsonElement::Array(Vec::new()),
+            false => JsonElement::Object(map_with_capacity(0)),
+        };
+        return Ok(Document {
+            key,
+            end: start + len as usize,
+            value,
+        });
+    }
+
+    fn insert(&mut self, key: String, element: JsonElement) {
+        match &mut self.value {
+            JsonElement::Array(elements) => elements.push(element),
+            JsonElement::Object(members) => {
+                members.insert(key, element);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn finish(self) -> (String, JsonElement) {
+        return (self.key, self.value);
+    }
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Input<'a> {
+    fn error(&self, msg: &str) -> BsonError {
+        return self.error_at(msg, self.offset);
+    }
+
+    fn error_at(&self, msg: &str, offset: usize) -> BsonError {
+        return BsonError::new(&format!("{} at byte {}", msg, offset));
+    }
+
+    fn remaining(&self) -> usize {
+        return self.bytes.len() - self.offset;
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BsonError> {
+        if len > self.remaining() {
+            return Err(self.error("Unexpected end of input"));
+        }
+        let taken = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        return Ok(taken);
+    }
+
+    fn byte(&mut self) -> Result<u8, BsonError> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn i32(&mut self) -> Result<i32, BsonError> {
+        return Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()));
+    }
+
+    fn u64(&mut self) -> Result<u64, BsonError> {
+        return Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()));
+    }
+
+    fn utf8(&self, bytes: &[u8], start: usize) -> Result<String, BsonError> {
+        return match core::str::from_utf8(bytes) {
+            Ok(value) => Ok(value.to_string()),
+            Err(_) => Err(self.error_at("Invalid UTF-8 in a string", start)),
+        };
+    }
+
+    fn cstring(&mut self) -> Result<String, BsonError> {
+        let start = self.offset;
+        let len = match self.bytes[start..].iter().position(|byte| *byte == 0) {
+            Some(len) => len,
+            None => return Err(self.error("Unexpected end of input")),
+        };
+        let bytes = self.take(len + 1)?;
+        return self.utf8(&bytes[..len], start);
+    }
+
+    fn string(&mut self) -> Result<String, BsonError> {
+        let start = self.offset;
+        let len = self.i32()?;
+        if len < 1 || len as usize > self.remaining() {
+            return Err(self.error_at("Invalid string length", start));
+        }
+        let bytes = self.take(len as usize)?;
+        if bytes[bytes.len() - 1] != 0 {
+            return Err(self.error_at("String is not NUL terminated", start));
+        }
+        return self.utf8(&bytes[..bytes.len() - 1], start);
+    }
+
+    fn object_id(&mut self) -> Result<JsonElement, BsonError> {
+        let mut hex = String::with_capacity(24);
+        for byte in self.take(12)? {
+            let _ = write!(hex, "{:02x}", byte);
+        }
+        return Ok(wrap("$oid", JsonElement::Str(hex)));
+    }
+}
+
+/// A single member object, the shape of every Extended JSON value.
+fn wrap(key: &str, value: JsonElement) -> JsonElement {
+    return members([(key, value)]);
+}
+
+fn members<const N: usize>(members: [(&str, JsonElement); N]) -> JsonElement {
+    let mut object = map_with_capacity(N);
+    for (key, value) in members {
+        object.insert(key.to_string(), value);
+    }
+    return JsonElement::Object(object);
+}
+
+fn number_long(n: i64) -> JsonElement {
+    return wrap("$numberLong", JsonElement::Str(n.to_string()));
+}
+
+fn read_value(
+    input: &mut Input,
+    kind: u8,
+    mode: Mode,
+    start: usize,
+) -> Result<JsonElement, BsonError> {
+    let canonical = mode == Mode::Canonical;
+    let value = match kind {
+        DOUBLE => {
+            let number = f64::from_bits(input.u64()?);
+            match number.is_finite() && !canonical {
+                true => JsonElement::Number(number),
+                false => wrap("$numberDouble", JsonElement::Str(format_double(number))),
+            }
+        }
+        STRING => JsonElement::Str(input.string()?),
+        BINARY => {
+            let len = input.i32()?;
+            let subtype = input.byte()?;
+            if len < 0 || len as usize > input.remaining() {
+                return Err(input.error_at("Invalid binary length", start));
+            }
+            let mut bytes = input.take(len as usize)?;
+            if subtype == 2 {
+                match bytes.split_first_chunk::<4>() {
+                    Some((inner, rest)) if i32::from_le_bytes(*inner) as usize == rest.len() => {
+                        bytes = rest
+                    }
+                    _ => return Err(input.error_at("Invalid binary length", start)),
+                }
+            }
+            let binary = members([
+                ("base64", JsonElement::Str(base64(bytes))),
+                ("subType", JsonElement::Str(format!("{:02x}", subtype))),
+            ]);
+            wrap("$binary", binary)
+        }
+        UNDEFINED => wrap("$undefined", JsonElement::Boolean(true)),
+        OBJECT_ID => input.object_id()?,
+        BOOLEAN => match input.byte()? {
+            0 => JsonElement::Boolean(false),
+            1 => JsonElement::Boolean(true),
+            _ => return Err(input.error_at("Invalid boolean", start)),
+        },
+        DATE => {
+            let millis = input.u64()? as i64;
+            match (0..=MAX_ISO_DATE).contains(&millis) && !canonical {
+                true => wrap("$date", JsonElement::Str(format_date(millis))),
+                false => wrap("$date", number_long(millis)),
+            }
+        }
+        NULL => JsonElement::Null,
+        REGEX => {
+            let regex = members([
+                ("pattern", JsonElement::Str(input.cstring()?)),
+                ("options", JsonElement::Str(input.cstring()?)),
+            ]);
+            wrap("$regularExpression", regex)
+        }
+        DB_POINTER => {
+            let namespace = JsonElement::Str(input.string()?);
+            let pointer = members([("$ref", namespace), ("$id", input.object_id()?)]);
+            wrap("$dbPointer", pointer)
+        }
+        CODE => wrap("$code", JsonElement::Str(input.string()?)),
+        SYMBOL => wrap("$symbol", JsonElement::Str(input.string()?)),
+        INT32 => {
+            let n = input.i32()?;
+            match canonical {
+                true => wrap("$numberInt", JsonElement::Str(n.to_string())),
+                false => JsonElement::Number(n as f64),
+            }
+        }
+        TIMESTAMP => {
+            let timestamp = input.u64()?;
+            let timestamp = members([
+                ("t", JsonElement::Number((timestamp >> 32) as f64)),
+                ("i", JsonElement::Number((timestamp & 0xffff_ffff) as f64)),
+            ]);
+            wrap("$timestamp", timestamp)
+        }
+        INT64 => {
+            let n = input.u64()? as i64;
+            // Relaxed mode keeps numbers a json parser would round as strings.
+            match !canonical && n.unsigned_abs() <= 1 << 53 {
+                true => JsonElement::Number(n as f64),
+                false => number_long(n),
+            }
+        }
+        DECIMAL128 => {
+            let bits = u128::from_le_bytes(input.take(16)?.try_into().unwrap());
+            wrap("$numberDecimal", JsonElement::Str(format_decimal(bits)))
+        }
+        MIN_KEY => wrap("$minKey", JsonElement::Number(1.0)),
+        MAX_KEY => wrap("$maxKey", JsonElement::Number(1.0)),
+        _ => {
+            let msg = format!("Unsupported element type 0x{:02x}", kind);
+            return Err(input.error_at(&msg, start));
+        }
+    };
+    return Ok(value);
+}
+
+/// Doubles as canonical Extended JSON writes them: `1.0`, `-0.0`, `1.5E+300`.
+fn format_double(number: f64) -> String {
+    if number.is_nan() {
+        return "NaN".to_string();
+    }
+    if number.is_infinite() {
+        return (if number > 0.0 {
+            "Infinity"
+        } else {
+            "-Infinity"
+        })
+        .to_string();
+    }
+    let mut text = String::new();
+    write_number(&mut text, number);
+    let (mantissa, exponent) = match text.split_once('e') {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (text.as_str(), None),
+    };
+    let mut out = mantissa.to_string();
+    if !out.contains('.') {
+        out.push_str(".0");
+    }
+    if let Some(exponent) = exponent {
+        out.push('E');
+        if !exponent.starts_with('-') {
+            out.push('+');
+        }
+        out.push_str(exponent);
+    }
+    return out;
+}
+
+/// 9999-12-31T23:59:59.999Z, the last date relaxed mode writes as a string.
+const MAX_ISO_DATE: i64 = 253402300799999;
+const DAY: i64 = 86_400_000;
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    return (year, month, day);
+}
+
+/// `1970-01-01T00:00:00Z`, with milliseconds only when there are some.
+fn format_date(millis: i64) -> String {
+    let (year, month, day) = civil_from_days(millis.div_euclid(DAY));
+    let time = millis.rem_euclid(DAY);
+    let mut out = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3_600_000,
+        time / 60_000 % 60,
+        time / 1000 % 60
+    );
+    if time % 1000 != 0 {
+        let _ = write!(out, ".{:03}", time % 1000);
+    }
+    out.push('Z');
+    return out;
+}
+
+/// Reads `YYYY-MM-DDTHH:MM:SS[.fraction]` followed by `Z` or an offset such
+/// as `+01:00`, to milliseconds since the epoch. Digits of the fraction past
+/// milliseconds are dropped.
+fn parse_date(text: &str) -> Option<i64> {
+    let bytes = text.as_bytes();
+    let number = |digits: Option<&str>| -> Option<i64> {
+        let digits = digits?;
+        if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        return digits.parse().ok();
+    };
+    let separators = [(4, b'-'), (7, b'-'), (10, b'T'), (13, b':'), (16, b':')];
+    if bytes.len() < 20 || separators.iter().any(|(i, c)| bytes[*i] != *c) {
+        return None;
+    }
+    let year = number(text.get(0..4))?;
+    let (month, day) = (number(text.get(5..7))?, number(text.get(8..10))?);
+    let hour = number(text.get(11..13))?;
+    let (minute, second) = (number(text.get(14..16))?, number(text.get(17..19))?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let mut rest = &text[19..];
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+        let digits = &fraction[..len.min(3)];
+        millis = digits.parse::<i64>().ok()? * 10i64.pow(3 - digits.len() as u32);
+        rest = &fraction[len..];
+    }
+    let offset = match rest.as_bytes().first() {
+        Some(b'Z') if rest.len() == 1 => 0,
+        Some(sign @ (b'+' | b'-')) => {
+            let minutes = match rest.len() {
+                6 if rest.as_bytes()[3] == b':' => rest.get(4..6),
+                5 => rest.get(3..5),
+                _ => None,
+            };
+            let (hours, minutes) = (number(rest.get(1..3))?, number(minutes)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = (hours * 60 + minutes) * 60_000;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return None,
+    };
+    let time = ((hour * 60 + minute) * 60 + second) * 1000 + millis;
+    return Some(days_from_civil(year, month, day) * DAY + time - offset);
+}
+
+/// 10^34 - 1, the largest coefficient of a decimal128.
+const MAX_COEFFICIENT: u128 = 10u128.pow(34) - 1;
+const EXPONENT_BIAS: i32 = 6176;
+const MAX_EXPONENT: i32 = 6111;
+const MIN_EXPONENT: i32 = -6176;
+const INFINITY_BITS: u128 = 0x1e << 122;
+const NAN_BITS: u128 = 0x1f << 122;
+
+/// Writes a decimal128 as the IEEE 754 to-scientific-string operation does,
+/// which is how Extended JSON writes them: `1.5`, `1E+3`, `-0.00001`.
+fn format_decimal(bits: u128) -> String {
+    let sign = if bits >> 127 == 1 { "-" } else { "" };
+    let (exponent, coefficient) = if bits >> 125 & 3 == 3 {
+        match bits >> 122 & 0x1f {
+            0x1e => return format!("{}Infinity", sign),
+            0x1f => return "NaN".to_string(),
+            // The coefficient is always larger than MAX_COEFFICIENT.
+            _ => ((bits >> 111 & 0x3fff) as i32, 0),
+        }
+    } else {
+        ((bits >> 113 & 0x3fff) as i32, bits & ((1 << 113) - 1))
+    };
+    let coefficient = if coefficient > MAX_COEFFICIENT {
+        0
+    } else {
+        coefficient
+    };
+    let exponent = exponent - EXPONENT_BIAS;
+    let digits = coefficient.to_string();
+    let adjusted = exponent + digits.len() as i32 - 1;
+    let mut out = String::from(sign);
+    if exponent <= 0 && adjusted >= -6 {
+        let point = digits.len() as i32 + exponent;
+        if exponent == 0 {
+            out.push_str(&digits);
+        } else if point > 0 {
+            out.push_str(&digits[..point as usize]);
+            out.push('.');
+            out.push_str(&digits[point as usize..]);
+        } else {
+            out.push_str("0.");
+            for _ in 0..-point {
+                out.push('0');
+            }
+            out.push_str(&digits);
+        }
+    } else {
+        out.push_str(&digits[..1]);
+        if digits.len() > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        let _ = write!(out, "E{:+}", adjusted);
+    }
+    return out;
+}
+
+/// Reads a decimal128 from text such as `-1.5E+3`, `Infinity` or `NaN`.
+/// Values that would need rounding are `None`.
+fn parse_decimal(text: &str) -> Option<u128> {
+    let (sign, rest) = match text.as_bytes().first() {
+        Some(b'-') => (1u128 << 127, &text[1..]),
+        Some(b'+') => (0, &text[1..]),
+        _ => (0, text),
+    };
+    if rest.eq_ignore_ascii_case("infinity") || rest.eq_ignore_ascii_case("inf") {
+        return Some(sign | INFINITY_BITS);
+    }
+    if rest.eq_ignore_ascii_case("nan") {
+        return Some(NAN_BITS);
+    }
+    let (number, exponent) = match rest.find(['e', 'E']) {
+        Some(at) => (&rest[..at], rest[at + 1..].parse::<i32>().ok()?),
+        None => (rest, 0),
+    };
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if whole.len() + fraction.len() == 0 || !all_digits(whole) || !all_digits(fraction) {
+        return None;
+    }
+    let mut digits: Vec<u8> = whole
+        .bytes()
+        .chain(fraction.bytes())
+        .skip_while(|d| *d == b'0')
+        .collect();
+    let mut exponent = exponent.checked_sub(fraction.len() as i32)?;
+    while digits.len() > 34 && digits.last() == Some(&b'0') {
+        digits.pop();
+        exponent = exponent.checked_add(1)?;
+    }
+    if digits.is_empty() {
+        exponent = exponent.clamp(MIN_EXPONENT, MAX_EXPONENT);
+    }
+    while exponent > MAX_EXPONENT && digits.len() < 34 {
+        digits.push(b'0');
+        exponent -= 1;
+    }
+    while exponent < MIN_EXPONENT && digits.last() == Some(&b'0') {
+        digits.pop();
+        exponent += 1;
+    }
+    if digits.len() > 34 || !(MIN_EXPONENT..=MAX_EXPONENT).contains(&exponent) {
+        return None;
+    }
+    let coefficient = digits
+        .iter()
+        .fold(0u128, |n, d| n * 10 + (d - b'0') as u128);
+    return Some(sign | ((exponent + EXPONENT_BIAS) as u128) << 113 | coefficient);
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    return out;
+}
+
+/// Reads padded base64. Unpadded input is accepted too.
+fn from_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        n = n << 6 | BASE64.iter().position(|b| *b == c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    // Leftover bits must be padding zeros, and a single character is not a byte.
+    if bits >= 6 || n & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    return Some(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::serializer::Serializer;
+    use crate::test_support::{arb_json_element, hex, Numbers};
+    use proptest::prelude::*;
+
+    const CANONICAL: DecodeOptions = DecodeOptions {
+        mode: Mode::Canonical,
+        max_depth: 128,
+    };
+
+    #[test]
+    fn should_match_known_documents() {
+        let documents = [
+            ("0c0000001061000100000000", r#"{"a":{"$numberInt":"1"}}"#),
+            ("10000000126100ffffffffffffff7f00", r#"{"a":{"$numberLong":"9223372036854775807"}}"#),
+            ("10000000016400000000000000f03f00", r#"{"d":{"$numberDouble":"1.0"}}"#),
+            ("10000000016400000000000000008000", r#"{"d":{"$numberDouble":"-0.0"}}"#),
+            ("10000000016400355800662deb417e00", r#"{"d":{"$numberDouble":"1.5E+300"}}"#),
+            (
+                "180000001364000100000000000000000000000000403000",
+                r#"{"d":{"$numberDecimal":"1"}}"#,
+            ),
+            (
+                "180000001364000000000000000000000000000000007c00",
+                r#"{"d":{"$numberDecimal":"NaN"}}"#,
+            ),
+            (
+                "18000000136400000000000000000000000000000000f800",
+                r#"{"d":{"$numberDecimal":"-Infinity"}}"#,
+            ),
+            (
+                "1400000007610056e1fc72e0c917e9c471416100",
+                r#"{"a":{"$oid":"56e1fc72e0c917e9c4714161"}}"#,
+            ),
+            (
+                "10000000096100c5d8d6cc3b01000000",
+                r#"{"a":{"$date":{"$numberLong":"1356351330501"}}}"#,
+            ),
+            (
+                "1c000000027800100000007465737420c3a9c3a9c3a9c3a9c3a90000",
+                "{\"x\":\"test \u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\"}",
+            ),
+            (
+                "1d000000057800100000000073ffd26444b34c6990e8e7d1dfc035d400",
+                r#"{"x":{"$binary":{"base64":"c//SZESzTGmQ6OfR38A11A==","subType":"00"}}}"#,
+            ),
+            (
+                "13000000057800060000000202000000ffff00",
+                r#"{"x":{"$binary":{"base64":"//8=","subType":"02"}}}"#,
+            ),
+            (
+                "0f0000000b610061626300696d0000",
+                r#"{"a":{"$regularExpression":{"pattern":"abc","options":"im"}}}"#,
+            ),
+            (
+                "100000001161002a00000015cd5b0700",
+                r#"{"a":{"$timestamp":{"t":123456789,"i":42}}}"#,
+            ),
+            (
+                "1a0000000c610002000000620056e1fc72e0c917e9c471416100",
+                r#"{"a":{"$dbPointer":{"$ref":"b","$id":{"$oid":"56e1fc72e0c917e9c4714161"}}}}"#,
+            ),
+            ("0800000006610000", r#"{"a":{"$undefined":true}}"#),
+            ("08000000ff610000", r#"{"a":{"$minKey":1}}"#),
+            ("080000007f610000", r#"{"a":{"$maxKey":1}}"#),
+            ("0c0000000a61000862000100", r#"{"a":null,"b":true}"#),
+            ("0d000000046100050000000000", r#"{"a":[]}"#),
+            (
+                "29000000036100210000000862000104630015000000103000070000000e3100020000007300000000",
+                r#"{"a":{"b":true,"c":[{"$numberInt":"7"},{"$symbol":"s"}]}}"#,
+            ),
+            (
+                "150000000d61000900000066756e6374696f6e0000",
+                r#"{"a":{"$code":"function"}}"#,
+            ),
+        ];
+        for (bytes, json) in documents {
+            let bytes = hex(bytes);
+            let element = Parser::parse(json).unwrap();
+            assert_eq!(
+                element,
+                decode_with_options(&bytes, &CANONICAL).unwrap(),
+                "{json}"
+            );
+            assert_eq!(bytes, encode(&element).unwrap(), "{json}");
+        }
+    }
+
+    #[test]
+    fn should_write_relaxed_values() {
+        let canonical = r#"{
+            "int": {"$numberInt": "-5"},
+            "long": {"$numberLong": "42"},
+            "huge": {"$numberLong": "9007199254740993"},
+            "double": {"$numberDouble": "0.5"},
+            "infinity": {"$numberDouble": "Infinity"},
+            "date": {"$date": {"$numberLong": "1356351330501"}},
+            "epoch": {"$date": "1970-01-01T00:00:00Z"},
+            "early": {"$date": {"$numberLong": "-1"}},
+            "offset": {"$date": "2012-12-24T13:15:30.501999+01:00"}
+        }"#;
+        let bytes = encode(&Parser::parse(canonical).unwrap()).unwrap();
+        let relaxed = r#"{
+            "int": -5,
+            "long": 42,
+            "huge": {"$numberLong": "9007199254740993"},
+            "double": 0.5,
+            "infinity": {"$numberDouble": "Infinity"},
+            "date": {"$date": "2012-12-24T12:15:30.501Z"},
+            "epoch": {"$date": "1970-01-01T00:00:00Z"},
+            "early": {"$date": {"$numberLong": "-1"}},
+            "offset": {"$date": "2012-12-24T12:15:30.501Z"}
+        }"#;
+        assert_eq!(Parser::parse(relaxed).unwrap(), decode(&bytes).unwrap());
+    }
+
+    #[test]
+    fn should_convert_decimals() {
+        let cases = [
+            ("0", "0"),
+            ("-0", "-0"),
+            ("1.5", "1.5"),
+            ("0.001234", "0.001234"),
+            ("0.0000001234", "1.234E-7"),
+            ("1000", "1000"),
+            ("1E3", "1E+3"),
+            ("1.23E+3", "1.23E+3"),
+            ("1230E-1", "123.0"),
+            ("-1.00E-8", "-1.00E-8"),
+            (
+                "12345678901234567890123456789012340",
+                "1.234567890123456789012345678901234E+34",
+            ),
+            ("1E+6144", "1.000000000000000000000000000000000E+6144"),
+            ("0E+9999", "0E+6111"),
+            ("1E-6176", "1E-6176"),
+            ("-inf", "-Infinity"),
+            ("-NaN", "NaN"),
+        ];
+        for (text, formatted) in cases {
+            assert_eq!(
+                formatted,
+                format_decimal(parse_decimal(text).unwrap()),
+                "{text}"
+            );
+        }
+        for text in [
+            "",
+            ".",
+            "1.2.3",
+            "1E",
+            "12345678901234567890123456789012345",
+            "1E+6145",
+            "1E-6177",
+            "x",
+        ] {
+            assert_eq!(None, parse_decimal(text), "{text}");
+        }
+    }
+
+    #[test]
+    fn should_report_invalid_documents() {
+        let cases = [
+            ("", "Unexpected end of input at byte 0"),
+            ("04000000", "Invalid document length at byte 0"),
+            ("0600000000", "Invalid document length at byte 0"),
+            (
+                "050000000000",
+                "Unexpected bytes after the document at byte 5",
+            ),
+            ("0600000000ff", "Document ended before its length at byte 4"),
+            (
+                "0c0000001061000100000001",
+                "Unexpected end of input at byte 12",
+            ),
+            ("090000000861000200", "Invalid boolean at byte 4"),
+            (
+                "0c000000026100ff00000000",
+                "Invalid string length at byte 7",
+            ),
+            (
+                "0f00000002610003000000c3280000",
+                "Invalid UTF-8 in a string at byte 7",
+            ),
+            (
+                "0e00000002610002000000c32800",
+                "String is not NUL terminated at byte 7",
+            ),
+            (
+                "080000001461000000",
+                "Unsupported element type 0x14 at byte 4",
+            ),
+            (
+                "0c000000036100060000000000",
+                "Element runs past the end of its document at byte 4",
+            ),
+        ];
+        for (bytes, details) in cases {
+            assert_eq!(
+                BsonError::new(details),
+                decode(&hex(bytes)).unwrap_err(),
+                "{bytes}"
+            );
+        }
+
+        let nested = |levels| (0..levels).fold(JsonElement::Null, |value, _| wrap("a", value));
+        assert!(decode(&encode(&nested(128)).unwrap()).is_ok());
+        assert_eq!(
+            BsonError::new("Nesting is deeper than 128 levels at byte 893"),
+            decode(&encode(&nested(129)).unwrap()).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_report_values_that_cannot_be_encoded() {
+        let cases = [
+            ("[]", "Only objects can be encoded as BSON documents"),
+            (
+                r#"{"$oid":"56e1fc72e0c917e9c4714161"}"#,
+                "Only objects can be encoded as BSON documents",
+            ),
+            (
+                r#"{"a":{"b":[{"$oid":"xyz"}]}}"#,
+                "Invalid $oid value at /a/b/0",
+            ),
+            (
+                r#"{"a":{"$numberInt":"2147483648"}}"#,
+                "Invalid $numberInt value at /a",
+            ),
+            (
+                r#"{"a":{"$date":"2021-02-29T00:00:00Z"}}"#,
+                "Invalid $date value at /a",
+            ),
+            (
+                r#"{"a":{"$binary":{"base64":"A","subType":"00"}}}"#,
+                "Invalid $binary value at /a",
+            ),
+            (
+                r#"{"a\u0000":1}"#,
+                "Keys and regular expressions cannot contain NUL at /a\u{0}",
+            ),
+        ];
+        for (json, details) in cases {
+            let element = Parser::parse(json).unwrap();
+            assert_eq!(
+                BsonError::new(details),
+                encode(&element).unwrap_err(),
+                "{json}"
+            );
+        }
+        // Objects that only look like Extended JSON stay documents.
+        let element =
+            Parser::parse(r#"{"a":{"$oid":"56e1fc72e0c917e9c4714161","b":1},"c":{"$other":1}}"#)
+                .unwrap();
+        assert_eq!(element, decode(&encode(&element).unwrap()).unwrap());
+    }
+
+    const KEYS: &str = "[a-z_]{0,4}";
+
+    /// BSON documents are objects, and keys starting with `$` would be read
+    /// as Extended JSON.
+    fn arb_document() -> impl Strategy<Value = JsonElement> {
+        let element = arb_json_element(Numbers::Any, KEYS);
+        return prop::collection::vec((KEYS, element), 0..6)
+            .prop_map(|members| JsonElement::Object(members.into_iter().collect()));
+    }
+
+    proptest! {
+        #[test]
+        fn should_round_trip_parsed_documents(element in arb_document()) {
+            let parsed = Parser::parse(&Serializer::serialize(&element)).unwrap();
+            let bytes = encode(&parsed).unwrap();
+            prop_assert_eq!(&parsed, &decode(&bytes).unwrap());
+
+            // Canonical Extended JSON survives the text parser and serializer.
+            let canonical = decode_with_options(&bytes, &CANONICAL).unwrap();
+            let text = Serializer::serialize(&canonical);
+            prop_assert_eq!(bytes, encode(&Parser::parse(&text).unwrap()).unwrap());
+        }
+    }
+
+    #[test]
+    fn should_convert_dates() {
+        for millis in [
+            0,
+            1,
+            999,
+            DAY - 1,
+            951782400000,
+            1356351330501,
+            MAX_ISO_DATE,
+        ] {
+            let text = format_date(millis);
+            assert_eq!(Some(millis), parse_date(&text), "{text}");
+        }
+        assert_eq!("2000-02-29T00:00:00Z", format_date(951782400000));
+        assert_eq!(Some(-3_600_000), parse_date("1970-01-01T00:00:00+0100"));
+        let invalid = [
+            "1970-01-01",
+            "1970-13-01T00:00:00Z",
+            "1970-01-01T24:00:00Z",
+            "1970-01-01T00:00:00.Z",
+            "1970-01-01T00:00:00+1",
+            "1970-01-01T00:00:00+01:\u{e9}",
+        ];
+        for text in invalid {
+            assert_eq!(None, parse_date(text), "{text}");
+        }
+        assert_eq!(vec![0u8, 1, 2], from_base64(&base64(&[0, 1, 2])).unwrap());
+    }
+}
@@ -0,0 +1,321 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! The JSON Canonicalization Scheme (RFC 8785), which gives every value exactly
+//! one serialization so it can be hashed or signed.
+//!
+//! Object members are sorted by the UTF-16 code units of their keys, numbers
+//! are written the way ECMAScript's `Number.prototype.toString` writes them,
+//! strings only escape what json requires and there is no whitespace.
+
+use crate::json_element::JsonElement;
+use crate::serializer::write_string;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::slice;
+
+#[derive(Debug, PartialEq)]
+pub struct CanonicalError {
+    pub details: String,
+}
+
+impl CanonicalError {
+    pub fn new(msg: &str) -> CanonicalError {
+        return CanonicalError {
+            details: msg.to_string(),
+        };
+    }
+}
+
+impl fmt::Display for CanonicalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl core::error::Error for CanonicalError {}
+
+/// Splits `{:e}` output, `d.ddde±x`, into its digits and exponent.
+fn split_scientific(scientific: &str) -> (String, i32) {
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    return (digits, exponent.parse().unwrap());
+}
+
+/// The shortest digits that read back as `number`, which must be positive and
+/// finite, with the decimal exponent of the first one.
+///
+/// Rust and ECMAScript agree on the digits except when `number` lies exactly
+/// halfway between two shortest candidates, where ECMAScript picks the one
+/// with an even last digit.
+fn shortest_digits(number: f64) -> (String, i32) {
+    let (digits, exponent) = split_scientific(&format!("{number:e}"));
+    if digits.as_bytes()[digits.len() - 1] % 2 == 0 {
+        return (digits, exponent);
+    }
+    // 767 significant digits are enough to write any f64 exactly.
+    let (exact, exact_exponent) = split_scientific(&format!("{number:.767e}"));
+    let exact = exact.trim_end_matches('0');
+    if exact_exponent != exponent || exact.len() != digits.len() + 1 || !exact.ends_with('5') {
+        return (digits, exponent);
+    }
+    // The candidates are the exact digits truncated and that plus one in the
+    // last place. `digits` is the odd one; the other is taken when it also
+    // reads back as `number`.
+    let below = &exact[..digits.len()];
+    let last = digits.as_bytes()[digits.len() - 1];
+    let even = if below != digits {
+        below.to_string()
+    } else if last != b'9' {
+        format!("{}{}", &digits[..digits.len() - 1], (last + 1) as char)
+    } else {
+        return (digits, exponent);
+    };
+    if format!("0.{even}e{}", exponent + 1).parse::<f64>() == Ok(number) {
+        return (even, exponent);
+    }
+    return (digits, exponent);
+}
+
+/// Writes `number` like ECMAScript does: the shortest digits that read back
+/// as the same `f64`, in plain notation from 1e-7 up to 1e21 and in exponent
+/// notation with an explicit sign outside that range.
+pub(crate) fn write_es_number(out: &mut String, number: f64) -> Result<(), CanonicalError> {
+    if !number.is_finite() {
+        return Err(CanonicalError::new(&format!(
+            "{number} cannot be represented in canonical json"
+        )));
+    }
+    if number == 0.0 {
+        out.push('0');
+        return Ok(());
+    }
+    if number < 0.0 {
+        out.push('-');
+    }
+    let (digits, exponent) = shortest_digits(number.abs());
+    // `point` is where the decimal point goes relative to the first digit.
+    let point = exponent + 1;
+    let k = digits.len() as i32;
+    if k <= point && point <= 21 {
+        out.push_str(&digits);
+        out.extend(core::iter::repeat_n('0', (point - k) as usize));
+    } else if 0 < point && point <= 21 {
+        out.push_str(&digits[..point as usize]);
+        out.push('.');
+        out.push_str(&digits[point as usize..]);
+    } else if -6 < point && point <= 0 {
+        out.push_str("0.");
+        out.extend(core::iter::repeat_n('0', -point as usize));
+        out.push_str(&digits);
+    } else {
+        let exponent = point - 1;
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push('e');
+        out.push(if exponent > 0 { '+' } else { '-' });
+        out.push_str(&exponent.abs().to_string());
+    }
+    return Ok(());
+}
+
+enum Frame<'a> {
+    Array(slice::Iter<'a, JsonElement>, bool),
+    Object(vec::IntoIter<(&'a String, &'a JsonElement)>, bool),
+}
+
+/// Serializes `element` canonically. Fails when it holds NaN or an infinity,
+/// which have no json representation.
+pub fn to_canonical_string(element: &JsonElement) -> Result<String, CanonicalError> {
+    let mut out = String::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut next = Some(element);
+    loop {
+        match next.take() {
+            Some(JsonElement::Array(array)) => {
+                out.push('[');
+                stack.push(Frame::Array(array.iter(), true));
+            }
+            Some(JsonElement::Object(object)) => {
+                out.push('{');
+                let mut members: Vec<(&String, &JsonElement)> = object.iter().collect();
+                members.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+                stack.push(Frame::Object(members.into_iter(), true));
+            }
+            Some(JsonElement::Str(s)) => write_string(&mut out, s),
+            Some(JsonElement::Number(n)) => write_es_number(&mut out, *n)?,
+            Some(JsonElement::Boolean(b)) => out.push_str(if *b { "true" } else { "false" }),
+            Some(JsonElement::Null) => out.push_str("null"),
+            None => {}
+        }
+        let frame = match stack.last_mut() {
+            Some(frame) => frame,
+            None => return Ok(out),
+        };
+        match frame {
+            Frame::Array(members, first) => match members.next() {
+                Some(value) => {
+                    if !core::mem::take(first) {
+                        out.push(',');
+                    }
+                    next = Some(value);
+                }
+                None => {
+                    out.push(']');
+                    stack.pop();
+                }
+            },
+            Frame::Object(members, first) => match members.next() {
+                Some((key, value)) => {
+                    if !core::mem::take(first) {
+                        out.push(',');
+                    }
+                    write_string(&mut out, key);
+                    out.push(':');
+                    next = Some(value);
+                }
+                None => {
+                    out.push('}');
+                    stack.pop();
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_canonical_string, write_es_number};
+    use crate::json_element::JsonElement;
+    use crate::parser::Parser;
+    use proptest::prelude::*;
+
+    fn canonical(json: &str) -> String {
+        let element = Parser::parse(json).ok().unwrap();
+        return to_canonical_string(&element).ok().unwrap();
+    }
+
+    /// RFC 8785, appendix B.
+    #[test]
+    fn should_format_rfc_number_vectors() {
+        let vectors: &[(u64, &str)] = &[
+            (0x0000000000000000, "0"),
+            (0x8000000000000000, "0"),
+            (0x0000000000000001, "5e-324"),
+            (0x8000000000000001, "-5e-324"),
+            (0x7fefffffffffffff, "1.7976931348623157e+308"),
+            (0xffefffffffffffff, "-1.7976931348623157e+308"),
+            (0x4340000000000000, "9007199254740992"),
+            (0xc340000000000000, "-9007199254740992"),
+            (0x4430000000000000, "295147905179352830000"),
+            (0x44b52d02c7e14af5, "9.999999999999997e+22"),
+            (0x44b52d02c7e14af6, "1e+23"),
+            (0x44b52d02c7e14af7, "1.0000000000000001e+23"),
+            (0x444b1ae4d6e2ef4e, "999999999999999700000"),
+            (0x444b1ae4d6e2ef4f, "999999999999999900000"),
+            (0x444b1ae4d6e2ef50, "1e+21"),
+            (0x3eb0c6f7a0b5ed8c, "9.999999999999997e-7"),
+            (0x3eb0c6f7a0b5ed8d, "0.000001"),
+            (0x41b3de4355555553, "333333333.3333332"),
+            (0x41b3de4355555554, "333333333.33333325"),
+            (0x41b3de4355555555, "333333333.3333333"),
+            (0x41b3de4355555556, "333333333.3333334"),
+            (0x41b3de4355555557, "333333333.33333343"),
+            (0xbecbf647612f3696, "-0.0000033333333333333333"),
+            (0x43143ff3c1cb0959, "1424953923781206.2"),
+        ];
+        for (bits, expected) in vectors {
+            let number = JsonElement::Number(f64::from_bits(*bits));
+            assert_eq!(
+                Some(expected.to_string()),
+                to_canonical_string(&number).ok(),
+                "{bits:#018x}"
+            );
+        }
+        for bits in [
+            0x7fffffffffffffff_u64,
+            0x7ff0000000000000,
+            0xfff0000000000000,
+        ] {
+            let number = JsonElement::Number(f64::from_bits(bits));
+            assert!(to_canonical_string(&number).is_err());
+        }
+    }
+
+    /// RFC 8785, section 3.2.2.
+    #[test]
+    fn should_canonicalize_rfc_example() {
+        let json = r#"{
+            "numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+            "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+            "literals": [null, true, false]
+        }"#;
+        assert_eq!(
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#,
+            canonical(json)
+        );
+    }
+
+    /// RFC 8785, section 3.2.3: keys sort by UTF-16 code units, so the emoji's
+    /// surrogate pair comes before U+FB33.
+    #[test]
+    fn should_sort_keys_by_utf16_code_units() {
+        let json = r#"{
+            "\u20ac": "Euro Sign",
+            "\r": "Carriage Return",
+            "\ufb33": "Hebrew Letter Dalet With Dagesh",
+            "1": "One",
+            "\ud83d\ude00": "Emoji: Grinning Face",
+            "\u0080": "Control",
+            "\u00f6": "Latin Small Letter O With Diaeresis"
+        }"#;
+        assert_eq!(
+            "{\"\\r\":\"Carriage Return\",\"1\":\"One\",\"\u{80}\":\"Control\",\
+             \"\u{f6}\":\"Latin Small Letter O With Diaeresis\",\"\u{20ac}\":\"Euro Sign\",\
+             \"\u{1f600}\":\"Emoji: Grinning Face\",\"\u{fb33}\":\"Hebrew Letter Dalet With Dagesh\"}",
+            canonical(json)
+        );
+    }
+
+    #[test]
+    fn should_write_nested_values_without_whitespace() {
+        assert_eq!(
+            "[{},[],{\"a\":[1,\"x\",{\"b\":null}]}]",
+            canonical("[ {} , [ ] , { \"a\" : [ 1.0 , \"x\" , { \"b\" : null } ] } ]")
+        );
+        assert_eq!("-0.5", canonical("-5e-1"));
+        assert_eq!("100", canonical("1e2"));
+        assert_eq!("1.5e-7", canonical("0.00000015"));
+    }
+
+    proptest! {
+        #[test]
+        fn should_write_numbers_that_read_back(bits in any::<u64>()) {
+            let number = f64::from_bits(bits);
+            prop_assume!(number.is_finite());
+            let mut out = String::new();
+            write_es_number(&mut out, number).ok().unwrap();
+            prop_assert_eq!(Some(JsonElement::Number(number)), Parser::parse(&out).ok());
+            prop_assert!(!out.contains("e+0") && !out.contains("e-0") && !out.ends_with('.'));
+        }
+    }
+}
@@ -0,0 +1,341 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Converts values to and from CBOR (RFC 8949).
+//!
+//! Encoding uses definite lengths and the shortest heads. Decoding accepts
+//! indefinite lengths, half and single precision floats, and reads tagged
+//! values as the value inside the tag; `undefined` becomes null and other
+//! simple values are errors. See `jsonp::binary` for how numbers, byte strings
+//! and map keys map to json.
+
+use crate::binary::{self, BinaryError, BinaryResult, DecodeOptions, Encoder, Input, Item};
+use crate::json_element::JsonElement;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Encodes `element` as CBOR.
+pub fn encode(element: &JsonElement) -> Vec<u8> {
+    let mut encoder = CborEncoder { out: Vec::new() };
+    binary::encode(element, &mut encoder);
+    return encoder.out;
+}
+
+/// Decodes a single CBOR value with the default `DecodeOptions`.
+pub fn decode(bytes: &[u8]) -> Result<JsonElement, BinaryError> {
+    return decode_with_options(bytes, &DecodeOptions::default());
+}
+
+pub fn decode_with_options(
+    bytes: &[u8],
+    options: &DecodeOptions,
+) -> Result<JsonElement, BinaryError> {
+    return binary::decode(&mut Input::new(bytes), options, read_item);
+}
+
+struct CborEncoder {
+    out: Vec<u8>,
+}
+
+impl CborEncoder {
+    fn head(&mut self, major: u8, n: u64) {
+        let major = major << 5;
+        if n < 24 {
+            self.out.push(major | n as u8);
+        } else if n <= u8::MAX as u64 {
+            self.out.extend_from_slice(&[major | 24, n as u8]);
+        } else if n <= u16::MAX as u64 {
+            self.out.push(major | 25);
+            self.out.extend_from_slice(&(n as u16).to_be_bytes());
+        } else if n <= u32::MAX as u64 {
+            self.out.push(major | 26);
+            self.out.extend_from_slice(&(n as u32).to_be_bytes());
+        } else {
+            self.out.push(major | 27);
+            self.out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+impl Encoder for CborEncoder {
+    fn null(&mut self) {
+        self.out.push(0xf6);
+    }
+
+    fn boolean(&mut self, value: bool) {
+        self.out.push(if value { 0xf5 } else { 0xf4 });
+    }
+
+    fn number(&mut self, number: f64) {
+        match binary::as_integer(number) {
+            Some(n) if n >= 0 => self.head(0, n as u64),
+            Some(n) => self.head(1, (-1 - n) as u64),
+            None => {
+                self.out.push(0xfb);
+                self.out.extend_from_slice(&number.to_be_bytes());
+            }
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.head(3, value.len() as u64);
+        self.out.extend_from_slice(value.as_bytes());
+    }
+
+    fn array(&mut self, len: usize) {
+        self.head(4, len as u64);
+    }
+
+    fn map(&mut self, len: usize) {
+        self.head(5, len as u64);
+    }
+}
+
+/// Reads the argument of a head with additional information `info`, or
+/// `None` for an indefinite length.
+fn argument(input: &mut Input, info: u8) -> BinaryResult<Option<u64>> {
+    return match info {
+        0..=23 => Ok(Some(info as u64)),
+        24..=27 => Ok(Some(input.uint(1 << (info - 24))?)),
+        31 => Ok(None),
+        _ => Err(input.error("Reserved additional information")),
+    };
+}
+
+/// Reads the chunks of a byte or text string with an indefinite length and
+/// joins them.
+fn chunks(input: &mut Input, major: u8) -> BinaryResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    loop {
+        let start = input.offset;
+        let head = input.byte()?;
+        if head == 0xff {
+            return Ok(bytes);
+        }
+        match argument(input, head & 31)? {
+            Some(len) if head >> 5 == major => {
+                let len = input.length(len)?;
+                let chunk = input.take(len)?;
+                if major == 3 && core::str::from_utf8(chunk).is_err() {
+                    return Err(BinaryError::new("Invalid UTF-8 in a string", start));
+                }
+                bytes.extend_from_slice(chunk);
+            }
+            _ => {
+                return Err(BinaryError::new(
+                    "Invalid chunk in an indefinite length string",
+                    start,
+                ))
+            }
+        }
+    }
+}
+
+fn read_item(input: &mut Input) -> BinaryResult<Item> {
+    loop {
+        let head = input.byte()?;
+        let (major, info) = (head >> 5, head & 31);
+        if major == 7 {
+            return match info {
+                20 => Ok(Item::Boolean(false)),
+                21 => Ok(Item::Boolean(true)),
+                22 | 23 => Ok(Item::Null),
+                25 => input.float(2),
+                26 => input.float(4),
+                27 => input.float(8),
+                31 => Ok(Item::Break),
+                _ => Err(input.error("Unsupported simple value")),
+            };
+        }
+        let argument = argument(input, info)?;
+        return match (major, argument) {
+            (0, Some(n)) => Ok(Item::Number(n as f64)),
+            (1, Some(n)) => Ok(Item::Number(-1.0 - n as f64)),
+            (2, None) => Ok(Item::Bytes(chunks(input, 2)?)),
+            (2, Some(len)) => {
+                let len = input.length(len)?;
+                Ok(Item::Bytes(input.take(len)?.to_vec()))
+            }
+            // The chunks were checked to be UTF-8 one by one.
+            (3, None) => Ok(Item::Str(String::from_utf8(chunks(input, 3)?).unwrap())),
+            (3, Some(len)) => {
+                let len = input.length(len)?;
+                Ok(Item::Str(input.string(len)?))
+            }
+            (4, None) => Ok(Item::Array(None)),
+            (4, Some(len)) => Ok(Item::Array(Some(input.length(len)?))),
+            (5, None) => Ok(Item::Map(None)),
+            (5, Some(len)) => Ok(Item::Map(Some(input.length(len.saturating_mul(2))? / 2))),
+            (6, Some(_)) => continue,
+            _ => Err(input.error("Indefinite length is not allowed here")),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{Bytes, Keys};
+    use crate::parser::Parser;
+    use crate::serializer::Serializer;
+    use crate::test_support::{arb_json_element, hex, Numbers};
+    use alloc::vec;
+    use proptest::prelude::*;
+
+    #[test]
+    fn should_match_the_rfc_examples() {
+        let examples = [
+            ("00", "0"),
+            ("17", "23"),
+            ("1818", "24"),
+            ("1903e8", "1000"),
+            ("1a000f4240", "1000000"),
+            ("1b000000e8d4a51000", "1000000000000"),
+            ("20", "-1"),
+            ("3903e7", "-1000"),
+            ("fb3ff199999999999a", "1.1"),
+            ("fb7e37e43c8800759c", "1e+300"),
+            ("fbc010666666666666", "-4.1"),
+            ("f4", "false"),
+            ("f5", "true"),
+            ("f6", "null"),
+            ("60", "\"\""),
+            ("6449455446", "\"IETF\""),
+            ("62225c", "\"\\\"\\\\\""),
+            ("62c3bc", "\"\u{fc}\""),
+            ("80", "[]"),
+            ("83010203", "[1,2,3]"),
+            ("8301820203820405", "[1,[2,3],[4,5]]"),
+            ("a0", "{}"),
+            ("a26161016162820203", "{\"a\":1,\"b\":[2,3]}"),
+        ];
+        for (bytes, json) in examples {
+            let element = Parser::parse(json).unwrap();
+            assert_eq!(element, decode(&hex(bytes)).unwrap(), "{bytes}");
+            assert_eq!(hex(bytes), encode(&element), "{json}");
+        }
+        // Forms only accepted when decoding.
+        let decoded = [
+            ("f90000", "0"),
+            ("f93c00", "1"),
+            ("f97bff", "65504"),
+            ("f90001", "5.960464477539063e-8"),
+            ("fa47c35000", "100000"),
+            ("1bffffffffffffffff", "18446744073709551615"),
+            ("3bffffffffffffffff", "-18446744073709551616"),
+            (
+                "c074323031332d30332d32315432303a30343a30305a",
+                "\"2013-03-21T20:04:00Z\"",
+            ),
+            ("f7", "null"),
+            ("7f657374726561646d696e67ff", "\"streaming\""),
+            ("9f018202039f0405ffff", "[1,[2,3],[4,5]]"),
+            ("bf61610161629f0203ffff", "{\"a\":1,\"b\":[2,3]}"),
+        ];
+        for (bytes, json) in decoded {
+            assert_eq!(
+                Parser::parse(json).unwrap(),
+                decode(&hex(bytes)).unwrap(),
+                "{bytes}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_map_bytes_and_keys_by_options() {
+        let bytes = hex("4401020304");
+        assert_eq!(JsonElement::Str("AQIDBA".into()), decode(&bytes).unwrap());
+        let options = DecodeOptions {
+            bytes: Bytes::Array,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            Parser::parse("[1,2,3,4]").unwrap(),
+            decode_with_options(&bytes, &options).unwrap()
+        );
+        let options = DecodeOptions {
+            bytes: Bytes::Error,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            BinaryError::new("Byte strings are not allowed", 0),
+            decode_with_options(&bytes, &options).unwrap_err()
+        );
+
+        let map = hex("a201020304");
+        assert_eq!(
+            BinaryError::new("Map keys must be strings", 1),
+            decode(&map).unwrap_err()
+        );
+        let options = DecodeOptions {
+            keys: Keys::Stringify,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            Parser::parse("{\"1\":2,\"3\":4}").unwrap(),
+            decode_with_options(&map, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_report_invalid_input() {
+        let cases = [
+            ("", "Unexpected end of input", 0),
+            ("1a0000", "Unexpected end of input", 1),
+            ("0001", "Unexpected bytes after the value", 1),
+            ("830102", "Length is larger than the input", 1),
+            ("1c", "Reserved additional information", 1),
+            ("ff", "Unexpected break", 0),
+            ("9f01", "Unexpected end of input", 2),
+            ("bf6161ff", "Unexpected break", 3),
+            ("62c328", "Invalid UTF-8 in a string", 1),
+            ("5f4101ff00", "Unexpected bytes after the value", 4),
+            (
+                "7f4101ff",
+                "Invalid chunk in an indefinite length string",
+                1,
+            ),
+            ("fb7ff8000000000000", "Numbers must be finite", 9),
+            ("f0", "Unsupported simple value", 1),
+            ("1f", "Indefinite length is not allowed here", 1),
+            ("9bffffffffffffffff", "Length is larger than the input", 9),
+        ];
+        for (bytes, details, offset) in cases {
+            assert_eq!(
+                BinaryError::new(details, offset),
+                decode(&hex(bytes)).unwrap_err(),
+                "{bytes}"
+            );
+        }
+
+        let mut deep = vec![0x81; 129];
+        deep.push(0x00);
+        assert_eq!(
+            BinaryError::new("Nesting is deeper than 128 levels", 128),
+            decode(&deep).unwrap_err()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn should_round_trip_parsed_documents(element in arb_json_element(Numbers::Any, "\\PC{0,4}")) {
+            let parsed = Parser::parse(&Serializer::serialize(&element)).unwrap();
+            let decoded = decode(&encode(&parsed)).unwrap();
+            prop_assert_eq!(&parsed, &decoded);
+            prop_assert_eq!(encode(&parsed), encode(&decoded));
+        }
+    }
+}
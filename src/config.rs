@@ -0,0 +1,535 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Layered configuration: json files merged in order, then environment
+//! variables such as `APP__DB__PORT=5433` on top, with `${VAR}` in strings
+//! replaced by the variable's value.
+//!
+//! Every value of the result remembers the file and line, or the variable, it
+//! came from, so a surprising setting can be traced back to its layer.
+
+use crate::json_element::{JsonElement, Map};
+use crate::merge::{MergeOptions, Merged};
+use crate::parser::Parser;
+use crate::pointer::{array_index, JsonPointer};
+use crate::visit::{walk_mut, Edit, VisitorMut};
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    pub details: String,
+}
+
+impl ConfigError {
+    pub fn new(msg: &str) -> ConfigError {
+        return ConfigError {
+            details: msg.to_string(),
+        };
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Where a configuration value was set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A file, with the position of the value in it.
+    File {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+    },
+    /// An environment variable, by name.
+    Env(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File { path, line, column } => {
+                write!(f, "{}:{line}:{column}", path.display())
+            }
+            Source::Env(name) => write!(f, "environment variable {name}"),
+        }
+    }
+}
+
+/// Loads a configuration. Files are merged in the order they were added, with
+/// `MergeOptions::default()` unless other options are set.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLoader {
+    files: Vec<(PathBuf, bool)>,
+    env_prefix: Option<String>,
+    vars: Option<Vec<(String, String)>>,
+    merge: MergeOptions,
+}
+
+impl ConfigLoader {
+    pub fn new() -> ConfigLoader {
+        return ConfigLoader::default();
+    }
+
+    /// Adds a file that must exist.
+    pub fn file(mut self, path: impl AsRef<Path>) -> ConfigLoader {
+        self.files.push((path.as_ref().to_path_buf(), true));
+        return self;
+    }
+
+    /// Adds a file that is skipped when it does not exist.
+    pub fn optional_file(mut self, path: impl AsRef<Path>) -> ConfigLoader {
+        self.files.push((path.as_ref().to_path_buf(), false));
+        return self;
+    }
+
+    /// Applies variables named `{prefix}__SECTION__KEY` on top of the files,
+    /// setting `/section/key`. Keys match existing ones regardless of case and
+    /// are lowercased otherwise; numeric parts index into arrays. A value
+    /// takes the type of the value it replaces, and new values are read as
+    /// json when they parse and as strings otherwise.
+    pub fn env_prefix(mut self, prefix: &str) -> ConfigLoader {
+        self.env_prefix = Some(prefix.to_string());
+        return self;
+    }
+
+    /// Uses these variables, for overrides and `${VAR}`, instead of the
+    /// environment of the process.
+    pub fn vars<K: Into<String>, V: Into<String>>(
+        mut self,
+        vars: impl IntoIterator<Item = (K, V)>,
+    ) -> ConfigLoader {
+        let vars = vars.into_iter().map(|(k, v)| (k.into(), v.into()));
+        self.vars = Some(vars.collect());
+        return self;
+    }
+
+    pub fn merge_options(mut self, options: MergeOptions) -> ConfigLoader {
+        self.merge = options;
+        return self;
+    }
+
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        let vars = match &self.vars {
+            Some(vars) => vars.clone(),
+            // Variables that are not valid UTF-8 cannot be json text, so they are
+            // left out rather than making `env::vars` panic.
+            None => std::env::vars_os()
+                .filter_map(|(name, value)| {
+                    Some((name.into_string().ok()?, value.into_string().ok()?))
+                })
+                .collect(),
+        };
+        let mut layers = Vec::new();
+        let mut files = Vec::new();
+        for (path, required) in &self.files {
+            let text = match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(error) if !required && error.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(error) => {
+                    return Err(ConfigError::new(&format!(
+                        "Could not read {}: {error}",
+                        path.display()
+                    )))
+                }
+            };
+            let (value, spans) = Parser::parse_with_spans(&text)
+                .map_err(|error| ConfigError::new(&format!("{}: {error}", path.display())))?;
+            layers.push(value);
+            files.push((path, spans));
+        }
+        let mut config = Config {
+            value: JsonElement::Object(Map::new()),
+            sources: Map::new(),
+        };
+        if !layers.is_empty() {
+            let Merged { value, provenance } = JsonElement::merge(&layers, &self.merge)
+                .map_err(|error| ConfigError::new(&error.details))?;
+            config.value = value;
+            for (pointer, provenance) in provenance {
+                let (path, spans) = &files[provenance.layer];
+                let start = spans.value(&provenance.path).unwrap().start;
+                let source = Source::File {
+                    path: path.to_path_buf(),
+                    line: start.line,
+                    column: start.column,
+                };
+                config.sources.insert(pointer, source);
+            }
+        }
+        let mut interpolate = Interpolate {
+            vars: &vars,
+            error: None,
+        };
+        walk_mut(&mut config.value, &mut interpolate);
+        if let Some(error) = interpolate.error {
+            return Err(error);
+        }
+        if let Some(prefix) = &self.env_prefix {
+            let prefix = format!("{prefix}__");
+            let mut overrides: Vec<&(String, String)> = vars
+                .iter()
+                .filter(|(name, _)| name.starts_with(&prefix) && name.len() > prefix.len())
+                .collect();
+            overrides.sort();
+            for (name, value) in overrides {
+                config.set_from_env(name, &name[prefix.len()..], value)?;
+            }
+        }
+        return Ok(config);
+    }
+}
+
+/// Replaces `${NAME}` and `${NAME:-default}` in strings, and `$$` with `$`.
+struct Interpolate<'a> {
+    vars: &'a [(String, String)],
+    error: Option<ConfigError>,
+}
+
+impl Interpolate<'_> {
+    fn expand(&self, text: &str, path: &JsonPointer) -> Result<String, ConfigError> {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('$') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("$$") {
+                out.push('$');
+                rest = after;
+                continue;
+            }
+            let inner = match rest.strip_prefix("${") {
+                Some(inner) => inner,
+                None => {
+                    out.push('$');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            let end = inner.find('}').ok_or_else(|| {
+                ConfigError::new(&format!("Unclosed ${{ in the string at {path}"))
+            })?;
+            let (name, default) = match inner[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&inner[..end], None),
+            };
+            let value = self.vars.iter().find(|(var, _)| var == name);
+            match (value, default) {
+                (Some((_, value)), _) => out.push_str(value),
+                (None, Some(default)) => out.push_str(default),
+                (None, None) => {
+                    return Err(ConfigError::new(&format!(
+                        "Variable {name} used at {path} is not set"
+                    )))
+                }
+            }
+            rest = &inner[end + 1..];
+        }
+        out.push_str(rest);
+        return Ok(out);
+    }
+}
+
+impl VisitorMut for Interpolate<'_> {
+    fn visit_scalar_mut(&mut self, path: &JsonPointer, element: &mut JsonElement) -> Edit {
+        let text = match element {
+            JsonElement::Str(text) if text.contains('$') => text,
+            _ => return Edit::Keep,
+        };
+        match self.expand(text, path) {
+            Ok(expanded) => *text = expanded,
+            Err(error) => {
+                self.error = Some(error);
+                return Edit::Stop;
+            }
+        }
+        return Edit::Keep;
+    }
+}
+
+/// The value of variable `name` read as the kind of value it replaces.
+fn coerce(
+    name: &str,
+    existing: Option<&JsonElement>,
+    value: &str,
+) -> Result<JsonElement, ConfigError> {
+    let invalid = |expected: &str| {
+        return ConfigError::new(&format!("{name} should be {expected}, but it is {value:?}"));
+    };
+    return match existing {
+        Some(JsonElement::Str(_)) => Ok(JsonElement::Str(value.to_string())),
+        Some(JsonElement::Number(_)) => match value.trim().parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(JsonElement::Number(number)),
+            _ => Err(invalid("a number")),
+        },
+        Some(JsonElement::Boolean(_)) => match value.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" => Ok(JsonElement::Boolean(true)),
+            "false" | "0" => Ok(JsonElement::Boolean(false)),
+            _ => Err(invalid("true or false")),
+        },
+        Some(JsonElement::Array(_)) => match Parser::parse(value) {
+            Ok(array @ JsonElement::Array(_)) => Ok(array),
+            _ => Err(invalid("a json array")),
+        },
+        Some(JsonElement::Object(_)) => match Parser::parse(value) {
+            Ok(object @ JsonElement::Object(_)) => Ok(object),
+            _ => Err(invalid("a json object")),
+        },
+        Some(JsonElement::Null) | None => {
+            Ok(Parser::parse(value).unwrap_or_else(|_| JsonElement::Str(value.to_string())))
+        }
+    };
+}
+
+/// A loaded configuration.
+#[derive(Debug, PartialEq)]
+pub struct Config {
+    value: JsonElement,
+    sources: Map<JsonPointer, Source>,
+}
+
+impl Config {
+    pub fn value(&self) -> &JsonElement {
+        return &self.value;
+    }
+
+    pub fn into_value(self) -> JsonElement {
+        return self.value;
+    }
+
+    /// The value at a path such as `db.port` (see `JsonPointer::from_path`).
+    pub fn get(&self, path: &str) -> Option<&JsonElement> {
+        return JsonPointer::from_path(path).ok()?.resolve(&self.value);
+    }
+
+    /// Where the value at `pointer` was set. Only leaves (see
+    /// `JsonElement::leaves`) have a source.
+    pub fn source(&self, pointer: &JsonPointer) -> Option<&Source> {
+        return self.sources.get(pointer);
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = (&JsonPointer, &Source)> {
+        return self.sources.iter();
+    }
+
+    /// Sets the value named by `key`, the part of variable `name` after the
+    /// prefix, creating the objects leading to it.
+    fn set_from_env(&mut self, name: &str, key: &str, value: &str) -> Result<(), ConfigError> {
+        let mut pointer = JsonPointer::root();
+        let mut current = &mut self.value;
+        for part in key.split("__") {
+            if part.is_empty() {
+                return Err(ConfigError::new(&format!("{name} has an empty key")));
+            }
+            current = match current {
+                JsonElement::Object(members) => {
+                    let key = match members.keys().find(|key| key.eq_ignore_ascii_case(part)) {
+                        Some(key) => key.clone(),
+                        None => part.to_ascii_lowercase(),
+                    };
+                    pointer.push(&key);
+                    members
+                        .entry(key)
+                        .or_insert_with(|| JsonElement::Object(Map::new()))
+                }
+                JsonElement::Array(elements) => {
+                    let index = array_index(part).filter(|index| *index <= elements.len());
+                    let index = index.ok_or_else(|| {
+                        ConfigError::new(&format!("{name}: {pointer} has no element {part}"))
+                    })?;
+                    if index == elements.len() {
+                        elements.push(JsonElement::Object(Map::new()));
+                    }
+                    pointer.push(part);
+                    &mut elements[index]
+                }
+                _ => {
+                    return Err(ConfigError::new(&format!(
+                        "{name}: {pointer} is not an object or array"
+                    )))
+                }
+            };
+        }
+        let created = matches!(current, JsonElement::Object(members) if members.is_empty())
+            && !self.sources.contains_key(&pointer);
+        let existing = if created { None } else { Some(&*current) };
+        *current = coerce(name, existing, value)?;
+        let prefix = pointer.tokens().len();
+        self.sources
+            .retain(|path, _| path.tokens().get(..prefix) != Some(pointer.tokens()));
+        for (path, _) in current.leaves() {
+            let mut full = pointer.clone();
+            for token in path.tokens() {
+                full.push(token);
+            }
+            self.sources.insert(full, Source::Env(name.to_string()));
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigLoader, Source};
+    use crate::json_element::JsonElement;
+    use crate::merge::{ArrayStrategy, MergeOptions};
+    use crate::pointer::JsonPointer;
+    use crate::test_support::json;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn pointer(text: &str) -> JsonPointer {
+        return JsonPointer::parse(text).ok().unwrap();
+    }
+
+    /// A fresh directory under the system temp dir, unique to the test.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jsonp-config-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn should_layer_files_and_environment() {
+        let dir = temp_dir("layers");
+        let defaults = dir.join("defaults.json");
+        let env = dir.join("production.json");
+        fs::write(
+            &defaults,
+            "{\n  \"db\": {\"host\": \"localhost\", \"port\": 5432},\n  \"debug\": true,\n  \"url\": \"http://${HOST:-localhost}:${PORT}/$$x\",\n  \"plugins\": [\"a\"]\n}",
+        )
+        .unwrap();
+        fs::write(
+            &env,
+            "{\"db\": {\"host\": \"db.internal\"},\n \"plugins\": [\"b\"]}",
+        )
+        .unwrap();
+        let config = ConfigLoader::new()
+            .file(&defaults)
+            .optional_file(&env)
+            .optional_file(dir.join("local.json"))
+            .env_prefix("APP")
+            .vars([
+                ("PORT", "8080"),
+                ("APP__DB__PORT", "5433"),
+                ("APP__Debug", "false"),
+                ("APP__PLUGINS__1", "c"),
+                ("APP__CACHE__TTL", "30"),
+                ("APP__CACHE__NAME", "main"),
+                ("OTHER__DEBUG", "true"),
+            ])
+            .merge_options(MergeOptions {
+                arrays: ArrayStrategy::Append,
+                ..MergeOptions::default()
+            })
+            .load()
+            .unwrap();
+        assert_eq!(
+            &json(
+                r#"{"db": {"host": "db.internal", "port": 5433}, "debug": false,
+                    "url": "http://localhost:8080/$x", "plugins": ["a", "c"],
+                    "cache": {"ttl": 30, "name": "main"}}"#
+            ),
+            config.value()
+        );
+        assert_eq!(Some(&JsonElement::Number(5433.0)), config.get("db.port"));
+
+        let file = |path: &PathBuf, line, column| Source::File {
+            path: path.clone(),
+            line,
+            column,
+        };
+        assert_eq!(
+            Some(&file(&env, 1, 17)),
+            config.source(&pointer("/db/host"))
+        );
+        assert_eq!(
+            Some(&file(&defaults, 4, 10)),
+            config.source(&pointer("/url"))
+        );
+        assert_eq!(
+            Some(&file(&defaults, 5, 15)),
+            config.source(&pointer("/plugins/0"))
+        );
+        assert_eq!(
+            Some(&Source::Env("APP__DB__PORT".to_string())),
+            config.source(&pointer("/db/port"))
+        );
+        assert_eq!(
+            Some(&Source::Env("APP__PLUGINS__1".to_string())),
+            config.source(&pointer("/plugins/1"))
+        );
+        assert_eq!(
+            "environment variable APP__CACHE__TTL",
+            config.source(&pointer("/cache/ttl")).unwrap().to_string()
+        );
+        assert_eq!(8, config.sources().count());
+    }
+
+    #[test]
+    fn should_report_errors() {
+        let dir = temp_dir("errors");
+        let base = dir.join("base.json");
+        fs::write(&base, "{\"port\": 1, \"name\": \"${NAME}\", \"tags\": []}").unwrap();
+        let error = |loader: ConfigLoader| loader.load().err().unwrap().details;
+
+        assert!(
+            error(ConfigLoader::new().file(dir.join("missing.json"))).starts_with("Could not read")
+        );
+        let broken = dir.join("broken.json");
+        fs::write(&broken, "{\n  \"a\": }").unwrap();
+        assert_eq!(
+            format!(
+                "{}: Invalid json value at line 2 column 8",
+                broken.display()
+            ),
+            error(ConfigLoader::new().file(&broken))
+        );
+        let loader = ConfigLoader::new().file(&base).env_prefix("APP");
+        assert_eq!(
+            "Variable NAME used at /name is not set",
+            error(loader.clone().vars([("X", "1")]))
+        );
+        let loader = loader.vars([("NAME", "x"), ("APP__PORT", "eighty")]);
+        assert_eq!(
+            "APP__PORT should be a number, but it is \"eighty\"",
+            error(loader.clone())
+        );
+        let loader = loader.vars([("NAME", "x"), ("APP__TAGS__3", "a")]);
+        assert_eq!(
+            "APP__TAGS__3: /tags has no element 3",
+            error(loader.clone())
+        );
+        let loader = loader.vars([("NAME", "x"), ("APP__PORT__X", "a")]);
+        assert_eq!(
+            "APP__PORT__X: /port is not an object or array",
+            error(loader)
+        );
+
+        let empty = ConfigLoader::new()
+            .vars([("APP__A", "[1]")])
+            .env_prefix("APP");
+        assert_eq!(&json(r#"{"a": [1]}"#), empty.load().unwrap().value());
+    }
+}
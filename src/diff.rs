@@ -0,0 +1,448 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Structural differences between two `JsonElement` trees.
+//!
+//! Arrays are aligned on their longest common subsequence, so inserting or
+//! removing an element reports just that element rather than every one after
+//! it. Elements that only changed position are reported as moves.
+
+use crate::json_element::{JsonElement, Map};
+use crate::pointer::JsonPointer;
+use crate::serializer::{Serializer, SerializerOptions};
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter;
+
+/// Largest number of element pairs compared when aligning two arrays. Past it,
+/// elements are compared index by index and moves are not detected.
+const MAX_ALIGNMENT_CELLS: usize = 1 << 22;
+
+/// One difference. `Removed` paths and `Moved::from` point into the old
+/// document, every other path points into the new one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added {
+        path: JsonPointer,
+        value: JsonElement,
+    },
+    Removed {
+        path: JsonPointer,
+        value: JsonElement,
+    },
+    Changed {
+        path: JsonPointer,
+        old: JsonElement,
+        new: JsonElement,
+    },
+    Moved {
+        from: JsonPointer,
+        to: JsonPointer,
+        value: JsonElement,
+    },
+}
+
+/// Pending work: a pair of values still to compare, or a change found while
+/// comparing their parents, kept in order with the pairs around it.
+enum Work<'a> {
+    Compare(&'a JsonElement, &'a JsonElement, JsonPointer),
+    Emit(Change),
+}
+
+/// Lists the changes that turn `old` into `new`, in document order.
+pub fn diff(old: &JsonElement, new: &JsonElement) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut stack = vec![Work::Compare(old, new, JsonPointer::root())];
+    while let Some(work) = stack.pop() {
+        let (old, new, path) = match work {
+            Work::Emit(change) => {
+                changes.push(change);
+                continue;
+            }
+            Work::Compare(old, new, path) => (old, new, path),
+        };
+        let mut children = Vec::new();
+        match (old, new) {
+            (JsonElement::Object(a), JsonElement::Object(b)) => {
+                diff_objects(a, b, &path, &mut children)
+            }
+            (JsonElement::Array(a), JsonElement::Array(b)) => {
+                diff_arrays(a, b, &path, &mut children)
+            }
+            _ if old == new => {}
+            _ => children.push(Work::Emit(Change::Changed {
+                path,
+                old: old.clone(),
+                new: new.clone(),
+            })),
+        }
+        stack.extend(children.into_iter().rev());
+    }
+    return changes;
+}
+
+fn diff_objects<'a>(
+    old: &'a Map<String, JsonElement>,
+    new: &'a Map<String, JsonElement>,
+    path: &JsonPointer,
+    out: &mut Vec<Work<'a>>,
+) {
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        out.push(match (old.get(key), new.get(key)) {
+            (Some(a), Some(b)) => Work::Compare(a, b, path.child(key)),
+            (Some(a), None) => Work::Emit(Change::Removed {
+                path: path.child(key),
+                value: a.clone(),
+            }),
+            (None, Some(b)) => Work::Emit(Change::Added {
+                path: path.child(key),
+                value: b.clone(),
+            }),
+            (None, None) => unreachable!(),
+        });
+    }
+}
+
+/// Index pairs of a longest common subsequence of `a` and `b`, in order.
+fn longest_common_subsequence(a: &[JsonElement], b: &[JsonElement]) -> Vec<(usize, usize)> {
+    let width = b.len() + 1;
+    let mut lengths = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i * width + j] = if a[i] == b[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    return pairs;
+}
+
+fn diff_arrays<'a>(
+    old: &'a [JsonElement],
+    new: &'a [JsonElement],
+    path: &JsonPointer,
+    out: &mut Vec<Work<'a>>,
+) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let align = a.len().saturating_mul(b.len()) <= MAX_ALIGNMENT_CELLS;
+    let anchors = if align {
+        longest_common_subsequence(a, b)
+    } else {
+        Vec::new()
+    };
+
+    // An unaligned element equal to an unaligned element on the other side moved.
+    let mut moved_from: Vec<Option<usize>> = vec![None; b.len()];
+    let mut moved = vec![false; a.len()];
+    if align {
+        let mut aligned_old = vec![false; a.len()];
+        let mut aligned_new = vec![false; b.len()];
+        for &(i, j) in &anchors {
+            aligned_old[i] = true;
+            aligned_new[j] = true;
+        }
+        for i in (0..a.len()).filter(|&i| !aligned_old[i]) {
+            let target =
+                (0..b.len()).find(|&j| !aligned_new[j] && moved_from[j].is_none() && a[i] == b[j]);
+            if let Some(j) = target {
+                moved_from[j] = Some(i);
+                moved[i] = true;
+            }
+        }
+    }
+
+    // Between two anchors, the remaining elements are compared pairwise and the
+    // ones left over on either side were removed or added.
+    let mut start = (0, 0);
+    for &(end_old, end_new) in anchors.iter().chain(iter::once(&(a.len(), b.len()))) {
+        let olds: Vec<usize> = (start.0..end_old).filter(|&i| !moved[i]).collect();
+        let unmoved_news = (start.1..end_new)
+            .filter(|&j| moved_from[j].is_none())
+            .count();
+        let paired = olds.len().min(unmoved_news);
+        for &i in &olds[paired..] {
+            out.push(Work::Emit(Change::Removed {
+                path: path.index(prefix + i),
+                value: a[i].clone(),
+            }));
+        }
+        let mut olds = olds[..paired].iter();
+        for j in start.1..end_new {
+            let to = path.index(prefix + j);
+            let paired_old = match moved_from[j] {
+                Some(_) => None,
+                None => olds.next(),
+            };
+            out.push(match (moved_from[j], paired_old) {
+                (Some(i), _) => Work::Emit(Change::Moved {
+                    from: path.index(prefix + i),
+                    to,
+                    value: b[j].clone(),
+                }),
+                (None, Some(&i)) => Work::Compare(&a[i], &b[j], to),
+                (None, None) => Work::Emit(Change::Added {
+                    path: to,
+                    value: b[j].clone(),
+                }),
+            });
+        }
+        start = (end_old + 1, end_new + 1);
+    }
+}
+
+fn compact(element: &JsonElement) -> String {
+    let options = SerializerOptions {
+        indent: None,
+        sort_keys: true,
+    };
+    return Serializer::serialize_with_options(element, &options);
+}
+
+fn display_path(path: &JsonPointer) -> String {
+    if path.tokens().is_empty() {
+        return "(root)".to_string();
+    }
+    return path.to_string();
+}
+
+/// Renders one change per line: `+` added, `-` removed, `~` changed and `>`
+/// moved, with values as compact json. `color` wraps each line in an ANSI
+/// color for terminals.
+pub fn render(changes: &[Change], color: bool) -> String {
+    let mut out = String::new();
+    for change in changes {
+        let (code, line) = match change {
+            Change::Added { path, value } => (
+                "32",
+                format!("+ {}: {}", display_path(path), compact(value)),
+            ),
+            Change::Removed { path, value } => (
+                "31",
+                format!("- {}: {}", display_path(path), compact(value)),
+            ),
+            Change::Changed { path, old, new } => (
+                "33",
+                format!(
+                    "~ {}: {} -> {}",
+                    display_path(path),
+                    compact(old),
+                    compact(new)
+                ),
+            ),
+            Change::Moved { from, to, value } => (
+                "36",
+                format!(
+                    "> {} -> {}: {}",
+                    display_path(from),
+                    display_path(to),
+                    compact(value)
+                ),
+            ),
+        };
+        if color {
+            out.push_str(&format!("\x1b[{code}m{line}\x1b[0m\n"));
+        } else {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    return out;
+}
+
+/// The changes as a json array of objects, with `op` named after the JSON
+/// Patch operations: `add`, `remove`, `replace` (with the `old` value too) and
+/// `move`.
+pub fn to_json(changes: &[Change]) -> JsonElement {
+    let entry = |op: &str, members: Vec<(&str, JsonElement)>| {
+        let mut object = Map::new();
+        object.insert("op".to_string(), JsonElement::Str(op.to_string()));
+        for (key, value) in members {
+            object.insert(key.to_string(), value);
+        }
+        return JsonElement::Object(object);
+    };
+    let pointer = |path: &JsonPointer| JsonElement::Str(path.to_string());
+    return JsonElement::Array(
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Added { path, value } => entry(
+                    "add",
+                    vec![("path", pointer(path)), ("value", value.clone())],
+                ),
+                Change::Removed { path, value } => entry(
+                    "remove",
+                    vec![("path", pointer(path)), ("value", value.clone())],
+                ),
+                Change::Changed { path, old, new } => entry(
+                    "replace",
+                    vec![
+                        ("path", pointer(path)),
+                        ("old", old.clone()),
+                        ("value", new.clone()),
+                    ],
+                ),
+                Change::Moved { from, to, value } => entry(
+                    "move",
+                    vec![
+                        ("from", pointer(from)),
+                        ("path", pointer(to)),
+                        ("value", value.clone()),
+                    ],
+                ),
+            })
+            .collect(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compact, diff, render, to_json, Change};
+    use crate::json_element::JsonElement;
+    use crate::parser::Parser;
+
+    fn diff_text(old: &str, new: &str) -> String {
+        let old = Parser::parse(old).ok().unwrap();
+        let new = Parser::parse(new).ok().unwrap();
+        return render(&diff(&old, &new), false);
+    }
+
+    #[test]
+    fn should_report_nothing_for_equal_documents() {
+        assert_eq!(
+            "",
+            diff_text("{\"a\": [1, {\"b\": null}]}", "{\"a\":[1,{\"b\":null}]}")
+        );
+        assert_eq!("", diff_text("[]", "[]"));
+    }
+
+    #[test]
+    fn should_diff_objects() {
+        assert_eq!(
+            "- /gone: true\n~ /nested/value: 1 -> 2\n+ /new: [1]\n~ /type: 1 -> \"1\"\n",
+            diff_text(
+                "{\"gone\": true, \"nested\": {\"value\": 1, \"same\": 0}, \"type\": 1}",
+                "{\"nested\": {\"value\": 2, \"same\": 0}, \"new\": [1], \"type\": \"1\"}"
+            )
+        );
+        assert_eq!("~ (root): {} -> []\n", diff_text("{}", "[]"));
+        assert_eq!("+ /a~1b~0c: 1\n", diff_text("{}", "{\"a/b~c\": 1}"));
+    }
+
+    #[test]
+    fn should_align_arrays() {
+        assert_eq!("+ /1: \"x\"\n", diff_text("[1, 2, 3]", "[1, \"x\", 2, 3]"));
+        assert_eq!("- /0: 1\n- /2: 3\n", diff_text("[1, 2, 3, 4]", "[2, 4]"));
+        assert_eq!("~ /1: 2 -> 5\n", diff_text("[1, 2, 3]", "[1, 5, 3]"));
+        assert_eq!(
+            "~ /1/name: \"b\" -> \"c\"\n",
+            diff_text(
+                "[{\"name\": \"a\"}, {\"name\": \"b\"}]",
+                "[{\"name\": \"a\"}, {\"name\": \"c\"}]"
+            )
+        );
+        assert_eq!("+ /2: 3\n+ /3: 4\n", diff_text("[1, 2]", "[1, 2, 3, 4]"));
+    }
+
+    #[test]
+    fn should_detect_moves() {
+        assert_eq!(
+            "> /0 -> /2: \"a\"\n",
+            diff_text("[\"a\", \"b\", \"c\"]", "[\"b\", \"c\", \"a\"]")
+        );
+        assert_eq!(
+            "> /3 -> /0: {\"id\":4}\n- /1: {\"id\":2}\n",
+            diff_text(
+                "[{\"id\": 1}, {\"id\": 2}, {\"id\": 3}, {\"id\": 4}]",
+                "[{\"id\": 4}, {\"id\": 1}, {\"id\": 3}]"
+            )
+        );
+    }
+
+    #[test]
+    fn should_pair_remaining_elements_around_moves() {
+        assert_eq!(
+            "> /3 -> /0: 8\n~ /1: 9 -> 7\n",
+            diff_text("[9, 1, 2, 8]", "[8, 7, 1, 2]")
+        );
+    }
+
+    #[test]
+    fn should_compare_long_arrays_by_index() {
+        let old = JsonElement::Array((0..5000).map(|i| JsonElement::Number(i as f64)).collect());
+        let new = JsonElement::Array(
+            (0..5000)
+                .map(|i| JsonElement::Number((i % 4999) as f64))
+                .collect(),
+        );
+        let changes = diff(&old, &new);
+        assert_eq!(1, changes.len());
+        assert!(matches!(&changes[0], Change::Changed { path, .. } if path.to_string() == "/4999"));
+
+        let old = JsonElement::Array((0..3000).map(|i| JsonElement::Number(i as f64)).collect());
+        let new = JsonElement::Array((0..3000).map(|i| JsonElement::Number(-i as f64)).collect());
+        assert_eq!(2999, diff(&old, &new).len());
+    }
+
+    #[test]
+    fn should_render_json_and_color() {
+        let old = Parser::parse("{\"a\": 1, \"l\": [\"x\", \"y\"]}")
+            .ok()
+            .unwrap();
+        let new = Parser::parse("{\"a\": 2, \"b\": null, \"l\": [\"y\", \"x\"]}")
+            .ok()
+            .unwrap();
+        let changes = diff(&old, &new);
+        assert_eq!(
+            "[{\"old\":1,\"op\":\"replace\",\"path\":\"/a\",\"value\":2},\
+             {\"op\":\"add\",\"path\":\"/b\",\"value\":null},\
+             {\"from\":\"/l/0\",\"op\":\"move\",\"path\":\"/l/1\",\"value\":\"x\"}]",
+            compact(&to_json(&changes))
+        );
+        assert_eq!(
+            "\x1b[33m~ /a: 1 -> 2\x1b[0m\n\x1b[32m+ /b: null\x1b[0m\n\x1b[36m> /l/0 -> /l/1: \"x\"\x1b[0m\n",
+            render(&changes, true)
+        );
+    }
+}
@@ -0,0 +1,358 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Differential tests comparing `Parser::parse` with `serde_json`.
+//!
+//! Every document is parsed by both implementations. They must agree on whether
+//! the document is valid and, when it is, on the resulting value. A divergence is
+//! reported together with the smallest input found that still diverges.
+
+use crate::json_element::{JsonElement, Map};
+use crate::parser::Parser;
+use crate::serializer::Serializer;
+
+use proptest::prelude::*;
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Accepted(JsonElement),
+    Rejected(String),
+}
+
+fn jsonp_outcome(json: &str) -> Outcome {
+    return match Parser::parse(json) {
+        Ok(element) => Outcome::Accepted(element),
+        Err(e) => Outcome::Rejected(e.details),
+    };
+}
+
+fn from_reference(value: serde_json::Value) -> JsonElement {
+    return match value {
+        serde_json::Value::Null => JsonElement::Null,
+        serde_json::Value::Bool(b) => JsonElement::Boolean(b),
+        serde_json::Value::Number(n) => JsonElement::Number(n.as_f64().unwrap()),
+        serde_json::Value::String(s) => JsonElement::Str(s),
+        serde_json::Value::Array(a) => {
+            JsonElement::Array(a.into_iter().map(from_reference).collect())
+        }
+        serde_json::Value::Object(o) => JsonElement::Object(
+            o.into_iter()
+                .map(|(k, v)| (k, from_reference(v)))
+                .collect::<Map<_, _>>(),
+        ),
+    };
+}
+
+fn reference_outcome(json: &str) -> Outcome {
+    return match serde_json::from_str::<serde_json::Value>(json) {
+        Ok(value) => Outcome::Accepted(from_reference(value)),
+        Err(e) => Outcome::Rejected(e.to_string()),
+    };
+}
+
+fn diverges(json: &str) -> bool {
+    let jsonp = jsonp_outcome(json);
+    let reference = reference_outcome(json);
+    return match (&jsonp, &reference) {
+        (Outcome::Rejected(_), Outcome::Rejected(_)) => false,
+        _ => jsonp != reference,
+    };
+}
+
+/// Greedily removes chunks of characters, from large to single ones, as long
+/// as `failing` still holds for the input.
+fn minimize(json: &str, failing: impl Fn(&str) -> bool) -> String {
+    let mut chars: Vec<char> = json.chars().collect();
+    let mut chunk = chars.len().max(1);
+    loop {
+        let mut start = 0;
+        while start < chars.len() {
+            let end = (start + chunk).min(chars.len());
+            let candidate: String = chars[..start].iter().chain(&chars[end..]).collect();
+            if failing(&candidate) {
+                chars.drain(start..end);
+            } else {
+                start += chunk;
+            }
+        }
+        if chunk == 1 {
+            return chars.into_iter().collect();
+        }
+        chunk /= 2;
+    }
+}
+
+fn check(json: &str) -> Result<(), String> {
+    if !diverges(json) {
+        return Ok(());
+    }
+    let minimized = minimize(json, diverges);
+    return Err(format!(
+        "{json:?} diverges (minimized: {minimized:?}): jsonp {:?}, serde_json {:?}",
+        jsonp_outcome(&minimized),
+        reference_outcome(&minimized),
+    ));
+}
+
+const CORPUS: &[&str] = &[
+    // Numbers.
+    "0",
+    "-0",
+    "0.0",
+    "-0.0",
+    "1",
+    "-1",
+    "01",
+    "-01",
+    "00",
+    "1.",
+    ".1",
+    "-.1",
+    "1.e1",
+    "+1",
+    "1e1",
+    "1E1",
+    "1e+1",
+    "1e-1",
+    "1e",
+    "1e+",
+    "1E-",
+    "-",
+    "--1",
+    "1.5e308",
+    "1e309",
+    "-1e309",
+    "1e-400",
+    "4.9e-324",
+    "2.2250738585072014e-308",
+    "1.7976931348623157e308",
+    "123456789012345678901234567890",
+    "9007199254740993",
+    "0.1",
+    "0.30000000000000004",
+    "333333333.33333329",
+    "1E400",
+    "0e0",
+    "0E+0",
+    "-0e-0",
+    "1.0000000000000002",
+    "1x",
+    "[1,]",
+    "[01]",
+    "[-]",
+    "[1.]",
+    "[.5]",
+    "[1e]",
+    "[0x10]",
+    "[Infinity]",
+    "[NaN]",
+    // Strings and escapes.
+    "\"\"",
+    "\"a\"",
+    "\"\\\"\"",
+    "\"\\\\\"",
+    "\"\\/\"",
+    "\"\\b\\f\\n\\r\\t\"",
+    "\"\\u0000\"",
+    "\"\\u001f\"",
+    "\"\\u00e9\"",
+    "\"\\u00E9\"",
+    "\"\\uFFFF\"",
+    "\"\\u306e\"",
+    "\"\\ud83d\\ude00\"",
+    "\"\\uD83D\\uDE00\"",
+    "\"\\ud83d\"",
+    "\"\\ude00\"",
+    "\"\\ud83dx\"",
+    "\"\\ud83d\\u0041\"",
+    "\"\\u12\"",
+    "\"\\u12g4\"",
+    "\"\\x\"",
+    "\"\\'\"",
+    "\"\\a\"",
+    "\"unterminated",
+    "\"\\",
+    "'single'",
+    "\"日本語 🦀\"",
+    "\"tab\there\"",
+    "\"new\nline\"",
+    "\"\u{0}\"",
+    "\"\u{1f}\"",
+    "\"\u{7f}\"",
+    "{\"k\u{1}\": 1}",
+    // Literals.
+    "true",
+    "false",
+    "null",
+    "tru",
+    "nul",
+    "True",
+    "NULL",
+    "truex",
+    "nullnull",
+    // Whitespace.
+    "",
+    " ",
+    " 1 ",
+    "\t\n\r 1\t\n\r ",
+    "\u{a0}1",
+    "\u{feff}1",
+    "\u{b}1",
+    "\u{c}1",
+    "[ 1 , 2 ]",
+    "{ \"a\" : 1 }",
+    "1 2",
+    "[] []",
+    "{}x",
+    "[1]]",
+    "{\"a\":1}}",
+    // Arrays and objects.
+    "[]",
+    "[ ]",
+    "[[]]",
+    "[[[]]]",
+    "[1,2,3]",
+    "[,]",
+    "[,1]",
+    "[1,,2]",
+    "[",
+    "]",
+    "[1",
+    "[1 2]",
+    "{}",
+    "{ }",
+    "{\"a\":1}",
+    "{\"a\":1,}",
+    "{,}",
+    "{\"a\"}",
+    "{\"a\":}",
+    "{a:1}",
+    "{1:1}",
+    "{\"a\":1 \"b\":2}",
+    "{\"a\":1,\"a\":2}",
+    "{\"\":\"\"}",
+    "{\"a\":{\"b\":[{\"c\":null}]}}",
+    "{\"a\":[1,{\"b\":2}],\"c\":\"d\"}",
+    "{",
+    "}",
+    "{\"a\":1",
+    "[{]",
+    "[{}]",
+    "{\"a\":[]}",
+];
+
+#[test]
+fn should_agree_with_reference_on_corpus() {
+    let failures: Vec<String> = CORPUS.iter().filter_map(|json| check(json).err()).collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn should_minimize_failing_input() {
+    let failing = |json: &str| json.contains('x') && json.contains("\\u");
+    assert_eq!("x\\u", minimize("[1, 2, {\"x\": \"\\u0041\"}, 3]", failing));
+}
+
+fn arb_number() -> impl Strategy<Value = String> {
+    return prop_oneof![
+        any::<f64>().prop_map(|n| format!("{n}")),
+        any::<f64>().prop_map(|n| format!("{n:e}")),
+        any::<f64>().prop_map(|n| format!("{n:E}")),
+        any::<i64>().prop_map(|n| n.to_string()),
+        "-?(0|[1-9][0-9]{0,30})(\\.[0-9]{1,30})?([eE][+-]?[0-9]{1,4})?",
+        "[-+0-9.eE]{1,12}",
+    ];
+}
+
+fn arb_string() -> impl Strategy<Value = String> {
+    return prop_oneof![
+        any::<String>().prop_map(|s| Serializer::serialize(&JsonElement::Str(s))),
+        "\"([a-z ]|\\\\[\"\\\\/bfnrtux]|\\\\u[0-9a-fA-F]{4}|\\\\u[dD][89abAB][0-9a-fA-F]{2})*\"",
+    ];
+}
+
+fn arb_whitespace() -> impl Strategy<Value = String> {
+    return "[ \t\n\r]{0,3}";
+}
+
+fn arb_document() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        arb_number(),
+        arb_string(),
+        Just("true".to_string()),
+        Just("false".to_string()),
+        Just("null".to_string()),
+    ];
+    return leaf.prop_recursive(6, 64, 6, |inner| {
+        let member = (arb_whitespace(), inner, arb_whitespace())
+            .prop_map(|(before, value, after)| format!("{before}{value}{after}"))
+            .boxed();
+        prop_oneof![
+            prop::collection::vec(member.clone(), 0..6)
+                .prop_map(|members| format!("[{}]", members.join(","))),
+            prop::collection::vec((arb_string(), member), 0..6).prop_map(|members| {
+                let members: Vec<String> = members
+                    .iter()
+                    .map(|(key, value)| format!("{key}:{value}"))
+                    .collect();
+                format!("{{{}}}", members.join(","))
+            }),
+        ]
+    });
+}
+
+/// Drops, duplicates or replaces a single character to produce near-valid input.
+fn mutate(json: &str, position: usize, operation: u8, replacement: char) -> String {
+    let mut chars: Vec<char> = json.chars().collect();
+    if chars.is_empty() {
+        return replacement.to_string();
+    }
+    let position = position % chars.len();
+    match operation % 3 {
+        0 => {
+            chars.remove(position);
+        }
+        1 => chars.insert(position, chars[position]),
+        _ => chars[position] = replacement,
+    }
+    return chars.into_iter().collect();
+}
+
+proptest! {
+    #[test]
+    fn should_agree_with_reference_on_generated_documents(
+        document in arb_document(),
+        before in arb_whitespace(),
+        after in arb_whitespace(),
+    ) {
+        let json = format!("{before}{document}{after}");
+        if let Err(e) = check(&json) {
+            prop_assert!(false, "{}", e);
+        }
+    }
+
+    #[test]
+    fn should_agree_with_reference_on_mutated_documents(
+        document in arb_document(),
+        position in any::<usize>(),
+        operation in any::<u8>(),
+        replacement in prop::sample::select(vec!['[', ']', '{', '}', '"', ':', ',', '\\', '-', '0', 'e', '.', ' ', 'a']),
+    ) {
+        let json = mutate(&document, position, operation, replacement);
+        if let Err(e) = check(&json) {
+            prop_assert!(false, "{}", e);
+        }
+    }
+}
@@ -0,0 +1,679 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! A json document stored in a few flat vectors instead of a tree of boxes.
+//!
+//! Every value is a node in one vector and refers to its children by index,
+//! and every string and key lives in one shared buffer. Parsing a document
+//! takes a handful of allocations however many values it holds, reading it
+//! walks contiguous memory, and dropping it frees everything at once.
+//!
+//! Unlike `JsonElement`, objects keep their members in document order,
+//! repeated keys included; looking a key up finds its last occurrence, which
+//! is the one `Parser::parse` keeps.
+
+use crate::json_element::{map_with_capacity, JsonElement, Map, MapIter};
+use crate::parser::{ParseError, Parser, Sink};
+use crate::pointer::{array_index, JsonPointer};
+use crate::serializer::{Contents, Serializable, Serializer, SerializerOptions};
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::slice;
+
+/// Names a node of the document it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Null,
+    Boolean,
+    Number,
+    Str,
+    Array,
+    Object,
+}
+
+/// A range of `Document::text`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TextRange {
+    start: usize,
+    end: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Entry {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    Str(TextRange),
+    /// The elements are `elements[start..start + len]`.
+    Array {
+        start: usize,
+        len: usize,
+    },
+    /// The members are `members[start..start + len]`.
+    Object {
+        start: usize,
+        len: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    nodes: Vec<Entry>,
+    text: String,
+    elements: Vec<NodeId>,
+    members: Vec<(TextRange, NodeId)>,
+    root: NodeId,
+}
+
+/// Iterates over the elements of an array, see `Node::elements`.
+pub struct Elements<'a> {
+    document: &'a Document,
+    ids: slice::Iter<'a, NodeId>,
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        return self.ids.next().map(|id| self.document.node(*id));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return self.ids.size_hint();
+    }
+}
+
+impl DoubleEndedIterator for Elements<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        return self.ids.next_back().map(|id| self.document.node(*id));
+    }
+}
+
+/// A node together with the document it lives in. It is a pair of a
+/// reference and an index, cheap to copy.
+#[derive(Debug, Clone, Copy)]
+pub struct Node<'a> {
+    document: &'a Document,
+    id: NodeId,
+}
+
+impl Document {
+    pub fn parse(json: &str) -> Result<Document, ParseError> {
+        return Parser::parse_document(json);
+    }
+
+    pub fn root(&self) -> Node<'_> {
+        return self.node(self.root);
+    }
+
+    /// The node `id` names. Panics when `id` comes from another document.
+    pub fn node(&self, id: NodeId) -> Node<'_> {
+        assert!(
+            id.0 < self.nodes.len(),
+            "{id:?} is not a node of this document"
+        );
+        return Node { document: self, id };
+    }
+
+    /// The number of values in the document, nested ones included.
+    pub fn node_count(&self) -> usize {
+        return self.nodes.len();
+    }
+
+    /// Copies `element` into a new document. Object members come in map order.
+    pub fn from_element(element: &JsonElement) -> Document {
+        enum Pending<'a> {
+            Array(slice::Iter<'a, JsonElement>, usize),
+            Object(MapIter<'a, String, JsonElement>, usize, TextRange),
+        }
+
+        let mut builder = Builder::default();
+        let mut stack: Vec<Pending> = Vec::new();
+        let mut next = Some(element);
+        loop {
+            let mut finished = match next.take() {
+                Some(JsonElement::Array(array)) => {
+                    stack.push(Pending::Array(array.iter(), builder.elements_mark()));
+                    None
+                }
+                Some(JsonElement::Object(object)) => {
+                    stack.push(Pending::Object(
+                        object.iter(),
+                        builder.members_mark(),
+                        TextRange::default(),
+                    ));
+                    None
+                }
+                Some(scalar) => Some(builder.scalar(scalar)),
+                None => None,
+            };
+            // Hand the finished node to its parent and move on to the next
+            // member, closing every container that has none left.
+            loop {
+                let frame = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => return builder.finish(finished.unwrap()),
+                };
+                match frame {
+                    Pending::Array(elements, start) => {
+                        if let Some(id) = finished.take() {
+                            builder.push_element(id);
+                        }
+                        if let Some(value) = elements.next() {
+                            next = Some(value);
+                            break;
+                        }
+                        finished = Some(builder.finish_array(*start));
+                    }
+                    Pending::Object(members, start, key) => {
+                        if let Some(id) = finished.take() {
+                            builder.push_member(*key, id);
+                        }
+                        if let Some((name, value)) = members.next() {
+                            *key = builder.key(name);
+                            next = Some(value);
+                            break;
+                        }
+                        finished = Some(builder.finish_object(*start));
+                    }
+                }
+                stack.pop();
+            }
+        }
+    }
+
+    pub fn to_element(&self) -> JsonElement {
+        return self.root().to_element();
+    }
+
+    /// Serializes the document without any whitespace, members in document order.
+    pub fn serialize(&self) -> String {
+        return self.serialize_with_options(&SerializerOptions::default());
+    }
+
+    /// Serializes the document like `Serializer::serialize_with_options` does
+    /// an element.
+    pub fn serialize_with_options(&self, options: &SerializerOptions) -> String {
+        return Serializer::write(self.root(), options);
+    }
+
+    fn str(&self, range: TextRange) -> &str {
+        return &self.text[range.start..range.end];
+    }
+}
+
+impl<'a> Node<'a> {
+    pub fn id(&self) -> NodeId {
+        return self.id;
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        return match self.entry() {
+            Entry::Null => NodeKind::Null,
+            Entry::Boolean(_) => NodeKind::Boolean,
+            Entry::Number(_) => NodeKind::Number,
+            Entry::Str(_) => NodeKind::Str,
+            Entry::Array { .. } => NodeKind::Array,
+            Entry::Object { .. } => NodeKind::Object,
+        };
+    }
+
+    pub fn is_null(&self) -> bool {
+        return matches!(self.entry(), Entry::Null);
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        return match self.entry() {
+            Entry::Boolean(b) => Some(b),
+            _ => None,
+        };
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        return match self.entry() {
+            Entry::Number(n) => Some(n),
+            _ => None,
+        };
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        return match self.entry() {
+            Entry::Str(range) => Some(self.document.str(range)),
+            _ => None,
+        };
+    }
+
+    /// The number of elements of an array or members of an object, and 0 for
+    /// any other value.
+    pub fn len(&self) -> usize {
+        return match self.entry() {
+            Entry::Array { len, .. } | Entry::Object { len, .. } => len,
+            _ => 0,
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// The element at `index` of an array.
+    pub fn at(&self, index: usize) -> Option<Node<'a>> {
+        return self.elements().nth(index);
+    }
+
+    /// The value of the last member named `key` of an object. Members are not
+    /// indexed by key, so this looks through them all, from the last one; for
+    /// many lookups in a large object, go through `members` once instead.
+    pub fn get(&self, key: &str) -> Option<Node<'a>> {
+        return self
+            .members()
+            .rev()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value);
+    }
+
+    /// The elements of an array; nothing for any other value.
+    pub fn elements(&self) -> Elements<'a> {
+        let document = self.document;
+        let ids: &'a [NodeId] = match self.entry() {
+            Entry::Array { start, len } => &document.elements[start..start + len],
+            _ => &[],
+        };
+        return Elements {
+            document,
+            ids: ids.iter(),
+        };
+    }
+
+    /// The members of an object in document order; nothing for any other value.
+    pub fn members(&self) -> impl DoubleEndedIterator<Item = (&'a str, Node<'a>)> + 'a {
+        let document = self.document;
+        let members: &'a [(TextRange, NodeId)] = match self.entry() {
+            Entry::Object { start, len } => &document.members[start..start + len],
+            _ => &[],
+        };
+        return members
+            .iter()
+            .map(move |(key, id)| (document.str(*key), document.node(*id)));
+    }
+
+    /// The node `pointer` names below this one, resolved like
+    /// `JsonPointer::resolve` does.
+    pub fn pointer(&self, pointer: &JsonPointer) -> Option<Node<'a>> {
+        let mut current = *self;
+        for token in pointer.tokens() {
+            current = match current.kind() {
+                NodeKind::Object => current.get(token)?,
+                NodeKind::Array => current.at(array_index(token)?)?,
+                _ => return None,
+            };
+        }
+        return Some(current);
+    }
+
+    /// Copies this node and everything below it into a `JsonElement`.
+    pub fn to_element(&self) -> JsonElement {
+        enum Frame<'a> {
+            Array(Vec<JsonElement>, slice::Iter<'a, NodeId>),
+            Object(
+                Map<String, JsonElement>,
+                slice::Iter<'a, (TextRange, NodeId)>,
+                &'a str,
+            ),
+        }
+
+        let document = self.document;
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut next = Some(self.id);
+        loop {
+            let mut value = match next.take().map(|id| document.nodes[id.0]) {
+                Some(Entry::Array { start, len }) => {
+                    let elements = &document.elements[start..start + len];
+                    stack.push(Frame::Array(Vec::with_capacity(len), elements.iter()));
+                    None
+                }
+                Some(Entry::Object { start, len }) => {
+                    let members = &document.members[start..start + len];
+                    stack.push(Frame::Object(map_with_capacity(len), members.iter(), ""));
+                    None
+                }
+                Some(Entry::Null) => Some(JsonElement::Null),
+                Some(Entry::Boolean(b)) => Some(JsonElement::Boolean(b)),
+                Some(Entry::Number(n)) => Some(JsonElement::Number(n)),
+                Some(Entry::Str(range)) => Some(JsonElement::Str(document.str(range).to_string())),
+                None => None,
+            };
+            loop {
+                let frame = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => return value.unwrap_or_default(),
+                };
+                match frame {
+                    Frame::Array(array, elements) => {
+                        if let Some(value) = value.take() {
+                            array.push(value);
+                        }
+                        if let Some(id) = elements.next() {
+                            next = Some(*id);
+                            break;
+                        }
+                        value = Some(JsonElement::Array(core::mem::take(array)));
+                    }
+                    Frame::Object(object, members, key) => {
+                        if let Some(value) = value.take() {
+                            object.insert(key.to_string(), value);
+                        }
+                        if let Some((name, id)) = members.next() {
+                            *key = document.str(*name);
+                            next = Some(*id);
+                            break;
+                        }
+                        value = Some(JsonElement::Object(core::mem::take(object)));
+                    }
+                }
+                stack.pop();
+            }
+        }
+    }
+
+    fn entry(&self) -> Entry {
+        return self.document.nodes[self.id.0];
+    }
+}
+
+impl<'a> Serializable<'a> for Node<'a> {
+    type Elements = Elements<'a>;
+
+    fn contents(self) -> Contents<'a, Self, Elements<'a>> {
+        return match self.entry() {
+            Entry::Null => Contents::Null,
+            Entry::Boolean(b) => Contents::Boolean(b),
+            Entry::Number(n) => Contents::Number(n),
+            Entry::Str(range) => Contents::Str(self.document.str(range)),
+            Entry::Array { .. } => Contents::Array(self.elements()),
+            Entry::Object { .. } => Contents::Object(self.members().collect()),
+        };
+    }
+}
+
+/// Builds a document bottom up. Nodes are added once their value is complete,
+/// so the children of a container are only known when it closes; until then
+/// they wait, in order, at the end of `open_elements` or `open_members`.
+#[derive(Default)]
+pub(crate) struct Builder {
+    nodes: Vec<Entry>,
+    text: String,
+    elements: Vec<NodeId>,
+    members: Vec<(TextRange, NodeId)>,
+    open_elements: Vec<NodeId>,
+    open_members: Vec<(TextRange, NodeId)>,
+}
+
+impl Builder {
+    fn add(&mut self, entry: Entry) -> NodeId {
+        self.nodes.push(entry);
+        return NodeId(self.nodes.len() - 1);
+    }
+
+    fn key(&mut self, key: &str) -> TextRange {
+        let start = self.text_mark();
+        self.text.push_str(key);
+        return self.key_from(start);
+    }
+
+    pub(crate) fn finish(self, root: NodeId) -> Document {
+        return Document {
+            nodes: self.nodes,
+            text: self.text,
+            elements: self.elements,
+            members: self.members,
+            root,
+        };
+    }
+}
+
+impl Sink for Builder {
+    type Node = NodeId;
+    type Key = TextRange;
+
+    fn text(&mut self) -> &mut String {
+        return &mut self.text;
+    }
+
+    fn text_mark(&self) -> usize {
+        return self.text.len();
+    }
+
+    fn key_from(&mut self, start: usize) -> TextRange {
+        return TextRange {
+            start,
+            end: self.text.len(),
+        };
+    }
+
+    fn string_from(&mut self, start: usize) -> NodeId {
+        let range = self.key_from(start);
+        return self.add(Entry::Str(range));
+    }
+
+    fn scalar(&mut self, element: &JsonElement) -> NodeId {
+        let entry = match element {
+            JsonElement::Boolean(b) => Entry::Boolean(*b),
+            JsonElement::Number(n) => Entry::Number(*n),
+            JsonElement::Str(s) => Entry::Str(self.key(s)),
+            _ => Entry::Null,
+        };
+        return self.add(entry);
+    }
+
+    fn elements_mark(&self) -> usize {
+        return self.open_elements.len();
+    }
+
+    fn members_mark(&self) -> usize {
+        return self.open_members.len();
+    }
+
+    fn push_element(&mut self, id: NodeId) -> usize {
+        self.open_elements.push(id);
+        return self.open_elements.len();
+    }
+
+    fn push_member(&mut self, key: TextRange, id: NodeId) -> usize {
+        self.open_members.push((key, id));
+        return self.open_members.len();
+    }
+
+    fn finish_array(&mut self, mark: usize) -> NodeId {
+        let start = self.elements.len();
+        self.elements.extend(self.open_elements.drain(mark..));
+        let len = self.elements.len() - start;
+        return self.add(Entry::Array { start, len });
+    }
+
+    fn finish_object(&mut self, mark: usize) -> NodeId {
+        let start = self.members.len();
+        self.members.extend(self.open_members.drain(mark..));
+        let len = self.members.len() - start;
+        return self.add(Entry::Object { start, len });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Document, NodeKind};
+    use crate::json_element::JsonElement;
+    use crate::parser::{Parser, ParserOptions};
+    use crate::pointer::JsonPointer;
+    use crate::serializer::{Serializer, SerializerOptions};
+    use crate::test_support::near_json;
+    use proptest::prelude::*;
+
+    #[test]
+    fn should_navigate_parsed_documents() {
+        let document = Document::parse(
+            r#" {"name": "jsonp", "tags": ["a", "b\n"], "n": -1.5e3, "ok": true, "none": null} "#,
+        )
+        .ok()
+        .unwrap();
+        let root = document.root();
+        assert_eq!(NodeKind::Object, root.kind());
+        assert_eq!(5, root.len());
+        assert_eq!(8, document.node_count());
+        assert_eq!(Some("jsonp"), root.get("name").and_then(|n| n.as_str()));
+        assert_eq!(Some(-1500.0), root.get("n").and_then(|n| n.as_f64()));
+        assert_eq!(Some(true), root.get("ok").and_then(|n| n.as_bool()));
+        assert!(root.get("none").unwrap().is_null());
+        assert!(root.get("missing").is_none());
+
+        let tags = root.get("tags").unwrap();
+        let tags: Vec<&str> = tags.elements().map(|tag| tag.as_str().unwrap()).collect();
+        assert_eq!(vec!["a", "b\n"], tags);
+        let keys: Vec<&str> = root.members().map(|(key, _)| key).collect();
+        assert_eq!(vec!["name", "tags", "n", "ok", "none"], keys);
+
+        let pointer = JsonPointer::parse("/tags/1").ok().unwrap();
+        let tag = root.pointer(&pointer).unwrap();
+        assert_eq!(Some("b\n"), tag.as_str());
+        assert_eq!(Some("b\n"), document.node(tag.id()).as_str());
+        assert!(root
+            .pointer(&JsonPointer::parse("/tags/01").ok().unwrap())
+            .is_none());
+        assert!(root.get("name").unwrap().at(0).is_none());
+        assert!(root.get("name").unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_convert_to_and_from_elements() {
+        let json = r#"[{"a": [1, {"b": []}, "x"], "c": {}}, 2, "\u00e9", null, [[]]]"#;
+        let element = Parser::parse(json).ok().unwrap();
+        let document = Document::parse(json).ok().unwrap();
+        assert_eq!(element, document.to_element());
+        assert_eq!(element, Document::from_element(&element).to_element());
+
+        let pretty = SerializerOptions::pretty("  ");
+        assert_eq!(
+            Serializer::serialize_with_options(&element, &pretty),
+            document.serialize_with_options(&pretty)
+        );
+        assert_eq!(
+            r#"[{"a":[1,{"b":[]},"x"],"c":{}},2,"é",null,[[]]]"#,
+            document.serialize()
+        );
+        assert_eq!(
+            Some(JsonElement::Number(1.0)),
+            document
+                .root()
+                .pointer(&JsonPointer::parse("/0/a/0").ok().unwrap())
+                .map(|node| node.to_element())
+        );
+    }
+
+    #[test]
+    fn should_keep_repeated_keys_in_order() {
+        let document = Document::parse(r#"{"a": 1, "b": 2, "a": 3}"#).ok().unwrap();
+        assert_eq!(Some(3.0), document.root().get("a").and_then(|n| n.as_f64()));
+        assert_eq!(r#"{"a":1,"b":2,"a":3}"#, document.serialize());
+        assert_eq!(
+            Parser::parse(r#"{"a": 3, "b": 2}"#).ok(),
+            Some(document.to_element())
+        );
+    }
+
+    #[test]
+    fn should_report_errors_like_parse() {
+        for json in [
+            "",
+            "[1,]",
+            "{\"a\" 1}",
+            "[1] 2",
+            "\"\\x\"",
+            "[1, {\"a\": tru}]",
+        ] {
+            assert_eq!(
+                Parser::parse(json).err(),
+                Document::parse(json).err(),
+                "{json}"
+            );
+        }
+        let options = ParserOptions {
+            max_depth: 2,
+            max_array_len: 2,
+            max_object_members: 1,
+            max_string_len: 3,
+            ..Default::default()
+        };
+        for json in [
+            "[[[]]]",
+            "[1, 2, 3]",
+            "[[1, 2], [3]]",
+            "{\"a\": 1, \"b\": 2}",
+            "[\"abcd\"]",
+            "{\"abcd\": 1}",
+        ] {
+            assert_eq!(
+                Parser::parse_with_options(json, &options).err(),
+                Parser::parse_document_with_options(json, &options).err(),
+                "{json}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_handle_deep_nesting_without_recursion() {
+        let depth = 100_000;
+        let mut element = JsonElement::Null;
+        for _ in 0..depth {
+            element = JsonElement::Array(vec![element]);
+        }
+        let document = Document::from_element(&element);
+        assert_eq!(depth + 1, document.node_count());
+        let json = document.serialize();
+        assert_eq!(Serializer::serialize(&element), json);
+        let options = ParserOptions {
+            max_depth: depth,
+            ..Default::default()
+        };
+        let parsed = Parser::parse_document_with_options(&json, &options)
+            .ok()
+            .unwrap();
+        assert_eq!(json, parsed.serialize());
+        let mut back = parsed.to_element();
+        for _ in 0..depth {
+            back = match &mut back {
+                JsonElement::Array(array) => core::mem::take(&mut array[0]),
+                _ => panic!("expected an array"),
+            };
+        }
+        assert_eq!(JsonElement::Null, back);
+    }
+
+    proptest! {
+        #[test]
+        fn should_parse_like_parse(json in near_json()) {
+            match (Parser::parse(&json), Document::parse(&json)) {
+                (Ok(element), Ok(document)) => {
+                    prop_assert_eq!(&element, &document.to_element());
+                    prop_assert_eq!(Some(element), Parser::parse(&document.serialize()).ok());
+                }
+                (element, document) => prop_assert_eq!(element.err(), document.err()),
+            }
+        }
+    }
+}
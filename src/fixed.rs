@@ -0,0 +1,679 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Parsing without an allocator, in the style of jsmn.
+//!
+//! `parse_into` checks a document and describes each of its values in a slice
+//! of `TokenRecord`s provided by the caller, in document order. Nothing is
+//! copied or decoded: a record holds the kind, the span of the value in the
+//! input and how many children it has. Strings are unescaped into a caller
+//! buffer with `TokenRecord::read_str`, and numbers read with `as_f64`.
+//!
+//! The tokens of an object are its keys, each followed by the tokens of its
+//! value, so a key has one child and the object has one child per member.
+//! Nothing in this module uses the heap.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenKind {
+    #[default]
+    Null,
+    Boolean,
+    Number,
+    Str,
+    /// An object key. Its value is the next token.
+    Key,
+    Array,
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TokenRecord {
+    pub kind: TokenKind,
+    /// Byte offset of the first character, a quote or bracket included.
+    pub start: usize,
+    /// Byte offset just after the last character.
+    pub end: usize,
+    /// Elements of an array, members of an object, one for a key and none
+    /// for the other kinds.
+    pub children: usize,
+    /// The array, object or key this value is in.
+    pub parent: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixedErrorKind {
+    /// The input is not valid json.
+    Invalid,
+    /// The input ends inside a value; more of it may complete the document.
+    Partial,
+    /// The token slice, or the buffer given to `read_str`, is too small.
+    NoMemory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedError {
+    pub kind: FixedErrorKind,
+    pub details: &'static str,
+    /// Byte offset in the input where the error was found.
+    pub offset: usize,
+}
+
+impl FixedError {
+    pub fn new(kind: FixedErrorKind, details: &'static str, offset: usize) -> FixedError {
+        return FixedError {
+            kind,
+            details,
+            offset,
+        };
+    }
+}
+
+impl fmt::Display for FixedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.details, self.offset)
+    }
+}
+
+impl core::error::Error for FixedError {}
+
+type FixedResult<T> = Result<T, FixedError>;
+
+fn invalid(details: &'static str, offset: usize) -> FixedError {
+    return FixedError::new(FixedErrorKind::Invalid, details, offset);
+}
+
+fn partial(offset: usize) -> FixedError {
+    return FixedError::new(FixedErrorKind::Partial, "Json ended inside a value", offset);
+}
+
+/// What may come next, after the input read so far.
+#[derive(Clone, Copy, PartialEq)]
+enum Expect {
+    Value,
+    /// An element or the `]` of an empty array.
+    FirstElement,
+    Key,
+    /// A key or the `}` of an empty object.
+    FirstKey,
+    Colon,
+    /// A `,` or the closing bracket of the innermost array or object.
+    Comma,
+}
+
+/// Fills `tokens` with the values of `json`, in document order, and returns
+/// how many were used. Accepts what `Parser::parse` accepts, without its
+/// limits.
+pub fn parse_into(json: &str, tokens: &mut [TokenRecord]) -> FixedResult<usize> {
+    let bytes = json.as_bytes();
+    let mut count = 0;
+    let mut expect = Expect::Value;
+    // The innermost array or object that is still open.
+    let mut open: Option<usize> = None;
+    let mut i = 0;
+    loop {
+        while i < bytes.len() && matches!(bytes[i], b' ' | b'\n' | b'\r' | b'\t') {
+            i += 1;
+        }
+        if i == bytes.len() {
+            if open.is_some() || expect != Expect::Comma {
+                return Err(partial(i));
+            }
+            return Ok(count);
+        }
+        let byte = bytes[i];
+        match expect {
+            Expect::Comma => {
+                let object = match open {
+                    Some(index) => tokens[index].kind == TokenKind::Object,
+                    None => return Err(invalid("Unexpected characters after the json value", i)),
+                };
+                match (byte, object) {
+                    (b',', false) => expect = Expect::Value,
+                    (b',', true) => expect = Expect::Key,
+                    (b']', false) | (b'}', true) => {
+                        let index = open.unwrap();
+                        tokens[index].end = i + 1;
+                        open = container(tokens, tokens[index].parent);
+                    }
+                    (_, false) => return Err(invalid("Expecting a ',' or ']'", i)),
+                    (_, true) => return Err(invalid("Expecting a ',' or '}'", i)),
+                }
+                i += 1;
+            }
+            Expect::FirstElement | Expect::FirstKey
+                if byte == b']' && expect == Expect::FirstElement
+                    || byte == b'}' && expect == Expect::FirstKey =>
+            {
+                let index = open.unwrap();
+                tokens[index].end = i + 1;
+                open = container(tokens, tokens[index].parent);
+                expect = Expect::Comma;
+                i += 1;
+            }
+            Expect::Key | Expect::FirstKey => {
+                if byte != b'"' {
+                    return Err(invalid("Expected a string as object key", i));
+                }
+                let end = string_end(bytes, i)?;
+                let key = push(tokens, &mut count, TokenKind::Key, i, end, open)?;
+                tokens[key].children = 1;
+                tokens[open.unwrap()].children += 1;
+                expect = Expect::Colon;
+                i = end;
+            }
+            Expect::Colon => {
+                if byte != b':' {
+                    return Err(invalid("Expected a colon", i));
+                }
+                expect = Expect::Value;
+                i += 1;
+            }
+            Expect::Value | Expect::FirstElement => {
+                // Inside an object the value belongs to the key just read.
+                let parent = match open {
+                    Some(index) if tokens[index].kind == TokenKind::Object => Some(count - 1),
+                    _ => open,
+                };
+                let (kind, end) = match byte {
+                    b'{' => (TokenKind::Object, i + 1),
+                    b'[' => (TokenKind::Array, i + 1),
+                    b'"' => (TokenKind::Str, string_end(bytes, i)?),
+                    b'-' | b'0'..=b'9' => (TokenKind::Number, number_end(bytes, i)?),
+                    b't' => (TokenKind::Boolean, literal_end(bytes, i, b"true")?),
+                    b'f' => (TokenKind::Boolean, literal_end(bytes, i, b"false")?),
+                    b'n' => (TokenKind::Null, literal_end(bytes, i, b"null")?),
+                    b'}' | b']' | b':' | b',' => return Err(invalid("Invalid json value", i)),
+                    _ => return Err(invalid("Expected true, false or null", i)),
+                };
+                let index = push(tokens, &mut count, kind, i, end, parent)?;
+                if let Some(array) = open.filter(|_| parent == open) {
+                    tokens[array].children += 1;
+                }
+                match kind {
+                    TokenKind::Object => {
+                        open = Some(index);
+                        expect = Expect::FirstKey;
+                    }
+                    TokenKind::Array => {
+                        open = Some(index);
+                        expect = Expect::FirstElement;
+                    }
+                    _ => expect = Expect::Comma,
+                }
+                i = end;
+            }
+        }
+    }
+}
+
+/// The array or object holding a value whose parent is `parent`.
+fn container(tokens: &[TokenRecord], parent: Option<usize>) -> Option<usize> {
+    return match parent {
+        Some(key) if tokens[key].kind == TokenKind::Key => tokens[key].parent,
+        _ => parent,
+    };
+}
+
+fn push(
+    tokens: &mut [TokenRecord],
+    count: &mut usize,
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+    parent: Option<usize>,
+) -> FixedResult<usize> {
+    let index = *count;
+    let token = match tokens.get_mut(index) {
+        Some(token) => token,
+        None => {
+            return Err(FixedError::new(
+                FixedErrorKind::NoMemory,
+                "Not enough tokens",
+                start,
+            ))
+        }
+    };
+    *token = TokenRecord {
+        kind,
+        start,
+        end,
+        children: 0,
+        parent,
+    };
+    *count += 1;
+    return Ok(index);
+}
+
+/// Where the string starting with the quote at `start` ends, after checking
+/// its escapes.
+fn string_end(bytes: &[u8], start: usize) -> FixedResult<usize> {
+    let mut i = start + 1;
+    loop {
+        match bytes.get(i) {
+            None => return Err(partial(i)),
+            Some(b'"') => return Ok(i + 1),
+            Some(b'\\') => {
+                i = escape_end(bytes, i)?;
+            }
+            Some(byte) if *byte < b' ' => {
+                return Err(invalid("Unescaped control character in string", i))
+            }
+            Some(_) => i += 1,
+        }
+    }
+}
+
+/// Where the escape starting with the backslash at `start` ends.
+fn escape_end(bytes: &[u8], start: usize) -> FixedResult<usize> {
+    match bytes.get(start + 1) {
+        None => return Err(partial(start + 1)),
+        Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => return Ok(start + 2),
+        Some(b'u') => {}
+        Some(_) => return Err(invalid("Unknown escaped character", start + 1)),
+    }
+    let high = hex_escape(bytes, start)?;
+    if (0xDC00..0xE000).contains(&high) {
+        return Err(invalid("Invalid unicode character", start));
+    }
+    if !(0xD800..0xDC00).contains(&high) {
+        return Ok(start + 6);
+    }
+    let low_start = start + 6;
+    match (bytes.get(low_start), bytes.get(low_start + 1)) {
+        (None, _) | (Some(b'\\'), None) => return Err(partial(low_start)),
+        (Some(b'\\'), Some(b'u')) => {}
+        _ => return Err(invalid("Expected a low surrogate", low_start)),
+    }
+    let low = hex_escape(bytes, low_start)?;
+    if !(0xDC00..0xE000).contains(&low) {
+        return Err(invalid("Expected a low surrogate", low_start));
+    }
+    return Ok(low_start + 6);
+}
+
+/// The value of the four hex digits of the `\u` escape at `start`.
+fn hex_escape(bytes: &[u8], start: usize) -> FixedResult<u32> {
+    let mut value = 0;
+    for i in start + 2..start + 6 {
+        let digit = match bytes.get(i) {
+            None => return Err(partial(i)),
+            Some(byte) => (*byte as char).to_digit(16),
+        };
+        value = value << 4 | digit.ok_or(invalid("Expected a hex digit after a \\u", i))?;
+    }
+    return Ok(value);
+}
+
+fn number_end(bytes: &[u8], start: usize) -> FixedResult<usize> {
+    let digits = |mut i: usize| {
+        while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+            i += 1;
+        }
+        return i;
+    };
+    let mut i = start;
+    if bytes[i] == b'-' {
+        i += 1;
+    }
+    match bytes.get(i) {
+        Some(b'0') => {
+            i += 1;
+            if bytes.get(i).is_some_and(u8::is_ascii_digit) {
+                return Err(invalid("Expected a '.' or 'e' or 'E'", i));
+            }
+        }
+        Some(b'1'..=b'9') => i = digits(i),
+        None => return Err(partial(i)),
+        Some(_) => return Err(invalid("Expected a number after a '-'", i)),
+    }
+    if bytes.get(i) == Some(&b'.') {
+        let end = digits(i + 1);
+        if end == i + 1 {
+            return Err(invalid("Expected a digit", end));
+        }
+        i = end;
+    }
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        i += 1;
+        if matches!(bytes.get(i), Some(b'+' | b'-')) {
+            i += 1;
+        }
+        let end = digits(i);
+        if end == i {
+            return Err(invalid("Expected a digit", end));
+        }
+        i = end;
+    }
+    // The number is ascii, and parsing it into an `f64` does not allocate.
+    let text = core::str::from_utf8(&bytes[start..i]).unwrap_or_default();
+    if !text.parse::<f64>().is_ok_and(f64::is_finite) {
+        return Err(invalid("Number is out of range", start));
+    }
+    return Ok(i);
+}
+
+fn literal_end(bytes: &[u8], start: usize, literal: &[u8]) -> FixedResult<usize> {
+    for (i, expected) in literal.iter().enumerate() {
+        match bytes.get(start + i) {
+            None => return Err(partial(start + i)),
+            Some(byte) if byte != expected => {
+                return Err(invalid("Expected true, false or null", start + i))
+            }
+            Some(_) => {}
+        }
+    }
+    return Ok(start + literal.len());
+}
+
+/// The characters of a string checked by `string_end`, between its quotes,
+/// with escapes decoded.
+struct Unescaped<'a> {
+    chars: core::str::Chars<'a>,
+}
+
+impl Unescaped<'_> {
+    fn hex(&mut self) -> u32 {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit = self.chars.next().and_then(|c| c.to_digit(16));
+            value = value << 4 | digit.unwrap_or(0);
+        }
+        return value;
+    }
+}
+
+impl Iterator for Unescaped<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c != '\\' {
+            return Some(c);
+        }
+        let c = match self.chars.next()? {
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let mut value = self.hex();
+                if (0xD800..0xDC00).contains(&value) {
+                    self.chars.nth(1);
+                    value = 0x10000 + ((value - 0xD800) << 10) + (self.hex() - 0xDC00);
+                }
+                char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER)
+            }
+            other => other,
+        };
+        return Some(c);
+    }
+}
+
+impl TokenRecord {
+    /// The text of this value in `json`, quotes and brackets included.
+    pub fn text<'a>(&self, json: &'a str) -> &'a str {
+        return &json[self.start..self.end];
+    }
+
+    pub fn as_bool(&self, json: &str) -> Option<bool> {
+        if self.kind != TokenKind::Boolean {
+            return None;
+        }
+        return Some(self.text(json) == "true");
+    }
+
+    pub fn as_f64(&self, json: &str) -> Option<f64> {
+        if self.kind != TokenKind::Number {
+            return None;
+        }
+        return self.text(json).parse().ok().filter(|n: &f64| n.is_finite());
+    }
+
+    fn unescaped<'a>(&self, json: &'a str) -> Unescaped<'a> {
+        return Unescaped {
+            chars: json[self.start + 1..self.end - 1].chars(),
+        };
+    }
+
+    /// Unescapes a string or key into `buffer` and returns the part of it that
+    /// was written. Fails with `NoMemory` when the buffer is too small, and
+    /// with `Invalid` for the other kinds.
+    pub fn read_str<'b>(&self, json: &str, buffer: &'b mut [u8]) -> FixedResult<&'b str> {
+        if !matches!(self.kind, TokenKind::Str | TokenKind::Key) {
+            return Err(invalid("Not a string", self.start));
+        }
+        let mut len = 0;
+        for c in self.unescaped(json) {
+            let end = len + c.len_utf8();
+            if end > buffer.len() {
+                return Err(FixedError::new(
+                    FixedErrorKind::NoMemory,
+                    "String does not fit in the buffer",
+                    self.start,
+                ));
+            }
+            c.encode_utf8(&mut buffer[len..end]);
+            len = end;
+        }
+        return Ok(core::str::from_utf8(&buffer[..len]).unwrap());
+    }
+
+    /// Whether this string or key, unescaped, is `text`.
+    pub fn str_eq(&self, json: &str, text: &str) -> bool {
+        return matches!(self.kind, TokenKind::Str | TokenKind::Key)
+            && self.unescaped(json).eq(text.chars());
+    }
+}
+
+/// The index just after the tokens of the value at `index` and everything in
+/// it, which is where its next sibling starts.
+pub fn skip(tokens: &[TokenRecord], index: usize) -> usize {
+    let mut next = index;
+    let mut remaining = 1;
+    while remaining > 0 {
+        remaining += tokens[next].children;
+        remaining -= 1;
+        next += 1;
+    }
+    return next;
+}
+
+/// The index of the value of the last member named `key` in the object at
+/// `object`, like `JsonElement` keeps the last of repeated keys.
+pub fn get(json: &str, tokens: &[TokenRecord], object: usize, key: &str) -> Option<usize> {
+    if tokens[object].kind != TokenKind::Object {
+        return None;
+    }
+    let mut found = None;
+    let mut next = object + 1;
+    for _ in 0..tokens[object].children {
+        if tokens[next].str_eq(json, key) {
+            found = Some(next + 1);
+        }
+        next = skip(tokens, next);
+    }
+    return found;
+}
+
+/// The index of element `position` of the array at `array`.
+pub fn at(tokens: &[TokenRecord], array: usize, position: usize) -> Option<usize> {
+    if tokens[array].kind != TokenKind::Array || position >= tokens[array].children {
+        return None;
+    }
+    let mut next = array + 1;
+    for _ in 0..position {
+        next = skip(tokens, next);
+    }
+    return Some(next);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{at, get, parse_into, skip, FixedErrorKind, TokenKind, TokenRecord};
+    use crate::json_element::JsonElement;
+    use crate::parser::Parser;
+    use crate::serializer::Serializer;
+    use crate::test_support::{arb_json_element, near_json, Numbers};
+
+    use proptest::prelude::*;
+
+    /// Rebuilds the value at `index` from the tokens.
+    fn to_element(json: &str, tokens: &[TokenRecord], index: usize) -> JsonElement {
+        let token = tokens[index];
+        let mut buffer = [0; 1024];
+        return match token.kind {
+            TokenKind::Null => JsonElement::Null,
+            TokenKind::Boolean => JsonElement::Boolean(token.as_bool(json).unwrap()),
+            TokenKind::Number => JsonElement::Number(token.as_f64(json).unwrap()),
+            TokenKind::Str | TokenKind::Key => {
+                JsonElement::Str(token.read_str(json, &mut buffer).unwrap().to_string())
+            }
+            TokenKind::Array => JsonElement::Array(
+                (0..token.children)
+                    .map(|i| to_element(json, tokens, at(tokens, index, i).unwrap()))
+                    .collect(),
+            ),
+            TokenKind::Object => {
+                let mut next = index + 1;
+                let mut members = Vec::new();
+                for _ in 0..token.children {
+                    let key = tokens[next]
+                        .read_str(json, &mut buffer)
+                        .unwrap()
+                        .to_string();
+                    members.push((key, to_element(json, tokens, next + 1)));
+                    next = skip(tokens, next);
+                }
+                JsonElement::Object(members.into_iter().collect())
+            }
+        };
+    }
+
+    #[test]
+    fn should_describe_every_value() {
+        let json = r#"{"id": 7, "tags": ["a\nb", true], "empty": {}, "none": null}"#;
+        let mut tokens = [TokenRecord::default(); 16];
+        assert_eq!(Ok(11), parse_into(json, &mut tokens));
+        let kinds: Vec<TokenKind> = tokens[..11].iter().map(|token| token.kind).collect();
+        assert_eq!(
+            vec![
+                TokenKind::Object,
+                TokenKind::Key,
+                TokenKind::Number,
+                TokenKind::Key,
+                TokenKind::Array,
+                TokenKind::Str,
+                TokenKind::Boolean,
+                TokenKind::Key,
+                TokenKind::Object,
+                TokenKind::Key,
+                TokenKind::Null,
+            ],
+            kinds
+        );
+        assert_eq!((0, json.len(), 4, None), {
+            let root = tokens[0];
+            (root.start, root.end, root.children, root.parent)
+        });
+        assert_eq!(r#"["a\nb", true]"#, tokens[4].text(json));
+        assert_eq!((2, Some(3)), (tokens[4].children, tokens[4].parent));
+        assert_eq!(Some(4), tokens[5].parent);
+
+        let mut buffer = [0; 8];
+        assert_eq!(Ok("a\nb"), tokens[5].read_str(json, &mut buffer));
+        assert_eq!(
+            FixedErrorKind::NoMemory,
+            tokens[5]
+                .read_str(json, &mut buffer[..2])
+                .err()
+                .unwrap()
+                .kind
+        );
+        assert_eq!(
+            Some(7.0),
+            tokens[get(json, &tokens, 0, "id").unwrap()].as_f64(json)
+        );
+        assert_eq!(Some(8), get(json, &tokens, 0, "empty"));
+        assert_eq!(None, get(json, &tokens, 0, "missing"));
+        assert_eq!(Some(6), at(&tokens, 4, 1));
+        assert_eq!(None, at(&tokens, 4, 2));
+        assert_eq!(7, skip(&tokens, 3));
+    }
+
+    #[test]
+    fn should_decode_escapes() {
+        let json = r#"["café 😀 \"\\\/", "plain"]"#;
+        let mut tokens = [TokenRecord::default(); 3];
+        assert_eq!(Ok(3), parse_into(json, &mut tokens));
+        let mut buffer = [0; 32];
+        assert_eq!(
+            Ok("caf\u{e9} \u{1f600} \"\\/"),
+            tokens[1].read_str(json, &mut buffer)
+        );
+        assert!(tokens[1].str_eq(json, "caf\u{e9} \u{1f600} \"\\/"));
+        assert!(!tokens[2].str_eq(json, "plai"));
+    }
+
+    #[test]
+    fn should_report_errors() {
+        let mut tokens = [TokenRecord::default(); 4];
+        for (json, kind, offset) in [
+            ("", FixedErrorKind::Partial, 0),
+            (r#"{"a": [1, "#, FixedErrorKind::Partial, 10),
+            (r#""\ud83d"#, FixedErrorKind::Partial, 7),
+            ("tr", FixedErrorKind::Partial, 2),
+            ("[1 2]", FixedErrorKind::Invalid, 3),
+            ("[1,]", FixedErrorKind::Invalid, 3),
+            (r#"{"a" 1}"#, FixedErrorKind::Invalid, 5),
+            (r#""\x""#, FixedErrorKind::Invalid, 2),
+            ("[\"a\nb\"]", FixedErrorKind::Invalid, 3),
+            ("01", FixedErrorKind::Invalid, 1),
+            ("1e400", FixedErrorKind::Invalid, 0),
+            ("[-1e999]", FixedErrorKind::Invalid, 1),
+            ("[] []", FixedErrorKind::Invalid, 3),
+            ("[1, 2, 3, 4]", FixedErrorKind::NoMemory, 10),
+        ] {
+            let error = parse_into(json, &mut tokens).err().unwrap();
+            assert_eq!((kind, offset), (error.kind, error.offset), "{json}");
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn should_read_back_serialized_values(element in arb_json_element(Numbers::Small, "\\PC{0,4}")) {
+            let json = Serializer::serialize(&element);
+            let mut tokens = [TokenRecord::default(); 1024];
+            let count = parse_into(&json, &mut tokens).unwrap();
+            prop_assert_eq!(count, skip(&tokens, 0));
+            prop_assert_eq!(element, to_element(&json, &tokens, 0));
+        }
+
+        #[test]
+        fn should_parse_like_parse(json in near_json()) {
+            let mut tokens = [TokenRecord::default(); 64];
+            match (Parser::parse(&json), parse_into(&json, &mut tokens)) {
+                (Ok(element), Ok(count)) => {
+                    prop_assert!(count > 0);
+                    prop_assert_eq!(count, skip(&tokens, 0));
+                    prop_assert_eq!(element, to_element(&json, &tokens, 0));
+                }
+                (parsed, fixed) => prop_assert_eq!(parsed.is_ok(), fixed.is_ok()),
+            }
+        }
+    }
+}
@@ -13,13 +13,82 @@
 * limitations under the License.
 */
 
-use std::collections::HashMap;
+use alloc::string::String;
+use alloc::vec::Vec;
 
+#[cfg(not(feature = "std"))]
+pub use alloc::collections::btree_map::{BTreeMap as Map, Iter as MapIter};
+/// The map holding an object's members: a `HashMap` with the `std` feature,
+/// a `BTreeMap` without it.
+#[cfg(feature = "std")]
+pub use std::collections::hash_map::{HashMap as Map, Iter as MapIter};
+
+/// An empty map with room for `len` members, when the map can reserve it.
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+pub(crate) fn map_with_capacity<V>(len: usize) -> Map<String, V> {
+    #[cfg(feature = "std")]
+    return Map::with_capacity(len);
+    #[cfg(not(feature = "std"))]
+    return Map::new();
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum JsonElement {
-    Object(HashMap<String, JsonElement>),
+    Object(Map<String, JsonElement>),
     Array(Vec<JsonElement>),
     Str(String),
     Number(f64),
     Boolean(bool),
+    #[default]
     Null,
 }
+
+/// Nested arrays and objects are dropped through an explicit stack rather than by
+/// recursion, so that dropping a deeply nested tree cannot overflow the stack.
+///
+/// Because of this, fields cannot be moved out of a `JsonElement` by matching on
+/// it by value. Use `into_string`, `into_array` and `into_object` instead.
+impl Drop for JsonElement {
+    fn drop(&mut self) {
+        let mut stack = match self {
+            JsonElement::Array(array) if !array.is_empty() => core::mem::take(array),
+            JsonElement::Object(object) if !object.is_empty() => {
+                core::mem::take(object).into_values().collect()
+            }
+            _ => return,
+        };
+        while let Some(mut element) = stack.pop() {
+            match &mut element {
+                JsonElement::Array(array) => stack.append(array),
+                JsonElement::Object(object) => stack.extend(core::mem::take(object).into_values()),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl JsonElement {
+    /// The string this value holds, moved out of it.
+    pub fn into_string(mut self) -> Option<String> {
+        return match &mut self {
+            JsonElement::Str(string) => Some(core::mem::take(string)),
+            _ => None,
+        };
+    }
+
+    /// The elements of this array, moved out of it.
+    pub fn into_array(mut self) -> Option<Vec<JsonElement>> {
+        return match &mut self {
+            JsonElement::Array(array) => Some(core::mem::take(array)),
+            _ => None,
+        };
+    }
+
+    /// The members of this object, moved out of it.
+    pub fn into_object(mut self) -> Option<Map<String, JsonElement>> {
+        return match &mut self {
+            JsonElement::Object(object) => Some(core::mem::take(object)),
+            _ => None,
+        };
+    }
+}
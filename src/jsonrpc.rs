@@ -0,0 +1,764 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! JSON-RPC 2.0 messages and a dispatcher routing requests to handlers by
+//! method name.
+//!
+//! The dispatcher works on message bodies, so it can sit behind any
+//! transport; `Dispatcher::serve` runs it over a reader and writer pair with
+//! one message per line or LSP style `Content-Length` headers.
+
+use crate::json_element::{JsonElement, Map};
+use crate::parser::{ParseError, Parser};
+use crate::serializer::Serializer;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+/// An error object, sent back in place of a result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<JsonElement>,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+
+    pub fn new(code: i64, msg: &str) -> RpcError {
+        return RpcError {
+            code,
+            message: msg.to_string(),
+            data: None,
+        };
+    }
+
+    pub fn invalid_request(msg: &str) -> RpcError {
+        return RpcError::new(RpcError::INVALID_REQUEST, msg);
+    }
+
+    pub fn method_not_found(method: &str) -> RpcError {
+        return RpcError::new(
+            RpcError::METHOD_NOT_FOUND,
+            &format!("Unknown method {method}"),
+        );
+    }
+
+    pub fn invalid_params(msg: &str) -> RpcError {
+        return RpcError::new(RpcError::INVALID_PARAMS, msg);
+    }
+
+    pub fn internal_error(msg: &str) -> RpcError {
+        return RpcError::new(RpcError::INTERNAL_ERROR, msg);
+    }
+
+    pub fn to_element(&self) -> JsonElement {
+        let mut members = Map::new();
+        members.insert("code".to_string(), JsonElement::Number(self.code as f64));
+        members.insert(
+            "message".to_string(),
+            JsonElement::Str(self.message.clone()),
+        );
+        if let Some(data) = &self.data {
+            members.insert("data".to_string(), data.clone());
+        }
+        return JsonElement::Object(members);
+    }
+
+    pub fn from_element(element: &JsonElement) -> Result<RpcError, RpcError> {
+        let invalid = || RpcError::invalid_request("Invalid error object");
+        let members = as_object(element).ok_or_else(invalid)?;
+        let code = match members.get("code") {
+            Some(JsonElement::Number(code)) if code.fract() == 0.0 => *code as i64,
+            _ => return Err(invalid()),
+        };
+        let message = match members.get("message") {
+            Some(JsonElement::Str(message)) => message.clone(),
+            _ => return Err(invalid()),
+        };
+        return Ok(RpcError {
+            code,
+            message,
+            data: members.get("data").cloned(),
+        });
+    }
+}
+
+impl From<ParseError> for RpcError {
+    fn from(error: ParseError) -> RpcError {
+        return RpcError::new(RpcError::PARSE_ERROR, &error.to_string());
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// A request id. Responses to requests whose id could not be read carry
+/// `Null`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Id {
+    Number(f64),
+    Str(String),
+    Null,
+}
+
+impl Id {
+    pub fn to_element(&self) -> JsonElement {
+        return match self {
+            Id::Number(number) => JsonElement::Number(*number),
+            Id::Str(id) => JsonElement::Str(id.clone()),
+            Id::Null => JsonElement::Null,
+        };
+    }
+
+    /// The id in a message, or `None` when it is not a number, string or null.
+    pub fn from_element(element: &JsonElement) -> Option<Id> {
+        return match element {
+            JsonElement::Number(number) => Some(Id::Number(*number)),
+            JsonElement::Str(id) => Some(Id::Str(id.clone())),
+            JsonElement::Null => Some(Id::Null),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub id: Id,
+    pub method: String,
+    /// An array or object, when there are parameters.
+    pub params: Option<JsonElement>,
+}
+
+/// A request that expects no response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub method: String,
+    pub params: Option<JsonElement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub id: Id,
+    pub result: Result<JsonElement, RpcError>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Request(Request),
+    Notification(Notification),
+    Response(Response),
+}
+
+fn as_object(element: &JsonElement) -> Option<&Map<String, JsonElement>> {
+    return match element {
+        JsonElement::Object(members) => Some(members),
+        _ => None,
+    };
+}
+
+fn envelope(members: Vec<(&str, JsonElement)>) -> JsonElement {
+    let mut object = Map::new();
+    object.insert("jsonrpc".to_string(), JsonElement::Str("2.0".to_string()));
+    for (key, value) in members {
+        object.insert(key.to_string(), value);
+    }
+    return JsonElement::Object(object);
+}
+
+impl Message {
+    /// Reads one message. The error is the Invalid Request error to answer
+    /// with, along with the id when the message had a valid one.
+    pub fn from_element(element: &JsonElement) -> Result<Message, (Id, RpcError)> {
+        let members = match as_object(element) {
+            Some(members) => members,
+            None => {
+                let error = RpcError::invalid_request("A message must be an object");
+                return Err((Id::Null, error));
+            }
+        };
+        let id = match members.get("id") {
+            Some(id) => match Id::from_element(id) {
+                Some(id) => Some(id),
+                None => {
+                    let error = RpcError::invalid_request("Invalid id");
+                    return Err((Id::Null, error));
+                }
+            },
+            None => None,
+        };
+        let fail = |msg: &str| {
+            (
+                id.clone().unwrap_or(Id::Null),
+                RpcError::invalid_request(msg),
+            )
+        };
+        if members.get("jsonrpc") != Some(&JsonElement::Str("2.0".to_string())) {
+            return Err(fail("The jsonrpc member must be \"2.0\""));
+        }
+        if let Some(method) = members.get("method") {
+            let method = match method {
+                JsonElement::Str(method) => method.clone(),
+                _ => return Err(fail("The method must be a string")),
+            };
+            let params = members.get("params").cloned();
+            if !matches!(
+                params,
+                None | Some(JsonElement::Array(_)) | Some(JsonElement::Object(_))
+            ) {
+                return Err(fail("The params must be an array or object"));
+            }
+            return Ok(match id {
+                Some(id) => Message::Request(Request { id, method, params }),
+                None => Message::Notification(Notification { method, params }),
+            });
+        }
+        let result = match (members.get("result"), members.get("error")) {
+            (Some(result), None) => Ok(result.clone()),
+            (None, Some(error)) => {
+                Err(RpcError::from_element(error).map_err(|e| fail(&e.message))?)
+            }
+            _ => return Err(fail("A response must have either a result or an error")),
+        };
+        return match id {
+            Some(id) => Ok(Message::Response(Response { id, result })),
+            None => Err(fail("A response must have an id")),
+        };
+    }
+
+    pub fn to_element(&self) -> JsonElement {
+        return match self {
+            Message::Request(request) => {
+                let mut members = vec![
+                    ("id", request.id.to_element()),
+                    ("method", JsonElement::Str(request.method.clone())),
+                ];
+                if let Some(params) = &request.params {
+                    members.push(("params", params.clone()));
+                }
+                envelope(members)
+            }
+            Message::Notification(notification) => {
+                let mut members = vec![("method", JsonElement::Str(notification.method.clone()))];
+                if let Some(params) = &notification.params {
+                    members.push(("params", params.clone()));
+                }
+                envelope(members)
+            }
+            Message::Response(response) => {
+                let outcome = match &response.result {
+                    Ok(result) => ("result", result.clone()),
+                    Err(error) => ("error", error.to_element()),
+                };
+                envelope(vec![("id", response.id.to_element()), outcome])
+            }
+        };
+    }
+}
+
+/// How messages are separated on a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// One message per line, as compact json.
+    #[default]
+    Lines,
+    /// A `Content-Length` header and a blank line before each message, as in
+    /// the Language Server Protocol.
+    ContentLength,
+}
+
+/// Largest message body `read_message` accepts, in bytes: the largest input
+/// `Parser::parse` accepts by default.
+pub const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+fn too_large() -> io::Error {
+    return io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Message is larger than {MAX_MESSAGE_LEN} bytes"),
+    );
+}
+
+/// Longest header line `read_message` accepts, in bytes.
+const MAX_HEADER_LEN: usize = 1024;
+
+/// The bytes of `line` without its line ending.
+fn trim_line_end(mut line: &[u8]) -> &[u8] {
+    while let Some((b'\r' | b'\n', rest)) = line.split_last() {
+        line = rest;
+    }
+    return line;
+}
+
+/// Reads one message body, or `None` when the input ends between messages.
+/// Bodies longer than `MAX_MESSAGE_LEN` are an `InvalidData` error. The body
+/// is not checked to be UTF-8; `Dispatcher::handle` answers bodies that are
+/// not with a Parse error.
+pub fn read_message(input: &mut impl BufRead, framing: Framing) -> io::Result<Option<Vec<u8>>> {
+    if framing == Framing::Lines {
+        loop {
+            let mut line = Vec::new();
+            let limit = MAX_MESSAGE_LEN as u64 + 2;
+            if input.by_ref().take(limit).read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            if trim_line_end(&line).len() > MAX_MESSAGE_LEN {
+                return Err(too_large());
+            }
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                return Ok(Some(line));
+            }
+        }
+    }
+    let mut length = None;
+    loop {
+        let mut line = Vec::new();
+        let limit = MAX_HEADER_LEN as u64 + 2;
+        if input.by_ref().take(limit).read_until(b'\n', &mut line)? == 0 {
+            if length.is_none() {
+                return Ok(None);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Input ended inside a message header",
+            ));
+        }
+        let line = trim_line_end(&line);
+        if line.len() > MAX_HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Message header is longer than {MAX_HEADER_LEN} bytes"),
+            ));
+        }
+        if line.is_empty() {
+            break;
+        }
+        let line = String::from_utf8_lossy(line);
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Message without a valid Content-Length header",
+            ))
+        }
+    };
+    if length > MAX_MESSAGE_LEN {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    if input.take(length as u64).read_to_end(&mut body)? < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Input ended inside a message",
+        ));
+    }
+    return Ok(Some(body));
+}
+
+pub fn write_message(
+    output: &mut impl Write,
+    framing: Framing,
+    message: &JsonElement,
+) -> io::Result<()> {
+    let body = Serializer::serialize(message);
+    match framing {
+        Framing::Lines => writeln!(output, "{body}")?,
+        Framing::ContentLength => write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?,
+    }
+    return output.flush();
+}
+
+type Handler<'a> = Box<dyn FnMut(Option<JsonElement>) -> Result<JsonElement, RpcError> + 'a>;
+
+/// Routes requests and notifications to handlers by method name. A handler
+/// gets the params and returns the result; for notifications the result is
+/// dropped.
+#[derive(Default)]
+pub struct Dispatcher<'a> {
+    handlers: HashMap<String, Handler<'a>>,
+}
+
+impl<'a> Dispatcher<'a> {
+    pub fn new() -> Dispatcher<'a> {
+        return Dispatcher::default();
+    }
+
+    pub fn method(
+        &mut self,
+        name: &str,
+        handler: impl FnMut(Option<JsonElement>) -> Result<JsonElement, RpcError> + 'a,
+    ) -> &mut Dispatcher<'a> {
+        self.handlers.insert(name.to_string(), Box::new(handler));
+        return self;
+    }
+
+    /// The response to one message, if it needs one. Responses sent to the
+    /// dispatcher are ignored.
+    fn dispatch(&mut self, element: &JsonElement) -> Option<JsonElement> {
+        let (id, result) = match Message::from_element(element) {
+            Ok(Message::Request(request)) => {
+                let result = match self.handlers.get_mut(&request.method) {
+                    Some(handler) => handler(request.params),
+                    None => Err(RpcError::method_not_found(&request.method)),
+                };
+                (request.id, result)
+            }
+            Ok(Message::Notification(notification)) => {
+                if let Some(handler) = self.handlers.get_mut(&notification.method) {
+                    let _ = handler(notification.params);
+                }
+                return None;
+            }
+            Ok(Message::Response(_)) => return None,
+            Err((id, error)) => (id, Err(error)),
+        };
+        return Some(Message::Response(Response { id, result }).to_element());
+    }
+
+    /// The reply to a message or batch, if there is one.
+    pub fn handle_element(&mut self, element: &JsonElement) -> Option<JsonElement> {
+        let batch = match element {
+            JsonElement::Array(batch) => batch,
+            _ => return self.dispatch(element),
+        };
+        if batch.is_empty() {
+            let error = Err(RpcError::invalid_request("A batch must not be empty"));
+            let response = Response {
+                id: Id::Null,
+                result: error,
+            };
+            return Some(Message::Response(response).to_element());
+        }
+        let replies: Vec<JsonElement> = batch.iter().filter_map(|m| self.dispatch(m)).collect();
+        if replies.is_empty() {
+            return None;
+        }
+        return Some(JsonElement::Array(replies));
+    }
+
+    /// The reply to a message body, with a Parse error response when it is
+    /// not valid UTF-8 or not valid json.
+    pub fn handle(&mut self, body: impl AsRef<[u8]>) -> Option<JsonElement> {
+        let parsed = match core::str::from_utf8(body.as_ref()) {
+            Ok(body) => Parser::parse(body).map_err(RpcError::from),
+            Err(_) => Err(RpcError::new(
+                RpcError::PARSE_ERROR,
+                "Message is not valid UTF-8",
+            )),
+        };
+        return match parsed {
+            Ok(element) => self.handle_element(&element),
+            Err(error) => {
+                let response = Response {
+                    id: Id::Null,
+                    result: Err(error),
+                };
+                Some(Message::Response(response).to_element())
+            }
+        };
+    }
+
+    /// Answers messages from `input` on `output` until the input ends.
+    pub fn serve(
+        &mut self,
+        mut input: impl BufRead,
+        mut output: impl Write,
+        framing: Framing,
+    ) -> io::Result<()> {
+        while let Some(body) = read_message(&mut input, framing)? {
+            if let Some(reply) = self.handle(&body) {
+                write_message(&mut output, framing, &reply)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_message, Dispatcher, Framing, Id, Message, Request, RpcError};
+    use crate::json_element::JsonElement;
+    use crate::test_support::json;
+
+    use std::cell::RefCell;
+    use std::io::{BufReader, Cursor, ErrorKind, Read};
+
+    fn subtract(params: Option<JsonElement>) -> Result<JsonElement, RpcError> {
+        let (a, b) = match &params {
+            Some(JsonElement::Array(args)) => match args.as_slice() {
+                [JsonElement::Number(a), JsonElement::Number(b)] => (*a, *b),
+                _ => return Err(RpcError::invalid_params("Expected two numbers")),
+            },
+            Some(JsonElement::Object(args)) => {
+                match (args.get("minuend"), args.get("subtrahend")) {
+                    (Some(JsonElement::Number(a)), Some(JsonElement::Number(b))) => (*a, *b),
+                    _ => return Err(RpcError::invalid_params("Expected two numbers")),
+                }
+            }
+            _ => return Err(RpcError::invalid_params("Expected two numbers")),
+        };
+        return Ok(JsonElement::Number(a - b));
+    }
+
+    #[test]
+    fn should_convert_messages() {
+        let request = Message::Request(Request {
+            id: Id::Str("a".to_string()),
+            method: "sum".to_string(),
+            params: Some(json("[1, 2]")),
+        });
+        let element = json(r#"{"jsonrpc": "2.0", "id": "a", "method": "sum", "params": [1, 2]}"#);
+        assert_eq!(element, request.to_element());
+        assert_eq!(Ok(request), Message::from_element(&element));
+
+        let error =
+            json(r#"{"jsonrpc": "2.0", "id": 1, "error": {"code": -32601, "message": "x"}}"#);
+        match Message::from_element(&error) {
+            Ok(Message::Response(response)) => {
+                assert_eq!(Id::Number(1.0), response.id);
+                assert_eq!(
+                    RpcError::METHOD_NOT_FOUND,
+                    response.result.err().unwrap().code
+                );
+            }
+            _ => panic!("expected a response"),
+        }
+        for (invalid, id) in [
+            (
+                r#"{"jsonrpc": "2.0", "method": 1, "params": "bar"}"#,
+                Id::Null,
+            ),
+            (
+                r#"{"jsonrpc": "1.0", "id": 4, "method": "a"}"#,
+                Id::Number(4.0),
+            ),
+            (
+                r#"{"jsonrpc": "2.0", "id": 5, "method": "a", "params": 3}"#,
+                Id::Number(5.0),
+            ),
+            (r#"{"jsonrpc": "2.0", "id": [], "method": "a"}"#, Id::Null),
+            (r#"{"jsonrpc": "2.0", "id": 6}"#, Id::Number(6.0)),
+            ("1", Id::Null),
+        ] {
+            let (found, error) = Message::from_element(&json(invalid)).err().unwrap();
+            assert_eq!(
+                (id, RpcError::INVALID_REQUEST),
+                (found, error.code),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_follow_the_specification_examples() {
+        let notified = RefCell::new(Vec::new());
+        let mut dispatcher = Dispatcher::new();
+        dispatcher
+            .method("subtract", subtract)
+            .method("notify_hello", |params| {
+                notified.borrow_mut().push(params);
+                Ok(JsonElement::Null)
+            })
+            .method("get_data", |_| Ok(json(r#"["hello", 5]"#)));
+
+        let examples = [
+            (
+                r#"{"jsonrpc": "2.0", "method": "subtract", "params": [42, 23], "id": 1}"#,
+                Some(r#"{"jsonrpc": "2.0", "result": 19, "id": 1}"#),
+            ),
+            (
+                r#"{"jsonrpc": "2.0", "method": "subtract", "params": {"subtrahend": 23, "minuend": 42}, "id": 3}"#,
+                Some(r#"{"jsonrpc": "2.0", "result": 19, "id": 3}"#),
+            ),
+            (
+                r#"{"jsonrpc": "2.0", "method": "notify_hello", "params": [7]}"#,
+                None,
+            ),
+            (r#"{"jsonrpc": "2.0", "method": "missing"}"#, None),
+            (
+                r#"{"jsonrpc": "2.0", "method": "foobar", "id": "1"}"#,
+                Some(
+                    r#"{"jsonrpc": "2.0", "error": {"code": -32601, "message": "Unknown method foobar"}, "id": "1"}"#,
+                ),
+            ),
+            (
+                r#"{"jsonrpc": "2.0", "method": 1, "params": "bar"}"#,
+                Some(
+                    r#"{"jsonrpc": "2.0", "error": {"code": -32600, "message": "The method must be a string"}, "id": null}"#,
+                ),
+            ),
+            (
+                "[]",
+                Some(
+                    r#"{"jsonrpc": "2.0", "error": {"code": -32600, "message": "A batch must not be empty"}, "id": null}"#,
+                ),
+            ),
+            (
+                "[1]",
+                Some(
+                    r#"[{"jsonrpc": "2.0", "error": {"code": -32600, "message": "A message must be an object"}, "id": null}]"#,
+                ),
+            ),
+            (
+                r#"[{"jsonrpc": "2.0", "method": "notify_hello", "params": [1]},
+                    {"jsonrpc": "2.0", "method": "subtract", "params": [42, 23], "id": "2"},
+                    {"foo": "boo"},
+                    {"jsonrpc": "2.0", "method": "get_data", "id": "9"}]"#,
+                Some(
+                    r#"[{"jsonrpc": "2.0", "result": 19, "id": "2"},
+                        {"jsonrpc": "2.0", "error": {"code": -32600, "message": "The jsonrpc member must be \"2.0\""}, "id": null},
+                        {"jsonrpc": "2.0", "result": ["hello", 5], "id": "9"}]"#,
+                ),
+            ),
+            (
+                r#"[{"jsonrpc": "2.0", "method": "notify_hello", "params": [2]}]"#,
+                None,
+            ),
+        ];
+        for (message, reply) in examples {
+            assert_eq!(reply.map(json), dispatcher.handle(message), "{message}");
+        }
+        let parse_error =
+            dispatcher.handle(r#"{"jsonrpc": "2.0", "method": "foobar, "params": "bar", "baz]"#);
+        match parse_error
+            .as_ref()
+            .and_then(|reply| Message::from_element(reply).ok())
+        {
+            Some(Message::Response(response)) => {
+                assert_eq!(Id::Null, response.id);
+                assert_eq!(RpcError::PARSE_ERROR, response.result.err().unwrap().code);
+            }
+            _ => panic!("expected a response"),
+        }
+        drop(dispatcher);
+        assert_eq!(
+            vec![Some(json("[7]")), Some(json("[1]")), Some(json("[2]"))],
+            notified.into_inner()
+        );
+    }
+
+    #[test]
+    fn should_serve_over_streams() {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.method("subtract", subtract);
+
+        let input = "{\"jsonrpc\":\"2.0\",\"method\":\"subtract\",\"params\":[5,3],\"id\":1}\n\n{\"jsonrpc\":\"2.0\",\"method\":\"subtract\"}\n";
+        let mut output = Vec::new();
+        dispatcher
+            .serve(
+                BufReader::new(input.as_bytes()),
+                &mut output,
+                Framing::Lines,
+            )
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let replies: Vec<JsonElement> = output.lines().map(json).collect();
+        assert_eq!(
+            vec![json(r#"{"jsonrpc": "2.0", "result": 2, "id": 1}"#)],
+            replies
+        );
+
+        let body = r#"{"jsonrpc":"2.0","method":"subtract","params":[1,3],"id":"x"}"#;
+        let input = format!("Content-Length: {}\r\n\r\n{body}", body.len());
+        let mut output = Cursor::new(Vec::new());
+        dispatcher
+            .serve(input.as_bytes(), &mut output, Framing::ContentLength)
+            .unwrap();
+        let output = String::from_utf8(output.into_inner()).unwrap();
+        let (header, reply) = output.split_once("\r\n\r\n").unwrap();
+        assert_eq!(format!("Content-Length: {}", reply.len()), header);
+        assert_eq!(
+            json(r#"{"jsonrpc": "2.0", "result": -2, "id": "x"}"#),
+            json(reply)
+        );
+    }
+
+    #[test]
+    fn should_reject_oversized_messages() {
+        let mut input = "Content-Length: 1000000000000\r\n\r\n{}".as_bytes();
+        let error = read_message(&mut input, Framing::ContentLength).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+
+        let mut input = "Content-Length: 10\r\n\r\n{}".as_bytes();
+        let error = read_message(&mut input, Framing::ContentLength).unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedEof, error.kind());
+
+        let line = format!("\"{}\"\n", "a".repeat(super::MAX_MESSAGE_LEN));
+        let error = read_message(&mut line.as_bytes(), Framing::Lines).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+
+        let header = format!("X-Padding: {}\r\n", "a".repeat(super::MAX_HEADER_LEN));
+        let mut input = header
+            .as_bytes()
+            .chain("Content-Length: 2\r\n\r\n{}".as_bytes());
+        let error = read_message(&mut input, Framing::ContentLength).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+    }
+
+    #[test]
+    fn should_answer_bodies_that_are_not_utf8_and_keep_serving() {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.method("subtract", subtract);
+        let request = br#"{"jsonrpc":"2.0","method":"subtract","params":[5,3],"id":1}"#;
+        let invalid = b"{\"jsonrpc\":\"2.0\",\"method\":\"\xff\",\"id\":2}";
+        let parse_error = json(
+            r#"{"jsonrpc": "2.0", "error": {"code": -32700, "message": "Message is not valid UTF-8"}, "id": null}"#,
+        );
+        let answer = json(r#"{"jsonrpc": "2.0", "result": 2, "id": 1}"#);
+
+        let input = [&invalid[..], b"\n", request, b"\n"].concat();
+        let mut output = Vec::new();
+        dispatcher
+            .serve(&input[..], &mut output, Framing::Lines)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let replies: Vec<JsonElement> = output.lines().map(json).collect();
+        assert_eq!(vec![parse_error.clone(), answer.clone()], replies);
+
+        let mut input = Vec::new();
+        for body in [&invalid[..], request] {
+            input.extend(format!("Content-Length: {}\r\n\r\n", body.len()).bytes());
+            input.extend(body);
+        }
+        let mut output = Vec::new();
+        dispatcher
+            .serve(&input[..], &mut output, Framing::ContentLength)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let replies: Vec<JsonElement> = output
+            .split("Content-Length: ")
+            .filter_map(|message| message.split_once("\r\n\r\n"))
+            .map(|(_, reply)| json(reply))
+            .collect();
+        assert_eq!(vec![parse_error, answer], replies);
+    }
+}
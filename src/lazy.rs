@@ -0,0 +1,450 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Values decoded only when they are read.
+//!
+//! A `LazyValue` is a slice of the input. Looking up a member or an element
+//! skips over the values before it without decoding them, and strings and
+//! numbers are only decoded by the accessor that returns them, so reading a
+//! couple of fields from a large document costs little more than finding them.
+//!
+//! By default the whole input is validated when it is opened, which keeps the
+//! lookups infallible. With `LazyOptions::validate` off nothing is checked up
+//! front: a lookup through broken structure finds nothing, and the errors of
+//! a value only surface when it is decoded.
+
+use crate::document::NodeKind;
+use crate::json_element::JsonElement;
+use crate::parser::{ParseError, Parser, ParserOptions};
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LazyOptions {
+    /// Checks the whole input before returning the root value.
+    pub validate: bool,
+    /// Limits for validation and for decoding values.
+    pub parser: ParserOptions,
+}
+
+impl Default for LazyOptions {
+    fn default() -> LazyOptions {
+        LazyOptions {
+            validate: true,
+            parser: ParserOptions::default(),
+        }
+    }
+}
+
+/// A value of a document, not decoded yet.
+#[derive(Debug, Clone, Copy)]
+pub struct LazyValue<'a> {
+    raw: &'a str,
+    options: ParserOptions,
+}
+
+fn is_whitespace(b: u8) -> bool {
+    return matches!(b, b' ' | b'\n' | b'\r' | b'\t');
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && is_whitespace(bytes[i]) {
+        i += 1;
+    }
+    return i;
+}
+
+/// The offset just past the string whose opening quote is at `start`.
+fn string_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => return Some(i + 1),
+            b'\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+    return None;
+}
+
+/// The offset just past the value starting at `start`, found by matching
+/// brackets and quotes only.
+fn value_end(bytes: &[u8], start: usize) -> Option<usize> {
+    match bytes.get(start)? {
+        b'"' => return string_end(bytes, start),
+        b'{' | b'[' => {}
+        _ => {
+            let mut i = start;
+            while i < bytes.len()
+                && !is_whitespace(bytes[i])
+                && !matches!(bytes[i], b',' | b']' | b'}')
+            {
+                i += 1;
+            }
+            return if i > start { Some(i) } else { None };
+        }
+    }
+    let mut closers: Vec<u8> = Vec::new();
+    let mut i = start;
+    loop {
+        match *bytes.get(i)? {
+            b'"' => {
+                i = string_end(bytes, i)?;
+                continue;
+            }
+            b'{' => closers.push(b'}'),
+            b'[' => closers.push(b']'),
+            c @ (b'}' | b']') => {
+                if closers.pop() != Some(c) {
+                    return None;
+                }
+                if closers.is_empty() {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+impl<'a> LazyValue<'a> {
+    pub fn parse(json: &'a str) -> Result<LazyValue<'a>, ParseError> {
+        return LazyValue::parse_with_options(json, &LazyOptions::default());
+    }
+
+    pub fn parse_with_options(
+        json: &'a str,
+        options: &LazyOptions,
+    ) -> Result<LazyValue<'a>, ParseError> {
+        if options.validate {
+            Parser::validate_with_options(json, &options.parser)?;
+        }
+        let raw = json.trim_matches(|c: char| c.is_ascii() && is_whitespace(c as u8));
+        if raw.is_empty() {
+            return Err(ParseError::new("Invalid json value"));
+        }
+        return Ok(LazyValue {
+            raw,
+            options: options.parser,
+        });
+    }
+
+    fn child(&self, raw: &'a str) -> LazyValue<'a> {
+        return LazyValue {
+            raw,
+            options: self.options,
+        };
+    }
+
+    /// The text of the value, exactly as it appears in the input.
+    pub fn raw(&self) -> &'a str {
+        return self.raw;
+    }
+
+    /// The kind of the value, from its first character.
+    pub fn kind(&self) -> NodeKind {
+        return match self.raw.as_bytes()[0] {
+            b'{' => NodeKind::Object,
+            b'[' => NodeKind::Array,
+            b'"' => NodeKind::Str,
+            b't' | b'f' => NodeKind::Boolean,
+            b'n' => NodeKind::Null,
+            _ => NodeKind::Number,
+        };
+    }
+
+    pub fn is_null(&self) -> bool {
+        return self.raw == "null";
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        return match self.raw {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+    }
+
+    /// The number, or `None` when the value is not a valid number.
+    pub fn as_f64(&self) -> Option<f64> {
+        if self.kind() != NodeKind::Number {
+            return None;
+        }
+        return match Parser::parse_with_options(self.raw, &self.options) {
+            Ok(JsonElement::Number(n)) => Some(n),
+            _ => None,
+        };
+    }
+
+    /// The string, borrowed from the input when it has no escapes, or `None`
+    /// when the value is not a valid string.
+    pub fn as_str(&self) -> Option<Cow<'a, str>> {
+        return decode_string(self.raw, &self.options);
+    }
+
+    /// The elements of an array, in order; nothing for any other value.
+    pub fn elements(&self) -> Elements<'a> {
+        return Elements {
+            parent: *self,
+            next: if self.kind() == NodeKind::Array {
+                Some(1)
+            } else {
+                None
+            },
+        };
+    }
+
+    /// The members of an object in document order, repeated keys included;
+    /// nothing for any other value.
+    pub fn members(&self) -> Members<'a> {
+        return Members {
+            parent: *self,
+            next: if self.kind() == NodeKind::Object {
+                Some(1)
+            } else {
+                None
+            },
+        };
+    }
+
+    pub fn at(&self, index: usize) -> Option<LazyValue<'a>> {
+        return self.elements().nth(index);
+    }
+
+    /// The value of the last member named `key`, the one `Parser::parse` keeps.
+    pub fn get(&self, key: &str) -> Option<LazyValue<'a>> {
+        return self
+            .members()
+            .filter(|(name, _)| name == key)
+            .last()
+            .map(|(_, value)| value);
+    }
+
+    /// Decodes the whole value with `Parser`.
+    pub fn to_element(&self) -> Result<JsonElement, ParseError> {
+        return Parser::parse_with_options(self.raw, &self.options);
+    }
+}
+
+fn decode_string<'a>(raw: &'a str, options: &ParserOptions) -> Option<Cow<'a, str>> {
+    if raw.len() < 2 || !raw.starts_with('"') {
+        return None;
+    }
+    if !raw.contains('\\') {
+        // Without validation, these are the checks the parser would make.
+        let contents = raw.strip_suffix('"')?.get(1..)?;
+        if contents.len() > options.max_string_len || contents.bytes().any(|b| b < b' ') {
+            return None;
+        }
+        return Some(Cow::Borrowed(contents));
+    }
+    let element = Parser::parse_with_options(raw, options).ok()?;
+    return element.into_string().map(Cow::Owned);
+}
+
+/// Iterates over the elements of an array, see `LazyValue::elements`.
+pub struct Elements<'a> {
+    parent: LazyValue<'a>,
+    /// Where to look for the next element, or `None` once the array ended.
+    next: Option<usize>,
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = LazyValue<'a>;
+
+    fn next(&mut self) -> Option<LazyValue<'a>> {
+        let raw = self.parent.raw;
+        let bytes = raw.as_bytes();
+        let start = skip_whitespace(bytes, self.next.take()?);
+        let end = value_end(bytes, start)?;
+        let after = skip_whitespace(bytes, end);
+        if bytes.get(after) == Some(&b',') {
+            self.next = Some(after + 1);
+        }
+        return Some(self.parent.child(&raw[start..end]));
+    }
+}
+
+/// Iterates over the members of an object, see `LazyValue::members`. Keys
+/// borrow from the input unless they have escapes.
+pub struct Members<'a> {
+    parent: LazyValue<'a>,
+    /// Where to look for the next member, or `None` once the object ended.
+    next: Option<usize>,
+}
+
+impl<'a> Iterator for Members<'a> {
+    type Item = (Cow<'a, str>, LazyValue<'a>);
+
+    fn next(&mut self) -> Option<(Cow<'a, str>, LazyValue<'a>)> {
+        let raw = self.parent.raw;
+        let bytes = raw.as_bytes();
+        let key_start = skip_whitespace(bytes, self.next.take()?);
+        if bytes.get(key_start) != Some(&b'"') {
+            return None;
+        }
+        let key_end = string_end(bytes, key_start)?;
+        let colon = skip_whitespace(bytes, key_end);
+        if bytes.get(colon) != Some(&b':') {
+            return None;
+        }
+        let start = skip_whitespace(bytes, colon + 1);
+        let end = value_end(bytes, start)?;
+        let after = skip_whitespace(bytes, end);
+        if bytes.get(after) == Some(&b',') {
+            self.next = Some(after + 1);
+        }
+        let key = decode_string(&raw[key_start..key_end], &self.parent.options)?;
+        return Some((key, self.parent.child(&raw[start..end])));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LazyOptions, LazyValue};
+    use crate::document::NodeKind;
+    use crate::json_element::JsonElement;
+    use crate::parser::{Parser, ParserOptions};
+    use crate::test_support::near_json;
+    use proptest::prelude::*;
+
+    use alloc::borrow::Cow;
+
+    #[test]
+    fn should_read_fields_without_decoding_the_rest() {
+        let json = r#"
+            {"meta": {"id": 42, "name": "abc", "tags": ["x", "y"]},
+             "data": [1, [2, "]"], {"}": "{"}],
+             "flag": false, "none": null, "path": "a/b"}
+        "#;
+        let root = LazyValue::parse(json).ok().unwrap();
+        assert_eq!(NodeKind::Object, root.kind());
+        let meta = root.get("meta").unwrap();
+        assert_eq!(Some(42.0), meta.get("id").and_then(|id| id.as_f64()));
+        assert_eq!(
+            Some(Cow::Owned("abc".to_string())),
+            meta.get("name").unwrap().as_str()
+        );
+        assert_eq!(
+            Some(Cow::Borrowed("a/b")),
+            root.get("path").unwrap().as_str()
+        );
+        assert_eq!(
+            Some("\"y\""),
+            meta.get("tags").and_then(|t| t.at(1)).map(|t| t.raw())
+        );
+        assert_eq!(Some(false), root.get("flag").and_then(|f| f.as_bool()));
+        assert!(root.get("none").unwrap().is_null());
+        assert!(root.get("missing").is_none());
+        assert!(meta.at(0).is_none());
+
+        let data = root.get("data").unwrap();
+        let raws: Vec<&str> = data.elements().map(|e| e.raw()).collect();
+        assert_eq!(vec!["1", r#"[2, "]"]"#, r#"{"}": "{"}"#], raws);
+        assert_eq!(
+            Some(Cow::Borrowed("{")),
+            data.at(2).and_then(|e| e.get("}")).and_then(|e| e.as_str())
+        );
+
+        let keys: Vec<Cow<str>> = root.members().map(|(key, _)| key).collect();
+        assert_eq!(vec!["meta", "data", "flag", "none", "path"], keys);
+        assert_eq!(
+            Parser::parse(r#"{"id": 42, "name": "abc", "tags": ["x", "y"]}"#).ok(),
+            meta.to_element().ok()
+        );
+    }
+
+    #[test]
+    fn should_match_keys_after_unescaping_and_keep_the_last() {
+        let root = LazyValue::parse(r#"{"a\/b": 1, "x": 2, "x": 3}"#)
+            .ok()
+            .unwrap();
+        assert_eq!("1", root.get("a/b").unwrap().raw());
+        assert_eq!("3", root.get("x").unwrap().raw());
+        assert_eq!(3, root.members().count());
+    }
+
+    #[test]
+    fn should_defer_errors_without_validation() {
+        let json = r#"{"ok": {"id": 7}, "bad": [1, tru, "\x"], "num": 1.2.3}"#;
+        assert!(LazyValue::parse(json).is_err());
+
+        let options = LazyOptions {
+            validate: false,
+            ..Default::default()
+        };
+        let root = LazyValue::parse_with_options(json, &options).ok().unwrap();
+        assert_eq!(
+            Some(7.0),
+            root.get("ok")
+                .and_then(|ok| ok.get("id"))
+                .and_then(|id| id.as_f64())
+        );
+        let bad = root.get("bad").unwrap();
+        assert!(bad.to_element().is_err());
+        assert_eq!(None, bad.at(2).unwrap().as_str());
+        assert_eq!(None, root.get("num").unwrap().as_f64());
+
+        let root = LazyValue::parse_with_options("[\"a\tb\", \"abc\"]", &options)
+            .ok()
+            .unwrap();
+        assert_eq!(None, root.at(0).unwrap().as_str());
+        let options = LazyOptions {
+            validate: false,
+            parser: ParserOptions {
+                max_string_len: 2,
+                ..Default::default()
+            },
+        };
+        let root = LazyValue::parse_with_options("[\"ab\", \"abc\"]", &options)
+            .ok()
+            .unwrap();
+        assert_eq!(Some(Cow::Borrowed("ab")), root.at(0).unwrap().as_str());
+        assert_eq!(None, root.at(1).unwrap().as_str());
+
+        // Lookups through broken structure find nothing.
+        let broken = LazyValue::parse_with_options(r#"{"a" 1, "b": 2}"#, &options)
+            .ok()
+            .unwrap();
+        assert!(broken.get("b").is_none());
+        assert!(LazyValue::parse_with_options(" \n", &options).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn should_decode_like_parse(json in near_json()) {
+            prop_assert_eq!(Parser::parse(&json).err(), Parser::validate(&json).err());
+            match Parser::parse(&json) {
+                Ok(element) => {
+                    let lazy = LazyValue::parse(&json).ok().unwrap();
+                    if let JsonElement::Object(members) = &element {
+                        for (key, value) in members {
+                            let found = lazy.get(key).and_then(|v| v.to_element().ok());
+                            prop_assert_eq!(Some(value), found.as_ref());
+                        }
+                    }
+                    if let JsonElement::Array(elements) = &element {
+                        let lazy: Vec<JsonElement> = lazy.elements().map(|e| e.to_element().ok().unwrap()).collect();
+                        prop_assert_eq!(elements, &lazy);
+                    }
+                    prop_assert_eq!(Some(element), lazy.to_element().ok());
+                }
+                Err(_) => prop_assert!(LazyValue::parse(&json).is_err()),
+            }
+        }
+    }
+}
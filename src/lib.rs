@@ -13,19 +13,58 @@
 * limitations under the License.
 */
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "tokio")]
+pub mod async_io;
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+pub mod binary;
+#[cfg(feature = "bson")]
+pub mod bson;
+pub mod canonical;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod diff;
+#[cfg(test)]
+mod differential;
+pub mod document;
+pub mod fixed;
 pub mod json_element;
+#[cfg(feature = "std")]
+pub mod jsonrpc;
+pub mod lazy;
+pub mod merge;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
 pub mod parser;
+pub mod pointer;
+pub mod push;
+pub mod query;
+pub mod serializer;
+#[cfg(feature = "simd")]
+pub mod simd;
+#[cfg(test)]
+mod test_support;
 mod token;
+pub mod visit;
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::get_first)]
 mod tests {
     use crate::json_element::JsonElement;
-    use crate::parser::Parser;
+    use crate::parser::{ParseErrorKind, Parser, ParserOptions};
+    use crate::serializer::Serializer;
+    use crate::test_support::{arb_json_element, near_json, Numbers};
+    use proptest::prelude::*;
 
     macro_rules! unwrap_json_element_result {
         ($element: expr, $pat: pat, $rule: block) => {
             match $element {
-                Ok(e) => match e {
+                Ok(e) => match &e {
                     $pat => $rule,
                     _ => assert!(false),
                 },
@@ -50,25 +89,25 @@ mod tests {
     #[test]
     fn should_parse_num() {
         unwrap_json_element_result!(Parser::parse("14.5E-10"), JsonElement::Number(n), {
-            assert_eq!(14.5E-10, n)
+            assert_eq!(14.5E-10, *n)
         });
         unwrap_json_element_result!(Parser::parse("-15"), JsonElement::Number(n), {
-            assert_eq!(-15.0, n)
+            assert_eq!(-15.0, *n)
         });
         unwrap_json_element_result!(Parser::parse("15e7"), JsonElement::Number(n), {
-            assert_eq!(15e7, n)
+            assert_eq!(15e7, *n)
         });
         unwrap_json_element_result!(Parser::parse("15.7"), JsonElement::Number(n), {
-            assert_eq!(15.7, n)
+            assert_eq!(15.7, *n)
         });
         unwrap_json_element_result!(Parser::parse("0"), JsonElement::Number(n), {
-            assert_eq!(0.0, n)
+            assert_eq!(0.0, *n)
         });
         unwrap_json_element_result!(Parser::parse("-0"), JsonElement::Number(n), {
-            assert_eq!(-0.0, n)
+            assert_eq!(-0.0, *n)
         });
         unwrap_json_element_result!(Parser::parse("-0.156"), JsonElement::Number(n), {
-            assert_eq!(-0.156, n)
+            assert_eq!(-0.156, *n)
         });
         match Parser::parse("04") {
             Err(_) => assert!(true),
@@ -79,9 +118,9 @@ mod tests {
     #[test]
     fn should_parse_string() {
         unwrap_json_element_result!(
-            Parser::parse("\"Test\\tString\\nSecond\rLine\\n\\\\Unicode test: \\u306e\""),
+            Parser::parse("\"Test\\tString\\nSecond\\rLine\\n\\\\Unicode test: \\u306e\""),
             JsonElement::Str(s),
-            { assert_eq!("Test\tString\nSecond\rLine\n\\Unicode test: つ", s) }
+            { assert_eq!("Test\tString\nSecond\rLine\n\\Unicode test: の", s) }
         );
     }
 
@@ -144,4 +183,223 @@ mod tests {
             }
         );
     }
+
+    fn expect_error_kind(json: &str, options: &ParserOptions, kind: ParseErrorKind) {
+        match Parser::parse_with_options(json, options) {
+            Err(e) => assert_eq!(kind, e.kind, "{}", e.details),
+            Ok(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn should_reject_deep_nesting_without_overflowing() {
+        let options = ParserOptions::default();
+        expect_error_kind(
+            &"[".repeat(100_000),
+            &options,
+            ParseErrorKind::DepthLimitExceeded,
+        );
+        expect_error_kind(
+            &"{\"a\":".repeat(100_000),
+            &options,
+            ParseErrorKind::DepthLimitExceeded,
+        );
+
+        let options = ParserOptions {
+            max_depth: 3,
+            ..ParserOptions::default()
+        };
+        unwrap_json_element_result!(
+            Parser::parse_with_options("[[[1]]]", &options),
+            JsonElement::Array(a),
+            { assert_eq!(1, a.len()) }
+        );
+        expect_error_kind("[[[[1]]]]", &options, ParseErrorKind::DepthLimitExceeded);
+    }
+
+    #[test]
+    fn should_enforce_size_limits() {
+        let options = ParserOptions {
+            max_input_bytes: 8,
+            max_string_len: 4,
+            max_array_len: 2,
+            max_object_members: 2,
+            max_number_len: 3,
+            ..ParserOptions::default()
+        };
+        expect_error_kind("  [1, 2]  ", &options, ParseErrorKind::InputTooLarge);
+        expect_error_kind("\"abcde\"", &options, ParseErrorKind::StringTooLong);
+        expect_error_kind("\"a\\tcde\"", &options, ParseErrorKind::StringTooLong);
+        expect_error_kind("[1,2,3]", &options, ParseErrorKind::ArrayTooLong);
+        expect_error_kind(
+            "{\"a\":1,\"b\":2,\"c\":3}",
+            &ParserOptions {
+                max_object_members: 2,
+                ..ParserOptions::default()
+            },
+            ParseErrorKind::TooManyObjectMembers,
+        );
+        expect_error_kind("-1234", &options, ParseErrorKind::NumberTooLong);
+        expect_error_kind("1.x", &options, ParseErrorKind::Syntax);
+
+        unwrap_json_element_result!(
+            Parser::parse_with_options("\"abcd\"", &options),
+            JsonElement::Str(s),
+            { assert_eq!("abcd", s) }
+        );
+        unwrap_json_element_result!(
+            Parser::parse_with_options("-12", &options),
+            JsonElement::Number(n),
+            { assert_eq!(-12.0, *n) }
+        );
+    }
+
+    #[test]
+    fn should_parse_nested_containers() {
+        unwrap_json_element_result!(
+            Parser::parse("[[1, [2]], {\"a\": [], \"b\": {\"c\": {}}}, []]"),
+            JsonElement::Array(a),
+            {
+                assert_eq!(3, a.len());
+                unwrap_json_element!(a.get(0).unwrap(), JsonElement::Array(inner), {
+                    assert_eq!(2, inner.len())
+                });
+                unwrap_json_element!(a.get(1).unwrap(), JsonElement::Object(o), {
+                    assert_eq!(2, o.len());
+                    unwrap_json_element!(o.get("b").unwrap(), JsonElement::Object(b), {
+                        assert!(b.contains_key("c"))
+                    });
+                });
+            }
+        );
+        match Parser::parse("[1 2]") {
+            Err(_) => assert!(true),
+            _ => assert!(false),
+        }
+        match Parser::parse("{\"a\": 1 \"b\": 2}") {
+            Err(_) => assert!(true),
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn should_parse_and_drop_deep_nesting_iteratively() {
+        let depth = 200_000;
+        let options = ParserOptions {
+            max_depth: depth,
+            max_input_bytes: usize::MAX,
+            ..ParserOptions::default()
+        };
+        let json = format!(
+            "{}null{}",
+            "[{\"a\":".repeat(depth / 2),
+            "}]".repeat(depth / 2)
+        );
+        let mut element = Parser::parse_with_options(&json, &options).ok().unwrap();
+        let mut levels = 0;
+        loop {
+            element = match element {
+                JsonElement::Array(_) => element.into_array().unwrap().swap_remove(0),
+                JsonElement::Object(_) => element.into_object().unwrap().remove("a").unwrap(),
+                _ => break,
+            };
+            levels += 1;
+            if levels == 10 {
+                break;
+            }
+        }
+        assert_eq!(10, levels);
+        drop(element);
+
+        let element =
+            Parser::parse_with_options(&json.replace("{\"a\":", "[").replace('}', "]"), &options);
+        assert!(element.is_ok());
+    }
+
+    #[test]
+    fn should_move_values_out_of_elements() {
+        let element = Parser::parse(r#"{"a": ["b", 1]}"#).unwrap();
+        let mut object = element.into_object().unwrap();
+        let array = object.remove("a").unwrap().into_array().unwrap();
+        assert_eq!(Some("b".to_string()), array[0].clone().into_string());
+        assert_eq!(None, array[1].clone().into_string());
+        assert_eq!(None, JsonElement::Null.into_array());
+        assert_eq!(None, JsonElement::Str("a".into()).into_object());
+    }
+
+    #[test]
+    fn should_decode_lowercase_and_uppercase_hex_escapes() {
+        unwrap_json_element_result!(
+            Parser::parse("\"\\u00e9\\u00E9\\u00ff\\u0041\""),
+            JsonElement::Str(s),
+            { assert_eq!("ééÿA", s) }
+        );
+    }
+
+    /// Inputs that made the parser panic, kept so they never do again.
+    #[test]
+    fn should_not_panic_on_fuzz_regressions() {
+        for json in [
+            "-a",
+            "-.",
+            "1.",
+            "[1.]",
+            "1e",
+            "1e+",
+            "-1E-",
+            "0.e",
+            "[-x]",
+            "{\"a\":1.",
+        ] {
+            match Parser::parse(json) {
+                Err(_) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+        for json in ["1e999", "-1e400"] {
+            match Parser::parse(json) {
+                Err(e) => assert!(e.details.contains("out of range")),
+                _ => assert!(false),
+            }
+        }
+    }
+
+    #[test]
+    fn should_report_error_position() {
+        match Parser::parse("[\n  1,\n  tru\n]") {
+            Err(e) => {
+                let position = e.position.unwrap();
+                assert_eq!((3, 6), (position.line, position.column));
+                assert_eq!("Expected a 'e' at line 3 column 6", e.to_string());
+            }
+            _ => assert!(false),
+        }
+        match Parser::parse("\"日本\" x") {
+            Err(e) => assert_eq!(6, e.position.unwrap().column),
+            _ => assert!(false),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn serialized_elements_should_parse_back(element in arb_json_element(Numbers::Any, "(?s:.){0,8}")) {
+            let json = Serializer::serialize(&element);
+            prop_assert_eq!(Some(element), Parser::parse(&json).ok());
+        }
+
+        #[test]
+        fn parse_should_not_panic_on_arbitrary_strings(json in any::<String>()) {
+            let _ = Parser::parse(&json);
+        }
+
+        #[test]
+        fn parse_should_not_panic_on_json_like_strings(
+            json in near_json()
+        ) {
+            if let Ok(element) = Parser::parse(&json) {
+                let serialized = Serializer::serialize(&element);
+                prop_assert_eq!(Some(element), Parser::parse(&serialized).ok());
+            }
+        }
+    }
 }
@@ -0,0 +1,558 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Deep merging of layered documents, such as a base configuration with
+//! environment and local overrides on top.
+//!
+//! Objects are always merged member by member. How arrays and everything else
+//! are combined is set in `MergeOptions`, for the whole document or for values
+//! at particular paths, and the result records which layer each value came
+//! from.
+
+use crate::json_element::{JsonElement, Map};
+use crate::pointer::{array_index, JsonPointer};
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// How deeply nested the layers may be. Merging recurses once per level.
+const MAX_DEPTH: usize = 512;
+
+#[derive(Debug, PartialEq)]
+pub struct MergeError {
+    pub details: String,
+}
+
+impl MergeError {
+    pub fn new(msg: &str) -> MergeError {
+        return MergeError {
+            details: msg.to_string(),
+        };
+    }
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl core::error::Error for MergeError {}
+
+/// How an array is combined with an array in a later layer.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ArrayStrategy {
+    /// The later array takes the place of the earlier one.
+    #[default]
+    Replace,
+    /// The later elements go after the earlier ones.
+    Append,
+    /// Objects with the same value for this member are merged, and the other
+    /// later elements appended.
+    UnionByKey(String),
+    /// Elements at the same index are merged, and later elements past the end
+    /// of the earlier array appended.
+    MergeByIndex,
+}
+
+/// How a value other than two objects or two arrays is combined with the value
+/// at the same path in a later layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScalarStrategy {
+    /// The later value wins.
+    #[default]
+    Override,
+    /// The earlier value stays.
+    KeepExisting,
+    /// Different values are an error.
+    ErrorOnConflict,
+}
+
+/// The strategies to merge with. A path set here may use `*` as a token
+/// matching any key or index. An array strategy applies to the array at that
+/// path; a scalar strategy also applies to everything inside the value there,
+/// unless a longer path sets another.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    pub arrays: ArrayStrategy,
+    pub scalars: ScalarStrategy,
+    pub array_paths: Vec<(JsonPointer, ArrayStrategy)>,
+    pub scalar_paths: Vec<(JsonPointer, ScalarStrategy)>,
+}
+
+impl MergeOptions {
+    pub fn array_at(mut self, path: JsonPointer, strategy: ArrayStrategy) -> MergeOptions {
+        self.array_paths.push((path, strategy));
+        return self;
+    }
+
+    pub fn scalar_at(mut self, path: JsonPointer, strategy: ScalarStrategy) -> MergeOptions {
+        self.scalar_paths.push((path, strategy));
+        return self;
+    }
+
+    /// The last strategy set for exactly this path, or the default.
+    fn array_strategy(&self, path: &JsonPointer) -> &ArrayStrategy {
+        let found = self.array_paths.iter().rfind(|(pattern, _)| {
+            pattern.tokens().len() == path.tokens().len() && matches(pattern, path)
+        });
+        return match found {
+            Some((_, strategy)) => strategy,
+            None => &self.arrays,
+        };
+    }
+
+    fn scalar_strategy(&self, path: &JsonPointer) -> ScalarStrategy {
+        let mut found: Option<(usize, ScalarStrategy)> = None;
+        for (pattern, strategy) in &self.scalar_paths {
+            let len = pattern.tokens().len();
+            if found.is_some_and(|(longest, _)| len < longest) || !matches(pattern, path) {
+                continue;
+            }
+            found = Some((len, *strategy));
+        }
+        return found.map_or(self.scalars, |(_, strategy)| strategy);
+    }
+}
+
+/// Whether `pattern` names `path` or one of the values holding it.
+fn matches(pattern: &JsonPointer, path: &JsonPointer) -> bool {
+    return pattern.tokens().len() <= path.tokens().len()
+        && pattern
+            .tokens()
+            .iter()
+            .zip(path.tokens())
+            .all(|(expected, token)| expected == "*" || expected == token);
+}
+
+/// Where a value of a merged document came from: the index of its layer and
+/// its path in that layer, which differs when arrays are combined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub layer: usize,
+    pub path: JsonPointer,
+}
+
+/// The merged document and where each of its leaves (see
+/// `JsonElement::leaves`) came from.
+#[derive(Debug, PartialEq)]
+pub struct Merged {
+    pub value: JsonElement,
+    pub provenance: Map<JsonPointer, Provenance>,
+}
+
+/// Where the values of a subtree came from, down to where they all came from
+/// the same place. Arrays and objects also keep where they came from.
+#[derive(Clone)]
+enum Origin {
+    Value(Provenance),
+    Array(Provenance, Vec<Origin>),
+    Object(Provenance, Map<String, Origin>),
+}
+
+impl Origin {
+    fn new(layer: usize, path: &JsonPointer) -> Origin {
+        return Origin::Value(Provenance {
+            layer,
+            path: path.clone(),
+        });
+    }
+
+    /// Splits a subtree that came from one place into its members or elements.
+    fn expand(&mut self, element: &JsonElement) {
+        let provenance = match self {
+            Origin::Value(provenance) => provenance.clone(),
+            _ => return,
+        };
+        let layer = provenance.layer;
+        *self = match element {
+            JsonElement::Array(array) => Origin::Array(
+                provenance.clone(),
+                (0..array.len())
+                    .map(|index| Origin::new(layer, &provenance.path.index(index)))
+                    .collect(),
+            ),
+            JsonElement::Object(object) => Origin::Object(
+                provenance.clone(),
+                object
+                    .keys()
+                    .map(|key| (key.clone(), Origin::new(layer, &provenance.path.child(key))))
+                    .collect(),
+            ),
+            _ => return,
+        };
+    }
+
+    fn elements(&mut self) -> &mut Vec<Origin> {
+        return match self {
+            Origin::Array(_, origins) => origins,
+            _ => unreachable!(),
+        };
+    }
+
+    fn provenance(&self) -> &Provenance {
+        return match self {
+            Origin::Value(provenance)
+            | Origin::Array(provenance, _)
+            | Origin::Object(provenance, _) => provenance,
+        };
+    }
+
+    /// Where the value at `path` in the merged document came from.
+    fn provenance_of(&self, path: &JsonPointer) -> Provenance {
+        let mut origin = self;
+        for (depth, token) in path.tokens().iter().enumerate() {
+            origin = match origin {
+                Origin::Value(provenance) => {
+                    let mut found = provenance.clone();
+                    for token in &path.tokens()[depth..] {
+                        found.path.push(token);
+                    }
+                    return found;
+                }
+                Origin::Array(_, origins) => &origins[array_index(token).unwrap()],
+                Origin::Object(_, origins) => &origins[token],
+            };
+        }
+        return origin.provenance().clone();
+    }
+}
+
+struct Merger<'a> {
+    options: &'a MergeOptions,
+    layer: usize,
+    /// The path of the value being merged in the merged document.
+    path: JsonPointer,
+    /// The path of the value being merged on top of it, in its layer.
+    source_path: JsonPointer,
+}
+
+impl Merger<'_> {
+    fn merge(
+        &mut self,
+        target: &mut JsonElement,
+        origin: &mut Origin,
+        source: &JsonElement,
+    ) -> Result<(), MergeError> {
+        if self.path.tokens().len() > MAX_DEPTH {
+            return Err(MergeError::new("Values are nested too deeply to merge"));
+        }
+        match (&*target, source) {
+            (JsonElement::Object(_), JsonElement::Object(members)) => {
+                origin.expand(target);
+                let mut keys: Vec<&String> = members.keys().collect();
+                keys.sort();
+                for key in keys {
+                    self.member(target, origin, key, &members[key])?;
+                }
+            }
+            (JsonElement::Array(_), JsonElement::Array(elements)) => {
+                match self.options.array_strategy(&self.path).clone() {
+                    ArrayStrategy::Replace => self.replace(target, origin, source),
+                    ArrayStrategy::Append => {
+                        origin.expand(target);
+                        for (index, element) in elements.iter().enumerate() {
+                            self.push(target, origin, index, element);
+                        }
+                    }
+                    ArrayStrategy::MergeByIndex => {
+                        origin.expand(target);
+                        for (index, element) in elements.iter().enumerate() {
+                            self.element(target, origin, index, index, element)?;
+                        }
+                    }
+                    ArrayStrategy::UnionByKey(key) => {
+                        origin.expand(target);
+                        for (index, element) in elements.iter().enumerate() {
+                            match find_by_key(target, &key, element) {
+                                Some(found) => {
+                                    self.element(target, origin, found, index, element)?
+                                }
+                                None => self.push(target, origin, index, element),
+                            }
+                        }
+                    }
+                }
+            }
+            // An equal value keeps the layer it first came from.
+            (existing, _) if existing == source => {}
+            (_, _) => match self.options.scalar_strategy(&self.path) {
+                ScalarStrategy::Override => self.replace(target, origin, source),
+                ScalarStrategy::KeepExisting => {}
+                ScalarStrategy::ErrorOnConflict => {
+                    return Err(MergeError::new(&format!(
+                        "Conflicting values at {} in layers {} and {}",
+                        self.path,
+                        origin.provenance().layer,
+                        self.layer
+                    )))
+                }
+            },
+        }
+        return Ok(());
+    }
+
+    fn replace(&self, target: &mut JsonElement, origin: &mut Origin, source: &JsonElement) {
+        *target = source.clone();
+        *origin = Origin::new(self.layer, &self.source_path);
+    }
+
+    fn member(
+        &mut self,
+        target: &mut JsonElement,
+        origin: &mut Origin,
+        key: &str,
+        source: &JsonElement,
+    ) -> Result<(), MergeError> {
+        let (members, origins) = match (target, origin) {
+            (JsonElement::Object(members), Origin::Object(_, origins)) => (members, origins),
+            _ => unreachable!(),
+        };
+        let existing = match members.get_mut(key) {
+            Some(existing) => existing,
+            None => {
+                members.insert(key.to_string(), source.clone());
+                let origin = Origin::new(self.layer, &self.source_path.child(key));
+                origins.insert(key.to_string(), origin);
+                return Ok(());
+            }
+        };
+        let (len, source_len) = (self.path.tokens().len(), self.source_path.tokens().len());
+        self.path.push(key);
+        self.source_path.push(key);
+        let result = self.merge(existing, origins.get_mut(key).unwrap(), source);
+        self.path.truncate(len);
+        self.source_path.truncate(source_len);
+        return result;
+    }
+
+    /// Merges element `source_index` of the later array into element `index`.
+    fn element(
+        &mut self,
+        target: &mut JsonElement,
+        origin: &mut Origin,
+        index: usize,
+        source_index: usize,
+        source: &JsonElement,
+    ) -> Result<(), MergeError> {
+        let existing = match target {
+            JsonElement::Array(elements) if index < elements.len() => &mut elements[index],
+            _ => {
+                self.push(target, origin, source_index, source);
+                return Ok(());
+            }
+        };
+        let (len, source_len) = (self.path.tokens().len(), self.source_path.tokens().len());
+        self.path.push(&index.to_string());
+        self.source_path.push(&source_index.to_string());
+        let result = self.merge(existing, &mut origin.elements()[index], source);
+        self.path.truncate(len);
+        self.source_path.truncate(source_len);
+        return result;
+    }
+
+    fn push(
+        &self,
+        target: &mut JsonElement,
+        origin: &mut Origin,
+        source_index: usize,
+        source: &JsonElement,
+    ) {
+        if let JsonElement::Array(elements) = target {
+            elements.push(source.clone());
+            let path = self.source_path.index(source_index);
+            origin.elements().push(Origin::new(self.layer, &path));
+        }
+    }
+}
+
+/// The index of the object in `target` with the same value for `key` as
+/// `element`, when `element` is an object with that member.
+fn find_by_key(target: &JsonElement, key: &str, element: &JsonElement) -> Option<usize> {
+    let wanted = match element {
+        JsonElement::Object(members) => members.get(key)?,
+        _ => return None,
+    };
+    let elements = match target {
+        JsonElement::Array(elements) => elements,
+        _ => return None,
+    };
+    return elements.iter().position(|candidate| match candidate {
+        JsonElement::Object(members) => members.get(key) == Some(wanted),
+        _ => false,
+    });
+}
+
+impl JsonElement {
+    /// Merges `layers` in order, each on top of the ones before it.
+    pub fn merge(layers: &[JsonElement], options: &MergeOptions) -> Result<Merged, MergeError> {
+        let (first, rest) = match layers.split_first() {
+            Some(split) => split,
+            None => return Err(MergeError::new("There are no layers to merge")),
+        };
+        let mut value = first.clone();
+        let mut origin = Origin::new(0, &JsonPointer::root());
+        for (index, layer) in rest.iter().enumerate() {
+            let mut merger = Merger {
+                options,
+                layer: index + 1,
+                path: JsonPointer::root(),
+                source_path: JsonPointer::root(),
+            };
+            merger.merge(&mut value, &mut origin, layer)?;
+        }
+        let provenance = value
+            .leaves()
+            .map(|(path, _)| {
+                let provenance = origin.provenance_of(&path);
+                (path, provenance)
+            })
+            .collect();
+        return Ok(Merged { value, provenance });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArrayStrategy, MergeOptions, Provenance, ScalarStrategy};
+    use crate::json_element::JsonElement;
+    use crate::pointer::JsonPointer;
+    use crate::test_support::json;
+
+    fn pointer(text: &str) -> JsonPointer {
+        return JsonPointer::parse(text).ok().unwrap();
+    }
+
+    #[test]
+    fn should_merge_objects_and_record_layers() {
+        let layers = [
+            json(r#"{"db": {"host": "localhost", "port": 5432}, "tags": ["a"], "debug": false}"#),
+            json(r#"{"db": {"host": "db.prod"}, "tags": ["b", "c"]}"#),
+            json(r#"{"debug": true, "extra": {}}"#),
+        ];
+        let merged = JsonElement::merge(&layers, &MergeOptions::default())
+            .ok()
+            .unwrap();
+        assert_eq!(
+            json(
+                r#"{"db": {"host": "db.prod", "port": 5432}, "tags": ["b", "c"],
+                    "debug": true, "extra": {}}"#
+            ),
+            merged.value
+        );
+        let mut provenance: Vec<(String, usize)> = merged
+            .provenance
+            .iter()
+            .map(|(path, provenance)| (path.to_string(), provenance.layer))
+            .collect();
+        provenance.sort();
+        assert_eq!(
+            vec![
+                ("/db/host".to_string(), 1),
+                ("/db/port".to_string(), 0),
+                ("/debug".to_string(), 2),
+                ("/extra".to_string(), 2),
+                ("/tags/0".to_string(), 1),
+                ("/tags/1".to_string(), 1),
+            ],
+            provenance
+        );
+    }
+
+    #[test]
+    fn should_combine_arrays_by_strategy() {
+        let layers = [
+            json(
+                r#"{"plugins": ["a"], "ports": [80, 443], "servers": [{"name": "x", "cpu": 1},
+                    {"name": "y", "cpu": 2}], "nested": [{"list": [1]}]}"#,
+            ),
+            json(
+                r#"{"plugins": ["b"], "ports": [8080], "servers": [{"name": "y", "cpu": 4},
+                    {"name": "z"}, 3], "nested": [{"list": [2]}]}"#,
+            ),
+        ];
+        let options = MergeOptions {
+            arrays: ArrayStrategy::Append,
+            ..MergeOptions::default()
+        }
+        .array_at(pointer("/ports"), ArrayStrategy::MergeByIndex)
+        .array_at(
+            pointer("/servers"),
+            ArrayStrategy::UnionByKey("name".to_string()),
+        )
+        .array_at(pointer("/nested"), ArrayStrategy::MergeByIndex)
+        .array_at(pointer("/nested/*/list"), ArrayStrategy::Replace);
+        let merged = JsonElement::merge(&layers, &options).ok().unwrap();
+        assert_eq!(
+            json(
+                r#"{"plugins": ["a", "b"], "ports": [8080, 443],
+                    "servers": [{"name": "x", "cpu": 1}, {"name": "y", "cpu": 4}, {"name": "z"}, 3],
+                    "nested": [{"list": [2]}]}"#
+            ),
+            merged.value
+        );
+        for (path, layer, source_path) in [
+            ("/servers/1/name", 0, "/servers/1/name"),
+            ("/servers/1/cpu", 1, "/servers/0/cpu"),
+            ("/servers/2/name", 1, "/servers/1/name"),
+            ("/ports/1", 0, "/ports/1"),
+            ("/plugins/1", 1, "/plugins/0"),
+            ("/nested/0/list/0", 1, "/nested/0/list/0"),
+        ] {
+            let provenance = Provenance {
+                layer,
+                path: pointer(source_path),
+            };
+            assert_eq!(
+                Some(&provenance),
+                merged.provenance.get(&pointer(path)),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_apply_scalar_strategies() {
+        let layers = [
+            json(r#"{"secrets": {"key": "a", "inner": {"token": "t"}}, "name": "base"}"#),
+            json(r#"{"secrets": {"key": "b", "inner": {"token": "u"}}, "name": "env"}"#),
+        ];
+        let options = MergeOptions::default()
+            .scalar_at(pointer("/secrets"), ScalarStrategy::KeepExisting)
+            .scalar_at(pointer("/secrets/inner"), ScalarStrategy::Override);
+        let merged = JsonElement::merge(&layers, &options).ok().unwrap();
+        assert_eq!(
+            json(r#"{"secrets": {"key": "a", "inner": {"token": "u"}}, "name": "env"}"#),
+            merged.value
+        );
+
+        let strict = MergeOptions {
+            scalars: ScalarStrategy::ErrorOnConflict,
+            ..MergeOptions::default()
+        };
+        assert_eq!(
+            "Conflicting values at /name in layers 0 and 1",
+            JsonElement::merge(&layers[..], &strict)
+                .err()
+                .unwrap()
+                .details
+        );
+        let same = [json(r#"{"a": 1, "b": [1]}"#), json(r#"{"a": 1, "c": 2}"#)];
+        assert!(JsonElement::merge(&same, &strict).is_ok());
+        assert!(JsonElement::merge(&[], &strict).is_err());
+    }
+}
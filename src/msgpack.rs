@@ -0,0 +1,311 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Converts values to and from MessagePack.
+//!
+//! Encoding uses the smallest format that holds each value. Decoding accepts
+//! every format except extension types, which are errors. See `jsonp::binary`
+//! for how numbers, bin values and map keys map to json.
+
+use crate::binary::{self, BinaryError, BinaryResult, DecodeOptions, Encoder, Input, Item};
+use crate::json_element::JsonElement;
+
+use alloc::vec::Vec;
+
+/// Encodes `element` as MessagePack.
+pub fn encode(element: &JsonElement) -> Vec<u8> {
+    let mut encoder = MsgpackEncoder { out: Vec::new() };
+    binary::encode(element, &mut encoder);
+    return encoder.out;
+}
+
+/// Decodes a single MessagePack value with the default `DecodeOptions`.
+pub fn decode(bytes: &[u8]) -> Result<JsonElement, BinaryError> {
+    return decode_with_options(bytes, &DecodeOptions::default());
+}
+
+pub fn decode_with_options(
+    bytes: &[u8],
+    options: &DecodeOptions,
+) -> Result<JsonElement, BinaryError> {
+    return binary::decode(&mut Input::new(bytes), options, read_item);
+}
+
+struct MsgpackEncoder {
+    out: Vec<u8>,
+}
+
+impl MsgpackEncoder {
+    /// Writes a length with the fix format when it fits in `fix_bits`, else
+    /// with the 8 bit format if there is one (arrays and maps have none), else
+    /// with the 16 or 32 bit format at `format` and `format + 1`.
+    fn length(&mut self, len: usize, fix: u8, fix_bits: u32, format_8: Option<u8>, format: u8) {
+        if len < 1 << fix_bits {
+            self.out.push(fix | len as u8);
+        } else if let (Some(format_8), true) = (format_8, len <= u8::MAX as usize) {
+            self.out.extend_from_slice(&[format_8, len as u8]);
+        } else if len <= u16::MAX as usize {
+            self.out.push(format);
+            self.out.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            self.out.push(format + 1);
+            self.out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+impl Encoder for MsgpackEncoder {
+    fn null(&mut self) {
+        self.out.push(0xc0);
+    }
+
+    fn boolean(&mut self, value: bool) {
+        self.out.push(if value { 0xc3 } else { 0xc2 });
+    }
+
+    fn number(&mut self, number: f64) {
+        let out = &mut self.out;
+        match binary::as_integer(number) {
+            Some(n @ -32..=127) => out.push(n as i8 as u8),
+            Some(n @ 0..=0xff) => out.extend_from_slice(&[0xcc, n as u8]),
+            Some(n @ 0..=0xffff) => {
+                out.push(0xcd);
+                out.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            Some(n @ 0..=0xffff_ffff) => {
+                out.push(0xce);
+                out.extend_from_slice(&(n as u32).to_be_bytes());
+            }
+            Some(n @ 0..) => {
+                out.push(0xcf);
+                out.extend_from_slice(&(n as u64).to_be_bytes());
+            }
+            Some(n @ -0x80..) => out.extend_from_slice(&[0xd0, n as i8 as u8]),
+            Some(n @ -0x8000..) => {
+                out.push(0xd1);
+                out.extend_from_slice(&(n as i16).to_be_bytes());
+            }
+            Some(n @ -0x8000_0000..) => {
+                out.push(0xd2);
+                out.extend_from_slice(&(n as i32).to_be_bytes());
+            }
+            Some(n) => {
+                out.push(0xd3);
+                out.extend_from_slice(&n.to_be_bytes());
+            }
+            None => {
+                out.push(0xcb);
+                out.extend_from_slice(&number.to_be_bytes());
+            }
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.length(value.len(), 0xa0, 5, Some(0xd9), 0xda);
+        self.out.extend_from_slice(value.as_bytes());
+    }
+
+    fn array(&mut self, len: usize) {
+        self.length(len, 0x90, 4, None, 0xdc);
+    }
+
+    fn map(&mut self, len: usize) {
+        self.length(len, 0x80, 4, None, 0xde);
+    }
+}
+
+fn read_item(input: &mut Input) -> BinaryResult<Item> {
+    let start = input.offset;
+    let format = input.byte()?;
+    return match format {
+        0x00..=0x7f => Ok(Item::Number(format as f64)),
+        0x80..=0x8f => Ok(Item::Map(Some(map_length(input, (format & 0x0f) as u64)?))),
+        0x90..=0x9f => Ok(Item::Array(Some(input.length((format & 0x0f) as u64)?))),
+        0xa0..=0xbf => Ok(Item::Str(input.string((format & 0x1f) as usize)?)),
+        0xc0 => Ok(Item::Null),
+        0xc1 => Err(BinaryError::new("Invalid format 0xc1", start)),
+        0xc2 => Ok(Item::Boolean(false)),
+        0xc3 => Ok(Item::Boolean(true)),
+        0xc4..=0xc6 => {
+            let len = input.uint(1 << (format - 0xc4))?;
+            let len = input.length(len)?;
+            Ok(Item::Bytes(input.take(len)?.to_vec()))
+        }
+        0xc7..=0xc9 | 0xd4..=0xd8 => {
+            Err(BinaryError::new("Extension types are not supported", start))
+        }
+        0xca => input.float(4),
+        0xcb => input.float(8),
+        0xcc..=0xcf => Ok(Item::Number(input.uint(1 << (format - 0xcc))? as f64)),
+        0xd0..=0xd3 => {
+            let size = 1 << (format - 0xd0);
+            let bits = input.uint(size)?;
+            // Sign-extends the integer to 64 bits.
+            let shift = 64 - 8 * size as u32;
+            Ok(Item::Number(((bits << shift) as i64 >> shift) as f64))
+        }
+        0xd9..=0xdb => {
+            let len = input.uint(1 << (format - 0xd9))?;
+            let len = input.length(len)?;
+            Ok(Item::Str(input.string(len)?))
+        }
+        0xdc | 0xdd => {
+            let len = input.uint(2 << (format - 0xdc))?;
+            Ok(Item::Array(Some(input.length(len)?)))
+        }
+        0xde | 0xdf => {
+            let len = input.uint(2 << (format - 0xde))?;
+            Ok(Item::Map(Some(map_length(input, len)?)))
+        }
+        0xe0..=0xff => Ok(Item::Number(format as i8 as f64)),
+    };
+}
+
+/// A map's entry count, checked like other lengths but for two items each.
+fn map_length(input: &Input, len: u64) -> BinaryResult<usize> {
+    return Ok(input.length(len.saturating_mul(2))? / 2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{Bytes, Keys};
+    use crate::parser::Parser;
+    use crate::serializer::Serializer;
+    use crate::test_support::{arb_json_element, Numbers};
+    use alloc::vec;
+    use proptest::prelude::*;
+
+    #[test]
+    fn should_use_the_smallest_formats() {
+        let examples: [(&str, &[u8]); 16] = [
+            ("0", &[0x00]),
+            ("127", &[0x7f]),
+            ("128", &[0xcc, 0x80]),
+            ("256", &[0xcd, 0x01, 0x00]),
+            ("65536", &[0xce, 0x00, 0x01, 0x00, 0x00]),
+            ("4294967296", &[0xcf, 0, 0, 0, 1, 0, 0, 0, 0]),
+            ("-32", &[0xe0]),
+            ("-33", &[0xd0, 0xdf]),
+            ("-129", &[0xd1, 0xff, 0x7f]),
+            ("-32769", &[0xd2, 0xff, 0xff, 0x7f, 0xff]),
+            (
+                "-2147483649",
+                &[0xd3, 0xff, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff],
+            ),
+            ("1.5", &[0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]),
+            ("[null,true,false]", &[0x93, 0xc0, 0xc3, 0xc2]),
+            ("\"ab\"", &[0xa2, b'a', b'b']),
+            (
+                "{\"b\":[],\"a\":{}}",
+                &[0x82, 0xa1, b'a', 0x80, 0xa1, b'b', 0x90],
+            ),
+            ("-0", &[0xcb, 0x80, 0, 0, 0, 0, 0, 0, 0]),
+        ];
+        for (json, bytes) in examples {
+            let element = Parser::parse(json).unwrap();
+            assert_eq!(bytes, encode(&element), "{json}");
+            assert_eq!(element, decode(bytes).unwrap(), "{json}");
+        }
+
+        let long = "x".repeat(40);
+        assert_eq!(&[0xd9, 40], &encode(&JsonElement::Str(long.clone()))[..2]);
+        let array = JsonElement::Array(vec![JsonElement::Null; 16]);
+        assert_eq!(&[0xdc, 0, 16], &encode(&array)[..3]);
+
+        // Formats only produced by other encoders.
+        let decoded: [(&[u8], &str); 6] = [
+            (&[0xca, 0x3f, 0xc0, 0x00, 0x00], "1.5"),
+            (&[0xd0, 0x05], "5"),
+            (
+                &[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+                "18446744073709551615",
+            ),
+            (&[0xda, 0x00, 0x01, b'x'], "\"x\""),
+            (&[0xdd, 0, 0, 0, 1, 0x01], "[1]"),
+            (&[0xdf, 0, 0, 0, 1, 0xa1, b'k', 0xc0], "{\"k\":null}"),
+        ];
+        for (bytes, json) in decoded {
+            assert_eq!(
+                Parser::parse(json).unwrap(),
+                decode(bytes).unwrap(),
+                "{json}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_map_bytes_and_keys_by_options() {
+        let bin = [0xc4, 0x03, 0xfb, 0xff, 0xbf];
+        assert_eq!(JsonElement::Str("-_-_".into()), decode(&bin).unwrap());
+        let options = DecodeOptions {
+            bytes: Bytes::Array,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            Parser::parse("[251,255,191]").unwrap(),
+            decode_with_options(&bin, &options).unwrap()
+        );
+
+        let map = [0x82, 0xc3, 0x01, 0x93, 0x01, 0x02, 0x03, 0x02];
+        assert_eq!(
+            BinaryError::new("Map keys must be strings", 1),
+            decode(&map).unwrap_err()
+        );
+        let options = DecodeOptions {
+            keys: Keys::Stringify,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            Parser::parse("{\"true\":1,\"[1,2,3]\":2}").unwrap(),
+            decode_with_options(&map, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_report_invalid_input() {
+        let cases: [(&[u8], &str, usize); 8] = [
+            (&[], "Unexpected end of input", 0),
+            (&[0xc1], "Invalid format 0xc1", 0),
+            (&[0xd4, 0x01, 0x00], "Extension types are not supported", 0),
+            (&[0xa2, b'a'], "Unexpected end of input", 1),
+            (&[0x92, 0x01], "Length is larger than the input", 1),
+            (&[0xc0, 0xc0], "Unexpected bytes after the value", 1),
+            (&[0xa1, 0xff], "Invalid UTF-8 in a string", 1),
+            (
+                &[0xdd, 0xff, 0xff, 0xff, 0xff],
+                "Length is larger than the input",
+                5,
+            ),
+        ];
+        for (bytes, details, offset) in cases {
+            assert_eq!(
+                BinaryError::new(details, offset),
+                decode(bytes).unwrap_err(),
+                "{bytes:?}"
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn should_round_trip_parsed_documents(element in arb_json_element(Numbers::Any, "\\PC{0,4}")) {
+            let parsed = Parser::parse(&Serializer::serialize(&element)).unwrap();
+            let decoded = decode(&encode(&parsed)).unwrap();
+            prop_assert_eq!(&parsed, &decoded);
+            prop_assert_eq!(encode(&parsed), encode(&decoded));
+        }
+    }
+}
@@ -13,57 +13,32 @@
 * limitations under the License.
 */
 
-use crate::json_element::JsonElement;
+use crate::json_element::{JsonElement, Map};
+#[cfg(feature = "simd")]
+use crate::simd::{StructuralCursor, StructuralIndex};
 use crate::token::Token;
 use crate::token::Tokenizer;
 
-use std::collections::HashMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
 
-macro_rules! unbox_token_char {
-    ($t:expr, $e:expr, $c:ident) => {
-        match $t {
-            Token::Character(c) => $c = c,
-            _ => return Err($e),
-        }
-    };
-}
+mod arena;
+mod extract;
+mod recover;
+mod spans;
+pub(crate) use arena::Sink;
+pub use recover::{Diagnostic, Fix, Recovered};
+use spans::SpanRecorder;
+pub use spans::SpanTable;
 
 macro_rules! expect_char {
-    ($token: expr, $c: expr) => {
-        let charac: char;
-        unbox_token_char!($token, ParseError::new("Expected a $c"), charac);
-        if charac != $c {
-            return Err(ParseError::new("Expected a $c"));
+    ($self: ident, $c: expr) => {
+        if !matches!($self.tokenizer.peek_token(), Token::Character(c) if c == $c) {
+            return Err(ParseError::new(&format!("Expected a '{}'", $c)));
         }
-    };
-}
-
-macro_rules! peek_character_or_return {
-    ($string: ident, $self: ident, $c: ident) => {
-        let token = $self.tokenizer.peek_token();
-        match token {
-            Token::Character(nc) => $c = nc,
-            _ => return Ok($string.parse::<f64>().unwrap()),
-        };
-    };
-    ($string: ident, $self: ident, $c: ident, $error: expr) => {
-        let token = $self.tokenizer.peek_token();
-        match token {
-            Token::Character(nc) => $c = nc,
-            _ => return Err($error),
-        };
-    };
-}
-macro_rules! add_c_to_str_and_peek_character {
-    ($string: ident, $self: ident, $c: ident) => {
-        $string.push($c);
-        $self.tokenizer.next_token();
-        peek_character_or_return!($string, $self, $c);
-    };
-    ($string: ident, $self: ident, $c: ident, $error: expr) => {
-        $string.push($c);
         $self.tokenizer.next_token();
-        peek_character_or_return!($string, $self, $c, $error);
     };
 }
 
@@ -74,76 +49,407 @@ macro_rules! parse_hex_digit {
         if !is_hex_digit($c) {
             return Err(ParseError::new("Expected a hex digit after a \\u"));
         }
-        $hex = $hex | (hex_char_to_u32($c) << ($index * 4))
+        $hex |= hex_char_to_u32($c) << ($index * 4)
     };
 }
 pub struct Parser<'a> {
     tokenizer: Tokenizer<'a>,
+    options: ParserOptions,
+    /// Set when the caller asked where each value is.
+    spans: Option<SpanRecorder>,
+    /// How deeply nested the value `parse_value` starts at is, when it parses
+    /// part of a document.
+    base_depth: usize,
+    #[cfg(feature = "simd")]
+    index: Option<StructuralCursor>,
+}
+
+/// Limits applied while parsing, so that untrusted input cannot exhaust the
+/// stack or the heap. Every limit is inclusive: a document using exactly
+/// `max_depth` levels of nesting is accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserOptions {
+    /// Maximum number of nested arrays and objects.
+    pub max_depth: usize,
+    /// Maximum size of the whole document, in bytes.
+    pub max_input_bytes: usize,
+    /// Maximum length of a string or object key after unescaping, in bytes.
+    pub max_string_len: usize,
+    /// Maximum number of elements in a single array.
+    pub max_array_len: usize,
+    /// Maximum number of members in a single object.
+    pub max_object_members: usize,
+    /// Maximum number of characters in a number literal.
+    pub max_number_len: usize,
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions {
+            max_depth: 128,
+            max_input_bytes: 64 * 1024 * 1024,
+            max_string_len: 16 * 1024 * 1024,
+            max_array_len: 1_000_000,
+            max_object_members: 1_000_000,
+            max_number_len: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The input is not valid json.
+    Syntax,
+    /// Arrays and objects are nested deeper than `ParserOptions::max_depth`.
+    DepthLimitExceeded,
+    /// The input is larger than `ParserOptions::max_input_bytes`.
+    InputTooLarge,
+    /// A string is longer than `ParserOptions::max_string_len`.
+    StringTooLong,
+    /// An array has more elements than `ParserOptions::max_array_len`.
+    ArrayTooLong,
+    /// An object has more members than `ParserOptions::max_object_members`.
+    TooManyObjectMembers,
+    /// A number is longer than `ParserOptions::max_number_len`.
+    NumberTooLong,
+    /// Reading the input failed.
+    Io,
+}
+
+/// A location in the input. `offset` is in bytes; `line` and `column` count
+/// from 1, with columns in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A range of the input, from `start` up to but not including `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// The offsets where lines start in a text, to turn byte offsets into
+/// positions without rescanning everything before them.
+pub(crate) struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
 }
 
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(text: &'a str) -> LineIndex<'a> {
+        let starts = core::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        return LineIndex { text, starts };
+    }
+
+    /// The position of `offset`, which must lie on a char boundary.
+    pub(crate) fn position(&self, offset: usize) -> Position {
+        let line = self.starts.partition_point(|start| *start <= offset) - 1;
+        return Position {
+            offset,
+            line: line + 1,
+            column: self.text[self.starts[line]..offset].chars().count() + 1,
+        };
+    }
+
+    pub(crate) fn span(&self, start: usize, end: usize) -> Span {
+        return Span {
+            start: self.position(start),
+            end: self.position(end),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ParseError {
+    pub kind: ParseErrorKind,
     pub details: String,
+    /// Where parsing stopped, when the error comes from `Parser`.
+    pub position: Option<Position>,
 }
 
 impl ParseError {
     pub fn new(msg: &str) -> ParseError {
+        return ParseError::with_kind(ParseErrorKind::Syntax, msg);
+    }
+
+    pub fn with_kind(kind: ParseErrorKind, msg: &str) -> ParseError {
         return ParseError {
+            kind,
             details: msg.to_string(),
+            position: None,
         };
     }
 }
 
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(
+                f,
+                "{} at line {} column {}",
+                self.details, position.line, position.column
+            ),
+            None => write!(f, "{}", self.details),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
 type BoxResult<T> = Result<T, ParseError>;
-type JsonObject = HashMap<String, JsonElement>;
+type JsonObject = Map<String, JsonElement>;
+
+/// An array or object whose members are still being parsed. Objects keep the
+/// key of the member whose value comes next.
+enum Frame {
+    Array(Vec<JsonElement>),
+    Object(JsonObject, String),
+}
 
 fn is_hex_digit(c: char) -> bool {
-    return (c >= '0' && c <= '9') || (c >= 'a' && c <= 'f') || (c >= 'A' && c <= 'F');
+    return c.is_ascii_hexdigit();
 }
 
 fn hex_char_to_u32(c: char) -> u32 {
-    if c >= '0' && c <= '9' {
-        return (c as u32 - '0' as u32) as u32;
+    if c.is_ascii_digit() {
+        return c as u32 - '0' as u32;
+    }
+    if ('a'..='f').contains(&c) {
+        return c as u32 - 'a' as u32 + 10;
+    }
+    return c as u32 - 'A' as u32 + 10;
+}
+
+#[cfg(feature = "simd")]
+fn is_whitespace(byte: u8) -> bool {
+    return matches!(byte, b' ' | b'\n' | b'\r' | b'\t');
+}
+
+/// Whether `word` is a whole number literal: an optional minus, an integer part
+/// without leading zeros, and optional fraction and exponent parts.
+#[cfg(feature = "simd")]
+fn is_number(word: &[u8]) -> bool {
+    fn digits(word: &[u8], i: &mut usize) -> usize {
+        let start = *i;
+        while word.get(*i).is_some_and(u8::is_ascii_digit) {
+            *i += 1;
+        }
+        return *i - start;
+    }
+    let mut i = usize::from(word.first() == Some(&b'-'));
+    match word.get(i) {
+        Some(b'0') => i += 1,
+        Some(b'1'..=b'9') => {
+            digits(word, &mut i);
+        }
+        _ => return false,
+    }
+    if word.get(i) == Some(&b'.') {
+        i += 1;
+        if digits(word, &mut i) == 0 {
+            return false;
+        }
     }
-    if c >= 'a' && c <= 'f' {
-        return (c as u32 - 'a' as u32) as u32;
+    if matches!(word.get(i), Some(b'e' | b'E')) {
+        i += 1;
+        if matches!(word.get(i), Some(b'+' | b'-')) {
+            i += 1;
+        }
+        if digits(word, &mut i) == 0 {
+            return false;
+        }
     }
-    return (c as u32 - 'A' as u32) as u32;
+    return i == word.len();
+}
+
+pub(crate) fn string_to_number(string: &str) -> BoxResult<f64> {
+    return match string.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        Ok(_) => Err(ParseError::new(&format!("Number {string} is out of range"))),
+        Err(_) => Err(ParseError::new(&format!("Invalid number {string}"))),
+    };
 }
 
 impl<'a> Parser<'a> {
+    fn new(json: &'a str, options: ParserOptions) -> Parser<'a> {
+        Parser {
+            tokenizer: Tokenizer::new(json),
+            options,
+            spans: None,
+            base_depth: 0,
+            #[cfg(feature = "simd")]
+            index: None,
+        }
+    }
+
+    /// A parser for `json`, with the structural index built when it is enabled.
+    fn indexed(json: &'a str, options: &ParserOptions) -> Parser<'a> {
+        let parser = Parser::new(json, *options);
+        #[cfg(feature = "simd")]
+        let parser = Parser {
+            index: (json.len() <= options.max_input_bytes)
+                .then(|| StructuralCursor::new(StructuralIndex::build(json))),
+            ..parser
+        };
+        return parser;
+    }
+
     fn consume_whitespace(&mut self) {
+        #[cfg(feature = "simd")]
+        if self.skip_indexed_whitespace() {
+            return;
+        }
         loop {
             let token = self.tokenizer.peek_token();
             match token {
-                Token::Character(c) => match c {
-                    ' ' | '\n' | '\r' | '\t' => {
-                        self.tokenizer.next_token();
-                    }
-                    _ => break,
-                },
+                Token::Character(' ' | '\n' | '\r' | '\t') => {
+                    self.tokenizer.next_token();
+                }
                 _ => break,
             }
         }
     }
 
     fn token_to_char(token: Token) -> BoxResult<char> {
-        let c: char;
-        match token {
-            Token::Character(character) => c = character,
-            Token::CloseBracket => c = '}',
-            Token::OpenBracket => c = '{',
-            Token::CloseSquareBracket => c = ']',
-            Token::OpenSquareBracket => c = '[',
-            Token::Colon => c = ':',
-            Token::Comma => c = ',',
-            Token::Quotion => c = '"',
+        let c = match token {
+            Token::Character(character) => character,
+            Token::CloseBracket => '}',
+            Token::OpenBracket => '{',
+            Token::CloseSquareBracket => ']',
+            Token::OpenSquareBracket => '[',
+            Token::Colon => ':',
+            Token::Comma => ',',
+            Token::Quotion => '"',
             Token::End => return Err(ParseError::new("Json ended without closing string")),
-        }
+        };
         return Ok(c);
     }
 
+    /// Moves straight to the next structural character when only whitespace lies
+    /// before it. Returns false when the whitespace has to be skipped one
+    /// character at a time instead.
+    #[cfg(feature = "simd")]
+    fn skip_indexed_whitespace(&mut self) -> bool {
+        let start = self.tokenizer.cursor();
+        let end = match &mut self.index {
+            Some(index) => index.at_or_after(start).unwrap_or(self.tokenizer.len()),
+            None => return false,
+        };
+        if !self.tokenizer.slice(start, end).bytes().all(is_whitespace) {
+            return false;
+        }
+        self.tokenizer.seek(end);
+        return true;
+    }
+
+    /// Reads the number or literal at the cursor, which ends where the next
+    /// structural character or whitespace is. Returns `None` when the value has
+    /// to go through the character by character path instead, which also
+    /// reports its errors.
+    #[cfg(feature = "simd")]
+    fn parse_indexed_scalar(&mut self) -> Option<JsonElement> {
+        let start = self.tokenizer.cursor();
+        let end = self
+            .index
+            .as_mut()?
+            .at_or_after(start)
+            .unwrap_or(self.tokenizer.len());
+        let word = self
+            .tokenizer
+            .slice(start, end)
+            .trim_end_matches([' ', '\n', '\r', '\t']);
+        let value = match word {
+            "true" => JsonElement::Boolean(true),
+            "false" => JsonElement::Boolean(false),
+            "null" => JsonElement::Null,
+            _ if word.len() <= self.options.max_number_len && is_number(word.as_bytes()) => {
+                JsonElement::Number(string_to_number(word).ok()?)
+            }
+            _ => return None,
+        };
+        self.tokenizer.seek(start + word.len());
+        return Some(value);
+    }
+
+    /// Copies a string without escapes or control characters straight from the
+    /// input, using the structural index to find its closing quote and to tell
+    /// whether it has any. Returns false when the string has to go through the
+    /// character by character path instead.
+    #[cfg(feature = "simd")]
+    fn parse_indexed_string(&mut self, string: &mut String) -> bool {
+        let start = self.tokenizer.cursor();
+        let index = match &mut self.index {
+            Some(index) => index,
+            None => return false,
+        };
+        let end = match index.next_after(start) {
+            Some(end) => end,
+            None => return false,
+        };
+        let contents = self.tokenizer.slice(start + 1, end);
+        if index.has_special_characters(start)
+            || contents.len() > self.options.max_string_len
+            || !self.tokenizer.slice(end, end + 1).starts_with('"')
+        {
+            return false;
+        }
+        string.push_str(contents);
+        self.tokenizer.seek(end + 1);
+        return true;
+    }
+
+    /// Reads the four hex digits after a `\\u`, and the low half of a surrogate
+    /// pair when they encode a high surrogate.
+    fn parse_unicode_escape(&mut self) -> BoxResult<u32> {
+        let mut c: char;
+        let mut hex: u32 = 0;
+        parse_hex_digit!(self, c, hex, 3);
+        parse_hex_digit!(self, c, hex, 2);
+        parse_hex_digit!(self, c, hex, 1);
+        parse_hex_digit!(self, c, hex, 0);
+        if !(0xD800..0xDC00).contains(&hex) {
+            return Ok(hex);
+        }
+        let high = hex;
+        if !matches!(self.tokenizer.next_token(), Token::Character('\\'))
+            || !matches!(self.tokenizer.next_token(), Token::Character('u'))
+        {
+            return Err(ParseError::new(&format!(
+                "Expected a low surrogate after \\u{high:x}"
+            )));
+        }
+        hex = 0;
+        parse_hex_digit!(self, c, hex, 3);
+        parse_hex_digit!(self, c, hex, 2);
+        parse_hex_digit!(self, c, hex, 1);
+        parse_hex_digit!(self, c, hex, 0);
+        if !(0xDC00..0xE000).contains(&hex) {
+            return Err(ParseError::new(&format!(
+                "Expected a low surrogate after \\u{high:x}"
+            )));
+        }
+        return Ok(0x10000 + ((high - 0xD800) << 10) + (hex - 0xDC00));
+    }
+
     fn parse_string(&mut self) -> BoxResult<String> {
         let mut string = String::new();
+        self.parse_string_into(&mut string)?;
+        return Ok(string);
+    }
+
+    /// Appends the contents of the string at the cursor to `string`.
+    fn parse_string_into(&mut self, string: &mut String) -> BoxResult<()> {
+        #[cfg(feature = "simd")]
+        if self.parse_indexed_string(string) {
+            return Ok(());
+        }
+        let start = string.len();
         let mut c: char;
         let mut token: Token;
         self.tokenizer.next_token();
@@ -161,15 +467,12 @@ impl<'a> Parser<'a> {
                         '\\' => string.push('\\'),
                         '/' => string.push('/'),
                         'b' => string.push(0x08 as char),
+                        'f' => string.push(0x0c as char),
                         'n' => string.push('\n'),
                         'r' => string.push('\r'),
                         't' => string.push('\t'),
                         'u' => {
-                            let mut hex: u32 = 0;
-                            parse_hex_digit!(self, c, hex, 3);
-                            parse_hex_digit!(self, c, hex, 2);
-                            parse_hex_digit!(self, c, hex, 1);
-                            parse_hex_digit!(self, c, hex, 0);
+                            let hex = self.parse_unicode_escape()?;
                             match char::from_u32(hex) {
                                 Some(uc) => string.push(uc),
                                 None => {
@@ -185,134 +488,135 @@ impl<'a> Parser<'a> {
                         }
                     }
                 }
+                _ if c < ' ' => {
+                    return Err(ParseError::new(&format!(
+                        "Unescaped control character U+{:04X} in string",
+                        c as u32
+                    )))
+                }
                 _ => string.push(c),
             }
+            if string.len() - start > self.options.max_string_len {
+                return Err(ParseError::with_kind(
+                    ParseErrorKind::StringTooLong,
+                    &format!(
+                        "String is longer than {} bytes",
+                        self.options.max_string_len
+                    ),
+                ));
+            }
         }
 
-        return Ok(string);
+        return Ok(());
     }
 
-    fn parse_object(&mut self) -> BoxResult<JsonObject> {
-        self.tokenizer.next_token();
+    fn parse_key(&mut self) -> BoxResult<String> {
+        let mut name = String::new();
+        self.parse_key_into(&mut name)?;
+        return Ok(name);
+    }
+
+    /// Appends an object key to `name` and moves past the colon after it.
+    fn parse_key_into(&mut self, name: &mut String) -> BoxResult<()> {
         self.consume_whitespace();
-        let mut c = self.tokenizer.peek_token();
-        let mut map: JsonObject = HashMap::new();
-        if matches!(c, Token::CloseBracket) {
-            self.tokenizer.next_token();
-            return Ok(map);
-        } else {
-            loop {
-                self.consume_whitespace();
-                let name_or_error = self.parse_string();
-                let name = name_or_error?;
-                
-                self.consume_whitespace();
-                c = self.tokenizer.next_token();
-                if !matches!(c, Token::Colon) {
-                    return Err(ParseError::new("Expected a colon"));
-                }
-                
-                self.consume_whitespace();
-                let value = self.parse_value();
-                map.insert(name, value?);
-                self.consume_whitespace();
-                c = self.tokenizer.peek_token();
-                if !matches!(c, Token::Comma) {
-                    break;
-                } else {
-                    self.tokenizer.next_token();
-                }
-            }
-            c = self.tokenizer.next_token();
-            match c {
-                Token::CloseBracket => return Ok(map),
-                _ => return Err(ParseError::new("Expecting a '}'")),
-            }
+        if !matches!(self.tokenizer.peek_token(), Token::Quotion) {
+            return Err(ParseError::new("Expected a string as object key"));
         }
+        let start = self.tokenizer.cursor();
+        self.parse_string_into(name)?;
+        if let Some(spans) = &mut self.spans {
+            spans.pending_key(start, self.tokenizer.cursor());
+        }
+        self.consume_whitespace();
+        if !matches!(self.tokenizer.peek_token(), Token::Colon) {
+            return Err(ParseError::new("Expected a colon"));
+        }
+        self.tokenizer.next_token();
+        self.consume_whitespace();
+        return Ok(());
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        return match self.tokenizer.peek_token() {
+            Token::Character(c) => Some(c),
+            _ => None,
+        };
     }
 
-    fn parse_array(&mut self) -> BoxResult<Vec<JsonElement>> {
+    /// Moves past one character of the number that started at `start`.
+    fn number_char(&mut self, start: usize) -> BoxResult<()> {
+        if self.tokenizer.cursor() - start >= self.options.max_number_len {
+            return Err(ParseError::with_kind(
+                ParseErrorKind::NumberTooLong,
+                &format!(
+                    "Number is longer than {} characters",
+                    self.options.max_number_len
+                ),
+            ));
+        }
         self.tokenizer.next_token();
-        self.consume_whitespace();
-        let mut c = self.tokenizer.peek_token();
-        let mut array: Vec<JsonElement> = Vec::new();
-        if matches!(c, Token::CloseSquareBracket) {
-            self.tokenizer.next_token();
-            return Ok(array);
-        } else {
-            loop {
-                self.consume_whitespace();
-                let value = self.parse_value();
-                array.push(value?);
-                self.consume_whitespace();
-                c = self.tokenizer.peek_token();
-                if !matches!(c, Token::Comma) {
-                    break;
-                } else {
-                    self.tokenizer.next_token();
-                }
-            }
+        return Ok(());
+    }
+
+    fn parse_digits(&mut self, start: usize) -> BoxResult<usize> {
+        let mut count = 0;
+        while self.peek_char().filter(char::is_ascii_digit).is_some() {
+            self.number_char(start)?;
+            count += 1;
         }
-        return Ok(array);
+        return Ok(count);
     }
 
-    fn parse_number(&mut self) -> BoxResult<f64> {
-        let token = self.tokenizer.peek_token();
-        let mut string = String::new();
-        match token {
-            Token::Character(mut c) => {
-                if c == '0' {
-                    add_c_to_str_and_peek_character!(string, self, c);
-                    if c != '.' && c != 'e' && c != 'E' {
-                        return Err(ParseError::new("Expected a '.' or 'e' or 'E'"));
-                    }
-                } else if c == '-' {
-                    add_c_to_str_and_peek_character!(
-                        string,
-                        self,
-                        c,
-                        ParseError::new("Expected a number after a '-'")
-                    );
-                    if c == '0' {
-                        add_c_to_str_and_peek_character!(string, self, c);
-                        if c != '.' && c != 'e' && c != 'E' {
-                            return Err(ParseError::new("Expected a '.' or 'e' or 'E'"));
-                        }
-                    }
-                } else {
-                    add_c_to_str_and_peek_character!(string, self, c);
-                }
-                while c >= '0' && c <= '9' {
-                    add_c_to_str_and_peek_character!(string, self, c);
-                }
-                if c == '.' {
-                    add_c_to_str_and_peek_character!(string, self, c);
-                    if c < '0' || c > '9' {
-                        return Err(ParseError::new("Expected a digit"));
-                    }
-                    while c >= '0' && c <= '9' {
-                        add_c_to_str_and_peek_character!(string, self, c);
-                    }
-                }
-                if c == 'E' || c == 'e' {
-                    add_c_to_str_and_peek_character!(string, self, c);
-                    if c == '-' || c == '+' {
-                        add_c_to_str_and_peek_character!(string, self, c);
-                    }
-                    if c < '0' || c > '9' {
-                        return Err(ParseError::new("Expected a digit"));
-                    }
-                    while c >= '0' && c <= '9' {
-                        add_c_to_str_and_peek_character!(string, self, c);
-                    }
+    /// Checks the number at the cursor, moves past it and returns its text.
+    fn skip_number(&mut self) -> BoxResult<&'a str> {
+        let start = self.tokenizer.cursor();
+        if self.peek_char() == Some('-') {
+            self.number_char(start)?;
+        }
+        match self.peek_char() {
+            Some('0') => {
+                self.number_char(start)?;
+                if self.peek_char().filter(char::is_ascii_digit).is_some() {
+                    return Err(ParseError::new("Expected a '.' or 'e' or 'E'"));
                 }
-                return Ok(string.parse::<f64>().unwrap());
             }
-            _ => {
-                self.tokenizer.next_token();
-                return Err(ParseError::new("Expected a number"));
+            Some(c) if c.is_ascii_digit() => {
+                self.parse_digits(start)?;
+            }
+            _ if self.tokenizer.cursor() == start => {
+                return Err(ParseError::new("Expected a number"))
+            }
+            _ => return Err(ParseError::new("Expected a number after a '-'")),
+        }
+        if self.peek_char() == Some('.') {
+            self.number_char(start)?;
+            if self.parse_digits(start)? == 0 {
+                return Err(ParseError::new("Expected a digit"));
+            }
+        }
+        if self
+            .peek_char()
+            .filter(|c| *c == 'e' || *c == 'E')
+            .is_some()
+        {
+            self.number_char(start)?;
+            if self
+                .peek_char()
+                .filter(|c| *c == '-' || *c == '+')
+                .is_some()
+            {
+                self.number_char(start)?;
+            }
+            if self.parse_digits(start)? == 0 {
+                return Err(ParseError::new("Expected a digit"));
             }
         }
+        return Ok(self.tokenizer.slice(start, self.tokenizer.cursor()));
+    }
+
+    fn parse_number(&mut self) -> BoxResult<f64> {
+        let number = self.skip_number()?;
+        return string_to_number(number);
     }
 
     fn parse_boolean(&mut self) -> BoxResult<bool> {
@@ -320,16 +624,16 @@ impl<'a> Parser<'a> {
         match token {
             Token::Character(c) => {
                 if c == 't' {
-                    expect_char!(self.tokenizer.next_token(), 'r');
-                    expect_char!(self.tokenizer.next_token(), 'u');
-                    expect_char!(self.tokenizer.next_token(), 'e');
+                    expect_char!(self, 'r');
+                    expect_char!(self, 'u');
+                    expect_char!(self, 'e');
                     return Ok(true);
                 }
                 if c == 'f' {
-                    expect_char!(self.tokenizer.next_token(), 'a');
-                    expect_char!(self.tokenizer.next_token(), 'l');
-                    expect_char!(self.tokenizer.next_token(), 's');
-                    expect_char!(self.tokenizer.next_token(), 'e');
+                    expect_char!(self, 'a');
+                    expect_char!(self, 'l');
+                    expect_char!(self, 's');
+                    expect_char!(self, 'e');
                     return Ok(false);
                 }
                 return Err(ParseError::new("Expected a 'f' or 't'"));
@@ -338,57 +642,227 @@ impl<'a> Parser<'a> {
         }
     }
     fn parse_null(&mut self) -> BoxResult<i8> {
-        expect_char!(self.tokenizer.next_token(), 'n');
-        expect_char!(self.tokenizer.next_token(), 'u');
-        expect_char!(self.tokenizer.next_token(), 'l');
-        expect_char!(self.tokenizer.next_token(), 'l');
+        expect_char!(self, 'n');
+        expect_char!(self, 'u');
+        expect_char!(self, 'l');
+        expect_char!(self, 'l');
         return Ok(0);
     }
 
-    fn parse_value(&mut self) -> BoxResult<JsonElement> {
-        let token = self.tokenizer.peek_token();
+    fn enter(&mut self, depth: usize) -> BoxResult<()> {
+        self.tokenizer.next_token();
+        if self.base_depth + depth >= self.options.max_depth {
+            return Err(ParseError::with_kind(
+                ParseErrorKind::DepthLimitExceeded,
+                &format!("Nesting is deeper than {} levels", self.options.max_depth),
+            ));
+        }
+        self.consume_whitespace();
+        return Ok(());
+    }
 
-        match token {
-            Token::OpenBracket => {
-                let obj = self.parse_object();
-                return Ok(JsonElement::Object(obj?));
-            }
-            Token::OpenSquareBracket => {
-                let array = self.parse_array();
-                return Ok(JsonElement::Array(array?));
-            }
+    fn parse_scalar_value(&mut self) -> BoxResult<JsonElement> {
+        match self.tokenizer.peek_token() {
             Token::Quotion => {
                 let string = self.parse_string();
                 return Ok(JsonElement::Str(string?));
             }
-            Token::Character(c) => match c {
-                '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' | '-' => {
-                    let num = self.parse_number();
-                    return Ok(JsonElement::Number(num?));
-                }
-                't' | 'f' => {
-                    let boolean = self.parse_boolean();
-                    return Ok(JsonElement::Boolean(boolean?));
+            Token::Character(c) => {
+                #[cfg(feature = "simd")]
+                if let Some(value) = self.parse_indexed_scalar() {
+                    return Ok(value);
                 }
-                'n' => {
-                    self.parse_null()?;
-                    return Ok(JsonElement::Null);
+                match c {
+                    '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' | '-' => {
+                        let num = self.parse_number();
+                        return Ok(JsonElement::Number(num?));
+                    }
+                    't' | 'f' => {
+                        let boolean = self.parse_boolean();
+                        return Ok(JsonElement::Boolean(boolean?));
+                    }
+                    'n' => {
+                        self.parse_null()?;
+                        return Ok(JsonElement::Null);
+                    }
+                    _ => return Err(ParseError::new("Expected true, false or null")),
                 }
-                _ => return Err(ParseError::new("Expected true, false or null")),
-            },
+            }
             _ => {
                 return Err(ParseError::new("Invalid json value"));
             }
         };
     }
 
-    pub fn parse (json: &str) -> BoxResult<JsonElement> {
-        let mut parser = Parser {
-            tokenizer: Tokenizer::new(json),
-        };
-        parser.consume_whitespace();
-        let element = parser.parse_value();
-        parser.consume_whitespace();
-        return element;
+    fn array_too_long(&self) -> ParseError {
+        return ParseError::with_kind(
+            ParseErrorKind::ArrayTooLong,
+            &format!(
+                "Array has more than {} elements",
+                self.options.max_array_len
+            ),
+        );
+    }
+
+    fn too_many_members(&self) -> ParseError {
+        return ParseError::with_kind(
+            ParseErrorKind::TooManyObjectMembers,
+            &format!(
+                "Object has more than {} members",
+                self.options.max_object_members
+            ),
+        );
+    }
+
+    fn open_span(&mut self, start: usize) {
+        if let Some(spans) = &mut self.spans {
+            spans.open(start);
+        }
+    }
+
+    /// Parses a value without recursing: arrays and objects being built are kept
+    /// on `stack`, so the call stack stays the same size however deep the input is.
+    fn parse_value(&mut self) -> BoxResult<JsonElement> {
+        let mut stack: Vec<Frame> = Vec::new();
+        loop {
+            let mut start = self.tokenizer.cursor();
+            if let Some(spans) = &mut self.spans {
+                spans.key(&stack);
+            }
+            let mut value = match self.tokenizer.peek_token() {
+                Token::OpenBracket => {
+                    self.enter(stack.len())?;
+                    if matches!(self.tokenizer.peek_token(), Token::CloseBracket) {
+                        self.tokenizer.next_token();
+                        JsonElement::Object(Map::new())
+                    } else {
+                        let key = self.parse_key()?;
+                        self.open_span(start);
+                        stack.push(Frame::Object(Map::new(), key));
+                        continue;
+                    }
+                }
+                Token::OpenSquareBracket => {
+                    self.enter(stack.len())?;
+                    if matches!(self.tokenizer.peek_token(), Token::CloseSquareBracket) {
+                        self.tokenizer.next_token();
+                        JsonElement::Array(Vec::new())
+                    } else {
+                        self.open_span(start);
+                        stack.push(Frame::Array(Vec::new()));
+                        continue;
+                    }
+                }
+                _ => self.parse_scalar_value()?,
+            };
+
+            // Hand the finished value to its parent, closing every container
+            // that ends right after it.
+            loop {
+                if let Some(spans) = &mut self.spans {
+                    spans.value(&stack, start, self.tokenizer.cursor());
+                }
+                let frame = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => return Ok(value),
+                };
+                self.consume_whitespace();
+                match frame {
+                    Frame::Array(array) => {
+                        array.push(value);
+                        if array.len() > self.options.max_array_len {
+                            return Err(self.array_too_long());
+                        }
+                        match self.tokenizer.peek_token() {
+                            Token::Comma => {
+                                self.tokenizer.next_token();
+                                self.consume_whitespace();
+                                break;
+                            }
+                            Token::CloseSquareBracket => {
+                                self.tokenizer.next_token();
+                                value = JsonElement::Array(core::mem::take(array));
+                            }
+                            _ => return Err(ParseError::new("Expecting a ',' or ']'")),
+                        }
+                    }
+                    Frame::Object(map, key) => {
+                        map.insert(core::mem::take(key), value);
+                        if map.len() > self.options.max_object_members {
+                            return Err(self.too_many_members());
+                        }
+                        match self.tokenizer.peek_token() {
+                            Token::Comma => {
+                                self.tokenizer.next_token();
+                                *key = self.parse_key()?;
+                                break;
+                            }
+                            Token::CloseBracket => {
+                                self.tokenizer.next_token();
+                                value = JsonElement::Object(core::mem::take(map));
+                            }
+                            _ => return Err(ParseError::new("Expecting a ',' or '}'")),
+                        }
+                    }
+                }
+                stack.pop();
+                if let Some(spans) = &mut self.spans {
+                    start = spans.close();
+                }
+            }
+        }
+    }
+
+    /// Line and column of the cursor, counting both from 1. Columns count
+    /// characters, not bytes.
+    fn position(&self) -> Position {
+        let offset = self.tokenizer.cursor();
+        return LineIndex::new(self.tokenizer.slice(0, offset)).position(offset);
+    }
+
+    /// Parses the whole input with `parse`, which reads one value.
+    fn run<T>(&mut self, parse: impl FnOnce(&mut Parser<'a>) -> BoxResult<T>) -> BoxResult<T> {
+        return self.run_value(parse).map_err(|mut e| {
+            e.position = Some(self.position());
+            e
+        });
+    }
+
+    fn run_value<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Parser<'a>) -> BoxResult<T>,
+    ) -> BoxResult<T> {
+        if self.tokenizer.len() > self.options.max_input_bytes {
+            return Err(ParseError::with_kind(
+                ParseErrorKind::InputTooLarge,
+                &format!(
+                    "Input is larger than {} bytes",
+                    self.options.max_input_bytes
+                ),
+            ));
+        }
+        self.consume_whitespace();
+        let element = parse(self)?;
+        self.consume_whitespace();
+        if !matches!(self.tokenizer.peek_token(), Token::End) {
+            return Err(ParseError::new(
+                "Unexpected characters after the json value",
+            ));
+        }
+        return Ok(element);
+    }
+
+    pub fn parse(json: &str) -> BoxResult<JsonElement> {
+        return Parser::parse_with_options(json, &ParserOptions::default());
+    }
+
+    pub fn parse_with_options(json: &str, options: &ParserOptions) -> BoxResult<JsonElement> {
+        return Parser::indexed(json, options).run(Parser::parse_value);
+    }
+
+    /// Parses without the structural index, for comparing against the indexed path.
+    #[cfg(all(test, feature = "simd"))]
+    pub(crate) fn parse_scalar(json: &str) -> BoxResult<JsonElement> {
+        return Parser::new(json, ParserOptions::default()).run(Parser::parse_value);
     }
 }
@@ -0,0 +1,238 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Parsing without building a `JsonElement` tree: straight into a `Document`,
+//! or only to check that the input is valid.
+
+use super::{BoxResult, ParseError, Parser, ParserOptions};
+use crate::document::{Builder, Document};
+use crate::json_element::JsonElement;
+use crate::token::Token;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Receives what `parse_node` reads. Values are reported bottom up: an array
+/// or object takes a mark when it opens, gets each finished child pushed, and
+/// turns into a node when it closes.
+pub(crate) trait Sink {
+    type Node: Copy;
+    type Key: Copy;
+
+    /// The buffer strings and keys are decoded into.
+    fn text(&mut self) -> &mut String;
+    /// Where the next string appended to `text` starts.
+    fn text_mark(&self) -> usize;
+    /// The text appended since `start`, as an object key.
+    fn key_from(&mut self, start: usize) -> Self::Key;
+    /// The text appended since `start`, as a string value.
+    fn string_from(&mut self, start: usize) -> Self::Node;
+    /// `element`, which is not an array or object.
+    fn scalar(&mut self, element: &JsonElement) -> Self::Node;
+    fn elements_mark(&self) -> usize;
+    fn members_mark(&self) -> usize;
+    /// Adds an element to the innermost open array and returns a count that,
+    /// less the array's mark, is how many elements it has.
+    fn push_element(&mut self, node: Self::Node) -> usize;
+    /// Adds a member to the innermost open object, counting like `push_element`.
+    fn push_member(&mut self, key: Self::Key, node: Self::Node) -> usize;
+    fn finish_array(&mut self, mark: usize) -> Self::Node;
+    fn finish_object(&mut self, mark: usize) -> Self::Node;
+}
+
+/// A sink that keeps nothing but the counts the limits need.
+#[derive(Default)]
+struct Validator {
+    scratch: String,
+    open_elements: usize,
+    open_members: usize,
+}
+
+impl Sink for Validator {
+    type Node = ();
+    type Key = ();
+
+    fn text(&mut self) -> &mut String {
+        return &mut self.scratch;
+    }
+
+    fn text_mark(&self) -> usize {
+        return 0;
+    }
+
+    fn key_from(&mut self, _start: usize) {
+        self.scratch.clear();
+    }
+
+    fn string_from(&mut self, _start: usize) {
+        self.scratch.clear();
+    }
+
+    fn scalar(&mut self, _element: &JsonElement) {}
+
+    fn elements_mark(&self) -> usize {
+        return self.open_elements;
+    }
+
+    fn members_mark(&self) -> usize {
+        return self.open_members;
+    }
+
+    fn push_element(&mut self, _node: ()) -> usize {
+        self.open_elements += 1;
+        return self.open_elements;
+    }
+
+    fn push_member(&mut self, _key: (), _node: ()) -> usize {
+        self.open_members += 1;
+        return self.open_members;
+    }
+
+    fn finish_array(&mut self, mark: usize) {
+        self.open_elements = mark;
+    }
+
+    fn finish_object(&mut self, mark: usize) {
+        self.open_members = mark;
+    }
+}
+
+/// An array or object whose members are still being parsed, with its mark in
+/// the sink. Objects keep the key of the member whose value comes next.
+enum Open<K> {
+    Array(usize),
+    Object(usize, K),
+}
+
+impl<'a> Parser<'a> {
+    /// Parses `json` into a `Document`. Strings, keys and nodes are written
+    /// into the document's shared buffers as they are read.
+    pub fn parse_document(json: &str) -> BoxResult<Document> {
+        return Parser::parse_document_with_options(json, &ParserOptions::default());
+    }
+
+    /// Like `parse_with_options`. `max_object_members` counts repeated keys,
+    /// which a document keeps.
+    pub fn parse_document_with_options(json: &str, options: &ParserOptions) -> BoxResult<Document> {
+        let mut builder = Builder::default();
+        let root = Parser::indexed(json, options).run(|parser| parser.parse_node(&mut builder))?;
+        return Ok(builder.finish(root));
+    }
+
+    /// Checks that `json` is valid without keeping any of it. It fails when
+    /// `parse` would, with the same error.
+    pub fn validate(json: &str) -> BoxResult<()> {
+        return Parser::validate_with_options(json, &ParserOptions::default());
+    }
+
+    /// Like `validate`. `max_object_members` counts repeated keys.
+    pub fn validate_with_options(json: &str, options: &ParserOptions) -> BoxResult<()> {
+        let mut validator = Validator::default();
+        return Parser::indexed(json, options).run(|parser| parser.parse_node(&mut validator));
+    }
+
+    fn parse_sink_key<S: Sink>(&mut self, sink: &mut S) -> BoxResult<S::Key> {
+        let start = sink.text_mark();
+        self.parse_key_into(sink.text())?;
+        return Ok(sink.key_from(start));
+    }
+
+    /// The same loop as `parse_value`, reporting values to `sink` instead of
+    /// building elements.
+    fn parse_node<S: Sink>(&mut self, sink: &mut S) -> BoxResult<S::Node> {
+        let mut stack: Vec<Open<S::Key>> = Vec::new();
+        loop {
+            let mut node = match self.tokenizer.peek_token() {
+                Token::OpenBracket => {
+                    self.enter(stack.len())?;
+                    let mark = sink.members_mark();
+                    if matches!(self.tokenizer.peek_token(), Token::CloseBracket) {
+                        self.tokenizer.next_token();
+                        sink.finish_object(mark)
+                    } else {
+                        let key = self.parse_sink_key(sink)?;
+                        stack.push(Open::Object(mark, key));
+                        continue;
+                    }
+                }
+                Token::OpenSquareBracket => {
+                    self.enter(stack.len())?;
+                    let mark = sink.elements_mark();
+                    if matches!(self.tokenizer.peek_token(), Token::CloseSquareBracket) {
+                        self.tokenizer.next_token();
+                        sink.finish_array(mark)
+                    } else {
+                        stack.push(Open::Array(mark));
+                        continue;
+                    }
+                }
+                Token::Quotion => {
+                    let start = sink.text_mark();
+                    self.parse_string_into(sink.text())?;
+                    sink.string_from(start)
+                }
+                _ => {
+                    let scalar = self.parse_scalar_value()?;
+                    sink.scalar(&scalar)
+                }
+            };
+
+            loop {
+                let open = match stack.last_mut() {
+                    Some(open) => open,
+                    None => return Ok(node),
+                };
+                self.consume_whitespace();
+                match open {
+                    Open::Array(mark) => {
+                        if sink.push_element(node) - *mark > self.options.max_array_len {
+                            return Err(self.array_too_long());
+                        }
+                        match self.tokenizer.peek_token() {
+                            Token::Comma => {
+                                self.tokenizer.next_token();
+                                self.consume_whitespace();
+                                break;
+                            }
+                            Token::CloseSquareBracket => {
+                                self.tokenizer.next_token();
+                                node = sink.finish_array(*mark);
+                            }
+                            _ => return Err(ParseError::new("Expecting a ',' or ']'")),
+                        }
+                    }
+                    Open::Object(mark, key) => {
+                        if sink.push_member(*key, node) - *mark > self.options.max_object_members {
+                            return Err(self.too_many_members());
+                        }
+                        match self.tokenizer.peek_token() {
+                            Token::Comma => {
+                                self.tokenizer.next_token();
+                                *key = self.parse_sink_key(sink)?;
+                                break;
+                            }
+                            Token::CloseBracket => {
+                                self.tokenizer.next_token();
+                                node = sink.finish_object(*mark);
+                            }
+                            _ => return Err(ParseError::new("Expecting a ',' or '}'")),
+                        }
+                    }
+                }
+                stack.pop();
+            }
+        }
+    }
+}
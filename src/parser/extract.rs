@@ -0,0 +1,370 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Pulling a few values out of a document in one pass, without building the
+//! rest of it.
+
+use super::{BoxResult, ParseError, Parser, ParserOptions};
+use crate::json_element::{JsonElement, Map};
+use crate::pointer::JsonPointer;
+use crate::token::Token;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// The requested pointers as a tree of tokens, so that the parser can tell at
+/// each value whether it is wanted, leads to a wanted value or can be skipped.
+struct PathTree {
+    nodes: Vec<PathNode>,
+}
+
+#[derive(Default)]
+struct PathNode {
+    parent: usize,
+    children: Map<String, usize>,
+    wanted: bool,
+}
+
+impl PathTree {
+    fn new(paths: &[JsonPointer]) -> PathTree {
+        let mut tree = PathTree {
+            nodes: vec![PathNode::default()],
+        };
+        for path in paths {
+            let mut node = 0;
+            for token in path.tokens() {
+                node = match tree.child(node, token) {
+                    Some(child) => child,
+                    None => {
+                        let child = tree.nodes.len();
+                        tree.nodes[node].children.insert(token.clone(), child);
+                        tree.nodes.push(PathNode {
+                            parent: node,
+                            ..PathNode::default()
+                        });
+                        child
+                    }
+                };
+            }
+            tree.nodes[node].wanted = true;
+        }
+        return tree;
+    }
+
+    fn child(&self, node: usize, token: &str) -> Option<usize> {
+        return self.nodes[node].children.get(token).copied();
+    }
+
+    /// The node of element `index` of the array at `parent`. `scratch` is
+    /// left empty.
+    fn element(&self, parent: Option<usize>, index: usize, scratch: &mut String) -> Option<usize> {
+        let parent = parent?;
+        let _ = write!(scratch, "{index}");
+        let node = self.child(parent, scratch);
+        scratch.clear();
+        return node;
+    }
+
+    /// Whether `node` is `ancestor` or below it.
+    fn is_within(&self, mut node: usize, ancestor: usize) -> bool {
+        while node != ancestor {
+            if node == 0 {
+                return false;
+            }
+            node = self.nodes[node].parent;
+        }
+        return true;
+    }
+}
+
+/// An array or object being walked, with its node in the path tree (`None`
+/// when it is skipped) and how many members have been read.
+enum Walk {
+    Array(Option<usize>, usize),
+    Object(Option<usize>, usize),
+}
+
+impl<'a> Parser<'a> {
+    /// Parses `json` once and returns the values at `paths`. Values that no
+    /// path leads into are checked but not built, and nothing is allocated for
+    /// them. Paths that name no value are left out of the map. It fails when
+    /// `parse` would, with the same error.
+    pub fn extract(json: &str, paths: &[JsonPointer]) -> BoxResult<Map<JsonPointer, JsonElement>> {
+        return Parser::extract_with_options(json, paths, &ParserOptions::default());
+    }
+
+    /// Like `parse_with_options`. `max_object_members` counts repeated keys.
+    pub fn extract_with_options(
+        json: &str,
+        paths: &[JsonPointer],
+        options: &ParserOptions,
+    ) -> BoxResult<Map<JsonPointer, JsonElement>> {
+        let tree = PathTree::new(paths);
+        let mut found = Map::new();
+        Parser::indexed(json, options).run(|parser| parser.extract_value(&tree, &mut found))?;
+
+        let mut extracted = Map::new();
+        for path in paths {
+            // Only the outermost wanted values are parsed; the paths below
+            // them are looked up in the parsed value.
+            let mut node = 0;
+            let mut depth = 0;
+            while !tree.nodes[node].wanted {
+                node = tree.child(node, &path.tokens()[depth]).unwrap();
+                depth += 1;
+            }
+            let mut rest = JsonPointer::root();
+            for token in &path.tokens()[depth..] {
+                rest.push(token);
+            }
+            if let Some(value) = found.get(&node).and_then(|value| rest.resolve(value)) {
+                extracted.insert(path.clone(), value.clone());
+            }
+        }
+        return Ok(extracted);
+    }
+
+    /// The same loop as `parse_node`, parsing the wanted values with
+    /// `parse_value` into `found`, keyed by their node in `tree`.
+    fn extract_value(
+        &mut self,
+        tree: &PathTree,
+        found: &mut Map<usize, JsonElement>,
+    ) -> BoxResult<()> {
+        let mut stack: Vec<Walk> = Vec::new();
+        let mut scratch = String::new();
+        let mut node = Some(0);
+        loop {
+            match node {
+                Some(wanted) if tree.nodes[wanted].wanted => {
+                    self.base_depth = stack.len();
+                    let value = self.parse_value();
+                    self.base_depth = 0;
+                    found.insert(wanted, value?);
+                }
+                _ => match self.tokenizer.peek_token() {
+                    Token::OpenBracket => {
+                        self.enter(stack.len())?;
+                        if matches!(self.tokenizer.peek_token(), Token::CloseBracket) {
+                            self.tokenizer.next_token();
+                        } else {
+                            stack.push(Walk::Object(node, 0));
+                            node = self.extract_member(tree, node, &mut scratch, found)?;
+                            continue;
+                        }
+                    }
+                    Token::OpenSquareBracket => {
+                        self.enter(stack.len())?;
+                        if matches!(self.tokenizer.peek_token(), Token::CloseSquareBracket) {
+                            self.tokenizer.next_token();
+                        } else {
+                            stack.push(Walk::Array(node, 0));
+                            node = tree.element(node, 0, &mut scratch);
+                            continue;
+                        }
+                    }
+                    Token::Quotion => {
+                        self.parse_string_into(&mut scratch)?;
+                        scratch.clear();
+                    }
+                    _ => {
+                        self.parse_scalar_value()?;
+                    }
+                },
+            }
+
+            loop {
+                let walk = match stack.last_mut() {
+                    Some(walk) => walk,
+                    None => return Ok(()),
+                };
+                self.consume_whitespace();
+                match walk {
+                    Walk::Array(parent, len) => {
+                        *len += 1;
+                        if *len > self.options.max_array_len {
+                            return Err(self.array_too_long());
+                        }
+                        match self.tokenizer.peek_token() {
+                            Token::Comma => {
+                                self.tokenizer.next_token();
+                                self.consume_whitespace();
+                                node = tree.element(*parent, *len, &mut scratch);
+                                break;
+                            }
+                            Token::CloseSquareBracket => {
+                                self.tokenizer.next_token();
+                            }
+                            _ => return Err(ParseError::new("Expecting a ',' or ']'")),
+                        }
+                    }
+                    Walk::Object(parent, len) => {
+                        *len += 1;
+                        if *len > self.options.max_object_members {
+                            return Err(self.too_many_members());
+                        }
+                        match self.tokenizer.peek_token() {
+                            Token::Comma => {
+                                self.tokenizer.next_token();
+                                let parent = *parent;
+                                node = self.extract_member(tree, parent, &mut scratch, found)?;
+                                break;
+                            }
+                            Token::CloseBracket => {
+                                self.tokenizer.next_token();
+                            }
+                            _ => return Err(ParseError::new("Expecting a ',' or '}'")),
+                        }
+                    }
+                }
+                stack.pop();
+            }
+        }
+    }
+
+    /// Reads a member's key and returns the node of its value. What was found
+    /// under an earlier member with the same key is dropped, since the last
+    /// one wins.
+    fn extract_member(
+        &mut self,
+        tree: &PathTree,
+        parent: Option<usize>,
+        scratch: &mut String,
+        found: &mut Map<usize, JsonElement>,
+    ) -> BoxResult<Option<usize>> {
+        self.parse_key_into(scratch)?;
+        let node = parent.and_then(|parent| tree.child(parent, scratch));
+        scratch.clear();
+        if let Some(node) = node {
+            found.retain(|found, _| !tree.is_within(*found, node));
+        }
+        return Ok(node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json_element::JsonElement;
+    use crate::parser::{Parser, ParserOptions};
+    use crate::pointer::JsonPointer;
+
+    use crate::json_element::Map;
+    use proptest::prelude::*;
+
+    fn pointers(paths: &[&str]) -> Vec<JsonPointer> {
+        return paths
+            .iter()
+            .map(|path| JsonPointer::parse(path).ok().unwrap())
+            .collect();
+    }
+
+    fn expected(pairs: &[(&str, &str)]) -> Map<JsonPointer, JsonElement> {
+        return pairs
+            .iter()
+            .map(|(path, json)| {
+                (
+                    JsonPointer::parse(path).ok().unwrap(),
+                    Parser::parse(json).ok().unwrap(),
+                )
+            })
+            .collect();
+    }
+
+    #[test]
+    fn should_extract_requested_paths() {
+        let record = r#"{"ts": 1700000000, "level": "warn", "msg": "disk \"almost\" full",
+            "ctx": {"host": "db-1", "tags": ["disk", "prod"], "load": [0.5, 1.25e1]},
+            "trace": [{"span": 1}, {"span": 2, "error": null}]}"#;
+        let paths = pointers(&[
+            "/level",
+            "/ctx/tags",
+            "/ctx/tags/1",
+            "/trace/1/span",
+            "/trace/2/span",
+            "/ctx/missing",
+            "/level/0",
+        ]);
+        assert_eq!(
+            expected(&[
+                ("/level", r#""warn""#),
+                ("/ctx/tags", r#"["disk", "prod"]"#),
+                ("/ctx/tags/1", r#""prod""#),
+                ("/trace/1/span", "2"),
+            ]),
+            Parser::extract(record, &paths).ok().unwrap()
+        );
+        assert_eq!(
+            expected(&[("", record)]),
+            Parser::extract(record, &pointers(&[""])).ok().unwrap()
+        );
+        assert!(Parser::extract(record, &[]).ok().unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_keep_the_last_duplicate() {
+        let json = r#"{"a": {"b": 1}, "a": {"c": 2}, "d": [1], "d": 3}"#;
+        assert_eq!(
+            expected(&[("/a/c", "2"), ("/d", "3")]),
+            Parser::extract(json, &pointers(&["/a/b", "/a/c", "/d", "/d/0"]))
+                .ok()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_fail_like_parse() {
+        let options = ParserOptions {
+            max_depth: 3,
+            max_array_len: 2,
+            ..ParserOptions::default()
+        };
+        let paths = pointers(&["/a/0", "/b"]);
+        for json in [
+            r#"{"a": [[[1]]], "b": 1}"#,
+            r#"{"a": [1], "b": [[[1]]]}"#,
+            r#"{"a": [1, 2, 3], "b": 1}"#,
+            r#"{"c": [1, 2, 3], "b": 1}"#,
+            r#"{"a": [1], "b": 01}"#,
+            r#"{"a": [1], "c": "\x"}"#,
+            r#"{"a": [1]} 2"#,
+        ] {
+            let error = Parser::extract_with_options(json, &paths, &options).err();
+            assert!(error.is_some(), "{json}");
+            assert_eq!(
+                Parser::parse_with_options(json, &options).err(),
+                error,
+                "{json}"
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn should_extract_like_parse(json in "[\\[\\]{}\":, 0-9ab]{0,48}") {
+            let paths = pointers(&["/a", "/a/b", "/0", "/1/a", "/b/0/a", "/a/1/b"]);
+            match Parser::parse(&json) {
+                Ok(element) => {
+                    let extracted = Parser::extract(&json, &paths).ok().unwrap();
+                    for path in &paths {
+                        prop_assert_eq!(path.resolve(&element), extracted.get(path));
+                    }
+                }
+                Err(error) => prop_assert_eq!(Some(error), Parser::extract(&json, &paths).err()),
+            }
+        }
+    }
+}
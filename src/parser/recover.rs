@@ -0,0 +1,869 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! A parse mode for editors, which keeps going after an error.
+//!
+//! After each mistake the parser resynchronizes at the next comma or closing
+//! bracket, so a document with several mistakes reports all of them. Values
+//! that cannot be read at all are replaced by `JsonElement::Null`, and listed in
+//! `Recovered::placeholders` to tell them apart from a `null` in the input.
+//!
+//! A string still open at the end of its line is taken to end there, so unlike
+//! `Parser::parse` this mode reports raw line breaks inside strings.
+
+use super::{Frame, LineIndex, ParseErrorKind, Parser, ParserOptions, Span};
+use crate::json_element::{JsonElement, Map};
+use crate::pointer::JsonPointer;
+use crate::serializer::write_string;
+use crate::token::Token;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// An edit that fixes a diagnostic: replace the text in `span`, which may be
+/// empty, with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    pub span: Span,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: ParseErrorKind,
+    pub message: String,
+    pub span: Span,
+    /// A likely fix, when the mistake is a common one such as a missing comma
+    /// or an unclosed string.
+    pub fix: Option<Fix>,
+}
+
+/// The best-effort value of a document together with every problem found in it.
+/// An empty `diagnostics` means the document is valid.
+#[derive(Debug, PartialEq)]
+pub struct Recovered {
+    pub value: JsonElement,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Where `value` holds a `null` standing in for a value that could not be
+    /// read, in the order they were found.
+    pub placeholders: Vec<JsonPointer>,
+}
+
+/// A diagnostic with byte offsets, turned into positions once parsing is done.
+struct Pending {
+    kind: ParseErrorKind,
+    message: String,
+    start: usize,
+    end: usize,
+    fix: Option<(usize, usize, String)>,
+}
+
+impl Pending {
+    fn new(message: &str, start: usize, end: usize) -> Pending {
+        return Pending::with_kind(ParseErrorKind::Syntax, message, start, end);
+    }
+
+    fn with_kind(kind: ParseErrorKind, message: &str, start: usize, end: usize) -> Pending {
+        return Pending {
+            kind,
+            message: message.to_string(),
+            start,
+            end,
+            fix: None,
+        };
+    }
+
+    fn fix(mut self, start: usize, end: usize, replacement: &str) -> Pending {
+        self.fix = Some((start, end, replacement.to_string()));
+        return self;
+    }
+}
+
+fn starts_value(token: &Token) -> bool {
+    return match token {
+        Token::Quotion | Token::OpenBracket | Token::OpenSquareBracket => true,
+        Token::Character(c) => c.is_alphanumeric() || *c == '-',
+        _ => false,
+    };
+}
+
+fn is_delimiter(c: char) -> bool {
+    return c.is_whitespace() || matches!(c, ',' | ':' | '[' | ']' | '{' | '}' | '"');
+}
+
+/// The json literal for a word other languages use in its place.
+fn literal_fix(word: &str) -> Option<&'static str> {
+    return match word {
+        "True" | "TRUE" => Some("true"),
+        "False" | "FALSE" => Some("false"),
+        "None" | "NULL" | "Null" | "nil" | "undefined" => Some("null"),
+        _ => None,
+    };
+}
+
+/// What follows a value inside an array or object.
+enum Step {
+    /// Another array element.
+    Value,
+    /// Another object member, with this key.
+    Key(String),
+    /// Nothing usable yet; look for a separator again.
+    Again,
+    /// The end of the array or object.
+    Close,
+}
+
+/// Where the next value handed to the innermost container on `stack` goes.
+fn slot(stack: &[Frame]) -> JsonPointer {
+    let mut pointer = JsonPointer::root();
+    for frame in stack {
+        match frame {
+            Frame::Array(array) => pointer.push(&array.len().to_string()),
+            Frame::Object(_, key) => pointer.push(key),
+        }
+    }
+    return pointer;
+}
+
+fn close(frame: Frame) -> JsonElement {
+    return match frame {
+        Frame::Array(array) => JsonElement::Array(array),
+        Frame::Object(object, _) => JsonElement::Object(object),
+    };
+}
+
+impl<'a> Parser<'a> {
+    /// Offset of the quote closing the string that starts at `start`, or of the
+    /// end of its line when the string is not closed on the same line.
+    fn string_end(&self, start: usize) -> (usize, bool) {
+        let rest = self.tokenizer.slice(start + 1, self.tokenizer.len());
+        let mut escaped = false;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '\n' => return (start + 1 + i, false),
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return (start + 1 + i, true),
+                _ => {}
+            }
+        }
+        return (self.tokenizer.len(), false);
+    }
+
+    /// Consumes a run of characters up to the next whitespace or structural
+    /// character, or the single character under the cursor when it is one.
+    fn take_word(&mut self) -> (usize, usize) {
+        let start = self.tokenizer.cursor();
+        let rest = self.tokenizer.slice(start, self.tokenizer.len());
+        let len = match rest.find(is_delimiter) {
+            Some(0) => rest.chars().next().map_or(0, char::len_utf8),
+            Some(len) => len,
+            None => rest.len(),
+        };
+        self.tokenizer.seek(start + len);
+        return (start, start + len);
+    }
+
+    /// Skips to the bracket closing the array or object the cursor is in,
+    /// without consuming it, or to the end of the input.
+    fn skip_balanced(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.tokenizer.peek_token() {
+                Token::End => return,
+                Token::Quotion => {
+                    let (end, closed) = self.string_end(self.tokenizer.cursor());
+                    self.tokenizer.seek(if closed { end + 1 } else { end });
+                    continue;
+                }
+                Token::OpenBracket | Token::OpenSquareBracket => depth += 1,
+                Token::CloseBracket | Token::CloseSquareBracket if depth == 0 => return,
+                Token::CloseBracket | Token::CloseSquareBracket => depth -= 1,
+                _ => {}
+            }
+            self.tokenizer.next_token();
+        }
+    }
+
+    /// Reads a string, treating one that is still open at the end of its line
+    /// as closed there.
+    fn recover_string(&mut self, pending: &mut Vec<Pending>) -> String {
+        let start = self.tokenizer.cursor();
+        let (end, closed) = self.string_end(start);
+        if closed {
+            return match self.parse_string() {
+                Ok(string) => string,
+                Err(e) => {
+                    pending.push(Pending::with_kind(e.kind, &e.details, start, end + 1));
+                    self.tokenizer.seek(end + 1);
+                    self.tokenizer.slice(start + 1, end).to_string()
+                }
+            };
+        }
+        let end = match self.tokenizer.slice(start + 1, end).strip_suffix('\r') {
+            Some(contents) => start + 1 + contents.len(),
+            None => end,
+        };
+        pending.push(Pending::new("Unclosed string", start, end).fix(end, end, "\""));
+        self.tokenizer.seek(end);
+        let contents = self.tokenizer.slice(start + 1, end);
+        let quoted = format!("\"{contents}\"");
+        return Parser::new(&quoted, self.options)
+            .parse_string()
+            .unwrap_or_else(|_| contents.to_string());
+    }
+
+    /// Reads a number or literal, up to the next delimiter. Returns `None` when
+    /// it is neither.
+    fn recover_word(&mut self, pending: &mut Vec<Pending>) -> Option<JsonElement> {
+        let (start, end) = self.take_word();
+        let word = self.tokenizer.slice(start, end);
+        return match Parser::new(word, self.options).run_value(Parser::parse_value) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                let message = format!("Invalid value {word}: {}", e.details);
+                let mut diagnostic = Pending::with_kind(e.kind, &message, start, end);
+                if let Some(literal) = literal_fix(word) {
+                    diagnostic = diagnostic.fix(start, end, literal);
+                }
+                pending.push(diagnostic);
+                None
+            }
+        };
+    }
+
+    /// Reads an object key and the colon after it. Returns `None` when the
+    /// object ends instead.
+    fn recover_key(&mut self, pending: &mut Vec<Pending>) -> Option<String> {
+        self.consume_whitespace();
+        while matches!(self.tokenizer.peek_token(), Token::Comma) {
+            let start = self.tokenizer.cursor();
+            pending.push(Pending::new("Unexpected ','", start, start + 1).fix(
+                start,
+                start + 1,
+                "",
+            ));
+            self.tokenizer.next_token();
+            self.consume_whitespace();
+        }
+        let start = self.tokenizer.cursor();
+        let key = match self.tokenizer.peek_token() {
+            Token::CloseBracket | Token::CloseSquareBracket | Token::End => return None,
+            Token::Quotion => self.recover_string(pending),
+            Token::Character(_) => {
+                let (start, end) = self.take_word();
+                let word = self.tokenizer.slice(start, end).to_string();
+                let mut quoted = String::new();
+                write_string(&mut quoted, &word);
+                pending.push(
+                    Pending::new("Object keys must be quoted", start, end).fix(start, end, &quoted),
+                );
+                word
+            }
+            Token::OpenBracket | Token::OpenSquareBracket | Token::Colon | Token::Comma => {
+                pending.push(Pending::new(
+                    "Expected a string as object key",
+                    start,
+                    start,
+                ));
+                String::new()
+            }
+        };
+        let key_end = self.tokenizer.cursor();
+        self.consume_whitespace();
+        let token = self.tokenizer.peek_token();
+        if matches!(token, Token::Colon) {
+            self.tokenizer.next_token();
+        } else if starts_value(&token) {
+            pending.push(Pending::new("Missing ':'", key_end, key_end).fix(key_end, key_end, ":"));
+        } else {
+            let here = self.tokenizer.cursor();
+            pending.push(Pending::new("Expected a colon", here, here));
+        }
+        self.consume_whitespace();
+        return Some(key);
+    }
+
+    /// Like `parse_value`, but records problems in `pending` instead of
+    /// stopping at the first one, and where values that could not be read are
+    /// in `placeholders`.
+    fn recover_value(
+        &mut self,
+        pending: &mut Vec<Pending>,
+        placeholders: &mut Vec<JsonPointer>,
+    ) -> JsonElement {
+        let mut stack: Vec<Frame> = Vec::new();
+        // Offsets of the opening brackets of the containers on `stack`.
+        let mut opens: Vec<usize> = Vec::new();
+        loop {
+            let start = self.tokenizer.cursor();
+            let token = self.tokenizer.peek_token();
+            let mut placeholder = true;
+            let mut value = match token {
+                Token::OpenBracket | Token::OpenSquareBracket
+                    if stack.len() >= self.options.max_depth =>
+                {
+                    self.tokenizer.next_token();
+                    self.skip_balanced();
+                    if !matches!(self.tokenizer.peek_token(), Token::End) {
+                        self.tokenizer.next_token();
+                    }
+                    let message =
+                        format!("Nesting is deeper than {} levels", self.options.max_depth);
+                    let end = self.tokenizer.cursor();
+                    pending.push(Pending::with_kind(
+                        ParseErrorKind::DepthLimitExceeded,
+                        &message,
+                        start,
+                        end,
+                    ));
+                    Some(JsonElement::Null)
+                }
+                Token::OpenBracket => {
+                    placeholder = false;
+                    self.tokenizer.next_token();
+                    opens.push(start);
+                    match self.recover_key(pending) {
+                        Some(key) => {
+                            stack.push(Frame::Object(Map::new(), key));
+                            continue;
+                        }
+                        None => {
+                            stack.push(Frame::Object(Map::new(), String::new()));
+                            None
+                        }
+                    }
+                }
+                Token::OpenSquareBracket => {
+                    placeholder = false;
+                    self.tokenizer.next_token();
+                    opens.push(start);
+                    stack.push(Frame::Array(Vec::new()));
+                    self.consume_whitespace();
+                    match self.tokenizer.peek_token() {
+                        Token::CloseBracket | Token::CloseSquareBracket | Token::End => None,
+                        _ => continue,
+                    }
+                }
+                Token::Colon => {
+                    pending.push(Pending::new("Unexpected ':'", start, start + 1).fix(
+                        start,
+                        start + 1,
+                        "",
+                    ));
+                    self.tokenizer.next_token();
+                    self.consume_whitespace();
+                    continue;
+                }
+                Token::Comma | Token::CloseBracket | Token::CloseSquareBracket | Token::End => {
+                    pending.push(Pending::new("Expected a value", start, start));
+                    Some(JsonElement::Null)
+                }
+                Token::Quotion => {
+                    placeholder = false;
+                    Some(JsonElement::Str(self.recover_string(pending)))
+                }
+                Token::Character(_) => {
+                    let word = self.recover_word(pending);
+                    placeholder = word.is_none();
+                    Some(word.unwrap_or_default())
+                }
+            };
+
+            // Hand the value to its parent, then look for what separates it from
+            // the next one, closing containers and fixing separators on the way.
+            let mut value_end = self.tokenizer.cursor();
+            loop {
+                if value.is_some() {
+                    value_end = self.tokenizer.cursor();
+                }
+                if value.is_some() {
+                    // A value that is dropped, or replaces a member with the same
+                    // key, takes the placeholders inside it along.
+                    let (dropped, replaces) = match stack.last() {
+                        Some(Frame::Array(array)) => {
+                            (array.len() >= self.options.max_array_len, false)
+                        }
+                        Some(Frame::Object(map, key)) => {
+                            let replaces = map.contains_key(key);
+                            let full = map.len() >= self.options.max_object_members;
+                            (full && !replaces, replaces)
+                        }
+                        None => (false, false),
+                    };
+                    if placeholder || ((dropped || replaces) && !placeholders.is_empty()) {
+                        let slot = slot(&stack);
+                        placeholders.retain(|p| !p.tokens().starts_with(slot.tokens()));
+                        if placeholder && !dropped {
+                            placeholders.push(slot);
+                        }
+                    }
+                    placeholder = false;
+                }
+                let depth = stack.len();
+                let frame = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => return value.unwrap_or_default(),
+                };
+                if let Some(value) = value.take() {
+                    let limit = match frame {
+                        Frame::Array(array) if array.len() < self.options.max_array_len => {
+                            array.push(value);
+                            None
+                        }
+                        Frame::Array(_) => Some((
+                            ParseErrorKind::ArrayTooLong,
+                            format!(
+                                "Array has more than {} elements",
+                                self.options.max_array_len
+                            ),
+                        )),
+                        Frame::Object(map, key)
+                            if map.len() < self.options.max_object_members
+                                || map.contains_key(key) =>
+                        {
+                            map.insert(core::mem::take(key), value);
+                            None
+                        }
+                        Frame::Object(..) => Some((
+                            ParseErrorKind::TooManyObjectMembers,
+                            format!(
+                                "Object has more than {} members",
+                                self.options.max_object_members
+                            ),
+                        )),
+                    };
+                    if let Some((kind, message)) = limit {
+                        pending.push(Pending::with_kind(kind, &message, start, value_end));
+                        self.skip_balanced();
+                    }
+                }
+                let is_array = matches!(frame, Frame::Array(_));
+                let expected = if is_array { "]" } else { "}" };
+                self.consume_whitespace();
+                let here = self.tokenizer.cursor();
+                let token = self.tokenizer.peek_token();
+                let step = match token {
+                    Token::Comma => {
+                        self.tokenizer.next_token();
+                        self.consume_whitespace();
+                        while matches!(self.tokenizer.peek_token(), Token::Comma) {
+                            let extra = self.tokenizer.cursor();
+                            pending.push(Pending::new("Unexpected ','", extra, extra + 1).fix(
+                                extra,
+                                extra + 1,
+                                "",
+                            ));
+                            self.tokenizer.next_token();
+                            self.consume_whitespace();
+                        }
+                        match self.tokenizer.peek_token() {
+                            Token::CloseBracket | Token::CloseSquareBracket | Token::End => {
+                                pending.push(Pending::new("Trailing ','", here, here + 1).fix(
+                                    here,
+                                    here + 1,
+                                    "",
+                                ));
+                                Step::Again
+                            }
+                            _ if is_array => Step::Value,
+                            _ => self.recover_key(pending).map_or(Step::Again, Step::Key),
+                        }
+                    }
+                    Token::CloseSquareBracket if is_array => {
+                        self.tokenizer.next_token();
+                        Step::Close
+                    }
+                    Token::CloseBracket if !is_array => {
+                        self.tokenizer.next_token();
+                        Step::Close
+                    }
+                    // A bracket of the other kind either closes an outer container,
+                    // so this one is missing its bracket, or is a typo for this one's.
+                    Token::CloseBracket | Token::CloseSquareBracket => {
+                        let message = format!("Expected a '{expected}'");
+                        let closes_outer = stack[..depth - 1]
+                            .iter()
+                            .any(|outer| matches!(outer, Frame::Array(_)) != is_array);
+                        let diagnostic = Pending::new(&message, here, here + 1);
+                        if closes_outer {
+                            pending.push(diagnostic.fix(value_end, value_end, expected));
+                        } else {
+                            pending.push(diagnostic.fix(here, here + 1, expected));
+                            self.tokenizer.next_token();
+                        }
+                        Step::Close
+                    }
+                    Token::End => {
+                        let open = opens[opens.len() - 1];
+                        let message = if is_array {
+                            "Unclosed array"
+                        } else {
+                            "Unclosed object"
+                        };
+                        pending
+                            .push(Pending::new(message, open, open + 1).fix(here, here, expected));
+                        Step::Close
+                    }
+                    _ if starts_value(&token) => {
+                        pending.push(
+                            Pending::new("Missing ','", value_end, value_end)
+                                .fix(value_end, value_end, ","),
+                        );
+                        if is_array {
+                            Step::Value
+                        } else {
+                            self.recover_key(pending).map_or(Step::Again, Step::Key)
+                        }
+                    }
+                    _ => {
+                        let (start, end) = self.take_word();
+                        let message = format!("Unexpected {}", self.tokenizer.slice(start, end));
+                        pending.push(Pending::new(&message, start, end).fix(start, end, ""));
+                        Step::Again
+                    }
+                };
+                match step {
+                    Step::Value => break,
+                    Step::Key(next) => {
+                        if let Some(Frame::Object(_, key)) = stack.last_mut() {
+                            *key = next;
+                        }
+                        break;
+                    }
+                    Step::Again => {}
+                    Step::Close => {
+                        opens.pop();
+                        value = stack.pop().map(close);
+                    }
+                }
+            }
+        }
+    }
+
+    fn recover_document(
+        &mut self,
+        pending: &mut Vec<Pending>,
+        placeholders: &mut Vec<JsonPointer>,
+    ) -> JsonElement {
+        let len = self.tokenizer.len();
+        if len > self.options.max_input_bytes {
+            let message = format!(
+                "Input is larger than {} bytes",
+                self.options.max_input_bytes
+            );
+            pending.push(Pending::with_kind(
+                ParseErrorKind::InputTooLarge,
+                &message,
+                0,
+                0,
+            ));
+            placeholders.push(JsonPointer::root());
+            return JsonElement::Null;
+        }
+        self.consume_whitespace();
+        let value = self.recover_value(pending, placeholders);
+        self.consume_whitespace();
+        let rest = self.tokenizer.cursor();
+        if rest < len {
+            pending.push(Pending::new(
+                "Unexpected characters after the json value",
+                rest,
+                len,
+            ));
+        }
+        return value;
+    }
+
+    /// Parses `json` without stopping at errors. See `Recovered`.
+    pub fn parse_recovering(json: &str) -> Recovered {
+        return Parser::parse_recovering_with_options(json, &ParserOptions::default());
+    }
+
+    pub fn parse_recovering_with_options(json: &str, options: &ParserOptions) -> Recovered {
+        let mut parser = Parser::new(json, *options);
+        let mut pending = Vec::new();
+        let mut placeholders = Vec::new();
+        let value = parser.recover_document(&mut pending, &mut placeholders);
+        let lines = LineIndex::new(json);
+        let diagnostics = pending
+            .into_iter()
+            .map(|diagnostic| Diagnostic {
+                kind: diagnostic.kind,
+                message: diagnostic.message,
+                span: lines.span(diagnostic.start, diagnostic.end),
+                fix: diagnostic.fix.map(|(start, end, replacement)| Fix {
+                    span: lines.span(start, end),
+                    replacement,
+                }),
+            })
+            .collect();
+        return Recovered {
+            value,
+            diagnostics,
+            placeholders,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Recovered;
+    use crate::json_element::JsonElement;
+    use crate::parser::{ParseErrorKind, Parser, ParserOptions};
+    use proptest::prelude::*;
+
+    /// Each diagnostic as `line:column message`, followed by its fix if any, as
+    /// `line:column-line:column => replacement`.
+    fn describe(recovered: &Recovered) -> Vec<String> {
+        return recovered
+            .diagnostics
+            .iter()
+            .map(|d| {
+                let mut text = format!(
+                    "{}:{} {}",
+                    d.span.start.line, d.span.start.column, d.message
+                );
+                if let Some(fix) = &d.fix {
+                    text.push_str(&format!(
+                        " [{}:{}-{}:{} => {:?}]",
+                        fix.span.start.line,
+                        fix.span.start.column,
+                        fix.span.end.line,
+                        fix.span.end.column,
+                        fix.replacement
+                    ));
+                }
+                text
+            })
+            .collect();
+    }
+
+    fn placeholders(recovered: &Recovered) -> Vec<String> {
+        return recovered
+            .placeholders
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+    }
+
+    fn value(json: &str) -> JsonElement {
+        return Parser::parse(json).ok().unwrap();
+    }
+
+    #[test]
+    fn should_not_report_anything_for_valid_documents() {
+        for json in [
+            "{}",
+            " [1, 2.5e3, \"a\\nb\", true, false, null, {\"k\": [{}]}] ",
+            "\"\\ud83d\\ude00\"",
+        ] {
+            let recovered = Parser::parse_recovering(json);
+            assert!(recovered.diagnostics.is_empty(), "{json}");
+            assert!(recovered.placeholders.is_empty(), "{json}");
+            assert_eq!(value(json), recovered.value);
+        }
+    }
+
+    #[test]
+    fn should_report_every_mistake() {
+        let json = "{\n  \"name\": \"jsonp\"\n  \"tags\": [\"a\", \"b\",],\n  \"port\": 80,\n  \"debug\": True\n}";
+        let recovered = Parser::parse_recovering(json);
+        assert_eq!(
+            vec![
+                "2:18 Missing ',' [2:18-2:18 => \",\"]",
+                "3:20 Trailing ',' [3:20-3:21 => \"\"]",
+                "5:12 Invalid value True: Expected true, false or null [5:12-5:16 => \"true\"]",
+            ],
+            describe(&recovered)
+        );
+        assert_eq!(
+            value("{\"name\": \"jsonp\", \"tags\": [\"a\", \"b\"], \"port\": 80, \"debug\": null}"),
+            recovered.value
+        );
+        assert_eq!(vec!["/debug"], placeholders(&recovered));
+    }
+
+    #[test]
+    fn should_close_strings_at_the_end_of_the_line() {
+        let recovered = Parser::parse_recovering("{\"a\": \"hello,\r\n \"b\": 1}");
+        assert_eq!(
+            vec![
+                "1:7 Unclosed string [1:14-1:14 => \"\\\"\"]",
+                "1:14 Missing ',' [1:14-1:14 => \",\"]",
+            ],
+            describe(&recovered)
+        );
+        assert_eq!(value("{\"a\": \"hello,\", \"b\": 1}"), recovered.value);
+
+        let recovered = Parser::parse_recovering("[\"a\\u00e9");
+        assert_eq!(value("[\"a\\u00e9\"]"), recovered.value);
+        assert_eq!(2, recovered.diagnostics.len());
+
+        let recovered = Parser::parse_recovering("[\"a\tb\", 1]");
+        assert_eq!(
+            vec!["1:2 Unescaped control character U+0009 in string"],
+            describe(&recovered)
+        );
+        assert_eq!(value("[\"a\\tb\", 1]"), recovered.value);
+    }
+
+    #[test]
+    fn should_close_unclosed_containers() {
+        let recovered = Parser::parse_recovering("[1, {\"a\": [2");
+        assert_eq!(
+            vec![
+                "1:11 Unclosed array [1:13-1:13 => \"]\"]",
+                "1:5 Unclosed object [1:13-1:13 => \"}\"]",
+                "1:1 Unclosed array [1:13-1:13 => \"]\"]",
+            ],
+            describe(&recovered)
+        );
+        assert_eq!(value("[1, {\"a\": [2]}]"), recovered.value);
+
+        let recovered = Parser::parse_recovering("{\"a\": [1, 2}");
+        assert_eq!(
+            vec!["1:12 Expected a ']' [1:12-1:12 => \"]\"]"],
+            describe(&recovered)
+        );
+        assert_eq!(value("{\"a\": [1, 2]}"), recovered.value);
+
+        let recovered = Parser::parse_recovering("[1, 2}");
+        assert_eq!(
+            vec!["1:6 Expected a ']' [1:6-1:7 => \"]\"]"],
+            describe(&recovered)
+        );
+        assert_eq!(value("[1, 2]"), recovered.value);
+    }
+
+    #[test]
+    fn should_recover_keys_and_values() {
+        let recovered = Parser::parse_recovering("{name: 1, \"b\" 2, \"c\": }");
+        assert_eq!(
+            vec![
+                "1:2 Object keys must be quoted [1:2-1:6 => \"\\\"name\\\"\"]",
+                "1:14 Missing ':' [1:14-1:14 => \":\"]",
+                "1:23 Expected a value",
+            ],
+            describe(&recovered)
+        );
+        assert_eq!(
+            value("{\"name\": 1, \"b\": 2, \"c\": null}"),
+            recovered.value
+        );
+        assert_eq!(vec!["/c"], placeholders(&recovered));
+
+        let recovered = Parser::parse_recovering("[1,, 2 ; 3]");
+        assert_eq!(
+            vec![
+                "1:4 Unexpected ',' [1:4-1:5 => \"\"]",
+                "1:8 Unexpected ; [1:8-1:9 => \"\"]",
+                "1:7 Missing ',' [1:7-1:7 => \",\"]",
+            ],
+            describe(&recovered)
+        );
+        assert_eq!(value("[1, 2, 3]"), recovered.value);
+        assert!(recovered.placeholders.is_empty());
+
+        let recovered = Parser::parse_recovering("[tru, -, \"\\x\", 1.]");
+        assert_eq!(4, recovered.diagnostics.len());
+        assert_eq!(value("[null, null, \"\\\\x\", null]"), recovered.value);
+        assert_eq!(vec!["/0", "/1", "/3"], placeholders(&recovered));
+
+        let recovered = Parser::parse_recovering("[null, nul]");
+        assert_eq!(value("[null, null]"), recovered.value);
+        assert_eq!(vec!["/1"], placeholders(&recovered));
+    }
+
+    #[test]
+    fn should_forget_placeholders_that_are_replaced_or_dropped() {
+        let recovered = Parser::parse_recovering("{\"a\": [tru], \"a\": null, \"b\": [x]}");
+        assert_eq!(value("{\"a\": null, \"b\": [null]}"), recovered.value);
+        assert_eq!(vec!["/b/0"], placeholders(&recovered));
+
+        let options = ParserOptions {
+            max_array_len: 1,
+            ..Default::default()
+        };
+        let recovered = Parser::parse_recovering_with_options("[[x], [y]]", &options);
+        assert_eq!(value("[[null]]"), recovered.value);
+        assert_eq!(vec!["/0/0"], placeholders(&recovered));
+    }
+
+    #[test]
+    fn should_skip_long_runs_of_commas() {
+        let commas = ",".repeat(100_000);
+        for (json, expected) in [
+            (format!("{{{commas}}}"), "{}"),
+            (format!("{{{commas}\"a\": 1}}"), "{\"a\": 1}"),
+        ] {
+            let recovered = Parser::parse_recovering(&json);
+            assert_eq!(100_000, recovered.diagnostics.len());
+            assert_eq!(value(expected), recovered.value);
+        }
+    }
+
+    #[test]
+    fn should_report_documents_without_a_single_value() {
+        let recovered = Parser::parse_recovering("");
+        assert_eq!(vec!["1:1 Expected a value"], describe(&recovered));
+        assert_eq!(vec![""], placeholders(&recovered));
+        let recovered = Parser::parse_recovering("[1] x");
+        assert_eq!(
+            vec!["1:5 Unexpected characters after the json value"],
+            describe(&recovered)
+        );
+        assert_eq!(value("[1]"), recovered.value);
+    }
+
+    #[test]
+    fn should_apply_limits() {
+        let options = ParserOptions {
+            max_depth: 2,
+            max_array_len: 2,
+            ..Default::default()
+        };
+        let recovered =
+            Parser::parse_recovering_with_options("[[[1, [2]]], [3, 4, 5, [6]], 7]", &options);
+        let kinds: Vec<ParseErrorKind> = recovered.diagnostics.iter().map(|d| d.kind).collect();
+        assert_eq!(
+            vec![
+                ParseErrorKind::DepthLimitExceeded,
+                ParseErrorKind::ArrayTooLong,
+                ParseErrorKind::ArrayTooLong
+            ],
+            kinds
+        );
+        assert_eq!(value("[[null], [3, 4]]"), recovered.value);
+        assert_eq!(vec!["/0/0"], placeholders(&recovered));
+    }
+
+    proptest! {
+        #[test]
+        fn should_agree_with_parse_without_diagnostics(json in "[\\[\\]{}\",: 0-9a-z\\\\.\n-]{0,40}") {
+            let recovered = Parser::parse_recovering(&json);
+            for placeholder in &recovered.placeholders {
+                prop_assert_eq!(Some(&JsonElement::Null), placeholder.resolve(&recovered.value));
+            }
+            if recovered.diagnostics.is_empty() {
+                prop_assert!(recovered.placeholders.is_empty());
+                prop_assert_eq!(Parser::parse(&json).ok(), Some(recovered.value));
+            } else {
+                prop_assert!(Parser::parse(&json).is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Source locations of parsed values, for pointing at a value in messages
+//! after parsing succeeded.
+
+use super::{BoxResult, Frame, LineIndex, Parser, ParserOptions, Span};
+use crate::json_element::{JsonElement, Map};
+use crate::pointer::JsonPointer;
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// Where every value and object key of a document is, keyed by the JSON
+/// Pointer of the value. When an object repeats a key, the last member wins,
+/// as it does in the parsed object.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SpanTable {
+    values: Map<JsonPointer, Span>,
+    keys: Map<JsonPointer, Span>,
+}
+
+impl SpanTable {
+    /// The span of the value at `pointer`, from its first character to just
+    /// after its last, quotes and brackets included.
+    pub fn value(&self, pointer: &JsonPointer) -> Option<Span> {
+        return self.values.get(pointer).copied();
+    }
+
+    /// The span of the quoted key of the object member at `pointer`.
+    pub fn key(&self, pointer: &JsonPointer) -> Option<Span> {
+        return self.keys.get(pointer).copied();
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = (&JsonPointer, &Span)> {
+        return self.values.iter();
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = (&JsonPointer, &Span)> {
+        return self.keys.iter();
+    }
+}
+
+/// Byte ranges collected while parsing, turned into spans once it is done.
+#[derive(Default)]
+pub(super) struct SpanRecorder {
+    /// Start offsets of the arrays and objects being parsed.
+    opens: Vec<usize>,
+    /// The key just parsed, recorded once its member is on the stack.
+    pending_key: Option<(usize, usize)>,
+    values: Vec<(JsonPointer, usize, usize)>,
+    keys: Vec<(JsonPointer, usize, usize)>,
+}
+
+/// The pointer of the value being parsed: every open array contributes the
+/// index of its next element and every open object the key of its current member.
+fn pointer(stack: &[Frame]) -> JsonPointer {
+    let mut pointer = JsonPointer::root();
+    for frame in stack {
+        match frame {
+            Frame::Array(array) => pointer.push(&array.len().to_string()),
+            Frame::Object(_, key) => pointer.push(key),
+        }
+    }
+    return pointer;
+}
+
+fn is_descendant(pointer: &JsonPointer, ancestor: &JsonPointer) -> bool {
+    return pointer.tokens().len() > ancestor.tokens().len()
+        && pointer.tokens().starts_with(ancestor.tokens());
+}
+
+impl SpanRecorder {
+    pub(super) fn open(&mut self, start: usize) {
+        self.opens.push(start);
+    }
+
+    /// The start of the innermost array or object, which just closed.
+    pub(super) fn close(&mut self) -> usize {
+        return self.opens.pop().unwrap_or_default();
+    }
+
+    pub(super) fn value(&mut self, stack: &[Frame], start: usize, end: usize) {
+        self.values.push((pointer(stack), start, end));
+    }
+
+    pub(super) fn pending_key(&mut self, start: usize, end: usize) {
+        self.pending_key = Some((start, end));
+    }
+
+    pub(super) fn key(&mut self, stack: &[Frame]) {
+        if let Some((start, end)) = self.pending_key.take() {
+            self.keys.push((pointer(stack), start, end));
+        }
+    }
+
+    /// Values are recorded as they end, so the descendants of a value come
+    /// right before it. Going backwards, a pointer seen twice belongs to a member
+    /// whose key is repeated later on, and it is dropped along with them.
+    fn into_table(self, json: &str) -> SpanTable {
+        let lines = LineIndex::new(json);
+        let mut values = Map::new();
+        let mut ranges = self.values.into_iter().rev().peekable();
+        while let Some((pointer, start, end)) = ranges.next() {
+            if values.contains_key(&pointer) {
+                while ranges
+                    .peek()
+                    .is_some_and(|(inner, _, _)| is_descendant(inner, &pointer))
+                {
+                    ranges.next();
+                }
+                continue;
+            }
+            values.insert(pointer, lines.span(start, end));
+        }
+        let mut keys = Map::new();
+        for (pointer, start, end) in self.keys {
+            keys.insert(pointer, lines.span(start, end));
+        }
+        keys.retain(|pointer, _| values.contains_key(pointer));
+        return SpanTable { values, keys };
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// Parses `json` and records where each value and object key is.
+    pub fn parse_with_spans(json: &str) -> BoxResult<(JsonElement, SpanTable)> {
+        return Parser::parse_with_spans_and_options(json, &ParserOptions::default());
+    }
+
+    pub fn parse_with_spans_and_options(
+        json: &str,
+        options: &ParserOptions,
+    ) -> BoxResult<(JsonElement, SpanTable)> {
+        let mut parser = Parser::new(json, *options);
+        parser.spans = Some(SpanRecorder::default());
+        let element = parser.run(Parser::parse_value)?;
+        let spans = parser.spans.take().unwrap_or_default();
+        return Ok((element, spans.into_table(json)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpanTable;
+    use crate::parser::{Parser, ParserOptions, Span};
+    use crate::pointer::JsonPointer;
+
+    /// A span as `line:column-line:column`.
+    fn describe(span: Option<Span>) -> String {
+        return match span {
+            Some(span) => format!(
+                "{}:{}-{}:{}",
+                span.start.line, span.start.column, span.end.line, span.end.column
+            ),
+            None => "none".to_string(),
+        };
+    }
+
+    fn value(table: &SpanTable, pointer: &str) -> String {
+        return describe(table.value(&JsonPointer::parse(pointer).ok().unwrap()));
+    }
+
+    fn key(table: &SpanTable, pointer: &str) -> String {
+        return describe(table.key(&JsonPointer::parse(pointer).ok().unwrap()));
+    }
+
+    #[test]
+    fn should_record_values_and_keys() {
+        let json = "{\n  \"name\": \"jsonp\",\n  \"ports\": [80, 443],\n  \"tls\": {\"on\": true},\n  \"empty\": []\n}\n";
+        let (element, table) = Parser::parse_with_spans(json).ok().unwrap();
+        assert_eq!(Parser::parse(json).ok(), Some(element));
+        assert_eq!("1:1-6:2", value(&table, ""));
+        assert_eq!("2:11-2:18", value(&table, "/name"));
+        assert_eq!("2:3-2:9", key(&table, "/name"));
+        assert_eq!("3:12-3:21", value(&table, "/ports"));
+        assert_eq!("3:13-3:15", value(&table, "/ports/0"));
+        assert_eq!("3:17-3:20", value(&table, "/ports/1"));
+        assert_eq!("4:10-4:22", value(&table, "/tls"));
+        assert_eq!("4:17-4:21", value(&table, "/tls/on"));
+        assert_eq!("4:11-4:15", key(&table, "/tls/on"));
+        assert_eq!("5:12-5:14", value(&table, "/empty"));
+        assert_eq!("none", value(&table, "/ports/2"));
+        assert_eq!("none", key(&table, "/ports/0"));
+        assert_eq!(8, table.values().count());
+        assert_eq!(5, table.keys().count());
+
+        let span = table
+            .value(&JsonPointer::parse("/ports/1").ok().unwrap())
+            .unwrap();
+        assert_eq!("443", &json[span.start.offset..span.end.offset]);
+    }
+
+    #[test]
+    fn should_record_nested_arrays_and_escaped_keys() {
+        let json = "[[1, [\"\u{e9}\"]], {\"a/b\": {\"\": null}}]";
+        let (_, table) = Parser::parse_with_spans(json).ok().unwrap();
+        assert_eq!("1:1-1:34", value(&table, ""));
+        assert_eq!("1:6-1:11", value(&table, "/0/1"));
+        assert_eq!("1:7-1:10", value(&table, "/0/1/0"));
+        assert_eq!("1:27-1:31", value(&table, "/1/a~1b/"));
+        assert_eq!("1:23-1:25", key(&table, "/1/a~1b/"));
+    }
+
+    #[test]
+    fn should_keep_the_last_duplicate_key() {
+        let (element, table) = Parser::parse_with_spans("{\"a\": 1, \"a\": [2]}")
+            .ok()
+            .unwrap();
+        assert_eq!(Parser::parse("{\"a\": [2]}").ok(), Some(element));
+        assert_eq!("1:15-1:18", value(&table, "/a"));
+        assert_eq!("1:10-1:13", key(&table, "/a"));
+
+        let json = "{\"a\": {\"x\": 1, \"y\": [2]}, \"b\": 3, \"a\": {\"y\": 4}}";
+        let (_, table) = Parser::parse_with_spans(json).ok().unwrap();
+        assert_eq!("1:40-1:48", value(&table, "/a"));
+        assert_eq!("1:46-1:47", value(&table, "/a/y"));
+        assert_eq!("1:41-1:44", key(&table, "/a/y"));
+        assert_eq!("1:32-1:33", value(&table, "/b"));
+        assert_eq!("none", value(&table, "/a/x"));
+        assert_eq!("none", key(&table, "/a/x"));
+        assert_eq!("none", value(&table, "/a/y/0"));
+        assert_eq!(4, table.values().count());
+        assert_eq!(3, table.keys().count());
+    }
+
+    #[test]
+    fn should_report_errors_like_parse() {
+        for json in ["[1,", "{\"a\" 1}", "[1] 2"] {
+            let error = Parser::parse_with_spans(json).err().unwrap();
+            assert_eq!(Parser::parse(json).err().unwrap(), error);
+        }
+        let options = ParserOptions {
+            max_depth: 1,
+            ..Default::default()
+        };
+        assert!(Parser::parse_with_spans_and_options("[[]]", &options).is_err());
+    }
+}
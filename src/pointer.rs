@@ -0,0 +1,284 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! JSON Pointers (RFC 6901), such as `/users/0/name`, naming a value inside a
+//! document.
+
+use crate::json_element::JsonElement;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, PartialEq)]
+pub struct PointerError {
+    pub details: String,
+}
+
+impl PointerError {
+    pub fn new(msg: &str) -> PointerError {
+        return PointerError {
+            details: msg.to_string(),
+        };
+    }
+}
+
+impl fmt::Display for PointerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl core::error::Error for PointerError {}
+
+/// The reference tokens of a pointer, unescaped. The empty pointer names the
+/// whole document.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct JsonPointer {
+    tokens: Vec<String>,
+}
+
+impl JsonPointer {
+    pub fn root() -> JsonPointer {
+        return JsonPointer::default();
+    }
+
+    /// Parses the string form of a pointer, where `~1` stands for `/` and `~0`
+    /// for `~` inside a token.
+    pub fn parse(pointer: &str) -> Result<JsonPointer, PointerError> {
+        if pointer.is_empty() {
+            return Ok(JsonPointer::root());
+        }
+        let rest = match pointer.strip_prefix('/') {
+            Some(rest) => rest,
+            None => return Err(PointerError::new("A json pointer must start with '/'")),
+        };
+        let mut tokens = Vec::new();
+        for token in rest.split('/') {
+            let mut unescaped = String::with_capacity(token.len());
+            let mut chars = token.chars();
+            while let Some(c) = chars.next() {
+                if c != '~' {
+                    unescaped.push(c);
+                    continue;
+                }
+                match chars.next() {
+                    Some('0') => unescaped.push('~'),
+                    Some('1') => unescaped.push('/'),
+                    _ => {
+                        return Err(PointerError::new(&format!(
+                            "Invalid escape in json pointer token {token}"
+                        )))
+                    }
+                }
+            }
+            tokens.push(unescaped);
+        }
+        return Ok(JsonPointer { tokens });
+    }
+
+    /// Parses a simple path such as `ctx.tags[0]` or `.ctx.host`: keys joined
+    /// by dots, with array indices in brackets. An empty path or `.` names the
+    /// whole document. Keys containing `.`, `[` or `]` need a pointer instead.
+    pub fn from_path(path: &str) -> Result<JsonPointer, PointerError> {
+        let mut pointer = JsonPointer::root();
+        let rest = path.strip_prefix('.').unwrap_or(path);
+        if rest.is_empty() {
+            return Ok(pointer);
+        }
+        let invalid = || PointerError::new(&format!("Invalid path {path}"));
+        let mut chars = rest.chars().peekable();
+        loop {
+            if chars.peek() == Some(&'[') {
+                chars.next();
+                let mut index = String::new();
+                while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                    index.push(c);
+                }
+                if index.is_empty() || chars.next() != Some(']') {
+                    return Err(invalid());
+                }
+                pointer.tokens.push(index);
+            } else {
+                let mut key = String::new();
+                while let Some(c) = chars.next_if(|c| !matches!(c, '.' | '[' | ']')) {
+                    key.push(c);
+                }
+                if key.is_empty() {
+                    return Err(invalid());
+                }
+                pointer.tokens.push(key);
+            }
+            match chars.peek() {
+                None => return Ok(pointer),
+                Some('[') => {}
+                Some('.') => {
+                    chars.next();
+                    if chars.peek().is_none() {
+                        return Err(invalid());
+                    }
+                }
+                Some(_) => return Err(invalid()),
+            }
+        }
+    }
+
+    pub fn tokens(&self) -> &[String] {
+        return &self.tokens;
+    }
+
+    pub fn push(&mut self, token: &str) {
+        self.tokens.push(token.to_string());
+    }
+
+    /// Keeps the first `len` tokens.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.tokens.truncate(len);
+    }
+
+    /// This pointer followed by one more token.
+    pub fn child(&self, token: &str) -> JsonPointer {
+        let mut child = self.clone();
+        child.push(token);
+        return child;
+    }
+
+    /// This pointer followed by an array index.
+    pub fn index(&self, index: usize) -> JsonPointer {
+        return self.child(&index.to_string());
+    }
+
+    /// The value this pointer names in `element`, if there is one. Array indices
+    /// must be written in decimal without leading zeros.
+    pub fn resolve<'a>(&self, element: &'a JsonElement) -> Option<&'a JsonElement> {
+        let mut current = element;
+        for token in &self.tokens {
+            current = match current {
+                JsonElement::Object(object) => object.get(token)?,
+                JsonElement::Array(array) => array.get(array_index(token)?)?,
+                _ => return None,
+            };
+        }
+        return Some(current);
+    }
+
+    pub fn resolve_mut<'a>(&self, element: &'a mut JsonElement) -> Option<&'a mut JsonElement> {
+        let mut current = element;
+        for token in &self.tokens {
+            current = match current {
+                JsonElement::Object(object) => object.get_mut(token)?,
+                JsonElement::Array(array) => array.get_mut(array_index(token)?)?,
+                _ => return None,
+            };
+        }
+        return Some(current);
+    }
+}
+
+pub(crate) fn array_index(token: &str) -> Option<usize> {
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) {
+        return None;
+    }
+    if !token.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    return token.parse().ok();
+}
+
+impl fmt::Display for JsonPointer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for token in &self.tokens {
+            write!(f, "/{}", token.replace('~', "~0").replace('/', "~1"))?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonPointer;
+    use crate::json_element::JsonElement;
+    use crate::parser::Parser;
+
+    #[test]
+    fn should_resolve_rfc_examples() {
+        let document = Parser::parse(
+            r#"{"foo": ["bar", "baz"], "": 0, "a/b": 1, "c%d": 2, "e^f": 3,
+                "g|h": 4, "i\\j": 5, "k\"l": 6, " ": 7, "m~n": 8}"#,
+        )
+        .ok()
+        .unwrap();
+        let examples = [
+            ("/foo/0", JsonElement::Str("bar".to_string())),
+            ("/", JsonElement::Number(0.0)),
+            ("/a~1b", JsonElement::Number(1.0)),
+            ("/c%d", JsonElement::Number(2.0)),
+            ("/e^f", JsonElement::Number(3.0)),
+            ("/g|h", JsonElement::Number(4.0)),
+            ("/i\\j", JsonElement::Number(5.0)),
+            ("/k\"l", JsonElement::Number(6.0)),
+            ("/ ", JsonElement::Number(7.0)),
+            ("/m~0n", JsonElement::Number(8.0)),
+        ];
+        for (pointer, expected) in examples {
+            let parsed = JsonPointer::parse(pointer).ok().unwrap();
+            assert_eq!(Some(&expected), parsed.resolve(&document), "{pointer}");
+            assert_eq!(pointer, parsed.to_string());
+        }
+        let root = JsonPointer::parse("").ok().unwrap();
+        assert_eq!(Some(&document), root.resolve(&document));
+        assert_eq!("", root.to_string());
+    }
+
+    #[test]
+    fn should_parse_paths() {
+        for (path, pointer) in [
+            ("", ""),
+            (".", ""),
+            ("ctx", "/ctx"),
+            (".ctx.host", "/ctx/host"),
+            ("ctx.tags[0]", "/ctx/tags/0"),
+            ("[1][2].a b", "/1/2/a b"),
+            ("a/b.~c", "/a~1b/~0c"),
+        ] {
+            let parsed = JsonPointer::from_path(path).ok().unwrap();
+            assert_eq!(pointer, parsed.to_string(), "{path}");
+        }
+        for invalid in [
+            "..", "a.", "a..b", "a[", "a[x]", "a[]", "a]", "a[0]b", ".[0",
+        ] {
+            assert!(JsonPointer::from_path(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_pointers() {
+        assert!(JsonPointer::parse("foo").is_err());
+        assert!(JsonPointer::parse("/a~2").is_err());
+        assert!(JsonPointer::parse("/a~").is_err());
+
+        let mut document = Parser::parse("[[1, 2]]").ok().unwrap();
+        for missing in ["/1", "/0/01", "/0/-", "/0/x", "/0/0/0"] {
+            let pointer = JsonPointer::parse(missing).ok().unwrap();
+            assert_eq!(None, pointer.resolve(&document), "{missing}");
+        }
+
+        let pointer = JsonPointer::root().index(0).index(1);
+        *pointer.resolve_mut(&mut document).unwrap() = JsonElement::Null;
+        assert_eq!("/0/1", pointer.to_string());
+        assert_eq!(Parser::parse("[[1, null]]").ok(), Some(document));
+    }
+}
@@ -0,0 +1,800 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Parsing input that arrives in pieces.
+//!
+//! A `PushParser` is fed chunks of bytes as they are received, split anywhere,
+//! even inside a string, a number or a `\u` escape. Everything it has read so
+//! far is kept in its state, so each byte is looked at once. Events and values
+//! are handed out as soon as they are complete, and `Status::NeedMoreData`
+//! tells a chunk boundary apart from an error.
+//!
+//! The input is a stream of values separated by optional whitespace, such as
+//! one document per connection or newline delimited records.
+
+use crate::json_element::{JsonElement, Map};
+use crate::parser::{string_to_number, ParseError, ParseErrorKind, ParserOptions, Position};
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// One step of a value, in input order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    StartObject,
+    EndObject,
+    StartArray,
+    EndArray,
+    /// An object key. The member's value comes next.
+    Key(String),
+    /// A string, number, boolean or null.
+    Scalar(JsonElement),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status<T> {
+    Ready(T),
+    /// Everything fed so far has been read. Feed more or call `finish`.
+    NeedMoreData,
+    /// `finish` was called and every value has been read.
+    Done,
+}
+
+/// Where a number is in the grammar, after the characters read so far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Start,
+    Minus,
+    Zero,
+    Int,
+    Dot,
+    Frac,
+    Exp,
+    ExpSign,
+    ExpDigits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Backslash,
+    /// Inside `\u`, with the hex digits read so far and the high surrogate
+    /// whose low half this is, if any.
+    Unicode(u8, u32, Option<u32>),
+    /// After a high surrogate, before the `\` of its low half.
+    LowBackslash(u32),
+    /// After a high surrogate and a `\`, before the `u`.
+    LowU(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Between top level values.
+    Top,
+    /// Right after a top level number or literal, which the next value has to
+    /// be set apart from.
+    Separator,
+    /// After a `:` or a `,` in an array.
+    Value,
+    /// After a `[`.
+    FirstElement,
+    /// After a `{`.
+    FirstKey,
+    /// After a `,` in an object.
+    Key,
+    /// After an object key.
+    Colon,
+    /// After a value inside an array or object.
+    AfterValue,
+    /// Inside a string, which is an object key when the flag is set.
+    Str(bool, Escape),
+    Number(Number),
+    /// Inside `true`, `false` or `null`, with how many bytes matched.
+    Literal(&'static [u8], usize),
+}
+
+/// An open array or object and how many members it has so far.
+struct Open {
+    object: bool,
+    len: usize,
+}
+
+/// An array or object being assembled by `next_value`.
+enum Building {
+    Array(Vec<JsonElement>),
+    Object(Map<String, JsonElement>, String),
+}
+
+/// Parses json fed in chunks. Limits from `ParserOptions` apply to each top
+/// level value, with `max_input_bytes` bounding the size of a single value.
+/// Top level values may follow one another directly, except that whitespace has
+/// to come between a number or literal and a value starting with neither a
+/// bracket nor a quote.
+pub struct PushParser {
+    options: ParserOptions,
+    input: Vec<u8>,
+    /// How much of `input` has been read.
+    read: usize,
+    finished: bool,
+    state: State,
+    open: Vec<Open>,
+    /// The bytes of the string being read, escapes decoded.
+    text: Vec<u8>,
+    number: String,
+    /// Where the current top level value started.
+    value_start: usize,
+    position: Position,
+    error: Option<ParseError>,
+    building: Vec<Building>,
+}
+
+impl Default for PushParser {
+    fn default() -> PushParser {
+        return PushParser::with_options(ParserOptions::default());
+    }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    return matches!(byte, b' ' | b'\n' | b'\r' | b'\t');
+}
+
+fn push_char(text: &mut Vec<u8>, c: char) {
+    text.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+fn hex_value(byte: u8) -> Option<u32> {
+    return (byte as char).to_digit(16);
+}
+
+impl PushParser {
+    pub fn new() -> PushParser {
+        return PushParser::default();
+    }
+
+    pub fn with_options(options: ParserOptions) -> PushParser {
+        return PushParser {
+            options,
+            input: Vec::new(),
+            read: 0,
+            finished: false,
+            state: State::Top,
+            open: Vec::new(),
+            text: Vec::new(),
+            number: String::new(),
+            value_start: 0,
+            position: Position {
+                offset: 0,
+                line: 1,
+                column: 1,
+            },
+            error: None,
+            building: Vec::new(),
+        };
+    }
+
+    /// Adds the next chunk of input. Bytes already read are dropped first, so
+    /// only what has not been read yet is kept.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.input.drain(..self.read);
+        self.read = 0;
+        self.input.extend_from_slice(chunk);
+    }
+
+    /// Marks the end of the input. A value still open then is an error.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// The next event. After an error every call returns that error.
+    pub fn next_event(&mut self) -> Result<Status<Event>, ParseError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        loop {
+            let byte = self.input.get(self.read).copied();
+            if byte.is_none() && !self.finished {
+                return Ok(Status::NeedMoreData);
+            }
+            if byte.is_none() && matches!(self.state, State::Top | State::Separator) {
+                return Ok(Status::Done);
+            }
+            match self.read_byte(byte) {
+                Ok(Some(event)) => return Ok(Status::Ready(event)),
+                Ok(None) => {}
+                Err(mut error) => {
+                    error.position = Some(self.position);
+                    self.error = Some(error.clone());
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// The next complete top level value, assembled from events. Use either
+    /// this or `next_event` on a parser, not both.
+    pub fn next_value(&mut self) -> Result<Status<JsonElement>, ParseError> {
+        loop {
+            let event = match self.next_event()? {
+                Status::Ready(event) => event,
+                Status::NeedMoreData => return Ok(Status::NeedMoreData),
+                Status::Done => return Ok(Status::Done),
+            };
+            let value = match event {
+                Event::StartArray => {
+                    self.building.push(Building::Array(Vec::new()));
+                    continue;
+                }
+                Event::StartObject => {
+                    self.building
+                        .push(Building::Object(Map::new(), String::new()));
+                    continue;
+                }
+                Event::Key(name) => {
+                    if let Some(Building::Object(_, key)) = self.building.last_mut() {
+                        *key = name;
+                    }
+                    continue;
+                }
+                Event::Scalar(value) => value,
+                Event::EndArray | Event::EndObject => match self.building.pop() {
+                    Some(Building::Array(elements)) => JsonElement::Array(elements),
+                    Some(Building::Object(members, _)) => JsonElement::Object(members),
+                    None => continue,
+                },
+            };
+            match self.building.last_mut() {
+                None => return Ok(Status::Ready(value)),
+                Some(Building::Array(elements)) => elements.push(value),
+                Some(Building::Object(members, key)) => {
+                    members.insert(core::mem::take(key), value);
+                }
+            }
+        }
+    }
+
+    /// Steps over `byte` and moves past it when it was used up.
+    fn read_byte(&mut self, byte: Option<u8>) -> Result<Option<Event>, ParseError> {
+        let between = matches!(self.state, State::Top | State::Separator);
+        let (consumed, event) = self.step(byte)?;
+        if let (true, Some(byte)) = (consumed, byte) {
+            self.advance(byte);
+            if !(between && is_whitespace(byte))
+                && self.position.offset - self.value_start > self.options.max_input_bytes
+            {
+                return Err(ParseError::with_kind(
+                    ParseErrorKind::InputTooLarge,
+                    &format!(
+                        "Input is larger than {} bytes",
+                        self.options.max_input_bytes
+                    ),
+                ));
+            }
+        }
+        return Ok(event);
+    }
+
+    fn advance(&mut self, byte: u8) {
+        self.read += 1;
+        self.position.offset += 1;
+        if byte == b'\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else if byte & 0xC0 != 0x80 {
+            self.position.column += 1;
+        }
+    }
+
+    /// Reads `byte`, or the end of the input when it is `None`. Returns whether
+    /// the byte was used up, which it is not when it ends a number, and the
+    /// event it completed.
+    fn step(&mut self, byte: Option<u8>) -> Result<(bool, Option<Event>), ParseError> {
+        let skip = byte.filter(|byte| is_whitespace(*byte)).is_some();
+        match self.state {
+            State::Str(key, escape) => return self.string(key, escape, byte),
+            State::Number(number) => return self.number(number, byte),
+            State::Literal(word, matched) => {
+                if byte != Some(word[matched]) {
+                    return Err(ParseError::new(&format!(
+                        "Expected a '{}'",
+                        word[matched] as char
+                    )));
+                }
+                if matched + 1 < word.len() {
+                    self.state = State::Literal(word, matched + 1);
+                    return Ok((true, None));
+                }
+                let value = match word {
+                    b"true" => JsonElement::Boolean(true),
+                    b"false" => JsonElement::Boolean(false),
+                    _ => JsonElement::Null,
+                };
+                self.scalar_done()?;
+                return Ok((true, Some(Event::Scalar(value))));
+            }
+            State::Separator if skip => {
+                self.state = State::Top;
+                return Ok((true, None));
+            }
+            _ if skip => return Ok((true, None)),
+            State::Separator if matches!(byte, Some(b'-' | b'0'..=b'9' | b't' | b'f' | b'n')) => {
+                return Err(ParseError::new("Expected whitespace between values"));
+            }
+            State::Top | State::Separator => {
+                self.value_start = self.position.offset;
+                return self.value(byte);
+            }
+            State::Value => return self.value(byte),
+            State::FirstElement if byte == Some(b']') => return self.close(),
+            State::FirstElement => return self.value(byte),
+            State::FirstKey if byte == Some(b'}') => return self.close(),
+            State::FirstKey | State::Key => {
+                if byte != Some(b'"') {
+                    return Err(ParseError::new("Expected a string as object key"));
+                }
+                self.text.clear();
+                self.state = State::Str(true, Escape::None);
+                return Ok((true, None));
+            }
+            State::Colon => {
+                if byte != Some(b':') {
+                    return Err(ParseError::new("Expected a colon"));
+                }
+                self.state = State::Value;
+                return Ok((true, None));
+            }
+            State::AfterValue => {
+                let object = self.open.last().is_some_and(|open| open.object);
+                match (byte, object) {
+                    (Some(b','), false) => self.state = State::Value,
+                    (Some(b','), true) => self.state = State::Key,
+                    (Some(b']'), false) | (Some(b'}'), true) => return self.close(),
+                    (_, false) => return Err(ParseError::new("Expecting a ',' or ']'")),
+                    (_, true) => return Err(ParseError::new("Expecting a ',' or '}'")),
+                }
+                return Ok((true, None));
+            }
+        }
+    }
+
+    /// Starts the value beginning with `byte`.
+    fn value(&mut self, byte: Option<u8>) -> Result<(bool, Option<Event>), ParseError> {
+        let byte = match byte {
+            Some(byte) => byte,
+            None => return Err(ParseError::new("Invalid json value")),
+        };
+        match byte {
+            b'{' | b'[' => {
+                if self.open.len() >= self.options.max_depth {
+                    return Err(ParseError::with_kind(
+                        ParseErrorKind::DepthLimitExceeded,
+                        &format!("Nesting is deeper than {} levels", self.options.max_depth),
+                    ));
+                }
+                let object = byte == b'{';
+                self.open.push(Open { object, len: 0 });
+                if object {
+                    self.state = State::FirstKey;
+                    return Ok((true, Some(Event::StartObject)));
+                }
+                self.state = State::FirstElement;
+                return Ok((true, Some(Event::StartArray)));
+            }
+            b'"' => {
+                self.text.clear();
+                self.state = State::Str(false, Escape::None);
+                return Ok((true, None));
+            }
+            b'-' | b'0'..=b'9' => {
+                self.number.clear();
+                self.state = State::Number(Number::Start);
+                return Ok((false, None));
+            }
+            b't' => self.state = State::Literal(b"true", 1),
+            b'f' => self.state = State::Literal(b"false", 1),
+            b'n' => self.state = State::Literal(b"null", 1),
+            b'}' | b']' | b':' | b',' => return Err(ParseError::new("Invalid json value")),
+            _ => return Err(ParseError::new("Expected true, false or null")),
+        }
+        return Ok((true, None));
+    }
+
+    /// Closes the innermost array or object.
+    fn close(&mut self) -> Result<(bool, Option<Event>), ParseError> {
+        let event = match self.open.pop() {
+            Some(Open { object: true, .. }) => Event::EndObject,
+            _ => Event::EndArray,
+        };
+        self.value_done()?;
+        return Ok((true, Some(event)));
+    }
+
+    /// Counts a finished number or literal in its array or object.
+    fn scalar_done(&mut self) -> Result<(), ParseError> {
+        self.value_done()?;
+        if self.state == State::Top {
+            self.state = State::Separator;
+        }
+        return Ok(());
+    }
+
+    /// Counts a finished value in its array or object.
+    fn value_done(&mut self) -> Result<(), ParseError> {
+        let open = match self.open.last_mut() {
+            Some(open) => open,
+            None => {
+                self.state = State::Top;
+                return Ok(());
+            }
+        };
+        open.len += 1;
+        if !open.object && open.len > self.options.max_array_len {
+            return Err(ParseError::with_kind(
+                ParseErrorKind::ArrayTooLong,
+                &format!(
+                    "Array has more than {} elements",
+                    self.options.max_array_len
+                ),
+            ));
+        }
+        if open.object && open.len > self.options.max_object_members {
+            return Err(ParseError::with_kind(
+                ParseErrorKind::TooManyObjectMembers,
+                &format!(
+                    "Object has more than {} members",
+                    self.options.max_object_members
+                ),
+            ));
+        }
+        self.state = State::AfterValue;
+        return Ok(());
+    }
+
+    fn string(
+        &mut self,
+        key: bool,
+        escape: Escape,
+        byte: Option<u8>,
+    ) -> Result<(bool, Option<Event>), ParseError> {
+        let byte = match byte {
+            Some(byte) => byte,
+            None => return Err(ParseError::new("Json ended without closing string")),
+        };
+        let mut next = Escape::None;
+        match escape {
+            Escape::None => match byte {
+                b'"' => return self.end_string(key),
+                b'\\' => next = Escape::Backslash,
+                _ if byte < b' ' => {
+                    return Err(ParseError::new(&format!(
+                        "Unescaped control character U+{byte:04X} in string"
+                    )))
+                }
+                _ => self.text.push(byte),
+            },
+            Escape::Backslash => match byte {
+                b'"' | b'\\' | b'/' => self.text.push(byte),
+                b'b' => self.text.push(0x08),
+                b'f' => self.text.push(0x0c),
+                b'n' => self.text.push(b'\n'),
+                b'r' => self.text.push(b'\r'),
+                b't' => self.text.push(b'\t'),
+                b'u' => next = Escape::Unicode(0, 0, None),
+                _ => {
+                    return Err(ParseError::new(&format!(
+                        "Unknown escaped character {}",
+                        String::from_utf8_lossy(&[byte])
+                    )))
+                }
+            },
+            Escape::Unicode(digits, value, high) => {
+                let value = match hex_value(byte) {
+                    Some(digit) => value << 4 | digit,
+                    None => return Err(ParseError::new("Expected a hex digit after a \\u")),
+                };
+                if digits < 3 {
+                    next = Escape::Unicode(digits + 1, value, high);
+                } else if let Some(high) = high {
+                    if !(0xDC00..0xE000).contains(&value) {
+                        return Err(ParseError::new(&format!(
+                            "Expected a low surrogate after \\u{high:x}"
+                        )));
+                    }
+                    let c = 0x10000 + ((high - 0xD800) << 10) + (value - 0xDC00);
+                    push_char(&mut self.text, char::from_u32(c).unwrap());
+                } else if (0xD800..0xDC00).contains(&value) {
+                    next = Escape::LowBackslash(value);
+                } else {
+                    match char::from_u32(value) {
+                        Some(c) => push_char(&mut self.text, c),
+                        None => {
+                            return Err(ParseError::new(&format!(
+                                "Invalid unicode character {:x}",
+                                value
+                            )))
+                        }
+                    }
+                }
+            }
+            Escape::LowBackslash(high) if byte == b'\\' => next = Escape::LowU(high),
+            Escape::LowU(high) if byte == b'u' => next = Escape::Unicode(0, 0, Some(high)),
+            Escape::LowBackslash(high) | Escape::LowU(high) => {
+                return Err(ParseError::new(&format!(
+                    "Expected a low surrogate after \\u{high:x}"
+                )));
+            }
+        }
+        if self.text.len() > self.options.max_string_len {
+            return Err(ParseError::with_kind(
+                ParseErrorKind::StringTooLong,
+                &format!(
+                    "String is longer than {} bytes",
+                    self.options.max_string_len
+                ),
+            ));
+        }
+        self.state = State::Str(key, next);
+        return Ok((true, None));
+    }
+
+    fn end_string(&mut self, key: bool) -> Result<(bool, Option<Event>), ParseError> {
+        let string = match String::from_utf8(core::mem::take(&mut self.text)) {
+            Ok(string) => string,
+            Err(_) => return Err(ParseError::new("Invalid UTF-8 in string")),
+        };
+        if key {
+            self.state = State::Colon;
+            return Ok((true, Some(Event::Key(string))));
+        }
+        self.value_done()?;
+        return Ok((true, Some(Event::Scalar(JsonElement::Str(string)))));
+    }
+
+    fn number(
+        &mut self,
+        number: Number,
+        byte: Option<u8>,
+    ) -> Result<(bool, Option<Event>), ParseError> {
+        let digit = byte.filter(u8::is_ascii_digit).is_some();
+        let exponent = matches!(byte, Some(b'e' | b'E'));
+        let next = match number {
+            Number::Start if byte == Some(b'-') => Number::Minus,
+            Number::Start | Number::Minus if byte == Some(b'0') => Number::Zero,
+            Number::Start | Number::Minus if digit => Number::Int,
+            Number::Start | Number::Minus => {
+                return Err(ParseError::new("Expected a number after a '-'"))
+            }
+            Number::Zero if digit => {
+                return Err(ParseError::new("Expected a '.' or 'e' or 'E'"));
+            }
+            Number::Int if digit => Number::Int,
+            Number::Zero | Number::Int if byte == Some(b'.') => Number::Dot,
+            Number::Dot | Number::Frac if digit => Number::Frac,
+            Number::Zero | Number::Int | Number::Frac if exponent => Number::Exp,
+            Number::Exp if matches!(byte, Some(b'+' | b'-')) => Number::ExpSign,
+            Number::Exp | Number::ExpSign | Number::ExpDigits if digit => Number::ExpDigits,
+            Number::Dot | Number::Exp | Number::ExpSign => {
+                return Err(ParseError::new("Expected a digit"));
+            }
+            Number::Zero | Number::Int | Number::Frac | Number::ExpDigits => {
+                let value = string_to_number(&self.number)?;
+                self.scalar_done()?;
+                return Ok((false, Some(Event::Scalar(JsonElement::Number(value)))));
+            }
+        };
+        if self.number.len() >= self.options.max_number_len {
+            return Err(ParseError::with_kind(
+                ParseErrorKind::NumberTooLong,
+                &format!(
+                    "Number is longer than {} characters",
+                    self.options.max_number_len
+                ),
+            ));
+        }
+        if let Some(byte) = byte {
+            self.number.push(byte as char);
+        }
+        self.state = State::Number(next);
+        return Ok((true, None));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, PushParser, Status};
+    use crate::json_element::JsonElement;
+    use crate::parser::{ParseErrorKind, Parser, ParserOptions, Position};
+    use crate::test_support::near_json;
+
+    use proptest::prelude::*;
+
+    /// Feeds `chunks` one by one and collects every value, ending with the
+    /// first error if there is one.
+    fn values(chunks: &[&[u8]]) -> (Vec<JsonElement>, Option<String>) {
+        let mut parser = PushParser::new();
+        let mut values = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            parser.feed(chunk);
+            if i + 1 == chunks.len() {
+                parser.finish();
+            }
+            loop {
+                match parser.next_value() {
+                    Ok(Status::Ready(value)) => values.push(value),
+                    Ok(Status::NeedMoreData) => break,
+                    Ok(Status::Done) => return (values, None),
+                    Err(error) => return (values, Some(error.details)),
+                }
+            }
+        }
+        return (values, None);
+    }
+
+    fn scalar(json: &str) -> Event {
+        return Event::Scalar(Parser::parse(json).ok().unwrap());
+    }
+
+    #[test]
+    fn should_parse_across_any_chunk_boundary() {
+        let json = "{\"name\": \"caf\u{e9} \\u00e9\\ud83d\\ude00\\n\", \"n\": [-0.5e+3, 10, 0],\r\n \"ok\": [true, false, null, {}, []]}";
+        let expected = Parser::parse(json).ok().unwrap();
+        let bytes = json.as_bytes();
+        for split in 0..=bytes.len() {
+            let (left, right) = bytes.split_at(split);
+            assert_eq!(
+                (vec![expected.clone()], None),
+                values(&[left, right]),
+                "{split}"
+            );
+        }
+        let single: Vec<&[u8]> = bytes.chunks(1).collect();
+        assert_eq!((vec![expected], None), values(&single));
+    }
+
+    #[test]
+    fn should_report_events_as_they_complete() {
+        let mut parser = PushParser::new();
+        parser.feed(br#"{"a": [1, tr"#);
+        let mut events = Vec::new();
+        while let Ok(Status::Ready(event)) = parser.next_event() {
+            events.push(event);
+        }
+        assert_eq!(
+            vec![
+                Event::StartObject,
+                Event::Key("a".to_string()),
+                Event::StartArray,
+                scalar("1")
+            ],
+            events
+        );
+        assert_eq!(Ok(Status::NeedMoreData), parser.next_event());
+
+        parser.feed(b"ue]} 12");
+        assert_eq!(Ok(Status::Ready(scalar("true"))), parser.next_event());
+        assert_eq!(Ok(Status::Ready(Event::EndArray)), parser.next_event());
+        assert_eq!(Ok(Status::Ready(Event::EndObject)), parser.next_event());
+        // `12` may go on in the next chunk.
+        assert_eq!(Ok(Status::NeedMoreData), parser.next_event());
+        parser.finish();
+        assert_eq!(Ok(Status::Ready(scalar("12"))), parser.next_event());
+        assert_eq!(Ok(Status::Done), parser.next_event());
+    }
+
+    #[test]
+    fn should_read_a_stream_of_values() {
+        let (values, error) = values(&[b"{\"id\": 1}\n{\"id\"", b": 2}\n[]\"x\"null 3[4]\n"]);
+        assert_eq!(None, error);
+        let expected: Vec<JsonElement> = [
+            r#"{"id": 1}"#,
+            r#"{"id": 2}"#,
+            "[]",
+            r#""x""#,
+            "null",
+            "3",
+            "[4]",
+        ]
+        .iter()
+        .map(|json| Parser::parse(json).ok().unwrap())
+        .collect();
+        assert_eq!(expected, values);
+    }
+
+    #[test]
+    fn should_report_errors_as_soon_as_they_are_read() {
+        let mut parser = PushParser::new();
+        parser.feed(b"[1,\n 2 3");
+        let error = loop {
+            match parser.next_event() {
+                Ok(Status::Ready(_)) => {}
+                Ok(status) => panic!("{status:?}"),
+                Err(error) => break error,
+            }
+        };
+        assert_eq!("Expecting a ',' or ']'", error.details);
+        assert_eq!(
+            Some(Position {
+                offset: 7,
+                line: 2,
+                column: 4
+            }),
+            error.position
+        );
+        assert_eq!(Err(error), parser.next_event());
+
+        for (json, details) in [
+            (&b"[1, "[..], "Invalid json value"),
+            (b"{\"a\": \"b", "Json ended without closing string"),
+            (b"\"\\ud83dx\"", "Expected a low surrogate after \\ud83d"),
+            (b"\"\xc3\"", "Invalid UTF-8 in string"),
+            (
+                b"[\"a\tb\"]",
+                "Unescaped control character U+0009 in string",
+            ),
+            (b"tru", "Expected a 'e'"),
+            (b"-", "Expected a number after a '-'"),
+            (b"{\"a\" 1}", "Expected a colon"),
+            (b"1 ]", "Invalid json value"),
+            (b"1true", "Expected whitespace between values"),
+            (b"1-2", "Expected whitespace between values"),
+            (b"nullnull", "Expected whitespace between values"),
+            (b"false\t0-1", "Expected whitespace between values"),
+        ] {
+            let (left, right) = json.split_at(json.len() / 2);
+            assert_eq!(
+                Some(details.to_string()),
+                values(&[left, right]).1,
+                "{details}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_apply_limits_to_each_value() {
+        let options = ParserOptions {
+            max_depth: 2,
+            max_input_bytes: 8,
+            ..ParserOptions::default()
+        };
+        let mut parser = PushParser::with_options(options);
+        parser.feed(b"[[1]] [[2]] [[[3]]]");
+        parser.finish();
+        assert!(matches!(parser.next_value(), Ok(Status::Ready(_))));
+        assert!(matches!(parser.next_value(), Ok(Status::Ready(_))));
+        let error = parser.next_value().err().unwrap();
+        assert_eq!(ParseErrorKind::DepthLimitExceeded, error.kind);
+
+        let mut parser = PushParser::with_options(options);
+        parser.feed(b"\"12345678\"");
+        let error = parser.next_value().err().unwrap();
+        assert_eq!(ParseErrorKind::InputTooLarge, error.kind);
+    }
+
+    proptest! {
+        #[test]
+        fn should_parse_like_parse(
+            json in near_json(),
+            split in 0usize..64,
+        ) {
+            let bytes = json.as_bytes();
+            let (left, right) = bytes.split_at(split.min(bytes.len()));
+            let (values, error) = values(&[left, right]);
+            match Parser::parse(&json) {
+                Ok(element) => prop_assert_eq!((vec![element], None), (values, error)),
+                Err(_) => prop_assert!(error.is_some() || values.len() != 1),
+            }
+        }
+    }
+}
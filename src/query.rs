@@ -0,0 +1,1152 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! A small jq-like filter language over `JsonElement`.
+//!
+//! A filter takes one input value and produces any number of output values.
+//! Supported syntax, from lowest to highest precedence:
+//!
+//! - `a | b` feeds every output of `a` into `b`; `a, b` concatenates outputs
+//! - `a or b`, `a and b`; comparisons `==`, `!=`, `<`, `<=`, `>`, `>=`
+//! - arithmetic `+`, `-`, `*`, `/`, `%` and unary `-`
+//! - `.` (identity), `.name`, `."name"`, `.[index]`, `.[start:end]`, `.[]`,
+//!   and `?` after any of them to drop errors
+//! - literals, `(a)`, `[a]` and `{key: a, "key": a, (a): a, key}`
+//! - functions `select(f)`, `map(f)`, `keys`, `length`, `not`, `empty`,
+//!   `type`, `has(key)` and `add`
+
+use crate::json_element::{JsonElement, Map};
+use crate::parser::Parser;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+
+/// Deepest nesting accepted in a filter. Parentheses, brackets, braces and pipes
+/// count, and so does every link of a chain such as `.a.b` or `1 + 2 + 3`.
+const MAX_NESTING: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    pub details: String,
+}
+
+impl QueryError {
+    pub fn new(msg: &str) -> QueryError {
+        return QueryError {
+            details: msg.to_string(),
+        };
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl core::error::Error for QueryError {}
+
+type QueryResult<T> = Result<T, QueryError>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    Ident(String),
+    Literal(JsonElement),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "|", ",", "(", ")", "[", "]", "{", "}", ":", "?", "+", "-", "*", "/",
+    "%", "<", ">",
+];
+
+fn tokenize(source: &str) -> QueryResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == '.' && !rest[1..].starts_with(|d: char| d.is_ascii_digit()) {
+            tokens.push(Token::Dot);
+            rest = &rest[1..];
+        } else if c == '"' {
+            let mut end = 1;
+            let bytes = rest.as_bytes();
+            while end < bytes.len() && bytes[end] != b'"' {
+                end += if bytes[end] == b'\\' { 2 } else { 1 };
+            }
+            if end >= bytes.len() {
+                return Err(QueryError::new("Unterminated string in filter"));
+            }
+            let literal = Parser::parse(&rest[..=end])
+                .map_err(|e| QueryError::new(&format!("Invalid string in filter: {e}")))?;
+            tokens.push(Token::Literal(literal));
+            rest = &rest[end + 1..];
+        } else if c.is_ascii_digit() || c == '.' {
+            // A sign only belongs to the number right after its exponent marker.
+            let bytes = rest.as_bytes();
+            let end = (1..bytes.len())
+                .find(|&i| match bytes[i] {
+                    b'+' | b'-' => !matches!(bytes[i - 1], b'e' | b'E'),
+                    d => !(d.is_ascii_alphanumeric() || d == b'.'),
+                })
+                .unwrap_or(rest.len());
+            let text = if rest.starts_with('.') {
+                format!("0{}", &rest[..end])
+            } else {
+                rest[..end].to_string()
+            };
+            let literal = Parser::parse(&text).map_err(|_| {
+                QueryError::new(&format!("Invalid number {} in filter", &rest[..end]))
+            })?;
+            tokens.push(Token::Literal(literal));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|d: char| !(d.is_ascii_alphanumeric() || d == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(match &rest[..end] {
+                "true" => Token::Literal(JsonElement::Boolean(true)),
+                "false" => Token::Literal(JsonElement::Boolean(false)),
+                "null" => Token::Literal(JsonElement::Null),
+                ident => Token::Ident(ident.to_string()),
+            });
+            rest = &rest[end..];
+        } else {
+            match SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+                Some(symbol) => {
+                    tokens.push(Token::Symbol(symbol));
+                    rest = &rest[symbol.len()..];
+                }
+                None => {
+                    return Err(QueryError::new(&format!(
+                        "Unexpected character {c} in filter"
+                    )))
+                }
+            }
+        }
+    }
+    return Ok(tokens);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Identity,
+    Literal(JsonElement),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+    Iterate(Box<Expr>),
+    Optional(Box<Expr>),
+    Array(Option<Box<Expr>>),
+    Object(Vec<(Expr, Expr)>),
+    Pipe(Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+struct QueryParser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.position);
+    }
+
+    fn peek_symbol(&self, symbol: &str) -> bool {
+        return matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        if self.peek_symbol(symbol) {
+            self.position += 1;
+            return true;
+        }
+        return false;
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> QueryResult<()> {
+        if !self.eat_symbol(symbol) {
+            return Err(QueryError::new(&format!("Expected '{symbol}' in filter")));
+        }
+        return Ok(());
+    }
+
+    fn eat_ident(&mut self, ident: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(i)) if i == ident) {
+            self.position += 1;
+            return true;
+        }
+        return false;
+    }
+
+    /// Consumes a field name, written either as an identifier or a string.
+    fn eat_name(&mut self) -> Option<String> {
+        let name = match self.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            Some(Token::Literal(JsonElement::Str(name))) => name.clone(),
+            _ => return None,
+        };
+        self.position += 1;
+        return Some(name);
+    }
+
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut QueryParser) -> QueryResult<T>,
+    ) -> QueryResult<T> {
+        self.deepen()?;
+        let result = parse(self);
+        self.depth -= 1;
+        return result;
+    }
+
+    fn deepen(&mut self) -> QueryResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(QueryError::new("Filter is nested too deeply"));
+        }
+        return Ok(());
+    }
+
+    /// Parses a chain of links with `parse`, counting each link it adds toward
+    /// the nesting, since evaluating the chain recurses once per link.
+    fn chain(
+        &mut self,
+        parse: impl FnOnce(&mut QueryParser) -> QueryResult<Expr>,
+    ) -> QueryResult<Expr> {
+        let depth = self.depth;
+        let result = parse(self);
+        self.depth = depth;
+        return result;
+    }
+
+    fn parse_pipe(&mut self) -> QueryResult<Expr> {
+        let left = self.parse_comma()?;
+        if self.eat_symbol("|") {
+            let right = self.nested(QueryParser::parse_pipe)?;
+            return Ok(Expr::Pipe(Box::new(left), Box::new(right)));
+        }
+        return Ok(left);
+    }
+
+    fn parse_comma(&mut self) -> QueryResult<Expr> {
+        return self.chain(|parser| {
+            let mut left = parser.parse_or()?;
+            while parser.eat_symbol(",") {
+                parser.deepen()?;
+                let right = parser.parse_or()?;
+                left = Expr::Comma(Box::new(left), Box::new(right));
+            }
+            Ok(left)
+        });
+    }
+
+    fn parse_or(&mut self) -> QueryResult<Expr> {
+        return self.chain(|parser| {
+            let mut left = parser.parse_and()?;
+            while parser.eat_ident("or") {
+                parser.deepen()?;
+                let right = parser.parse_and()?;
+                left = Expr::Binary(Op::Or, Box::new(left), Box::new(right));
+            }
+            Ok(left)
+        });
+    }
+
+    fn parse_and(&mut self) -> QueryResult<Expr> {
+        return self.chain(|parser| {
+            let mut left = parser.parse_comparison()?;
+            while parser.eat_ident("and") {
+                parser.deepen()?;
+                let right = parser.parse_comparison()?;
+                left = Expr::Binary(Op::And, Box::new(left), Box::new(right));
+            }
+            Ok(left)
+        });
+    }
+
+    fn parse_comparison(&mut self) -> QueryResult<Expr> {
+        let left = self.parse_additive()?;
+        let operators = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        for (symbol, op) in operators {
+            if self.eat_symbol(symbol) {
+                let right = self.parse_additive()?;
+                return Ok(Expr::Binary(op, Box::new(left), Box::new(right)));
+            }
+        }
+        return Ok(left);
+    }
+
+    fn parse_additive(&mut self) -> QueryResult<Expr> {
+        return self.chain(|parser| {
+            let mut left = parser.parse_multiplicative()?;
+            loop {
+                let op = if parser.eat_symbol("+") {
+                    Op::Add
+                } else if parser.eat_symbol("-") {
+                    Op::Sub
+                } else {
+                    return Ok(left);
+                };
+                parser.deepen()?;
+                let right = parser.parse_multiplicative()?;
+                left = Expr::Binary(op, Box::new(left), Box::new(right));
+            }
+        });
+    }
+
+    fn parse_multiplicative(&mut self) -> QueryResult<Expr> {
+        return self.chain(|parser| {
+            let mut left = parser.parse_unary()?;
+            loop {
+                let op = if parser.eat_symbol("*") {
+                    Op::Mul
+                } else if parser.eat_symbol("/") {
+                    Op::Div
+                } else if parser.eat_symbol("%") {
+                    Op::Rem
+                } else {
+                    return Ok(left);
+                };
+                parser.deepen()?;
+                let right = parser.parse_unary()?;
+                left = Expr::Binary(op, Box::new(left), Box::new(right));
+            }
+        });
+    }
+
+    fn parse_unary(&mut self) -> QueryResult<Expr> {
+        if self.eat_symbol("-") {
+            let operand = self.nested(QueryParser::parse_unary)?;
+            return Ok(Expr::Negate(Box::new(operand)));
+        }
+        return self.parse_postfix();
+    }
+
+    /// Parses what follows a `.` or `[`: a field name, or a bracketed index,
+    /// slice or iteration.
+    fn parse_suffix(&mut self, target: Expr, after_dot: bool) -> QueryResult<Expr> {
+        if after_dot {
+            if let Some(name) = self.eat_name() {
+                return Ok(Expr::Index(
+                    Box::new(target),
+                    Box::new(Expr::Literal(JsonElement::Str(name))),
+                ));
+            }
+        }
+        self.expect_symbol("[")?;
+        if self.eat_symbol("]") {
+            return Ok(Expr::Iterate(Box::new(target)));
+        }
+        return self.nested(|parser| {
+            let start = if parser.peek_symbol(":") {
+                None
+            } else {
+                Some(Box::new(parser.parse_pipe()?))
+            };
+            if parser.eat_symbol(":") {
+                let end = if parser.peek_symbol("]") {
+                    None
+                } else {
+                    Some(Box::new(parser.parse_pipe()?))
+                };
+                parser.expect_symbol("]")?;
+                return Ok(Expr::Slice(Box::new(target), start, end));
+            }
+            parser.expect_symbol("]")?;
+            return Ok(Expr::Index(Box::new(target), start.unwrap()));
+        });
+    }
+
+    fn parse_postfix(&mut self) -> QueryResult<Expr> {
+        return self.chain(|parser| {
+            let mut expr = parser.parse_term()?;
+            loop {
+                if parser.eat_symbol("?") {
+                    parser.deepen()?;
+                    expr = Expr::Optional(Box::new(expr));
+                } else if parser.peek() == Some(&Token::Dot) {
+                    parser.position += 1;
+                    parser.deepen()?;
+                    expr = parser.parse_suffix(expr, true)?;
+                } else if parser.peek_symbol("[") {
+                    parser.deepen()?;
+                    expr = parser.parse_suffix(expr, false)?;
+                } else {
+                    return Ok(expr);
+                }
+            }
+        });
+    }
+
+    fn parse_object(&mut self) -> QueryResult<Expr> {
+        let mut entries = Vec::new();
+        if self.eat_symbol("}") {
+            return Ok(Expr::Object(entries));
+        }
+        loop {
+            let key = match self.eat_name() {
+                Some(name) => JsonElement::Str(name),
+                None if self.eat_symbol("(") => {
+                    let key = self.nested(|parser| {
+                        let key = parser.parse_pipe()?;
+                        parser.expect_symbol(")")?;
+                        Ok(key)
+                    })?;
+                    self.expect_symbol(":")?;
+                    entries.push((key, self.parse_or()?));
+                    if !self.eat_symbol(",") {
+                        self.expect_symbol("}")?;
+                        return Ok(Expr::Object(entries));
+                    }
+                    continue;
+                }
+                None => return Err(QueryError::new("Expected an object key in filter")),
+            };
+            let value = if self.eat_symbol(":") {
+                self.parse_or()?
+            } else {
+                Expr::Index(
+                    Box::new(Expr::Identity),
+                    Box::new(Expr::Literal(key.clone())),
+                )
+            };
+            entries.push((Expr::Literal(key), value));
+            if !self.eat_symbol(",") {
+                self.expect_symbol("}")?;
+                return Ok(Expr::Object(entries));
+            }
+        }
+    }
+
+    fn parse_term(&mut self) -> QueryResult<Expr> {
+        let token = match self.peek().cloned() {
+            Some(token) => token,
+            None => return Err(QueryError::new("Unexpected end of filter")),
+        };
+        self.position += 1;
+        return match token {
+            Token::Dot => match self.peek() {
+                Some(Token::Ident(_)) | Some(Token::Literal(JsonElement::Str(_))) => {
+                    self.parse_suffix(Expr::Identity, true)
+                }
+                Some(Token::Symbol("[")) => self.parse_suffix(Expr::Identity, false),
+                _ => Ok(Expr::Identity),
+            },
+            Token::Literal(literal) => Ok(Expr::Literal(literal)),
+            Token::Symbol("(") => self.nested(|parser| {
+                let expr = parser.parse_pipe()?;
+                parser.expect_symbol(")")?;
+                Ok(expr)
+            }),
+            Token::Symbol("[") => self.nested(|parser| {
+                if parser.eat_symbol("]") {
+                    return Ok(Expr::Array(None));
+                }
+                let expr = parser.parse_pipe()?;
+                parser.expect_symbol("]")?;
+                Ok(Expr::Array(Some(Box::new(expr))))
+            }),
+            Token::Symbol("{") => self.nested(QueryParser::parse_object),
+            Token::Ident(name) => {
+                let mut args = Vec::new();
+                if self.eat_symbol("(") {
+                    args.push(self.nested(|parser| {
+                        let arg = parser.parse_pipe()?;
+                        parser.expect_symbol(")")?;
+                        Ok(arg)
+                    })?);
+                }
+                let arity = match name.as_str() {
+                    "select" | "map" | "has" => 1,
+                    "keys" | "length" | "not" | "empty" | "type" | "add" => 0,
+                    _ => return Err(QueryError::new(&format!("Unknown function {name}"))),
+                };
+                if args.len() != arity {
+                    return Err(QueryError::new(&format!(
+                        "{name} takes {arity} argument(s), got {}",
+                        args.len()
+                    )));
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Token::Symbol(symbol) => {
+                Err(QueryError::new(&format!("Unexpected '{symbol}' in filter")))
+            }
+        };
+    }
+}
+
+fn type_name(element: &JsonElement) -> &'static str {
+    return match element {
+        JsonElement::Null => "null",
+        JsonElement::Boolean(_) => "boolean",
+        JsonElement::Number(_) => "number",
+        JsonElement::Str(_) => "string",
+        JsonElement::Array(_) => "array",
+        JsonElement::Object(_) => "object",
+    };
+}
+
+fn type_rank(element: &JsonElement) -> u8 {
+    return match element {
+        JsonElement::Null => 0,
+        JsonElement::Boolean(false) => 1,
+        JsonElement::Boolean(true) => 2,
+        JsonElement::Number(_) => 3,
+        JsonElement::Str(_) => 4,
+        JsonElement::Array(_) => 5,
+        JsonElement::Object(_) => 6,
+    };
+}
+
+fn sorted_keys(object: &Map<String, JsonElement>) -> Vec<&String> {
+    let mut keys: Vec<&String> = object.keys().collect();
+    keys.sort();
+    return keys;
+}
+
+/// Orders values the way jq does: null < false < true < numbers < strings <
+/// arrays < objects, comparing containers member by member.
+fn compare(a: &JsonElement, b: &JsonElement) -> Ordering {
+    let rank = type_rank(a).cmp(&type_rank(b));
+    if rank != Ordering::Equal {
+        return rank;
+    }
+    return match (a, b) {
+        (JsonElement::Number(x), JsonElement::Number(y)) => {
+            x.partial_cmp(y).unwrap_or(Ordering::Equal)
+        }
+        (JsonElement::Str(x), JsonElement::Str(y)) => x.cmp(y),
+        (JsonElement::Array(x), JsonElement::Array(y)) => {
+            for (u, v) in x.iter().zip(y.iter()) {
+                let order = compare(u, v);
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            x.len().cmp(&y.len())
+        }
+        (JsonElement::Object(x), JsonElement::Object(y)) => {
+            let (keys_x, keys_y) = (sorted_keys(x), sorted_keys(y));
+            let order = keys_x.cmp(&keys_y);
+            if order != Ordering::Equal {
+                return order;
+            }
+            for key in keys_x {
+                let order = compare(&x[key], &y[key]);
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            Ordering::Equal
+        }
+        _ => Ordering::Equal,
+    };
+}
+
+fn is_truthy(element: &JsonElement) -> bool {
+    return !matches!(element, JsonElement::Null | JsonElement::Boolean(false));
+}
+
+/// `f64::trunc`, which `core` does not have. Numbers this large have no
+/// fractional part.
+fn trunc(x: f64) -> f64 {
+    if x.is_nan() || x.abs() >= 4_503_599_627_370_496.0 {
+        return x;
+    }
+    return (x as i64 as f64).copysign(x);
+}
+
+fn floor(x: f64) -> f64 {
+    let truncated = trunc(x);
+    return if truncated > x {
+        truncated - 1.0
+    } else {
+        truncated
+    };
+}
+
+fn arithmetic(op: Op, a: &JsonElement, b: &JsonElement) -> QueryResult<JsonElement> {
+    let result = match (op, a, b) {
+        (Op::Add, JsonElement::Null, other) | (Op::Add, other, JsonElement::Null) => other.clone(),
+        (_, JsonElement::Number(x), JsonElement::Number(y)) => JsonElement::Number(match op {
+            Op::Add => x + y,
+            Op::Sub => x - y,
+            Op::Mul => x * y,
+            Op::Div if *y == 0.0 => return Err(QueryError::new("Division by zero")),
+            Op::Div => x / y,
+            Op::Rem if trunc(*y) == 0.0 => return Err(QueryError::new("Remainder by zero")),
+            _ => trunc(*x) % trunc(*y),
+        }),
+        (Op::Add, JsonElement::Str(x), JsonElement::Str(y)) => JsonElement::Str(format!("{x}{y}")),
+        (Op::Add, JsonElement::Array(x), JsonElement::Array(y)) => {
+            JsonElement::Array(x.iter().chain(y.iter()).cloned().collect())
+        }
+        (Op::Sub, JsonElement::Array(x), JsonElement::Array(y)) => {
+            JsonElement::Array(x.iter().filter(|v| !y.contains(v)).cloned().collect())
+        }
+        (Op::Add, JsonElement::Object(x), JsonElement::Object(y)) => {
+            let mut merged = x.clone();
+            merged.extend(y.iter().map(|(k, v)| (k.clone(), v.clone())));
+            JsonElement::Object(merged)
+        }
+        _ => {
+            return Err(QueryError::new(&format!(
+                "Cannot apply {op:?} to {} and {}",
+                type_name(a),
+                type_name(b)
+            )))
+        }
+    };
+    if let JsonElement::Number(n) = result {
+        if !n.is_finite() {
+            return Err(QueryError::new("Arithmetic result is not a finite number"));
+        }
+    }
+    return Ok(result);
+}
+
+fn index(target: &JsonElement, key: &JsonElement) -> QueryResult<JsonElement> {
+    return match (target, key) {
+        (JsonElement::Null, JsonElement::Str(_) | JsonElement::Number(_)) => Ok(JsonElement::Null),
+        (JsonElement::Object(object), JsonElement::Str(name)) => {
+            Ok(object.get(name).cloned().unwrap_or_default())
+        }
+        (JsonElement::Array(array), JsonElement::Number(n)) => {
+            let i = floor(*n) as i64;
+            let i = if i < 0 { array.len() as i64 + i } else { i };
+            Ok(usize::try_from(i)
+                .ok()
+                .and_then(|i| array.get(i))
+                .cloned()
+                .unwrap_or_default())
+        }
+        _ => Err(QueryError::new(&format!(
+            "Cannot index {} with {}",
+            type_name(target),
+            type_name(key)
+        ))),
+    };
+}
+
+fn slice_bounds(len: usize, start: &JsonElement, end: &JsonElement) -> QueryResult<(usize, usize)> {
+    let bound = |value: &JsonElement, default: usize| -> QueryResult<usize> {
+        return match value {
+            JsonElement::Null => Ok(default),
+            JsonElement::Number(n) => {
+                let i = floor(*n) as i64;
+                let i = if i < 0 { len as i64 + i } else { i };
+                Ok(i.clamp(0, len as i64) as usize)
+            }
+            _ => Err(QueryError::new("Slice bounds must be numbers")),
+        };
+    };
+    let start = bound(start, 0)?;
+    let end = bound(end, len)?;
+    return Ok((start, end.max(start)));
+}
+
+fn slice(target: &JsonElement, start: &JsonElement, end: &JsonElement) -> QueryResult<JsonElement> {
+    return match target {
+        JsonElement::Null => Ok(JsonElement::Null),
+        JsonElement::Array(array) => {
+            let (start, end) = slice_bounds(array.len(), start, end)?;
+            Ok(JsonElement::Array(array[start..end].to_vec()))
+        }
+        JsonElement::Str(string) => {
+            let chars: Vec<char> = string.chars().collect();
+            let (start, end) = slice_bounds(chars.len(), start, end)?;
+            Ok(JsonElement::Str(chars[start..end].iter().collect()))
+        }
+        _ => Err(QueryError::new(&format!(
+            "Cannot slice {}",
+            type_name(target)
+        ))),
+    };
+}
+
+fn iterate(target: &JsonElement) -> QueryResult<Vec<JsonElement>> {
+    return match target {
+        JsonElement::Array(array) => Ok(array.clone()),
+        JsonElement::Object(object) => Ok(sorted_keys(object)
+            .into_iter()
+            .map(|key| object[key].clone())
+            .collect()),
+        _ => Err(QueryError::new(&format!(
+            "Cannot iterate over {}",
+            type_name(target)
+        ))),
+    };
+}
+
+fn call(name: &str, args: &[Expr], input: &JsonElement) -> QueryResult<Vec<JsonElement>> {
+    return match name {
+        "empty" => Ok(Vec::new()),
+        "not" => Ok(vec![JsonElement::Boolean(!is_truthy(input))]),
+        "type" => Ok(vec![JsonElement::Str(type_name(input).to_string())]),
+        "length" => Ok(vec![JsonElement::Number(match input {
+            JsonElement::Null => 0.0,
+            JsonElement::Boolean(_) => return Err(QueryError::new("boolean has no length")),
+            JsonElement::Number(n) => n.abs(),
+            JsonElement::Str(s) => s.chars().count() as f64,
+            JsonElement::Array(a) => a.len() as f64,
+            JsonElement::Object(o) => o.len() as f64,
+        })]),
+        "keys" => match input {
+            JsonElement::Object(object) => Ok(vec![JsonElement::Array(
+                sorted_keys(object)
+                    .into_iter()
+                    .map(|key| JsonElement::Str(key.clone()))
+                    .collect(),
+            )]),
+            JsonElement::Array(array) => Ok(vec![JsonElement::Array(
+                (0..array.len())
+                    .map(|i| JsonElement::Number(i as f64))
+                    .collect(),
+            )]),
+            _ => Err(QueryError::new(&format!(
+                "{} has no keys",
+                type_name(input)
+            ))),
+        },
+        "add" => {
+            let mut sum = JsonElement::Null;
+            for value in iterate(input)? {
+                sum = arithmetic(Op::Add, &sum, &value)?;
+            }
+            Ok(vec![sum])
+        }
+        "has" => {
+            let mut results = Vec::new();
+            for key in evaluate(&args[0], input)? {
+                results.push(JsonElement::Boolean(match (input, &key) {
+                    (JsonElement::Object(o), JsonElement::Str(k)) => o.contains_key(k),
+                    (JsonElement::Array(a), JsonElement::Number(n)) => {
+                        *n >= 0.0 && (*n as usize) < a.len()
+                    }
+                    _ => {
+                        return Err(QueryError::new(&format!(
+                            "Cannot check whether {} has a {} key",
+                            type_name(input),
+                            type_name(&key)
+                        )))
+                    }
+                }));
+            }
+            Ok(results)
+        }
+        "select" => {
+            let mut results = Vec::new();
+            for condition in evaluate(&args[0], input)? {
+                if is_truthy(&condition) {
+                    results.push(input.clone());
+                }
+            }
+            Ok(results)
+        }
+        "map" => {
+            let mut results = Vec::new();
+            for value in iterate(input)? {
+                results.extend(evaluate(&args[0], &value)?);
+            }
+            Ok(vec![JsonElement::Array(results)])
+        }
+        _ => Err(QueryError::new(&format!("Unknown function {name}"))),
+    };
+}
+
+/// Evaluates both operands and combines every pair of outputs, iterating over
+/// the right operand in the outer loop like jq does.
+fn binary(
+    left: &Expr,
+    right: &Expr,
+    input: &JsonElement,
+    combine: impl Fn(&JsonElement, &JsonElement) -> QueryResult<JsonElement>,
+) -> QueryResult<Vec<JsonElement>> {
+    let lefts = evaluate(left, input)?;
+    let mut results = Vec::new();
+    for b in evaluate(right, input)? {
+        for a in &lefts {
+            results.push(combine(a, &b)?);
+        }
+    }
+    return Ok(results);
+}
+
+fn evaluate(expr: &Expr, input: &JsonElement) -> QueryResult<Vec<JsonElement>> {
+    return match expr {
+        Expr::Identity => Ok(vec![input.clone()]),
+        Expr::Literal(literal) => Ok(vec![literal.clone()]),
+        Expr::Index(target, key) => {
+            let mut results = Vec::new();
+            for target in evaluate(target, input)? {
+                for key in evaluate(key, input)? {
+                    results.push(index(&target, &key)?);
+                }
+            }
+            Ok(results)
+        }
+        Expr::Slice(target, start, end) => {
+            let bound = |expr: &Option<Box<Expr>>| match expr {
+                Some(expr) => evaluate(expr, input),
+                None => Ok(vec![JsonElement::Null]),
+            };
+            let (starts, ends) = (bound(start)?, bound(end)?);
+            let mut results = Vec::new();
+            for target in evaluate(target, input)? {
+                for start in &starts {
+                    for end in &ends {
+                        results.push(slice(&target, start, end)?);
+                    }
+                }
+            }
+            Ok(results)
+        }
+        Expr::Iterate(target) => {
+            let mut results = Vec::new();
+            for target in evaluate(target, input)? {
+                results.extend(iterate(&target)?);
+            }
+            Ok(results)
+        }
+        Expr::Optional(inner) => Ok(evaluate(inner, input).unwrap_or_default()),
+        Expr::Array(None) => Ok(vec![JsonElement::Array(Vec::new())]),
+        Expr::Array(Some(inner)) => Ok(vec![JsonElement::Array(evaluate(inner, input)?)]),
+        Expr::Object(entries) => {
+            let mut objects = vec![Map::new()];
+            for (key, value) in entries {
+                let keys = evaluate(key, input)?;
+                let values = evaluate(value, input)?;
+                let mut next = Vec::new();
+                for object in &objects {
+                    for key in &keys {
+                        let key = match key {
+                            JsonElement::Str(key) => key,
+                            _ => {
+                                return Err(QueryError::new(&format!(
+                                    "Object keys must be strings, not {}",
+                                    type_name(key)
+                                )))
+                            }
+                        };
+                        for value in &values {
+                            let mut object: Map<String, JsonElement> = object.clone();
+                            object.insert(key.clone(), value.clone());
+                            next.push(object);
+                        }
+                    }
+                }
+                objects = next;
+            }
+            Ok(objects.into_iter().map(JsonElement::Object).collect())
+        }
+        Expr::Pipe(left, right) => {
+            let mut results = Vec::new();
+            for value in evaluate(left, input)? {
+                results.extend(evaluate(right, &value)?);
+            }
+            Ok(results)
+        }
+        Expr::Comma(left, right) => {
+            let mut results = evaluate(left, input)?;
+            results.extend(evaluate(right, input)?);
+            Ok(results)
+        }
+        Expr::Negate(operand) => evaluate(operand, input)?
+            .iter()
+            .map(|value| arithmetic(Op::Sub, &JsonElement::Number(0.0), value))
+            .collect(),
+        Expr::Binary(Op::And, left, right) | Expr::Binary(Op::Or, left, right) => {
+            let is_and = matches!(expr, Expr::Binary(Op::And, _, _));
+            let mut results = Vec::new();
+            for a in evaluate(left, input)? {
+                if is_truthy(&a) != is_and {
+                    results.push(JsonElement::Boolean(!is_and));
+                    continue;
+                }
+                for b in evaluate(right, input)? {
+                    results.push(JsonElement::Boolean(is_truthy(&b)));
+                }
+            }
+            Ok(results)
+        }
+        Expr::Binary(op, left, right) => {
+            let op = *op;
+            binary(left, right, input, |a, b| {
+                let order = compare(a, b);
+                return Ok(JsonElement::Boolean(match op {
+                    Op::Eq => order == Ordering::Equal,
+                    Op::Ne => order != Ordering::Equal,
+                    Op::Lt => order == Ordering::Less,
+                    Op::Le => order != Ordering::Greater,
+                    Op::Gt => order == Ordering::Greater,
+                    Op::Ge => order != Ordering::Less,
+                    _ => return arithmetic(op, a, b),
+                }));
+            })
+        }
+        Expr::Call(name, args) => call(name, args, input),
+    };
+}
+
+/// A compiled filter, which can be applied to any number of inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> QueryResult<Filter> {
+        let mut parser = QueryParser {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Ok(Filter {
+                expr: Expr::Identity,
+            });
+        }
+        let expr = parser.parse_pipe()?;
+        if let Some(token) = parser.peek() {
+            return Err(QueryError::new(&format!("Unexpected {token:?} in filter")));
+        }
+        return Ok(Filter { expr });
+    }
+
+    /// Runs the filter on `input` and returns every value it produces.
+    pub fn apply(&self, input: &JsonElement) -> QueryResult<Vec<JsonElement>> {
+        return evaluate(&self.expr, input);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, Filter, Token};
+    use crate::json_element::JsonElement;
+    use crate::parser::Parser;
+    use crate::serializer::{Serializer, SerializerOptions};
+
+    fn run(filter: &str, json: &str) -> Vec<String> {
+        let input = Parser::parse(json).ok().unwrap();
+        let filter = match Filter::parse(filter) {
+            Ok(filter) => filter,
+            Err(e) => panic!("{filter}: {}", e.details),
+        };
+        let options = SerializerOptions {
+            indent: None,
+            sort_keys: true,
+        };
+        return match filter.apply(&input) {
+            Ok(results) => results
+                .iter()
+                .map(|result| Serializer::serialize_with_options(result, &options))
+                .collect(),
+            Err(e) => panic!("{}", e.details),
+        };
+    }
+
+    const DOC: &str = r#"{
+        "name": "jsonp",
+        "tags": ["json", "parser"],
+        "users": [
+            {"id": 1, "name": "ana", "admin": true, "age": 31},
+            {"id": 2, "name": "bruno", "admin": false, "age": 27},
+            {"id": 3, "name": "caio", "age": 45}
+        ]
+    }"#;
+
+    #[test]
+    fn should_tokenize_numbers() {
+        for (source, number) in [
+            ("1", 1.0),
+            ("1.5", 1.5),
+            (".5", 0.5),
+            ("1e-5", 1e-5),
+            ("2E+3", 2e3),
+            ("2e3", 2e3),
+            ("1.5e-2", 1.5e-2),
+        ] {
+            assert_eq!(
+                vec![Token::Literal(JsonElement::Number(number))],
+                tokenize(source).ok().unwrap(),
+                "{source}"
+            );
+        }
+        assert_eq!(3, tokenize("1-2").ok().unwrap().len());
+        assert_eq!(3, tokenize("1e5+2").ok().unwrap().len());
+        assert!(tokenize("1e").is_err());
+        assert!(tokenize("1e+").is_err());
+        assert!(tokenize("1x").is_err());
+    }
+
+    #[test]
+    fn should_access_paths() {
+        assert_eq!(vec!["\"jsonp\""], run(".name", DOC));
+        assert_eq!(vec!["\"jsonp\""], run(".\"name\"", DOC));
+        assert_eq!(vec!["\"json\""], run(".tags[0]", DOC));
+        assert_eq!(vec!["\"parser\""], run(".tags[-1]", DOC));
+        assert_eq!(vec!["\"bruno\""], run(".users[1].name", DOC));
+        assert_eq!(vec!["null"], run(".missing.deeper", DOC));
+        assert_eq!(vec!["[\"parser\"]"], run(".tags[1:]", DOC));
+        assert_eq!(vec!["\"bru\""], run(".users[1].name[:3]", DOC));
+        assert_eq!(vec!["1", "2", "3"], run(".users[].id", DOC));
+        assert_eq!(vec!["1", "2"], run(".[]", "{\"b\": 2, \"a\": 1}"));
+    }
+
+    #[test]
+    fn should_pipe_select_and_map() {
+        assert_eq!(
+            vec!["\"ana\""],
+            run(".users[] | select(.admin) | .name", DOC)
+        );
+        assert_eq!(
+            vec!["[\"caio\"]"],
+            run(
+                "[.users[] | select(.age > 40 and .admin != true) | .name]",
+                DOC
+            )
+        );
+        assert_eq!(vec!["[32,28,46]"], run(".users | map(.age + 1)", DOC));
+        assert_eq!(vec!["\"jsonp\"", "2"], run(".name, (.tags | length)", DOC));
+    }
+
+    #[test]
+    fn should_construct_values() {
+        assert_eq!(
+            vec!["{\"count\":3,\"name\":\"jsonp\"}"],
+            run("{name, count: (.users | length)}", DOC)
+        );
+        assert_eq!(
+            vec!["{\"ana\":1}", "{\"bruno\":2}", "{\"caio\":3}"],
+            run(".users[] | {(.name): .id}", DOC)
+        );
+        assert_eq!(
+            vec!["[1,\"a\",null,true]"],
+            run("[1, \"a\", null, true]", "null")
+        );
+        assert_eq!(vec!["{\"a b\":[]}"], run("{\"a b\": []}", "null"));
+    }
+
+    #[test]
+    fn should_evaluate_builtins_and_arithmetic() {
+        assert_eq!(vec!["[\"name\",\"tags\",\"users\"]"], run("keys", DOC));
+        assert_eq!(vec!["[0,1]"], run(".tags | keys", DOC));
+        assert_eq!(vec!["5"], run(".name | length", DOC));
+        assert_eq!(vec!["103"], run(".users | map(.age) | add", DOC));
+        assert_eq!(
+            vec!["7", "-1", "12", "0.75", "1"],
+            run("3 + 4, 3 - 4, 3 * 4, 3 / 4, 7 % 3", "null")
+        );
+        assert_eq!(
+            vec!["\"ab\"", "[1,2]", "[1]"],
+            run("\"a\" + \"b\", [1] + [2], [1, 2] - [2]", "null")
+        );
+        assert_eq!(vec!["-5"], run("-.a", "{\"a\": 5}"));
+        assert_eq!(vec!["true", "false"], run("(1, null) | not | not", "null"));
+        assert_eq!(
+            vec!["true", "false"],
+            run("has(\"name\"), has(\"nope\")", DOC)
+        );
+        assert_eq!(
+            vec!["\"object\"", "\"array\""],
+            run("type, (.tags | type)", DOC)
+        );
+        assert_eq!(Vec::<String>::new(), run("empty", DOC));
+        assert_eq!(
+            vec!["true"],
+            run(
+                "null < false and false < 0 and 0 < \"\" and \"\" < [] and [] < {}",
+                "null"
+            )
+        );
+        assert_eq!(
+            vec!["11", "12", "21", "22"],
+            run("(1, 2) + (10, 20)", "null")
+        );
+    }
+
+    #[test]
+    fn should_report_errors() {
+        let input = JsonElement::Number(1.0);
+        assert!(Filter::parse(".[").is_err());
+        assert!(Filter::parse("map()").is_err());
+        assert!(Filter::parse("frobnicate").is_err());
+        assert!(Filter::parse(". |").is_err());
+        assert!(Filter::parse(&"(".repeat(100_000)).is_err());
+        assert!(Filter::parse(&".a".repeat(100_000)).is_err());
+        assert!(Filter::parse(&format!("1{}", "+1".repeat(100_000))).is_err());
+        assert!(Filter::parse(&format!("1{}", ",1".repeat(100_000))).is_err());
+        assert!(Filter::parse(&format!("-{}", "-1".repeat(100_000))).is_err());
+        assert_eq!(vec!["60"], run(&format!("0{}", "+1".repeat(60)), "null"));
+        assert_eq!(vec!["null"], run(&".a".repeat(60), "{}"));
+        let deep = format!("{}.{}", "[".repeat(60), "]".repeat(60));
+        assert_eq!(
+            1,
+            Filter::parse(&deep)
+                .ok()
+                .unwrap()
+                .apply(&input)
+                .ok()
+                .unwrap()
+                .len()
+        );
+
+        assert!(Filter::parse(".a").ok().unwrap().apply(&input).is_err());
+        assert!(Filter::parse(".[]").ok().unwrap().apply(&input).is_err());
+        assert_eq!(
+            Some(vec![]),
+            Filter::parse(".a?").ok().unwrap().apply(&input).ok()
+        );
+        assert!(Filter::parse("1 / 0").ok().unwrap().apply(&input).is_err());
+        assert_eq!(vec!["1"], run("", "1"));
+    }
+}
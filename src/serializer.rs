@@ -0,0 +1,314 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+use crate::json_element::JsonElement;
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::slice;
+
+pub struct Serializer;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SerializerOptions {
+    /// Written once per nesting level in front of every array element and object
+    /// member, each on its own line. `None` writes the whole value on one line
+    /// without any whitespace.
+    pub indent: Option<String>,
+    /// Writes object members ordered by key instead of in map order, so the same
+    /// value always serializes to the same text.
+    pub sort_keys: bool,
+}
+
+impl SerializerOptions {
+    /// Options for human readable output: sorted keys, indented by `indent`.
+    pub fn pretty(indent: &str) -> SerializerOptions {
+        SerializerOptions {
+            indent: Some(indent.to_string()),
+            sort_keys: true,
+        }
+    }
+}
+
+/// A value the serializer can write without copying it into a `JsonElement`
+/// first: an element, or a node of a `Document`.
+pub(crate) trait Serializable<'a>: Copy {
+    type Elements: Iterator<Item = Self>;
+
+    fn contents(self) -> Contents<'a, Self, Self::Elements>;
+}
+
+/// What a `Serializable` value holds. Object members come in the order they
+/// are written in when keys are not sorted.
+pub(crate) enum Contents<'a, V, E> {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    Str(&'a str),
+    Array(E),
+    Object(Vec<(&'a str, V)>),
+}
+
+impl<'a> Serializable<'a> for &'a JsonElement {
+    type Elements = slice::Iter<'a, JsonElement>;
+
+    fn contents(self) -> Contents<'a, Self, Self::Elements> {
+        return match self {
+            JsonElement::Null => Contents::Null,
+            JsonElement::Boolean(b) => Contents::Boolean(*b),
+            JsonElement::Number(n) => Contents::Number(*n),
+            JsonElement::Str(s) => Contents::Str(s),
+            JsonElement::Array(array) => Contents::Array(array.iter()),
+            JsonElement::Object(object) => Contents::Object(
+                object
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value))
+                    .collect(),
+            ),
+        };
+    }
+}
+
+/// Members of an array or object that still have to be written, and whether
+/// none has been written yet.
+enum Frame<'a, V, E> {
+    Array(E, bool),
+    Object(vec::IntoIter<(&'a str, V)>, bool),
+}
+
+pub(crate) fn write_string(out: &mut String, string: &str) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Writes the shortest representation that parses back to the same `f64`.
+/// Json has no representation for NaN and the infinities, so they become `null`.
+pub(crate) fn write_number(out: &mut String, number: f64) {
+    if !number.is_finite() {
+        out.push_str("null");
+        return;
+    }
+    let magnitude = number.abs();
+    if magnitude != 0.0 && !(1e-6..1e21).contains(&magnitude) {
+        let _ = write!(out, "{:e}", number);
+    } else {
+        let _ = write!(out, "{}", number);
+    }
+}
+
+pub(crate) fn new_line(out: &mut String, options: &SerializerOptions, depth: usize) {
+    if let Some(indent) = &options.indent {
+        out.push('\n');
+        for _ in 0..depth {
+            out.push_str(indent);
+        }
+    }
+}
+
+impl Serializer {
+    /// Serializes `element` without any whitespace.
+    pub fn serialize(element: &JsonElement) -> String {
+        return Serializer::serialize_with_options(element, &SerializerOptions::default());
+    }
+
+    /// Serializes `element` with sorted keys, indenting nested values by `indent`.
+    pub fn serialize_pretty(element: &JsonElement, indent: &str) -> String {
+        return Serializer::serialize_with_options(element, &SerializerOptions::pretty(indent));
+    }
+
+    pub fn serialize_with_options(element: &JsonElement, options: &SerializerOptions) -> String {
+        return Serializer::write(element, options);
+    }
+
+    /// Nested values are written through an explicit stack, like the parser
+    /// reads them, so deep trees cannot overflow the call stack.
+    pub(crate) fn write<'a, V: Serializable<'a>>(value: V, options: &SerializerOptions) -> String {
+        let mut out = String::new();
+        let mut stack: Vec<Frame<V, V::Elements>> = Vec::new();
+        let mut next = Some(value);
+        loop {
+            if let Some(value) = next.take() {
+                match value.contents() {
+                    Contents::Array(elements) => {
+                        out.push('[');
+                        stack.push(Frame::Array(elements, true));
+                    }
+                    Contents::Object(mut members) => {
+                        out.push('{');
+                        if options.sort_keys {
+                            members.sort_by(|a, b| a.0.cmp(b.0));
+                        }
+                        stack.push(Frame::Object(members.into_iter(), true));
+                    }
+                    Contents::Null => out.push_str("null"),
+                    Contents::Boolean(b) => out.push_str(if b { "true" } else { "false" }),
+                    Contents::Number(n) => write_number(&mut out, n),
+                    Contents::Str(s) => write_string(&mut out, s),
+                }
+            }
+            let depth = stack.len();
+            let frame = match stack.last_mut() {
+                Some(frame) => frame,
+                None => return out,
+            };
+            match frame {
+                Frame::Array(members, first) => match members.next() {
+                    Some(value) => {
+                        if !core::mem::take(first) {
+                            out.push(',');
+                        }
+                        new_line(&mut out, options, depth);
+                        next = Some(value);
+                    }
+                    None => {
+                        if !*first {
+                            new_line(&mut out, options, depth - 1);
+                        }
+                        out.push(']');
+                        stack.pop();
+                    }
+                },
+                Frame::Object(members, first) => match members.next() {
+                    Some((key, value)) => {
+                        if !core::mem::take(first) {
+                            out.push(',');
+                        }
+                        new_line(&mut out, options, depth);
+                        write_string(&mut out, key);
+                        out.push(':');
+                        if options.indent.is_some() {
+                            out.push(' ');
+                        }
+                        next = Some(value);
+                    }
+                    None => {
+                        if !*first {
+                            new_line(&mut out, options, depth - 1);
+                        }
+                        out.push('}');
+                        stack.pop();
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Serializer, SerializerOptions};
+    use crate::json_element::JsonElement;
+    use crate::parser::Parser;
+
+    use crate::json_element::Map;
+
+    #[test]
+    fn should_serialize_scalars() {
+        assert_eq!("null", Serializer::serialize(&JsonElement::Null));
+        assert_eq!("true", Serializer::serialize(&JsonElement::Boolean(true)));
+        assert_eq!("-15", Serializer::serialize(&JsonElement::Number(-15.0)));
+        assert_eq!("0.156", Serializer::serialize(&JsonElement::Number(0.156)));
+        assert_eq!(
+            "1.45e-9",
+            Serializer::serialize(&JsonElement::Number(14.5E-10))
+        );
+        assert_eq!("1e300", Serializer::serialize(&JsonElement::Number(1e300)));
+        assert_eq!(
+            "null",
+            Serializer::serialize(&JsonElement::Number(f64::NAN))
+        );
+        assert_eq!(
+            "\"a\\\"b\\\\c\\n\\u0001\u{e9}\"",
+            Serializer::serialize(&JsonElement::Str("a\"b\\c\n\u{1}\u{e9}".to_string()))
+        );
+    }
+
+    #[test]
+    fn should_serialize_containers() {
+        let mut object = Map::new();
+        object.insert("a".to_string(), JsonElement::Array(vec![]));
+        let element = JsonElement::Array(vec![
+            JsonElement::Number(1.0),
+            JsonElement::Object(object),
+            JsonElement::Object(Map::new()),
+            JsonElement::Array(vec![JsonElement::Null, JsonElement::Boolean(false)]),
+        ]);
+        assert_eq!(
+            "[1,{\"a\":[]},{},[null,false]]",
+            Serializer::serialize(&element)
+        );
+        let parsed = Parser::parse(&Serializer::serialize(&element))
+            .ok()
+            .unwrap();
+        assert_eq!(element, parsed);
+    }
+
+    #[test]
+    fn should_serialize_pretty() {
+        let mut inner = Map::new();
+        inner.insert("z".to_string(), JsonElement::Null);
+        inner.insert("a".to_string(), JsonElement::Array(vec![]));
+        let mut object = Map::new();
+        object.insert(
+            "list".to_string(),
+            JsonElement::Array(vec![
+                JsonElement::Number(1.0),
+                JsonElement::Str("two".to_string()),
+            ]),
+        );
+        object.insert("inner".to_string(), JsonElement::Object(inner));
+        object.insert("empty".to_string(), JsonElement::Object(Map::new()));
+        let element = JsonElement::Object(object);
+        assert_eq!(
+            "{\n  \"empty\": {},\n  \"inner\": {\n    \"a\": [],\n    \"z\": null\n  },\n  \"list\": [\n    1,\n    \"two\"\n  ]\n}",
+            Serializer::serialize_pretty(&element, "  ")
+        );
+        assert_eq!(
+            "{\"empty\":{},\"inner\":{\"a\":[],\"z\":null},\"list\":[1,\"two\"]}",
+            Serializer::serialize_with_options(
+                &element,
+                &SerializerOptions {
+                    indent: None,
+                    sort_keys: true,
+                }
+            )
+        );
+        assert_eq!(
+            "[]",
+            Serializer::serialize_pretty(&JsonElement::Array(vec![]), "\t")
+        );
+        assert_eq!(
+            "\"x\"",
+            Serializer::serialize_pretty(&JsonElement::Str("x".to_string()), "\t")
+        );
+    }
+}
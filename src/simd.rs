@@ -0,0 +1,444 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Structural indexing pass used by the `simd` feature.
+//!
+//! Before the parser runs, the input is scanned in blocks of 16 (SSE2) or 32 (AVX2)
+//! bytes to find every byte that may be interesting: quotes, backslashes, control
+//! characters and the structural characters `{ } [ ] : ,`. A small scalar state
+//! machine then walks only those candidates to drop everything that sits inside a
+//! string, leaving the byte offsets of the structural characters and of the quotes
+//! delimiting each string, along with the strings that contain escapes or control
+//! characters.
+//!
+//! The parser then steps through that index instead of reading the input one
+//! character at a time: whitespace up to the next structural character is skipped
+//! at once, a number or literal ends where the next structural character is, and
+//! strings without escapes or control characters are copied in bulk. Whenever the
+//! input between two indexed offsets is not what the index predicts, the parser
+//! falls back to its tokenizer, which also reports the error.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// Implementation used to classify input bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Scalar,
+    Sse2,
+    Avx2,
+}
+
+impl Backend {
+    /// The fastest backend supported by the running CPU.
+    pub fn detect() -> Backend {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Backend::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Backend::Sse2;
+            }
+        }
+        return Backend::Scalar;
+    }
+
+    /// Whether the running CPU can execute this backend.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Backend::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse2 => is_x86_feature_detected!("sse2"),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => false,
+        }
+    }
+}
+
+/// Byte offsets of the structural characters and string delimiters of a document.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StructuralIndex {
+    positions: Vec<usize>,
+    /// Opening quotes of the strings containing a backslash or a control character.
+    special: Vec<usize>,
+}
+
+impl StructuralIndex {
+    /// Builds the index using the best backend available at runtime.
+    pub fn build(json: &str) -> StructuralIndex {
+        return StructuralIndex::build_with(json, Backend::detect());
+    }
+
+    /// Builds the index with a specific backend, falling back to the scalar one
+    /// when `backend` is not supported by the running CPU.
+    pub fn build_with(json: &str, backend: Backend) -> StructuralIndex {
+        let bytes = json.as_bytes();
+        let mut scanner = Scanner::new(bytes.len());
+        let backend = if backend.is_supported() {
+            backend
+        } else {
+            Backend::Scalar
+        };
+        match backend {
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse2 => unsafe { classify_sse2(bytes, &mut scanner) },
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => unsafe { classify_avx2(bytes, &mut scanner) },
+            _ => classify_scalar(bytes, 0, &mut scanner),
+        }
+        return StructuralIndex {
+            positions: scanner.positions,
+            special: scanner.special_strings,
+        };
+    }
+
+    /// The indexed byte offsets, in increasing order.
+    pub fn positions(&self) -> &[usize] {
+        &self.positions
+    }
+
+    /// Whether the string opening at `quote` contains escapes or control characters.
+    pub(crate) fn has_special_characters(&self, quote: usize) -> bool {
+        return self.special.binary_search(&quote).is_ok();
+    }
+}
+
+/// A `StructuralIndex` read alongside a parser moving forward through the
+/// document, so that each lookup only walks the offsets passed since the last.
+pub(crate) struct StructuralCursor {
+    index: StructuralIndex,
+    next: usize,
+}
+
+impl StructuralCursor {
+    pub(crate) fn new(index: StructuralIndex) -> StructuralCursor {
+        return StructuralCursor { index, next: 0 };
+    }
+
+    /// The first indexed offset at or after `offset`.
+    pub(crate) fn at_or_after(&mut self, offset: usize) -> Option<usize> {
+        let positions = &self.index.positions;
+        if self.next > 0 && positions[self.next - 1] >= offset {
+            self.next = positions.partition_point(|p| *p < offset);
+        }
+        while positions.get(self.next).is_some_and(|p| *p < offset) {
+            self.next += 1;
+        }
+        return positions.get(self.next).copied();
+    }
+
+    /// The first indexed offset strictly after `position`, provided `position`
+    /// itself is indexed.
+    pub(crate) fn next_after(&mut self, position: usize) -> Option<usize> {
+        if self.at_or_after(position)? != position {
+            return None;
+        }
+        return self.index.positions.get(self.next + 1).copied();
+    }
+
+    /// Whether the string opening at `quote` contains escapes or control characters.
+    pub(crate) fn has_special_characters(&self, quote: usize) -> bool {
+        return self.index.has_special_characters(quote);
+    }
+}
+
+/// Filters candidate bytes, in order, down to the ones outside of strings.
+struct Scanner {
+    positions: Vec<usize>,
+    special_strings: Vec<usize>,
+    in_string: bool,
+    escaped: Option<usize>,
+}
+
+impl Scanner {
+    fn new(len: usize) -> Scanner {
+        Scanner {
+            positions: Vec::with_capacity(len / 8),
+            special_strings: Vec::new(),
+            in_string: false,
+            escaped: None,
+        }
+    }
+
+    fn candidate(&mut self, position: usize, byte: u8) {
+        if self.escaped == Some(position) {
+            return;
+        }
+        if self.in_string {
+            match byte {
+                b'"' => {
+                    self.in_string = false;
+                    self.positions.push(position);
+                }
+                b'\\' => {
+                    self.escaped = Some(position + 1);
+                    self.special_string();
+                }
+                _ if byte < 0x20 => self.special_string(),
+                _ => {}
+            }
+        } else {
+            match byte {
+                b'"' => {
+                    self.in_string = true;
+                    self.positions.push(position);
+                }
+                b'{' | b'}' | b'[' | b']' | b':' | b',' => self.positions.push(position),
+                _ => {}
+            }
+        }
+    }
+
+    /// Records that the string being scanned needs the character by character path.
+    fn special_string(&mut self) {
+        let quote = *self.positions.last().unwrap();
+        if self.special_strings.last() != Some(&quote) {
+            self.special_strings.push(quote);
+        }
+    }
+
+    fn mask(&mut self, bytes: &[u8], offset: usize, mut mask: u32) {
+        while mask != 0 {
+            let bit = mask.trailing_zeros() as usize;
+            self.candidate(offset + bit, bytes[offset + bit]);
+            mask &= mask - 1;
+        }
+    }
+}
+
+fn is_candidate(byte: u8) -> bool {
+    return byte < 0x20 || matches!(byte, b'"' | b'\\' | b'{' | b'}' | b'[' | b']' | b':' | b',');
+}
+
+fn classify_scalar(bytes: &[u8], from: usize, scanner: &mut Scanner) {
+    for (i, &byte) in bytes.iter().enumerate().skip(from) {
+        if is_candidate(byte) {
+            scanner.candidate(i, byte);
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn classify_sse2(bytes: &[u8], scanner: &mut Scanner) {
+    let targets = [
+        _mm_set1_epi8(b'"' as i8),
+        _mm_set1_epi8(b'\\' as i8),
+        _mm_set1_epi8(b'{' as i8),
+        _mm_set1_epi8(b'}' as i8),
+        _mm_set1_epi8(b'[' as i8),
+        _mm_set1_epi8(b']' as i8),
+        _mm_set1_epi8(b':' as i8),
+        _mm_set1_epi8(b',' as i8),
+    ];
+    let mut offset = 0;
+    while offset + 16 <= bytes.len() {
+        let block = _mm_loadu_si128(bytes.as_ptr().add(offset) as *const __m128i);
+        let control = _mm_set1_epi8(0x1f);
+        let mut matches = _mm_cmpeq_epi8(_mm_min_epu8(block, control), block);
+        for target in targets {
+            matches = _mm_or_si128(matches, _mm_cmpeq_epi8(block, target));
+        }
+        scanner.mask(bytes, offset, _mm_movemask_epi8(matches) as u32);
+        offset += 16;
+    }
+    classify_scalar(bytes, offset, scanner);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn classify_avx2(bytes: &[u8], scanner: &mut Scanner) {
+    let targets = [
+        _mm256_set1_epi8(b'"' as i8),
+        _mm256_set1_epi8(b'\\' as i8),
+        _mm256_set1_epi8(b'{' as i8),
+        _mm256_set1_epi8(b'}' as i8),
+        _mm256_set1_epi8(b'[' as i8),
+        _mm256_set1_epi8(b']' as i8),
+        _mm256_set1_epi8(b':' as i8),
+        _mm256_set1_epi8(b',' as i8),
+    ];
+    let mut offset = 0;
+    while offset + 32 <= bytes.len() {
+        let block = _mm256_loadu_si256(bytes.as_ptr().add(offset) as *const __m256i);
+        let control = _mm256_set1_epi8(0x1f);
+        let mut matches = _mm256_cmpeq_epi8(_mm256_min_epu8(block, control), block);
+        for target in targets {
+            matches = _mm256_or_si256(matches, _mm256_cmpeq_epi8(block, target));
+        }
+        scanner.mask(bytes, offset, _mm256_movemask_epi8(matches) as u32);
+        offset += 32;
+    }
+    classify_scalar(bytes, offset, scanner);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backend, StructuralIndex};
+    use crate::parser::Parser;
+
+    const CORPUS: &[&str] = &[
+        "",
+        " ",
+        "null",
+        "true",
+        "false",
+        "0",
+        "-0.156",
+        "14.5E-10",
+        "04",
+        "-",
+        "\"\"",
+        "\"plain string\"",
+        "\"Test\\tString\\nSecond\rLine\\n\\\\Unicode test: \\u306e\"",
+        "\"escaped \\\" quote\"",
+        "\"ends with backslash \\\\\"",
+        "\"\\\\\\\"\"",
+        "\"structural {}[]:, inside\"",
+        "\"unterminated",
+        "\"unterminated \\\"",
+        "\"bad escape \\x\"",
+        "\"bad unicode \\u12g4\"",
+        "\"multi-byte: ç, ã, 日本語, 🦀\"",
+        "\"raw\ttab\"",
+        "[\"bell \u{7}\", \"\u{1f}\"]",
+        "[ ]",
+        "[true, {\"name\":\"test\"}, 13e-17, null ]",
+        "{ }",
+        "{ \"test\": \"string\", \"num\": 156  }",
+        "{\"a\":{\"b\":{\"c\":[\"d\",\"e\\\"f\",{\"g\":\"h\"}]}}}",
+        "{\"key with spaces and \\\"quotes\\\"\": \"value, with: structurals]\"}",
+        "{\"a\" \"b\"}",
+        "{\"a\": }",
+        "[1, 2, \"three\", [\"four\"], {\"five\": 5}]",
+        "[\"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\", \"b\"]",
+        "  \t\n\r {\"padded\": \"value\"}  \n",
+        "{\"\\u00e9\\u00E9\": \"\\/\\b\\f\\n\\r\\t\"}",
+        "tru",
+        "nul",
+        "{\"a\": [1, 2, 3], \"b\": \"\\\\\"}",
+        "[1 2]",
+        "[1x]",
+        "[01]",
+        "[1.]",
+        "[-]",
+        "[1e+]",
+        "[-0.5e-3 , 2E+10\t,0]",
+        "[1\"a\"]",
+        "[true ,null\n, false]",
+        "[truefalse]",
+        "{\"a\" : 1 , \"b\":[ tru ]}",
+        "  12  ",
+        "1e400",
+    ];
+
+    fn long_documents() -> Vec<String> {
+        let mut documents = Vec::new();
+        let mut array = String::from("[");
+        for i in 0..200 {
+            if i > 0 {
+                array.push(',');
+            }
+            array.push_str(&format!(
+                "{{\"id\": {i}, \"name\": \"item \\\"{i}\\\" [x]\"}}"
+            ));
+        }
+        array.push(']');
+        documents.push(array);
+        // Escapes straddling every possible block boundary.
+        for padding in 0..40 {
+            let mut string = String::from("\"");
+            string.push_str(&"a".repeat(padding));
+            string.push_str("\\\\\\\"\\\\\", [1]");
+            documents.push(string);
+            documents.push(format!("[\"{}\u{1}\", \"b\"]", "a".repeat(padding)));
+        }
+        documents.push(format!("[{}]", "1".repeat(1024)));
+        documents.push(format!("[{}]", "1".repeat(1025)));
+        return documents;
+    }
+
+    fn scalar_index(json: &str) -> Vec<usize> {
+        let mut positions = Vec::new();
+        let mut in_string = false;
+        let mut escaped = false;
+        for (i, byte) in json.bytes().enumerate() {
+            if escaped {
+                escaped = false;
+            } else if in_string {
+                match byte {
+                    b'"' => {
+                        in_string = false;
+                        positions.push(i);
+                    }
+                    b'\\' => escaped = true,
+                    _ => {}
+                }
+            } else if byte == b'"' {
+                in_string = true;
+                positions.push(i);
+            } else if b"{}[]:,".contains(&byte) {
+                positions.push(i);
+            }
+        }
+        return positions;
+    }
+
+    #[test]
+    fn backends_should_build_identical_indexes() {
+        let mut documents: Vec<String> = CORPUS.iter().map(|s| s.to_string()).collect();
+        documents.extend(long_documents());
+        for json in &documents {
+            let expected = scalar_index(json);
+            for backend in [Backend::Scalar, Backend::Sse2, Backend::Avx2] {
+                let index = StructuralIndex::build_with(json, backend);
+                assert_eq!(expected, index.positions(), "{backend:?} on {json:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn index_should_record_strings_with_escapes_or_control_characters() {
+        let json = "[\"a\", \"b\\\"c\", \"\\\\\", \"{d}\", \"e\tf\"]";
+        for backend in [Backend::Scalar, Backend::Sse2, Backend::Avx2] {
+            let index = StructuralIndex::build_with(json, backend);
+            let special: Vec<usize> = index
+                .positions()
+                .iter()
+                .copied()
+                .filter(|p| index.has_special_characters(*p))
+                .collect();
+            assert_eq!(vec![6, 14, 27], special, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn indexed_parse_should_match_scalar_parse() {
+        let mut documents: Vec<String> = CORPUS.iter().map(|s| s.to_string()).collect();
+        documents.extend(long_documents());
+        for json in &documents {
+            let scalar = Parser::parse_scalar(json);
+            let indexed = Parser::parse(json);
+            match (scalar, indexed) {
+                (Ok(a), Ok(b)) => assert_eq!(a, b, "{json:?}"),
+                (Err(a), Err(b)) => assert_eq!(a.details, b.details, "{json:?}"),
+                _ => panic!("scalar and indexed parse disagree on {json:?}"),
+            }
+        }
+    }
+}
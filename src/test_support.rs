@@ -0,0 +1,81 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Strategies and helpers shared by the unit tests of several modules.
+
+use crate::json_element::JsonElement;
+use crate::parser::Parser;
+
+use proptest::prelude::*;
+
+/// The numbers `arb_json_element` puts in documents.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Numbers {
+    /// Numbers between -1e6 and 1e6.
+    Small,
+    /// Any finite number, with whole numbers across the `i64` range mixed in.
+    Any,
+}
+
+/// Documents of up to four levels, with arrays and objects of up to 20
+/// members so that the longer length encodings of binary formats come up.
+/// Object keys match the regular expression `keys`.
+pub(crate) fn arb_json_element(
+    numbers: Numbers,
+    keys: &'static str,
+) -> impl Strategy<Value = JsonElement> {
+    let number = match numbers {
+        Numbers::Small => (-1e6..1e6f64).boxed(),
+        Numbers::Any => prop_oneof![
+            -1e6..1e6f64,
+            any::<i64>().prop_map(|n| n as f64),
+            any::<f64>().prop_filter("json numbers are finite", |n| n.is_finite()),
+        ]
+        .boxed(),
+    };
+    let leaf = prop_oneof![
+        Just(JsonElement::Null),
+        any::<bool>().prop_map(JsonElement::Boolean),
+        number.prop_map(JsonElement::Number),
+        any::<String>().prop_map(JsonElement::Str),
+    ];
+    return leaf.prop_recursive(4, 64, 8, move |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..20).prop_map(JsonElement::Array),
+            prop::collection::vec((keys, inner), 0..20)
+                .prop_map(|members| JsonElement::Object(members.into_iter().collect())),
+        ]
+    });
+}
+
+/// Short strings made of json punctuation, literal and number characters and
+/// a non-ASCII letter, so that most of them are almost, but not quite, json.
+pub(crate) fn near_json() -> impl Strategy<Value = String> {
+    return "[\\[\\]{}\":, 0-9eE.+\\-tfnrulsa\\\\u\u{e9}]{0,48}";
+}
+
+/// The element parsed from `text`, which must be valid json.
+pub(crate) fn json(text: &str) -> JsonElement {
+    return Parser::parse(text).ok().unwrap();
+}
+
+/// The bytes written in hex in `text`, e.g. `"0aff"`.
+#[cfg(any(feature = "cbor", feature = "bson"))]
+pub(crate) fn hex(text: &str) -> Vec<u8> {
+    return (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+        .collect();
+}
@@ -13,9 +13,6 @@
 * limitations under the License.
 */
 
-use std::iter::Peekable;
-use std::str::Chars;
-
 pub enum Token {
     OpenBracket,
     CloseBracket,
@@ -30,7 +27,7 @@ pub enum Token {
 
 pub struct Tokenizer<'a> {
     cursor: usize,
-    iterator: Peekable<Chars<'a>>,
+    json: &'a str,
 }
 
 fn match_token(c: char) -> Token {
@@ -47,33 +44,44 @@ fn match_token(c: char) -> Token {
 }
 
 impl<'a> Tokenizer<'a> {
-    pub fn new(json: &str) -> Tokenizer {
-        Tokenizer {
-            cursor: 0,
-            iterator: json.chars().peekable(),
-        }
+    pub fn new(json: &str) -> Tokenizer<'_> {
+        Tokenizer { cursor: 0, json }
     }
 
     pub fn next_token(&mut self) -> Token {
-        let c: char;
-        let next = self.iterator.next();
-        match next {
-            Some(character) => c = character,
+        let c = match self.json[self.cursor..].chars().next() {
+            Some(character) => character,
             None => return Token::End,
-        }
-        let token = match_token(c);
-        self.cursor += 1;
-        return token;
+        };
+        self.cursor += c.len_utf8();
+        return match_token(c);
     }
 
     pub fn peek_token(&mut self) -> Token {
-        let c: char;
-        let next = self.iterator.peek();
-        match next {
-            Some(character) => c = *character,
-            None => return Token::End,
-        }
-        let token = match_token(c);
-        return token;
+        return match self.json[self.cursor..].chars().next() {
+            Some(character) => match_token(character),
+            None => Token::End,
+        };
+    }
+
+    /// Length of the whole input, in bytes.
+    pub fn len(&self) -> usize {
+        self.json.len()
+    }
+
+    /// Byte offset of the next character in the input.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The input between two byte offsets, or an empty string when out of bounds.
+    pub fn slice(&self, start: usize, end: usize) -> &'a str {
+        self.json.get(start..end).unwrap_or("")
+    }
+
+    /// Moves the cursor to `cursor`, which must lie on a char boundary.
+    pub fn seek(&mut self, cursor: usize) {
+        debug_assert!(self.json.is_char_boundary(cursor));
+        self.cursor = cursor;
     }
 }
@@ -0,0 +1,516 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Walking every value of a `JsonElement` tree with its JSON Pointer.
+//!
+//! Values are visited parents first, in document order, with object members
+//! sorted by key. The walks keep their own stack, so deep trees cannot
+//! overflow the call stack.
+
+use crate::json_element::{map_with_capacity, JsonElement, Map};
+use crate::pointer::JsonPointer;
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// What a `Visitor` wants after seeing a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Go on, into the value's members or elements.
+    Continue,
+    /// Go on with the value's next sibling.
+    Skip,
+    /// End the walk.
+    Stop,
+}
+
+/// What a `VisitorMut` wants done with a value after seeing it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// Keep the value, with any changes made to it, and walk into it.
+    Keep,
+    /// Keep the value without walking into it.
+    Skip,
+    /// Put this in its place. The replacement is not walked into.
+    Replace(JsonElement),
+    /// Take the value out of its array or object. The root becomes null.
+    Remove,
+    /// Keep the value and end the walk.
+    Stop,
+}
+
+/// The default `visit` hands each kind of value to its own method, and the
+/// defaults of those walk into everything.
+pub trait Visitor {
+    fn visit(&mut self, path: &JsonPointer, element: &JsonElement) -> Flow {
+        return match element {
+            JsonElement::Object(object) => self.visit_object(path, object),
+            JsonElement::Array(array) => self.visit_array(path, array),
+            _ => self.visit_scalar(path, element),
+        };
+    }
+
+    fn visit_object(&mut self, _path: &JsonPointer, _object: &Map<String, JsonElement>) -> Flow {
+        return Flow::Continue;
+    }
+
+    fn visit_array(&mut self, _path: &JsonPointer, _array: &[JsonElement]) -> Flow {
+        return Flow::Continue;
+    }
+
+    fn visit_scalar(&mut self, _path: &JsonPointer, _element: &JsonElement) -> Flow {
+        return Flow::Continue;
+    }
+}
+
+/// Like `Visitor`, with values that can be changed in place or replaced and
+/// removed through the returned `Edit`. Members added to an object or array
+/// before it is walked into are visited too.
+pub trait VisitorMut {
+    fn visit_mut(&mut self, path: &JsonPointer, element: &mut JsonElement) -> Edit {
+        return match element {
+            JsonElement::Object(object) => self.visit_object_mut(path, object),
+            JsonElement::Array(array) => self.visit_array_mut(path, array),
+            _ => self.visit_scalar_mut(path, element),
+        };
+    }
+
+    fn visit_object_mut(
+        &mut self,
+        _path: &JsonPointer,
+        _object: &mut Map<String, JsonElement>,
+    ) -> Edit {
+        return Edit::Keep;
+    }
+
+    fn visit_array_mut(&mut self, _path: &JsonPointer, _array: &mut Vec<JsonElement>) -> Edit {
+        return Edit::Keep;
+    }
+
+    fn visit_scalar_mut(&mut self, _path: &JsonPointer, _element: &mut JsonElement) -> Edit {
+        return Edit::Keep;
+    }
+}
+
+/// A value still to be visited, with how deep it is and its key or index in
+/// its parent.
+type Entry<'a> = (usize, Option<Token<'a>>, &'a JsonElement);
+
+enum Token<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// Pushes the members or elements of `element`, which is `depth` deep, so
+/// that the first is popped first.
+fn push_children<'a>(stack: &mut Vec<Entry<'a>>, depth: usize, element: &'a JsonElement) {
+    match element {
+        JsonElement::Array(array) => {
+            for (index, child) in array.iter().enumerate().rev() {
+                stack.push((depth + 1, Some(Token::Index(index)), child));
+            }
+        }
+        JsonElement::Object(object) => {
+            let mut members: Vec<(&String, &JsonElement)> = object.iter().collect();
+            members.sort_by(|a, b| b.0.cmp(a.0));
+            for (key, child) in members {
+                stack.push((depth + 1, Some(Token::Key(key)), child));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Makes `path` the path of an entry popped from the stack.
+fn follow(path: &mut JsonPointer, depth: usize, token: Option<Token>) {
+    path.truncate(depth.saturating_sub(1));
+    match token {
+        Some(Token::Key(key)) => path.push(key),
+        Some(Token::Index(index)) => path.push(&index.to_string()),
+        None => {}
+    }
+}
+
+pub fn walk<V: Visitor + ?Sized>(element: &JsonElement, visitor: &mut V) {
+    let mut path = JsonPointer::root();
+    let mut stack = vec![(0, None, element)];
+    while let Some((depth, token, element)) = stack.pop() {
+        follow(&mut path, depth, token);
+        match visitor.visit(&path, element) {
+            Flow::Continue => push_children(&mut stack, depth, element),
+            Flow::Skip => {}
+            Flow::Stop => return,
+        }
+    }
+}
+
+/// The members or elements of a container still to be visited by `walk_mut`.
+enum Pending {
+    Array(vec::IntoIter<JsonElement>, usize),
+    Object(vec::IntoIter<(String, JsonElement)>),
+}
+
+/// A container being walked into. Its members are taken out of `shell` and
+/// put back one at a time once they have been visited.
+struct Frame {
+    /// The key of the container in its parent object.
+    key: Option<String>,
+    shell: JsonElement,
+    pending: Pending,
+}
+
+impl Frame {
+    /// A frame for `shell` when it has members or elements to walk into, or
+    /// `shell` back when it has none.
+    fn new(
+        key: Option<String>,
+        mut shell: JsonElement,
+    ) -> Result<Frame, (Option<String>, JsonElement)> {
+        let pending = match &mut shell {
+            JsonElement::Array(array) if !array.is_empty() => {
+                let elements = core::mem::replace(array, Vec::with_capacity(array.len()));
+                Pending::Array(elements.into_iter(), 0)
+            }
+            JsonElement::Object(object) if !object.is_empty() => {
+                let len = object.len();
+                let mut members: Vec<(String, JsonElement)> =
+                    core::mem::replace(object, map_with_capacity(len))
+                        .into_iter()
+                        .collect();
+                members.sort_by(|a, b| a.0.cmp(&b.0));
+                Pending::Object(members.into_iter())
+            }
+            _ => return Err((key, shell)),
+        };
+        return Ok(Frame {
+            key,
+            shell,
+            pending,
+        });
+    }
+
+    /// The next member or element, with its path token and its key.
+    fn next(&mut self) -> Option<(String, Option<String>, JsonElement)> {
+        return match &mut self.pending {
+            Pending::Array(elements, index) => {
+                let element = elements.next()?;
+                *index += 1;
+                Some(((*index - 1).to_string(), None, element))
+            }
+            Pending::Object(members) => {
+                let (key, element) = members.next()?;
+                Some((key.clone(), Some(key), element))
+            }
+        };
+    }
+
+    /// Puts back what is left without visiting it.
+    fn finish(&mut self) {
+        match (&mut self.shell, &mut self.pending) {
+            (JsonElement::Array(array), Pending::Array(elements, _)) => array.extend(elements),
+            (JsonElement::Object(object), Pending::Object(members)) => object.extend(members),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Puts a visited value back into the innermost container, or makes it the
+/// root when there is none.
+fn put_back(
+    stack: &mut [Frame],
+    root: &mut JsonElement,
+    key: Option<String>,
+    element: JsonElement,
+) {
+    match stack.last_mut().map(|frame| &mut frame.shell) {
+        None => *root = element,
+        Some(JsonElement::Array(array)) => array.push(element),
+        Some(JsonElement::Object(object)) => {
+            object.insert(key.unwrap_or_default(), element);
+        }
+        Some(_) => unreachable!(),
+    }
+}
+
+/// Walks `element` with `visitor`, applying its edits as it goes.
+pub fn walk_mut<V: VisitorMut + ?Sized>(element: &mut JsonElement, visitor: &mut V) {
+    let mut root = JsonElement::Null;
+    let mut path = JsonPointer::root();
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut stopped = false;
+    let mut next = Some((None, core::mem::take(element)));
+    loop {
+        if let Some((key, mut element)) = next.take() {
+            match visitor.visit_mut(&path, &mut element) {
+                Edit::Keep => match Frame::new(key, element) {
+                    Ok(frame) => stack.push(frame),
+                    Err((key, element)) => put_back(&mut stack, &mut root, key, element),
+                },
+                Edit::Skip => put_back(&mut stack, &mut root, key, element),
+                Edit::Replace(replacement) => put_back(&mut stack, &mut root, key, replacement),
+                Edit::Remove => {}
+                Edit::Stop => {
+                    stopped = true;
+                    put_back(&mut stack, &mut root, key, element);
+                }
+            }
+        }
+        let depth = stack.len();
+        let frame = match stack.last_mut() {
+            Some(frame) => frame,
+            None => break,
+        };
+        if stopped {
+            frame.finish();
+        } else if let Some((token, key, element)) = frame.next() {
+            path.truncate(depth - 1);
+            path.push(&token);
+            next = Some((key, element));
+            continue;
+        }
+        let frame = stack.pop().unwrap();
+        put_back(&mut stack, &mut root, frame.key, frame.shell);
+    }
+    *element = root;
+}
+
+/// Every value inside an element, with its path, parents first.
+pub struct Descendants<'a> {
+    path: JsonPointer,
+    stack: Vec<Entry<'a>>,
+}
+
+impl<'a> Descendants<'a> {
+    /// Moves to the next value, leaving its path in `self.path`.
+    fn step(&mut self) -> Option<&'a JsonElement> {
+        let (depth, token, element) = self.stack.pop()?;
+        follow(&mut self.path, depth, token);
+        push_children(&mut self.stack, depth, element);
+        return Some(element);
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = (JsonPointer, &'a JsonElement);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let element = self.step()?;
+        return Some((self.path.clone(), element));
+    }
+}
+
+/// The values of an element that hold no other values, with their paths.
+pub struct Leaves<'a> {
+    values: Descendants<'a>,
+}
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = (JsonPointer, &'a JsonElement);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let element = self.values.step()?;
+            let leaf = match element {
+                JsonElement::Array(array) => array.is_empty(),
+                JsonElement::Object(object) => object.is_empty(),
+                _ => true,
+            };
+            if leaf {
+                return Some((self.values.path.clone(), element));
+            }
+        }
+    }
+}
+
+impl JsonElement {
+    /// The values inside this one, not including it.
+    pub fn descendants(&self) -> Descendants<'_> {
+        let mut stack = Vec::new();
+        push_children(&mut stack, 0, self);
+        return Descendants {
+            path: JsonPointer::root(),
+            stack,
+        };
+    }
+
+    /// The scalars and empty arrays and objects in this value, or the value
+    /// itself when it is one of those.
+    pub fn leaves(&self) -> Leaves<'_> {
+        return Leaves {
+            values: Descendants {
+                path: JsonPointer::root(),
+                stack: vec![(0, None, self)],
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{walk, walk_mut, Edit, Flow, Visitor, VisitorMut};
+    use crate::json_element::{JsonElement, Map};
+    use crate::pointer::JsonPointer;
+    use crate::test_support::json;
+
+    struct Numbers(Vec<(String, f64)>);
+
+    impl Visitor for Numbers {
+        fn visit_object(&mut self, path: &JsonPointer, object: &Map<String, JsonElement>) -> Flow {
+            if path.tokens().last().is_some_and(|key| key == "skipped") {
+                return Flow::Skip;
+            }
+            if object.contains_key("stop") {
+                return Flow::Stop;
+            }
+            return Flow::Continue;
+        }
+
+        fn visit_scalar(&mut self, path: &JsonPointer, element: &JsonElement) -> Flow {
+            if let JsonElement::Number(number) = element {
+                self.0.push((path.to_string(), *number));
+            }
+            return Flow::Continue;
+        }
+    }
+
+    #[test]
+    fn should_visit_values_in_order() {
+        let document =
+            json(r#"{"b": [1, {"c": 2}], "a": 3, "skipped": {"d": 4}, "z": {"stop": 5}, "zz": 6}"#);
+        let mut numbers = Numbers(Vec::new());
+        walk(&document, &mut numbers);
+        assert_eq!(
+            vec![
+                ("/a".to_string(), 3.0),
+                ("/b/0".to_string(), 1.0),
+                ("/b/1/c".to_string(), 2.0),
+            ],
+            numbers.0
+        );
+    }
+
+    /// Redacts passwords, renames `user` keys to `name` and drops nulls.
+    struct Cleanup;
+
+    impl VisitorMut for Cleanup {
+        fn visit_object_mut(
+            &mut self,
+            _path: &JsonPointer,
+            object: &mut Map<String, JsonElement>,
+        ) -> Edit {
+            if let Some(user) = object.remove("user") {
+                object.insert("name".to_string(), user);
+            }
+            return Edit::Keep;
+        }
+
+        fn visit_scalar_mut(&mut self, path: &JsonPointer, element: &mut JsonElement) -> Edit {
+            if path.tokens().last().is_some_and(|key| key == "password") {
+                return Edit::Replace(JsonElement::Str("***".to_string()));
+            }
+            if *element == JsonElement::Null {
+                return Edit::Remove;
+            }
+            if let JsonElement::Number(number) = element {
+                *number += 1.0;
+            }
+            return Edit::Keep;
+        }
+    }
+
+    #[test]
+    fn should_edit_values() {
+        let mut document = json(
+            r#"{"accounts": [{"user": "ann", "password": "hunter2", "age": 30},
+                null, {"user": {"user": "x"}, "password": null}], "note": null}"#,
+        );
+        walk_mut(&mut document, &mut Cleanup);
+        assert_eq!(
+            json(
+                r#"{"accounts": [{"name": "ann", "password": "***", "age": 31},
+                    {"name": {"name": "x"}, "password": "***"}]}"#
+            ),
+            document
+        );
+
+        let mut scalar = JsonElement::Null;
+        walk_mut(&mut scalar, &mut Cleanup);
+        assert_eq!(JsonElement::Null, scalar);
+    }
+
+    struct StopAt(&'static str);
+
+    impl VisitorMut for StopAt {
+        fn visit_mut(&mut self, path: &JsonPointer, element: &mut JsonElement) -> Edit {
+            if path.to_string() == self.0 {
+                return Edit::Stop;
+            }
+            if let JsonElement::Number(number) = element {
+                *number = -*number;
+            }
+            return Edit::Keep;
+        }
+    }
+
+    #[test]
+    fn should_keep_the_rest_when_stopping() {
+        let mut document = json(r#"{"a": [1, 2, [3]], "b": 4}"#);
+        walk_mut(&mut document, &mut StopAt("/a/1"));
+        assert_eq!(json(r#"{"a": [-1, 2, [3]], "b": 4}"#), document);
+    }
+
+    #[test]
+    fn should_list_descendants_and_leaves() {
+        let document = json(r#"{"b": [1, []], "a": {"c": null, "d": {}}}"#);
+        let paths: Vec<String> = document
+            .descendants()
+            .map(|(path, _)| path.to_string())
+            .collect();
+        assert_eq!(vec!["/a", "/a/c", "/a/d", "/b", "/b/0", "/b/1"], paths);
+        let leaves: Vec<(String, &JsonElement)> = document
+            .leaves()
+            .map(|(path, element)| (path.to_string(), element))
+            .collect();
+        assert_eq!(
+            vec![
+                ("/a/c".to_string(), &JsonElement::Null),
+                ("/a/d".to_string(), &json("{}")),
+                ("/b/0".to_string(), &JsonElement::Number(1.0)),
+                ("/b/1".to_string(), &json("[]")),
+            ],
+            leaves
+        );
+        let scalar = JsonElement::Boolean(true);
+        assert_eq!(0, scalar.descendants().count());
+        assert_eq!(
+            vec![(JsonPointer::root(), &scalar)],
+            scalar.leaves().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_walk_deep_trees() {
+        let mut document = JsonElement::Number(1.0);
+        for _ in 0..100_000 {
+            document = JsonElement::Array(vec![document]);
+        }
+        let mut numbers = Numbers(Vec::new());
+        walk(&document, &mut numbers);
+        assert_eq!(1, numbers.0.len());
+        walk_mut(&mut document, &mut Cleanup);
+        assert_eq!(1, document.leaves().count());
+    }
+}
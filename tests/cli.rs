@@ -0,0 +1,246 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Runs the `jsonp` binary.
+
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn jsonp(args: &[&str], stdin: Option<&str>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jsonp"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = child.stdin.take().unwrap();
+    // The command may exit without reading its input, e.g. on a usage error.
+    match input.write_all(stdin.unwrap_or("").as_bytes()) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => panic!("{e}"),
+        _ => {}
+    }
+    drop(input);
+    return child.wait_with_output().unwrap();
+}
+
+fn stdout(output: &Output) -> String {
+    return String::from_utf8(output.stdout.clone()).unwrap();
+}
+
+fn stderr(output: &Output) -> String {
+    return String::from_utf8(output.stderr.clone()).unwrap();
+}
+
+/// A fresh directory under the system temp dir, unique to the test.
+fn scratch_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jsonp-cli-{}-{test}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+#[test]
+fn validate_should_report_line_and_column() {
+    let output = jsonp(&["validate"], Some("{\"a\": 1}"));
+    assert_eq!(Some(0), output.status.code());
+
+    let output = jsonp(&["validate"], Some("{\n  \"a\": 1,\n  \"b\" 2\n}"));
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!("<stdin>:3:7: Expected a colon\n", stderr(&output));
+}
+
+#[test]
+fn fmt_should_pretty_print_with_indent_options() {
+    let json = "{\"b\":[1,2],\"a\":{}}";
+    let output = jsonp(&["fmt"], Some(json));
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "{\n  \"a\": {},\n  \"b\": [\n    1,\n    2\n  ]\n}\n",
+        stdout(&output)
+    );
+
+    let output = jsonp(&["fmt", "--indent", "4", "-"], Some("[1]"));
+    assert_eq!("[\n    1\n]\n", stdout(&output));
+
+    let output = jsonp(&["fmt", "--tab"], Some("[1]"));
+    assert_eq!("[\n\t1\n]\n", stdout(&output));
+
+    let output = jsonp(&["fmt", "--indent", "x"], Some("[1]"));
+    assert_eq!(Some(2), output.status.code());
+}
+
+#[test]
+fn fmt_should_rewrite_files_in_place() {
+    let dir = scratch_dir("fmt");
+    let good = dir.join("good.json");
+    let bad = dir.join("bad.json");
+    fs::write(&good, "[true,null]").unwrap();
+    fs::write(&bad, "[true,").unwrap();
+
+    let output = jsonp(
+        &["fmt", "-i", good.to_str().unwrap(), bad.to_str().unwrap()],
+        None,
+    );
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!("", stdout(&output));
+    assert_eq!(
+        "[\n  true,\n  null\n]\n",
+        fs::read_to_string(&good).unwrap()
+    );
+    assert_eq!("[true,", fs::read_to_string(&bad).unwrap());
+
+    let output = jsonp(&["fmt", "--in-place"], Some("[]"));
+    assert_eq!(Some(2), output.status.code());
+}
+
+#[test]
+fn minify_should_strip_whitespace() {
+    let output = jsonp(&["minify"], Some(" {\n \"k\" : [ 1 , \"v\" ] \n}\n"));
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("{\"k\":[1,\"v\"]}\n", stdout(&output));
+}
+
+#[test]
+fn check_should_expand_globs_and_summarize() {
+    let dir = scratch_dir("check");
+    fs::create_dir_all(dir.join("nested/deeper")).unwrap();
+    fs::write(dir.join("one.json"), "1").unwrap();
+    fs::write(dir.join("two.json"), "[2").unwrap();
+    fs::write(dir.join("notes.txt"), "not json").unwrap();
+    fs::write(dir.join("nested/deeper/three.json"), "{}").unwrap();
+
+    let pattern = format!("{}/**/*.json", dir.to_str().unwrap());
+    let output = jsonp(&["check", &pattern], None);
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!("3 files checked: 2 valid, 1 invalid\n", stdout(&output));
+    assert!(stderr(&output).contains("two.json:1:3: "));
+
+    let pattern = format!("{}/o?e.*", dir.to_str().unwrap());
+    let output = jsonp(&["check", &pattern], None);
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("1 files checked: 1 valid, 0 invalid\n", stdout(&output));
+
+    let pattern = format!("{}/*.yaml", dir.to_str().unwrap());
+    let output = jsonp(&["check", &pattern], None);
+    assert_eq!(Some(2), output.status.code());
+}
+
+#[cfg(unix)]
+#[test]
+fn check_should_not_follow_symlinked_directories() {
+    let dir = scratch_dir("check-symlinks");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join("nested/one.json"), "1").unwrap();
+    std::os::unix::fs::symlink("..", dir.join("nested/parent")).unwrap();
+
+    let pattern = format!("{}/**/*.json", dir.to_str().unwrap());
+    let output = jsonp(&["check", &pattern], None);
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("1 files checked: 1 valid, 0 invalid\n", stdout(&output));
+}
+
+#[test]
+fn should_reject_unknown_commands() {
+    let output = jsonp(&["frobnicate"], None);
+    assert_eq!(Some(2), output.status.code());
+    assert!(stderr(&output).contains("Usage: jsonp"));
+}
+
+#[test]
+fn query_should_filter_documents() {
+    let json =
+        "{\"users\": [{\"name\": \"ana\", \"age\": 31}, {\"name\": \"bruno\", \"age\": 27}]}";
+    let output = jsonp(
+        &["query", ".users[] | select(.age > 30) | .name"],
+        Some(json),
+    );
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("\"ana\"\n", stdout(&output));
+
+    let output = jsonp(&["query", "-r", ".users[].name"], Some(json));
+    assert_eq!("ana\nbruno\n", stdout(&output));
+
+    let output = jsonp(&["query", "{n: (.users | length)}"], Some(json));
+    assert_eq!("{\n  \"n\": 2\n}\n", stdout(&output));
+
+    let output = jsonp(&["query", "-c", ".users | map({name})"], Some(json));
+    assert_eq!(
+        "[{\"name\":\"ana\"},{\"name\":\"bruno\"}]\n",
+        stdout(&output)
+    );
+
+    let output = jsonp(&["query", ".[] | .a"], Some(json));
+    assert_eq!(Some(1), output.status.code());
+    assert!(stderr(&output).starts_with("<stdin>: Cannot index array"));
+
+    let output = jsonp(&["query", ".["], Some(json));
+    assert_eq!(Some(2), output.status.code());
+}
+
+#[test]
+fn query_should_read_and_write_ndjson() {
+    let lines = "{\"id\": 1, \"tags\": [\"a\"]}\n\n{\"id\": 2, \"tags\": []}\n{\"id\": 3,\n";
+    let output = jsonp(
+        &["query", "--ndjson", "{id, n: (.tags | length)}"],
+        Some(lines),
+    );
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!("{\"id\":1,\"n\":1}\n{\"id\":2,\"n\":0}\n", stdout(&output));
+    assert!(stderr(&output).starts_with("<stdin>:4:"));
+}
+
+#[test]
+fn diff_should_print_changes() {
+    let dir = scratch_dir("diff");
+    let old = dir.join("old.json");
+    let new = dir.join("new.json");
+    fs::write(
+        &old,
+        "{\"port\": 80, \"hosts\": [\"a\", \"b\"], \"debug\": true}",
+    )
+    .unwrap();
+    fs::write(&new, "{\"port\": 8080, \"hosts\": [\"a\", \"c\", \"b\"]}").unwrap();
+    let (old, new) = (old.to_str().unwrap(), new.to_str().unwrap());
+
+    let output = jsonp(&["diff", old, new], None);
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(
+        "- /debug: true\n+ /hosts/1: \"c\"\n~ /port: 80 -> 8080\n",
+        stdout(&output)
+    );
+
+    let output = jsonp(&["diff", "--color", "always", old, old], None);
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("", stdout(&output));
+
+    let output = jsonp(
+        &["diff", "--json", old, "-"],
+        Some("{\"port\": 80, \"hosts\": [\"a\", \"b\"]}"),
+    );
+    assert_eq!(
+        "[\n  {\n    \"op\": \"remove\",\n    \"path\": \"/debug\",\n    \"value\": true\n  }\n]\n",
+        stdout(&output)
+    );
+
+    let output = jsonp(&["diff", old, "-"], Some("{"));
+    assert_eq!(Some(2), output.status.code());
+    let output = jsonp(&["diff", old], None);
+    assert_eq!(Some(2), output.status.code());
+}
@@ -0,0 +1,298 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Drives `jsonp-lsp` with scripted JSON-RPC sessions.
+
+#![cfg(feature = "lsp")]
+
+use jsonp::json_element::JsonElement;
+use jsonp::parser::Parser;
+use jsonp::pointer::JsonPointer;
+use jsonp::serializer::Serializer;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const URI: &str = "file:///config.json";
+
+/// Sends every message, framed, then closes the input. Returns the messages
+/// the server wrote and its exit code.
+fn session(messages: &[String]) -> (Vec<JsonElement>, Option<i32>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jsonp-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = child.stdin.take().unwrap();
+    for message in messages {
+        // The server may exit before reading everything, e.g. after `exit`.
+        let framed = format!("Content-Length: {}\r\n\r\n{message}", message.len());
+        if input.write_all(framed.as_bytes()).is_err() {
+            break;
+        }
+    }
+    drop(input);
+    let output = child.wait_with_output().unwrap();
+    let mut stdout = String::from_utf8(output.stdout).unwrap();
+    let mut replies = Vec::new();
+    while !stdout.is_empty() {
+        let (header, rest) = stdout.split_once("\r\n\r\n").unwrap();
+        let length: usize = header["Content-Length: ".len()..].parse().unwrap();
+        replies.push(Parser::parse(&rest[..length]).ok().unwrap());
+        stdout = rest[length..].to_string();
+    }
+    return (replies, output.status.code());
+}
+
+fn request(id: usize, method: &str, params: &str) -> String {
+    return format!(
+        r#"{{"jsonrpc": "2.0", "id": {id}, "method": "{method}", "params": {params}}}"#
+    );
+}
+
+fn notification(method: &str, params: &str) -> String {
+    return format!(r#"{{"jsonrpc": "2.0", "method": "{method}", "params": {params}}}"#);
+}
+
+fn open(text: &str) -> String {
+    let text = Serializer::serialize(&JsonElement::Str(text.to_string()));
+    return notification(
+        "textDocument/didOpen",
+        &format!(
+            r#"{{"textDocument": {{"uri": "{URI}", "languageId": "json", "version": 1, "text": {text}}}}}"#
+        ),
+    );
+}
+
+fn document() -> String {
+    return format!(r#"{{"textDocument": {{"uri": "{URI}"}}}}"#);
+}
+
+/// The session every test runs: initialize, `body`, then a clean shutdown.
+fn scripted(body: Vec<String>) -> Vec<JsonElement> {
+    let mut messages = vec![
+        request(0, "initialize", r#"{"capabilities": {}}"#),
+        notification("initialized", "{}"),
+    ];
+    messages.extend(body);
+    messages.push(request(99, "shutdown", "null"));
+    messages.push(notification("exit", "null"));
+    let (replies, code) = session(&messages);
+    assert_eq!(Some(0), code);
+    return replies;
+}
+
+fn get<'a>(message: &'a JsonElement, pointer: &str) -> &'a JsonElement {
+    return JsonPointer::parse(pointer)
+        .ok()
+        .unwrap()
+        .resolve(message)
+        .unwrap_or_else(|| panic!("{pointer} missing in {}", Serializer::serialize(message)));
+}
+
+fn json(text: &str) -> JsonElement {
+    return Parser::parse(text).ok().unwrap();
+}
+
+/// The reply to request `id`.
+fn reply(replies: &[JsonElement], id: usize) -> &JsonElement {
+    let id = JsonElement::Number(id as f64);
+    return replies
+        .iter()
+        .find(|reply| JsonPointer::parse("/id").ok().unwrap().resolve(reply) == Some(&id))
+        .unwrap();
+}
+
+#[test]
+fn should_initialize_and_shut_down() {
+    let replies = scripted(Vec::new());
+    assert_eq!(2, replies.len());
+    let capabilities = get(&replies[0], "/result/capabilities");
+    for capability in [
+        "documentFormattingProvider",
+        "documentSymbolProvider",
+        "hoverProvider",
+        "foldingRangeProvider",
+    ] {
+        assert_eq!(
+            &JsonElement::Boolean(true),
+            get(capabilities, &format!("/{capability}"))
+        );
+    }
+    assert_eq!(&JsonElement::Null, get(&replies[1], "/result"));
+
+    // Exiting without a shutdown request is an error.
+    let (_, code) = session(&[notification("exit", "null")]);
+    assert_eq!(Some(1), code);
+}
+
+#[test]
+fn should_publish_diagnostics_on_open_change_and_close() {
+    let change = notification(
+        "textDocument/didChange",
+        &format!(
+            r#"{{"textDocument": {{"uri": "{URI}", "version": 2}}, "contentChanges": [{{"text": "{{\"a\": 1}}"}}]}}"#
+        ),
+    );
+    let close = notification("textDocument/didClose", &document());
+    let replies = scripted(vec![
+        open("{\"a\": 1,\n \"\u{1f600}\": [1 2]}"),
+        change,
+        close,
+    ]);
+    let published: Vec<&JsonElement> = replies
+        .iter()
+        .filter(|reply| {
+            JsonPointer::parse("/method").ok().unwrap().resolve(reply)
+                == Some(&json(r#""textDocument/publishDiagnostics""#))
+        })
+        .collect();
+    assert_eq!(3, published.len());
+    assert_eq!(
+        &JsonElement::Str(URI.to_string()),
+        get(published[0], "/params/uri")
+    );
+    assert_eq!(
+        &json(
+            r#"[{"range": {"start": {"line": 1, "character": 9}, "end": {"line": 1, "character": 9}},
+                   "severity": 1, "source": "jsonp", "message": "Missing ','"}]"#
+        ),
+        get(published[0], "/params/diagnostics")
+    );
+    assert_eq!(&json("[]"), get(published[1], "/params/diagnostics"));
+    assert_eq!(&json("[]"), get(published[2], "/params/diagnostics"));
+}
+
+#[test]
+fn should_format_documents() {
+    let replies = scripted(vec![
+        open("{\"b\": [1, 2], \"a\": {}}"),
+        request(
+            1,
+            "textDocument/formatting",
+            &format!(
+                r#"{{"textDocument": {{"uri": "{URI}"}}, "options": {{"tabSize": 4, "insertSpaces": true}}}}"#
+            ),
+        ),
+        request(
+            2,
+            "textDocument/formatting",
+            &format!(
+                r#"{{"textDocument": {{"uri": "{URI}"}}, "options": {{"tabSize": 4, "insertSpaces": false}}}}"#
+            ),
+        ),
+        open("{"),
+        request(
+            3,
+            "textDocument/formatting",
+            &format!(
+                r#"{{"textDocument": {{"uri": "{URI}"}}, "options": {{"tabSize": 4, "insertSpaces": true}}}}"#
+            ),
+        ),
+        request(
+            4,
+            "textDocument/formatting",
+            r#"{"textDocument": {"uri": "file:///missing.json"}, "options": {}}"#,
+        ),
+    ]);
+    assert_eq!(
+        &json(
+            r#"[{"range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 22}},
+                   "newText": "{\n    \"a\": {},\n    \"b\": [\n        1,\n        2\n    ]\n}\n"}]"#
+        ),
+        get(reply(&replies, 1), "/result")
+    );
+    assert_eq!(
+        &JsonElement::Str("{\n\t\"a\": {},\n\t\"b\": [\n\t\t1,\n\t\t2\n\t]\n}\n".to_string()),
+        get(reply(&replies, 2), "/result/0/newText")
+    );
+    assert_eq!(&JsonElement::Null, get(reply(&replies, 3), "/result"));
+    assert_eq!(
+        &JsonElement::Number(-32602.0),
+        get(reply(&replies, 4), "/error/code")
+    );
+}
+
+#[test]
+fn should_outline_hover_and_fold() {
+    let text = "{\n  \"name\": \"jsonp\",\n  \"ports\": [\n    80,\n    443\n  ],\n  \"tls\": {\"on\": true}\n}\n";
+    let hover = |id: usize, line: usize, character: usize| {
+        request(
+            id,
+            "textDocument/hover",
+            &format!(
+                r#"{{"textDocument": {{"uri": "{URI}"}}, "position": {{"line": {line}, "character": {character}}}}}"#
+            ),
+        )
+    };
+    let replies = scripted(vec![
+        open(text),
+        request(1, "textDocument/documentSymbol", &document()),
+        hover(2, 4, 5),
+        hover(3, 6, 12),
+        hover(4, 0, 0),
+        hover(5, 2, 3),
+        request(6, "textDocument/foldingRange", &document()),
+        request(7, "textDocument/unknown", &document()),
+    ]);
+
+    let symbols = get(reply(&replies, 1), "/result");
+    assert_eq!(&json(r#""name""#), get(symbols, "/0/name"));
+    assert_eq!(&json(r#""ports""#), get(symbols, "/1/name"));
+    assert_eq!(&json("18"), get(symbols, "/1/kind"));
+    assert_eq!(
+        &json(r#"{"start": {"line": 2, "character": 2}, "end": {"line": 5, "character": 3}}"#),
+        get(symbols, "/1/range")
+    );
+    assert_eq!(
+        &json(r#"{"start": {"line": 2, "character": 2}, "end": {"line": 2, "character": 9}}"#),
+        get(symbols, "/1/selectionRange")
+    );
+    assert_eq!(&json(r#""1""#), get(symbols, "/1/children/1/name"));
+    assert_eq!(&json("16"), get(symbols, "/1/children/1/kind"));
+    assert_eq!(&json(r#""on""#), get(symbols, "/2/children/0/name"));
+    assert_eq!(&json("17"), get(symbols, "/2/children/0/kind"));
+
+    assert_eq!(
+        &json(r#""/ports/1""#),
+        get(reply(&replies, 2), "/result/contents/value")
+    );
+    assert_eq!(
+        &json(r#"{"start": {"line": 4, "character": 4}, "end": {"line": 4, "character": 7}}"#),
+        get(reply(&replies, 2), "/result/range")
+    );
+    assert_eq!(
+        &json(r#""/tls/on""#),
+        get(reply(&replies, 3), "/result/contents/value")
+    );
+    assert_eq!(
+        &json(r#""(root)""#),
+        get(reply(&replies, 4), "/result/contents/value")
+    );
+    assert_eq!(
+        &json(r#""/ports""#),
+        get(reply(&replies, 5), "/result/contents/value")
+    );
+
+    assert_eq!(
+        &json(r#"[{"startLine": 0, "endLine": 6}, {"startLine": 2, "endLine": 4}]"#),
+        get(reply(&replies, 6), "/result")
+    );
+    assert_eq!(
+        &JsonElement::Number(-32601.0),
+        get(reply(&replies, 7), "/error/code")
+    );
+}
@@ -0,0 +1,70 @@
+/* Copyright 2022 Bruno Flavio Ferreira
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*   https://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*/
+
+//! Checks that the library builds with `#![no_std]` and only `alloc`.
+
+use std::path::Path;
+use std::process::Command;
+
+/// A bare metal target, without `std`.
+const TARGET: &str = "thumbv7em-none-eabihf";
+
+/// Runs `cargo check` on the library without default features, for `target`
+/// or for the host.
+fn check(target: Option<&str>) {
+    let mut command = Command::new(env!("CARGO"));
+    command
+        .args(["check", "--lib", "--offline", "--no-default-features"])
+        .arg("--target-dir")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_std"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+    if let Some(target) = target {
+        command.args(["--target", target]);
+    }
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn is_installed(target: &str) -> bool {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(rustc)
+        .args(["--print", "sysroot"])
+        .output()
+        .unwrap();
+    let sysroot = String::from_utf8(output.stdout).unwrap();
+    return Path::new(sysroot.trim())
+        .join("lib/rustlib")
+        .join(target)
+        .exists();
+}
+
+#[test]
+fn should_build_without_std() {
+    check(None);
+}
+
+#[test]
+#[ignore = "needs `rustup target add thumbv7em-none-eabihf`, run it with --ignored"]
+fn should_build_for_a_bare_metal_target() {
+    assert!(
+        is_installed(TARGET),
+        "install the target with `rustup target add {TARGET}`"
+    );
+    check(Some(TARGET));
+}